@@ -0,0 +1,103 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use viking::functions::{self, Info, Status, WriteOptions};
+
+/// Keeps track of the peak heap usage and the number of allocations, so that the memory usage
+/// of reading a function list can be reported alongside the timings.
+struct PeakAllocator;
+
+static CURRENT_BYTES: AtomicUsize = AtomicUsize::new(0);
+static PEAK_BYTES: AtomicUsize = AtomicUsize::new(0);
+static NUM_ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+fn record_alloc(size: usize) {
+    NUM_ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    let current = CURRENT_BYTES.fetch_add(size, Ordering::Relaxed) + size;
+    PEAK_BYTES.fetch_max(current, Ordering::Relaxed);
+}
+
+unsafe impl GlobalAlloc for PeakAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            record_alloc(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        CURRENT_BYTES.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            CURRENT_BYTES.fetch_sub(layout.size(), Ordering::Relaxed);
+            record_alloc(new_size);
+        }
+        new_ptr
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: PeakAllocator = PeakAllocator;
+
+/// Writes a function list that is about as large as the ones in real projects.
+fn make_test_csv(num_functions: u64) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("viking_bench_{}.csv", num_functions));
+    let functions: Vec<Info> = (0..num_functions)
+        .map(|i| {
+            let status = match i % 4 {
+                0 => Status::Matching,
+                1 => Status::NonMatchingMinor,
+                2 => Status::Wip,
+                _ => Status::NotDecompiled,
+            };
+            Info::new(
+                i * 0x40,
+                0x40,
+                format!("_ZN4ksys3act8BaseProc{}EPN4sead4HeapE", i),
+                status,
+            )
+        })
+        .collect();
+    // The default options don't depend on the config, which the benchmarks don't have.
+    functions::write_functions_to_path_ex(&path, &functions, &WriteOptions::default()).unwrap();
+    path
+}
+
+/// Prints the peak heap usage and the number of allocations while reading the function list
+/// at `path`. Parsing a row should only allocate its name (and the growth of the result).
+fn report_peak_memory(path: &Path, num_functions: u64) {
+    PEAK_BYTES.store(CURRENT_BYTES.load(Ordering::Relaxed), Ordering::Relaxed);
+    let baseline = PEAK_BYTES.load(Ordering::Relaxed);
+    let allocations_before = NUM_ALLOCATIONS.load(Ordering::Relaxed);
+    let functions = functions::get_functions_for_path(path).unwrap();
+    let peak = PEAK_BYTES.load(Ordering::Relaxed) - baseline;
+    let allocations = NUM_ALLOCATIONS.load(Ordering::Relaxed) - allocations_before;
+    println!(
+        "get_functions_for_path ({} functions): peak heap usage {} KiB, {:.2} allocations per row",
+        num_functions,
+        peak / 1024,
+        allocations as f64 / num_functions as f64
+    );
+    drop(functions);
+}
+
+fn bench_get_functions_for_path(c: &mut Criterion) {
+    for &num_functions in &[110_000, 1_000] {
+        let path = make_test_csv(num_functions);
+        report_peak_memory(&path, num_functions);
+        c.bench_function(
+            &format!("get_functions_for_path ({} functions)", num_functions),
+            |b| b.iter(|| functions::get_functions_for_path(&path).unwrap()),
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
+
+criterion_group!(benches, bench_get_functions_for_path);
+criterion_main!(benches);
@@ -0,0 +1,19 @@
+fn main() {
+    // Builds the C test program for the C interface; see tests/ffi.rs.
+    #[cfg(feature = "ffi")]
+    {
+        println!("cargo:rerun-if-changed=tests/ffi.c");
+        println!("cargo:rerun-if-changed=include/viking.h");
+        cc::Build::new()
+            .file("tests/ffi.c")
+            .include("include")
+            .define("VIKING_FFI_TEST_HARNESS", None)
+            // Only the test links against this library (see tests/ffi.rs), not the crate.
+            .cargo_metadata(false)
+            .compile("viking_ffi_test");
+        println!(
+            "cargo:rustc-link-search=native={}",
+            std::env::var("OUT_DIR").unwrap()
+        );
+    }
+}
@@ -0,0 +1,23 @@
+// Writes the functions whose demangled name matches a glob-style pattern to a new function list.
+//
+// Usage: cargo run --example export_subset -- data/functions.csv 'nn::os::*' nn_os.csv
+
+use anyhow::{bail, Result};
+use viking::prelude::*;
+
+fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.len() != 3 {
+        bail!("usage: export_subset <functions.csv> <pattern> <output.csv>");
+    }
+
+    let functions = get_functions_for_path(args[0].as_ref())?;
+    let subset: Vec<Info> = get_functions_with_pattern(&functions, &args[1])?
+        .into_iter()
+        .cloned()
+        .collect();
+
+    write_functions_to_path(args[2].as_ref(), &subset)?;
+    println!("wrote {} functions to {}", subset.len(), args[2]);
+    Ok(())
+}
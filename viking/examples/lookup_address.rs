@@ -0,0 +1,35 @@
+// Prints the function that contains an address.
+//
+// Usage: cargo run --example lookup_address -- data/functions.csv 0x7100001234
+
+use anyhow::{bail, Context, Result};
+use viking::prelude::*;
+
+fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.len() != 2 {
+        bail!("usage: lookup_address <functions.csv> <address>");
+    }
+
+    let functions = get_functions_for_path(args[0].as_ref())?;
+    let addr = parse_address_or_offset(&args[1])
+        .with_context(|| format!("invalid address: {}", args[1]))?;
+
+    let info = functions
+        .iter()
+        .find(|info| info.addr <= addr && addr < info.addr + info.size as u64)
+        .with_context(|| format!("no function contains {}", format_addr(addr)))?;
+
+    let name = demangle_str(&info.name).unwrap_or_else(|_| info.name.clone());
+    println!(
+        "{} (size {:#x}, {}): {}",
+        format_addr(info.addr),
+        info.size,
+        info.status.description(),
+        name
+    );
+    if addr != info.addr {
+        println!("offset {:#x}", addr - info.addr);
+    }
+    Ok(())
+}
@@ -0,0 +1,40 @@
+// Prints progress statistics for a function list.
+//
+// Usage: cargo run --example print_stats -- data/functions.csv
+
+use anyhow::{Context, Result};
+use std::path::PathBuf;
+use viking::prelude::*;
+
+fn main() -> Result<()> {
+    let csv_path: PathBuf = std::env::args()
+        .nth(1)
+        .context("usage: print_stats <functions.csv>")?
+        .into();
+
+    let functions = get_functions_for_path(&csv_path)?;
+    let stats = compute_stats(&functions);
+
+    println!(
+        "{} functions ({} bytes)",
+        stats.total.functions, stats.total.bytes
+    );
+    for status in &[
+        Status::Matching,
+        Status::NonMatchingMinor,
+        Status::NonMatchingMajor,
+        Status::Wip,
+        Status::NotDecompiled,
+        Status::Library,
+    ] {
+        let counts = stats.get(status);
+        println!(
+            "{:>20}: {:>6} functions, {:>9} bytes",
+            status.description(),
+            counts.functions,
+            counts.bytes
+        );
+    }
+    println!("{:.3}% matching", stats.matching_byte_fraction() * 100.0);
+    Ok(())
+}
@@ -0,0 +1,40 @@
+// Prints the functions that are most worth working on next, with the owner of each function
+// according to the metadata sidecar. Functions in the project's ignore list are left out.
+//
+// Usage: cargo run --example print_top_actionable -- data/functions.csv [metadata.toml] [count]
+
+use anyhow::{Context, Result};
+use std::path::PathBuf;
+use viking::ignore::IgnoreSet;
+use viking::metadata::Metadata;
+use viking::paginate::{Paginated, RenderBudget};
+use viking::prelude::*;
+use viking::search;
+
+fn main() -> Result<()> {
+    let mut args = std::env::args().skip(1);
+    let csv_path: PathBuf = args
+        .next()
+        .context("usage: print_top_actionable <functions.csv> [metadata.toml] [count]")?
+        .into();
+    let metadata = match args.next() {
+        Some(path) => Metadata::load_from_path(&PathBuf::from(path))?,
+        None => Metadata::default(),
+    };
+    let count = match args.next() {
+        Some(count) => count.parse().context("count must be a number")?,
+        None => 20,
+    };
+
+    let functions = get_functions_for_path(&csv_path)?;
+    let top = search::get_top_n_actionable(&functions, count, &IgnoreSet::load()?);
+    print!(
+        "{}",
+        search::render_results_with_owners(
+            &Paginated::new(top),
+            &RenderBudget::unlimited(),
+            &metadata
+        )
+    );
+    Ok(())
+}
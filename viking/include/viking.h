@@ -0,0 +1,124 @@
+
+/*
+ * C interface to the viking function list tools.
+ *
+ * Build the shared library with:
+ *   cargo rustc --release --lib --features ffi --crate-type cdylib
+ *
+ * Ownership:
+ * - Handles are created by viking_load and must be freed with viking_free.
+ * - Name pointers in ViKingInfo are owned by the handle they were returned for. They stay
+ *   valid (and unchanged) until viking_free is called on that handle, and must not be freed
+ *   by the caller.
+ * - viking_demangle writes into a caller-provided buffer and does not allocate anything
+ *   that the caller needs to free.
+ *
+ * Thread safety:
+ * - A handle is never modified after viking_load returns, so viking_lookup_addr and
+ *   viking_lookup_name can be called on the same handle from several threads at once.
+ * - viking_free must not be called while another thread is still using the handle
+ *   (or any name pointer that was returned for it).
+ * - viking_load and viking_demangle can be called from any thread at any time.
+ */
+
+
+#ifndef VIKING_H
+#define VIKING_H
+
+/* Generated by cbindgen from src/ffi.rs. Do not edit this file manually. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * A loaded function list, for C code (e.g. IDA plugins). Opaque to C code.
+ *
+ * See `include/viking.h` (generated with `cbindgen --config cbindgen.toml`) for thread safety
+ * and ownership rules. To build a shared library:
+ * `cargo rustc --release --lib --features ffi --crate-type cdylib`
+ */
+typedef struct ViKingHandle ViKingHandle;
+
+/**
+ * A function entry.
+ */
+typedef struct ViKingInfo {
+  /**
+   * Address of the function, including the base address of the executable (0x7100000000).
+   */
+  uint64_t address;
+  uint32_t size;
+  /**
+   * Status code, as in the function list (e.g. 'O' for matching functions).
+   */
+  char status;
+  /**
+   * Mangled name of the function (empty for unnamed functions).
+   * Owned by the handle: valid until `viking_free` is called.
+   */
+  const char *name;
+} ViKingInfo;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Loads a function list. Returns null on failure.
+ *
+ * # Safety
+ * `path` must be a valid NUL-terminated string.
+ */
+ViKingHandle *viking_load(const char *path);
+
+/**
+ * Looks up the function that contains `addr` (including the base address).
+ *
+ * Returns 1 and fills `out_info` if the function was found, 0 if it was not found
+ * and -1 if a pointer is null.
+ *
+ * # Safety
+ * `handle` must have been returned by `viking_load` and not freed; `out_info` must be
+ * valid for writes.
+ */
+int32_t viking_lookup_addr(const ViKingHandle *handle, uint64_t addr, ViKingInfo *out_info);
+
+/**
+ * Looks up a function by mangled name.
+ *
+ * Returns 1 and fills `out_info` if the function was found, 0 if it was not found
+ * and -1 if a pointer is null.
+ *
+ * # Safety
+ * `handle` must have been returned by `viking_load` and not freed; `name` must be a valid
+ * NUL-terminated string; `out_info` must be valid for writes.
+ */
+int32_t viking_lookup_name(const ViKingHandle *handle, const char *name, ViKingInfo *out_info);
+
+/**
+ * Demangles a C++ symbol into `buf` (NUL-terminated, truncated to `buflen - 1` bytes).
+ *
+ * Returns the length of the full demangled name (excluding the NUL terminator), like
+ * `snprintf`, or 0 if the name could not be demangled.
+ *
+ * # Safety
+ * `name` must be a valid NUL-terminated string; `buf` must be valid for `buflen` bytes
+ * of writes (or null if `buflen` is 0).
+ */
+uintptr_t viking_demangle(const char *name, char *buf, uintptr_t buflen);
+
+/**
+ * Frees a handle. Names that were returned for this handle become invalid.
+ *
+ * # Safety
+ * `handle` must be null or have been returned by `viking_load` and not freed yet.
+ */
+void viking_free(ViKingHandle *handle);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* VIKING_H */
@@ -0,0 +1,1447 @@
+use crate::binary::BaseBinary;
+use crate::capstone_utils::translate_cs_error;
+use crate::functions::{self, Info, Status};
+use crate::outlined;
+use crate::paginate::{Paginated, RenderBudget};
+use crate::repo;
+use crate::stats::Stats;
+use anyhow::{anyhow, bail, Result};
+use capstone as cs;
+use cs::arch::BuildsCapstone;
+use rayon::prelude::*;
+use rustc_hash::{FxHashMap, FxHashSet};
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::io::Write;
+use std::ops::Range;
+
+#[derive(Clone, Debug, Default)]
+pub struct DensityBucket {
+    /// Start address of the bucket.
+    /// *Note*: does not contain the IDA base (0x7100000000).
+    pub addr: u64,
+    pub function_count: usize,
+    pub total_bytes: u64,
+    pub matched_bytes: u64,
+    /// `matched_bytes / total_bytes`, or 0 for buckets that contain no functions.
+    pub matched_fraction: f64,
+}
+
+/// Splits the address space covered by `functions` (from the lowest to the highest function
+/// address) into buckets of `bucket_size` bytes and counts the functions in each bucket.
+///
+/// Functions are assigned to the bucket that contains their start address.
+/// Fails if `bucket_size` is 0.
+pub fn get_function_density_map(
+    functions: &[Info],
+    bucket_size: u64,
+) -> Result<Vec<DensityBucket>> {
+    if bucket_size == 0 {
+        bail!("bucket size must not be 0");
+    }
+
+    let min_addr = match functions.iter().map(|info| info.addr).min() {
+        Some(addr) => addr,
+        None => return Ok(Vec::new()),
+    };
+    let max_addr = functions.iter().map(|info| info.addr).max().unwrap();
+
+    let num_buckets = ((max_addr - min_addr) / bucket_size + 1) as usize;
+    let mut buckets: Vec<DensityBucket> = (0..num_buckets)
+        .map(|i| DensityBucket {
+            addr: min_addr + i as u64 * bucket_size,
+            ..Default::default()
+        })
+        .collect();
+
+    for function in functions {
+        let bucket = &mut buckets[((function.addr - min_addr) / bucket_size) as usize];
+        bucket.function_count += 1;
+        bucket.total_bytes += function.size as u64;
+        if function.status == Status::Matching {
+            bucket.matched_bytes += function.size as u64;
+        }
+    }
+
+    for bucket in &mut buckets {
+        if bucket.total_bytes != 0 {
+            bucket.matched_fraction = bucket.matched_bytes as f64 / bucket.total_bytes as f64;
+        }
+    }
+
+    Ok(buckets)
+}
+
+/// Default for the `min_gap_size` parameter of `detect_potential_inlined_functions`
+/// (one instruction).
+pub const DEFAULT_MIN_GAP_SIZE: u32 = 4;
+
+/// Space between two functions that is not covered by any function.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AddressGap {
+    /// Start address of the gap (end of the previous function).
+    /// *Note*: does not contain the IDA base (0x7100000000).
+    pub addr: u64,
+    pub size: u32,
+    /// Address of the function that precedes the gap.
+    pub previous_function_addr: u64,
+}
+
+/// Returns gaps between functions that are at least `min_gap_size` bytes large,
+/// sorted by size (largest first).
+///
+/// Smaller gaps are most likely alignment padding. Larger gaps can be code that is missing
+/// from the function list (e.g. inlined code that the previous function should cover)
+/// or padding tables. This is only a heuristic.
+pub fn detect_potential_inlined_functions(
+    functions: &[Info],
+    min_gap_size: u32,
+) -> Vec<AddressGap> {
+    let mut ranges: Vec<(u64, u64)> = functions
+        .iter()
+        .map(|info| (info.addr, info.addr + info.size as u64))
+        .collect();
+    ranges.par_sort_unstable();
+
+    let mut gaps = Vec::new();
+    let mut previous: Option<(u64, u64)> = None;
+    for (addr, end) in ranges {
+        if let Some((previous_addr, previous_end)) = previous {
+            if addr > previous_end && addr - previous_end >= min_gap_size as u64 {
+                gaps.push(AddressGap {
+                    addr: previous_end,
+                    size: (addr - previous_end) as u32,
+                    previous_function_addr: previous_addr,
+                });
+            }
+            // Aliases and overlapping functions must not shrink the covered range.
+            if end <= previous_end {
+                continue;
+            }
+        }
+        previous = Some((addr, end));
+    }
+
+    gaps.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.addr.cmp(&b.addr)));
+    gaps
+}
+
+/// Default for `CoverageOptions::gap_threshold`: smaller gaps are most likely alignment padding.
+pub const DEFAULT_COVERAGE_GAP_THRESHOLD: u64 = 0x10;
+
+#[derive(Clone, Debug)]
+pub struct CoverageOptions {
+    /// Only uncovered ranges that are larger than this many bytes are listed as gaps.
+    /// Smaller gaps still count as uncovered bytes.
+    pub gap_threshold: u64,
+}
+
+impl Default for CoverageOptions {
+    fn default() -> Self {
+        Self {
+            gap_threshold: DEFAULT_COVERAGE_GAP_THRESHOLD,
+        }
+    }
+}
+
+/// Part of the code that is not covered by any function.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CoverageGap {
+    /// *Note*: does not contain the IDA base (0x7100000000).
+    pub addr: u64,
+    pub size: u64,
+    /// Closest named functions before and after the gap, for orientation.
+    pub previous_function: Option<String>,
+    pub next_function: Option<String>,
+}
+
+/// Two functions that cover the same bytes, which indicates an error in the function list.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CoverageOverlap {
+    /// Start of the bytes that are covered twice.
+    pub addr: u64,
+    pub size: u64,
+    /// Address and name of the function that starts first, and of the function that starts
+    /// inside of it.
+    pub first: (u64, String),
+    pub second: (u64, String),
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CoverageReport {
+    pub text_range: Range<u64>,
+    pub covered_bytes: u64,
+    pub uncovered_bytes: u64,
+    /// Gaps that are larger than the threshold, sorted by size (largest first).
+    pub gaps: Vec<CoverageGap>,
+    /// Sorted by address.
+    pub overlaps: Vec<CoverageOverlap>,
+    /// Number of functions that are (at least partly) outside of `text_range`.
+    /// Bytes outside of the range are not counted.
+    pub functions_outside_range: usize,
+}
+
+impl CoverageReport {
+    /// Fraction of the code that is covered by functions, from 0 to 1.
+    pub fn covered_fraction(&self) -> f64 {
+        let total = self.covered_bytes + self.uncovered_bytes;
+        if total == 0 {
+            0.0
+        } else {
+            self.covered_bytes as f64 / total as f64
+        }
+    }
+
+    /// Renders a summary line followed by the gaps and overlaps, each within `budget`.
+    pub fn render_text(&self, budget: &RenderBudget) -> String {
+        let mut out = format!(
+            "{}..{}: {}/{} bytes covered ({:.3}%), {} gaps, {} overlaps\n",
+            functions::format_addr(self.text_range.start),
+            functions::format_addr(self.text_range.end),
+            self.covered_bytes,
+            self.covered_bytes + self.uncovered_bytes,
+            self.covered_fraction() * 100.0,
+            self.gaps.len(),
+            self.overlaps.len()
+        );
+        if self.functions_outside_range != 0 {
+            out += &format!(
+                "{} functions are outside of the text range\n",
+                self.functions_outside_range
+            );
+        }
+
+        if !self.gaps.is_empty() {
+            out += "gaps:\n";
+            out += &Paginated::new(self.gaps.iter().collect()).render(budget, |gap| {
+                format!(
+                    "  {} {:#x} bytes, after {}, before {}",
+                    functions::format_addr(gap.addr),
+                    gap.size,
+                    gap.previous_function.as_deref().unwrap_or("(start)"),
+                    gap.next_function.as_deref().unwrap_or("(end)")
+                )
+            });
+        }
+        if !self.overlaps.is_empty() {
+            out += "overlaps:\n";
+            out += &Paginated::new(self.overlaps.iter().collect()).render(budget, |overlap| {
+                format!(
+                    "  {} {:#x} bytes, {} ({}) overlaps {} ({})",
+                    functions::format_addr(overlap.addr),
+                    overlap.size,
+                    overlap.second.1,
+                    functions::format_addr(overlap.second.0),
+                    overlap.first.1,
+                    functions::format_addr(overlap.first.0)
+                )
+            });
+        }
+        out
+    }
+
+    /// Renders the report as a JSON object. Nothing is left out.
+    pub fn render_json(&self) -> String {
+        let gaps: Vec<serde_json::Value> = self
+            .gaps
+            .iter()
+            .map(|gap| {
+                serde_json::json!({
+                    "address": functions::format_addr(gap.addr),
+                    "size": gap.size,
+                    "previous_function": gap.previous_function,
+                    "next_function": gap.next_function,
+                })
+            })
+            .collect();
+        let overlaps: Vec<serde_json::Value> = self
+            .overlaps
+            .iter()
+            .map(|overlap| {
+                serde_json::json!({
+                    "address": functions::format_addr(overlap.addr),
+                    "size": overlap.size,
+                    "first": {
+                        "address": functions::format_addr(overlap.first.0),
+                        "name": overlap.first.1,
+                    },
+                    "second": {
+                        "address": functions::format_addr(overlap.second.0),
+                        "name": overlap.second.1,
+                    },
+                })
+            })
+            .collect();
+        serde_json::json!({
+            "start": functions::format_addr(self.text_range.start),
+            "end": functions::format_addr(self.text_range.end),
+            "covered_bytes": self.covered_bytes,
+            "uncovered_bytes": self.uncovered_bytes,
+            "functions_outside_range": self.functions_outside_range,
+            "gaps": gaps,
+            "overlaps": overlaps,
+        })
+        .to_string()
+    }
+}
+
+/// Same as `coverage_ex`, with the default options.
+pub fn coverage(functions: &[Info], text_range: Range<u64>) -> CoverageReport {
+    coverage_ex(functions, text_range, &CoverageOptions::default())
+}
+
+/// Same as `coverage`, for the text segment of `binary`.
+pub fn coverage_for_binary(binary: &BaseBinary, functions: &[Info]) -> Result<CoverageReport> {
+    Ok(coverage(functions, binary.text_range()?))
+}
+
+/// Computes how much of `text_range` is covered by functions, and lists the gaps
+/// (code that is missing from the function list) and overlapping functions.
+/// Entries at the same address are aliases of one function and do not overlap each other.
+///
+/// `text_range` must not contain the IDA base (0x7100000000); see `BaseBinary::text_range`.
+pub fn coverage_ex(
+    functions: &[Info],
+    text_range: Range<u64>,
+    options: &CoverageOptions,
+) -> CoverageReport {
+    let mut sorted: Vec<&Info> = functions.iter().collect();
+    sorted.par_sort_by_key(|info| (info.addr, info.size));
+
+    let mut report = CoverageReport {
+        text_range: text_range.clone(),
+        ..Default::default()
+    };
+
+    // Index of the function that ends last so far, and its end address.
+    let mut furthest: Option<(usize, u64)> = None;
+    // Same as `furthest`, but only for functions before the current address.
+    let mut furthest_before: Option<(usize, u64)> = None;
+    let mut previous_named: Option<&str> = None;
+    // Gaps whose next named function is not known yet.
+    let mut pending_gaps: Vec<usize> = Vec::new();
+    let mut cursor = text_range.start;
+
+    for (i, info) in sorted.iter().enumerate() {
+        let end = info.addr + info.size as u64;
+        if info.addr < text_range.start || end > text_range.end {
+            report.functions_outside_range += 1;
+        }
+
+        if i == 0 || sorted[i - 1].addr != info.addr {
+            furthest_before = furthest;
+        }
+        // Overlaps are only checked once per address, for the longest alias (the last one).
+        let is_longest_alias = !matches!(sorted.get(i + 1), Some(next) if next.addr == info.addr);
+        if let Some((j, furthest_end)) = furthest_before.filter(|_| is_longest_alias) {
+            if info.addr < furthest_end && info.size != 0 {
+                let other = sorted[j];
+                report.overlaps.push(CoverageOverlap {
+                    addr: info.addr,
+                    size: end.min(furthest_end) - info.addr,
+                    first: (other.addr, other.name.clone()),
+                    second: (info.addr, info.name.clone()),
+                });
+            }
+        }
+
+        let start = info.addr.clamp(text_range.start, text_range.end);
+        let clamped_end = end.clamp(text_range.start, text_range.end);
+        if start > cursor {
+            report.uncovered_bytes += start - cursor;
+            if start - cursor > options.gap_threshold {
+                pending_gaps.push(report.gaps.len());
+                report.gaps.push(CoverageGap {
+                    addr: cursor,
+                    size: start - cursor,
+                    previous_function: previous_named.map(str::to_string),
+                    next_function: None,
+                });
+            }
+        }
+        if clamped_end > cursor {
+            report.covered_bytes += clamped_end - cursor.max(start);
+            cursor = clamped_end;
+        }
+
+        if !matches!(furthest, Some((_, furthest_end)) if furthest_end >= end) {
+            furthest = Some((i, end));
+        }
+        if !info.name.is_empty() {
+            for gap in pending_gaps.drain(..) {
+                report.gaps[gap].next_function = Some(info.name.clone());
+            }
+            previous_named = Some(&info.name);
+        }
+    }
+
+    if text_range.end > cursor {
+        report.uncovered_bytes += text_range.end - cursor;
+        if text_range.end - cursor > options.gap_threshold {
+            report.gaps.push(CoverageGap {
+                addr: cursor,
+                size: text_range.end - cursor,
+                previous_function: previous_named.map(str::to_string),
+                next_function: None,
+            });
+        }
+    }
+
+    report
+        .gaps
+        .sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.addr.cmp(&b.addr)));
+    report
+}
+
+/// Name of the bucket for functions that are not part of a class.
+pub const GLOBAL_CLASS_NAME: &str = "(global)";
+/// Name of the bucket for outlined functions (see `outlined::is_outlined_function`).
+pub const OUTLINED_CLASS_NAME: &str = "(outlined)";
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ClassSizeEntry {
+    pub class_name: String,
+    pub total_bytes: u64,
+    pub matched_bytes: u64,
+    pub function_count: usize,
+    pub matched_count: usize,
+}
+
+impl std::fmt::Display for ClassSizeEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:>10} {:>10} {:>6} {:>6}  {}",
+            self.total_bytes,
+            self.matched_bytes,
+            self.function_count,
+            self.matched_count,
+            self.class_name
+        )
+    }
+}
+
+/// Returns the demangled class (or namespace) that a function belongs to, i.e. its qualified
+/// name without the last component, or None for free functions and names that fail demangling.
+fn get_demangled_class_name(info: &Info) -> Option<String> {
+    let demangled = functions::demangle_str(&info.name).ok()?;
+    let components = functions::split_qualified_name(&demangled);
+    if components.len() > 1 {
+        Some(components[..components.len() - 1].join("::"))
+    } else {
+        None
+    }
+}
+
+/// Same as `get_demangled_class_name`, but outlined functions and functions that are not part
+/// of a class are grouped under `OUTLINED_CLASS_NAME` and `GLOBAL_CLASS_NAME`.
+pub(crate) fn get_class_name(info: &Info) -> String {
+    if outlined::is_outlined_function(&info.name) {
+        return OUTLINED_CLASS_NAME.to_string();
+    }
+    get_demangled_class_name(info).unwrap_or_else(|| GLOBAL_CLASS_NAME.to_string())
+}
+
+/// Groups functions by class and returns the total and matched size of every class,
+/// sorted by total size (largest first).
+///
+/// Unnamed functions and functions that are not part of a class are grouped under
+/// `GLOBAL_CLASS_NAME`, and outlined functions under `OUTLINED_CLASS_NAME`.
+/// Note that namespaces cannot be told apart from classes.
+pub fn generate_symbol_size_report(functions: &[Info]) -> Vec<ClassSizeEntry> {
+    let class_names: Vec<String> = functions.par_iter().map(get_class_name).collect();
+
+    let mut entries: FxHashMap<String, ClassSizeEntry> = FxHashMap::default();
+    for (function, class_name) in functions.iter().zip(class_names) {
+        let entry = entries
+            .entry(class_name)
+            .or_insert_with_key(|class_name| ClassSizeEntry {
+                class_name: class_name.clone(),
+                ..Default::default()
+            });
+        entry.total_bytes += function.size as u64;
+        entry.function_count += 1;
+        if function.status == Status::Matching {
+            entry.matched_bytes += function.size as u64;
+            entry.matched_count += 1;
+        }
+    }
+
+    let mut report: Vec<ClassSizeEntry> = entries.into_values().collect();
+    report.sort_by(|a, b| {
+        b.total_bytes
+            .cmp(&a.total_bytes)
+            .then_with(|| a.class_name.cmp(&b.class_name))
+    });
+    report
+}
+
+/// Formats a report as a text table.
+pub fn format_symbol_size_report(report: &[ClassSizeEntry]) -> String {
+    let mut table = format!(
+        "{:>10} {:>10} {:>6} {:>6}  {}\n",
+        "size", "matched", "count", "match", "class"
+    );
+    for entry in report {
+        table += &format!("{}\n", entry);
+    }
+    table
+}
+
+pub fn export_symbol_size_report_csv(
+    report: &[ClassSizeEntry],
+    writer: &mut dyn Write,
+) -> Result<()> {
+    let mut writer = csv::Writer::from_writer(writer);
+    writer.write_record([
+        "Class",
+        "Total bytes",
+        "Matched bytes",
+        "Functions",
+        "Matched functions",
+    ])?;
+    for entry in report {
+        writer.write_record(&[
+            entry.class_name.clone(),
+            entry.total_bytes.to_string(),
+            entry.matched_bytes.to_string(),
+            entry.function_count.to_string(),
+            entry.matched_count.to_string(),
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Splits a function list into one list per class. Functions keep their order within each list.
+///
+/// The key is the demangled class (or namespace) name, or None for free functions and
+/// functions whose name cannot be demangled.
+pub fn split_by_class(functions: &[Info]) -> FxHashMap<Option<String>, Vec<&Info>> {
+    let class_names: Vec<Option<String>> =
+        functions.par_iter().map(get_demangled_class_name).collect();
+
+    let mut classes: FxHashMap<Option<String>, Vec<&Info>> = FxHashMap::default();
+    for (function, class_name) in functions.iter().zip(class_names) {
+        classes.entry(class_name).or_default().push(function);
+    }
+    classes
+}
+
+/// Same as `split_by_class`, but takes ownership of the functions.
+pub fn split_by_class_owned(functions: Vec<Info>) -> FxHashMap<Option<String>, Vec<Info>> {
+    let class_names: Vec<Option<String>> =
+        functions.par_iter().map(get_demangled_class_name).collect();
+
+    let mut classes: FxHashMap<Option<String>, Vec<Info>> = FxHashMap::default();
+    for (function, class_name) in functions.into_iter().zip(class_names) {
+        classes.entry(class_name).or_default().push(function);
+    }
+    classes
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ClassCompletion {
+    pub class_name: String,
+    pub total_functions: usize,
+    pub matched_functions: usize,
+    pub total_bytes: u64,
+}
+
+/// Returns how many functions of every class are matching, sorted by total size (largest first).
+/// Functions that are not part of a class (see `split_by_class`) are listed under
+/// `GLOBAL_CLASS_NAME`.
+pub fn get_class_completion_report(functions: &[Info]) -> Vec<ClassCompletion> {
+    let mut report: Vec<ClassCompletion> = split_by_class(functions)
+        .into_iter()
+        .map(|(class_name, functions)| ClassCompletion {
+            class_name: class_name.unwrap_or_else(|| GLOBAL_CLASS_NAME.to_string()),
+            total_functions: functions.len(),
+            matched_functions: functions
+                .iter()
+                .filter(|function| function.status == Status::Matching)
+                .count(),
+            total_bytes: functions.iter().map(|function| function.size as u64).sum(),
+        })
+        .collect();
+    report.sort_by(|a, b| {
+        b.total_bytes
+            .cmp(&a.total_bytes)
+            .then_with(|| a.class_name.cmp(&b.class_name))
+    });
+    report
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortKey {
+    TotalBytes,
+    MatchedPct,
+    FunctionCount,
+}
+
+#[derive(Clone, Debug)]
+pub struct NamespaceReportOptions {
+    /// Namespaces with fewer bytes are left out.
+    pub min_bytes: u64,
+    /// Rows are sorted in decreasing order of this key, then by namespace.
+    pub sort_by: SortKey,
+    pub max_rows: Option<usize>,
+}
+
+impl Default for NamespaceReportOptions {
+    fn default() -> Self {
+        Self {
+            min_bytes: 0,
+            sort_by: SortKey::TotalBytes,
+            max_rows: None,
+        }
+    }
+}
+
+/// Returns the outermost namespace (or class) of a function, or `GLOBAL_CLASS_NAME`.
+fn get_top_level_namespace(info: &Info) -> String {
+    if let Ok(demangled) = functions::demangle_str(&info.name) {
+        let components = functions::split_qualified_name(&demangled);
+        if components.len() > 1 {
+            return components[0].to_string();
+        }
+    }
+    GLOBAL_CLASS_NAME.to_string()
+}
+
+/// Writes a table with the progress of every top-level namespace.
+///
+/// Functions that are not in a namespace (C functions, unnamed functions and names that
+/// cannot be demangled) are grouped under `GLOBAL_CLASS_NAME`.
+pub fn report_namespace_progress(
+    functions: &[Info],
+    writer: &mut dyn Write,
+    opts: &NamespaceReportOptions,
+) -> Result<()> {
+    let namespaces: Vec<String> = functions.par_iter().map(get_top_level_namespace).collect();
+
+    let mut stats: FxHashMap<String, Stats> = FxHashMap::default();
+    for (info, namespace) in functions.iter().zip(namespaces) {
+        stats.entry(namespace).or_default().add(info);
+    }
+
+    let mut rows: Vec<(String, Stats)> = stats
+        .into_iter()
+        .filter(|(_, stats)| stats.total.bytes >= opts.min_bytes)
+        .collect();
+    rows.sort_by(|(a_name, a), (b_name, b)| {
+        let ordering = match opts.sort_by {
+            SortKey::TotalBytes => b.total.bytes.cmp(&a.total.bytes),
+            SortKey::MatchedPct => b
+                .matching_byte_fraction()
+                .partial_cmp(&a.matching_byte_fraction())
+                .unwrap_or(std::cmp::Ordering::Equal),
+            SortKey::FunctionCount => b.total.functions.cmp(&a.total.functions),
+        };
+        ordering.then_with(|| a_name.cmp(b_name))
+    });
+
+    let hidden_rows = match opts.max_rows {
+        Some(max_rows) if rows.len() > max_rows => rows.len() - max_rows,
+        _ => 0,
+    };
+    rows.truncate(rows.len() - hidden_rows);
+
+    let width = rows
+        .iter()
+        .map(|(name, _)| name.len())
+        .chain(std::iter::once("namespace".len()))
+        .max()
+        .unwrap_or_default();
+
+    writeln!(
+        writer,
+        "{:<width$} {:>8} {:>8} {:>8}",
+        "namespace",
+        "funcs",
+        "matched",
+        "byte_pct",
+        width = width
+    )?;
+    for (name, stats) in &rows {
+        writeln!(
+            writer,
+            "{:<width$} {:>8} {:>8} {:>7.2}%",
+            name,
+            stats.total.functions,
+            stats.matching.functions,
+            stats.matching_byte_fraction() * 100.0,
+            width = width
+        )?;
+    }
+    if hidden_rows != 0 {
+        writeln!(writer, "({} more namespaces)", hidden_rows)?;
+    }
+    Ok(())
+}
+
+/// Classes with at most this many functions left to match are reported as nearly complete
+/// (see `ClassGroup::is_nearly_complete`).
+pub const NEARLY_COMPLETE_MAX_REMAINING: usize = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClassGroupKind {
+    Class,
+    /// A namespace (or a class that could not be told apart from one), for free functions.
+    Namespace,
+}
+
+/// Functions that belong to the same class or namespace. See `group_by_class`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClassGroup {
+    pub name: String,
+    pub kind: ClassGroupKind,
+    /// Addresses of the functions in the group, in list order.
+    pub functions: Vec<u64>,
+    pub stats: Stats,
+}
+
+impl ClassGroup {
+    /// Returns the number of functions that are not matching yet. Library functions
+    /// are not counted.
+    pub fn remaining_functions(&self) -> usize {
+        self.stats.total.functions - self.stats.matching.functions - self.stats.library.functions
+    }
+
+    /// Returns whether the class is at most `NEARLY_COMPLETE_MAX_REMAINING` functions away
+    /// from being fully matched. Namespaces are never nearly complete.
+    pub fn is_nearly_complete(&self) -> bool {
+        self.kind == ClassGroupKind::Class
+            && (1..=NEARLY_COMPLETE_MAX_REMAINING).contains(&self.remaining_functions())
+    }
+}
+
+/// Operators that can only be declared as member functions.
+const MEMBER_ONLY_OPERATORS: &[&str] = &["operator=", "operator()", "operator[]", "operator->"];
+
+/// Scope of a function for `group_by_class`.
+struct FunctionScope {
+    /// Qualified name of the scope, without template arguments.
+    name: String,
+    /// Whether the function shows that the scope is a class rather than a namespace.
+    is_class: bool,
+}
+
+/// Returns the offset of the first parenthesis that is not in template arguments.
+fn find_top_level_paren(scope: &str) -> Option<usize> {
+    let mut depth = 0usize;
+    for (i, c) in scope.bytes().enumerate() {
+        match c {
+            b'<' => depth += 1,
+            b'>' => depth = depth.saturating_sub(1),
+            b'(' if depth == 0 => return Some(i),
+            _ => (),
+        }
+    }
+    None
+}
+
+fn get_function_scope(info: &Info) -> Option<FunctionScope> {
+    let demangled = functions::demangle_str(&info.name).ok()?;
+    let signature = functions::parse_demangled_signature(&demangled)?;
+    let scope = signature.scope?;
+
+    // Local entities (e.g. lambdas) belong to the scope of their enclosing function.
+    if let Some(paren) = find_top_level_paren(scope) {
+        let components = functions::split_qualified_name(&scope[..paren]);
+        if components.len() < 2 {
+            return None;
+        }
+        return Some(FunctionScope {
+            name: components[..components.len() - 1].join("::"),
+            is_class: false,
+        });
+    }
+
+    let components = functions::split_qualified_name(scope);
+    let class_name = *components.last()?;
+    let name = *signature.components.last()?;
+    let is_class = functions::is_virtual_thunk(&info.name)
+        // Namespaces cannot be templates.
+        || scope.ends_with('>')
+        || name == class_name
+        || name.strip_prefix('~') == Some(class_name)
+        || MEMBER_ONLY_OPERATORS.contains(&name)
+        || !signature.qualifiers.is_empty();
+    Some(FunctionScope {
+        name: components.join("::"),
+        is_class,
+    })
+}
+
+/// Groups functions by the class or namespace that they belong to, based on their
+/// demangled names, and computes the progress of every group.
+///
+/// * The scope of a function is the qualified name that precedes its name, without template
+///   arguments: all instantiations of a class template are grouped together (e.g.
+///   `sead::Buffer<int>::size` and `sead::Buffer<float>::size` are in `sead::Buffer`).
+/// * Nested classes have their own group, e.g. `A::B::f` is in `A::B` and not in `A`.
+/// * Functions of local entities (e.g. lambdas or local classes) are grouped with the function
+///   that they are defined in, so `A::f()::{lambda()#1}::operator()` is in `A`.
+/// * Demangled names do not say whether a scope is a class or a namespace, so a scope is
+///   only considered a class if any of its functions is a constructor, a destructor, a virtual
+///   thunk, an operator that must be a member (e.g. `operator=`) or has cv or ref qualifiers,
+///   or if it has template arguments. Other scopes are namespaces.
+/// * Functions that are not in a scope or cannot be demangled are grouped under
+///   `GLOBAL_CLASS_NAME`, and outlined functions under `OUTLINED_CLASS_NAME`.
+pub fn group_by_class(functions: &[Info]) -> BTreeMap<String, ClassGroup> {
+    let scopes: Vec<Option<FunctionScope>> = functions
+        .par_iter()
+        .map(|info| {
+            if outlined::is_outlined_function(&info.name) {
+                None
+            } else {
+                get_function_scope(info)
+            }
+        })
+        .collect();
+
+    let class_names: FxHashSet<&str> = scopes
+        .iter()
+        .flatten()
+        .filter(|scope| scope.is_class)
+        .map(|scope| scope.name.as_str())
+        .collect();
+
+    let mut groups: BTreeMap<String, ClassGroup> = BTreeMap::new();
+    for (info, scope) in functions.iter().zip(&scopes) {
+        let (name, kind) = match scope {
+            Some(scope) if class_names.contains(scope.name.as_str()) => {
+                (scope.name.as_str(), ClassGroupKind::Class)
+            }
+            Some(scope) => (scope.name.as_str(), ClassGroupKind::Namespace),
+            None if outlined::is_outlined_function(&info.name) => {
+                (OUTLINED_CLASS_NAME, ClassGroupKind::Namespace)
+            }
+            None => (GLOBAL_CLASS_NAME, ClassGroupKind::Namespace),
+        };
+        let group = groups
+            .entry(name.to_string())
+            .or_insert_with(|| ClassGroup {
+                name: name.to_string(),
+                kind,
+                functions: Vec::new(),
+                stats: Stats::default(),
+            });
+        group.functions.push(info.addr);
+        group.stats.add(info);
+    }
+    groups
+}
+
+/// Returns the classes that are nearly complete (see `ClassGroup::is_nearly_complete`),
+/// with the fewest remaining functions first, then the largest classes first.
+pub fn get_nearly_complete_classes(groups: &BTreeMap<String, ClassGroup>) -> Vec<&ClassGroup> {
+    let mut classes: Vec<&ClassGroup> = groups
+        .values()
+        .filter(|group| group.is_nearly_complete())
+        .collect();
+    classes.sort_by(|a, b| {
+        a.remaining_functions()
+            .cmp(&b.remaining_functions())
+            .then_with(|| b.stats.total.bytes.cmp(&a.stats.total.bytes))
+            .then_with(|| a.name.cmp(&b.name))
+    });
+    classes
+}
+
+/// Renders groups (see `group_by_class`) as a markdown table, sorted in decreasing order
+/// of `sort_by`, then by name. Nearly complete classes are marked in the Remaining column.
+pub fn format_class_groups_markdown(
+    groups: &BTreeMap<String, ClassGroup>,
+    sort_by: SortKey,
+) -> String {
+    let mut rows: Vec<&ClassGroup> = groups.values().collect();
+    rows.sort_by(|a, b| {
+        let ordering = match sort_by {
+            SortKey::TotalBytes => b.stats.total.bytes.cmp(&a.stats.total.bytes),
+            SortKey::MatchedPct => b
+                .stats
+                .matching_byte_fraction()
+                .partial_cmp(&a.stats.matching_byte_fraction())
+                .unwrap_or(std::cmp::Ordering::Equal),
+            SortKey::FunctionCount => b.stats.total.functions.cmp(&a.stats.total.functions),
+        };
+        ordering.then_with(|| a.name.cmp(&b.name))
+    });
+
+    let mut table = String::from(
+        "| Name | Kind | Functions | Matching | Remaining | Size | Matching bytes |\n\
+         |---|---|---:|---:|---:|---:|---:|\n",
+    );
+    for group in rows {
+        let kind = match group.kind {
+            ClassGroupKind::Class => "class",
+            ClassGroupKind::Namespace => "namespace",
+        };
+        let remaining = if group.is_nearly_complete() {
+            format!("**{}**", group.remaining_functions())
+        } else {
+            group.remaining_functions().to_string()
+        };
+        table += &format!(
+            "| `{}` | {} | {} | {} | {} | {:#x} | {:.2}% |\n",
+            group.name.replace('|', "\\|"),
+            kind,
+            group.stats.total.functions,
+            group.stats.matching.functions,
+            remaining,
+            group.stats.total.bytes,
+            group.stats.matching_byte_fraction() * 100.0
+        );
+    }
+    table
+}
+
+/// Heuristics to run in `verify_sizes_ex`. All of them can have false positives.
+#[derive(Clone, Debug)]
+pub struct SizeCheckOptions {
+    /// Flag functions that contain bytes that cannot be decoded as instructions
+    /// (the size probably overshoots into data).
+    pub check_decoding: bool,
+    /// Flag functions that are immediately followed by another function but do not end
+    /// with a branch, return or trap.
+    pub check_terminator: bool,
+    /// Flag functions that are followed by instructions other than padding
+    /// (the size probably undershoots).
+    pub check_trailing_code: bool,
+}
+
+impl Default for SizeCheckOptions {
+    fn default() -> Self {
+        Self {
+            check_decoding: true,
+            check_terminator: true,
+            check_trailing_code: true,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SizeSuspicionKind {
+    /// The bytes at `offset` (from the start of the function) are not a valid instruction.
+    DecodingFailed { offset: u32 },
+    /// The last instruction (`insn`) does not end the function.
+    NoTerminator { insn: u32 },
+    /// There is code `offset` bytes after the end of the function, before the next function.
+    TrailingCode { offset: u32 },
+}
+
+/// A function whose size looks wrong. See `verify_sizes`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SizeSuspicion {
+    pub addr: u64,
+    pub name: String,
+    pub size: u32,
+    pub kind: SizeSuspicionKind,
+}
+
+/// Returns whether an instruction can end a function: returns, unconditional branches
+/// (including tail calls), calls (to functions that do not return) and traps.
+fn is_function_terminator(insn: u32) -> bool {
+    insn & 0xffff_fc1f == 0xd65f_0000 // ret
+        || insn & 0xffff_fc1f == 0xd61f_0000 // br
+        || insn & 0xfc00_0000 == 0x1400_0000 // b
+        || insn & 0xfc00_0000 == 0x9400_0000 // bl
+        || insn & 0xffe0_001f == 0xd420_0000 // brk
+        || insn == 0 // udf #0
+}
+
+/// Returns whether an instruction is used to pad functions for alignment.
+fn is_padding(insn: u32) -> bool {
+    insn == 0 || insn == 0xd503_201f // udf #0, nop
+}
+
+fn make_cs() -> Result<cs::Capstone> {
+    cs::Capstone::new()
+        .arm64()
+        .mode(cs::arch::arm64::ArchMode::Arm)
+        .build()
+        .or_else(translate_cs_error)
+}
+
+/// Returns the number of bytes at the start of `code` that can be decoded.
+fn get_decodable_len(cs: &cs::Capstone, code: &[u8], addr: u64) -> Result<usize> {
+    let instructions = cs.disasm_iter(code, addr).or_else(translate_cs_error)?;
+    Ok(instructions.count() * 4)
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn check_function_size(
+    cs: &cs::Capstone,
+    binary: &BaseBinary,
+    info: &Info,
+    next_addr: Option<u64>,
+    options: &SizeCheckOptions,
+) -> Result<Option<SizeSuspicionKind>> {
+    // Functions that cannot be read at all are reported by other checks.
+    let code = match binary.read(info.addr, info.size as usize) {
+        Ok(code) if code.len() >= 4 => code,
+        _ => return Ok(None),
+    };
+    let end = info.addr + info.size as u64;
+
+    if options.check_decoding {
+        let len = get_decodable_len(cs, code, info.addr)?;
+        if len + 4 <= code.len() {
+            return Ok(Some(SizeSuspicionKind::DecodingFailed {
+                offset: len as u32,
+            }));
+        }
+    }
+
+    if options.check_terminator && next_addr == Some(end) {
+        let insn = read_u32(code, code.len() / 4 * 4 - 4);
+        if !is_function_terminator(insn) {
+            return Ok(Some(SizeSuspicionKind::NoTerminator { insn }));
+        }
+    }
+
+    if options.check_trailing_code {
+        if let Some(next_addr) = next_addr.filter(|&next_addr| next_addr > end) {
+            let gap = match binary.read(end, (next_addr - end) as usize) {
+                Ok(gap) => gap,
+                Err(_) => return Ok(None),
+            };
+            for offset in (0..gap.len() / 4 * 4).step_by(4) {
+                let insn = read_u32(gap, offset);
+                if is_padding(insn) {
+                    continue;
+                }
+                // Bytes that are not instructions are data, e.g. a literal pool.
+                let addr = end + offset as u64;
+                if get_decodable_len(cs, &gap[offset..offset + 4], addr)? != 0 {
+                    return Ok(Some(SizeSuspicionKind::TrailingCode {
+                        offset: offset as u32,
+                    }));
+                }
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+/// Same as `verify_sizes_ex`, with all heuristics enabled.
+pub fn verify_sizes(binary: &BaseBinary, functions: &[Info]) -> Result<Vec<SizeSuspicion>> {
+    verify_sizes_ex(binary, functions, &SizeCheckOptions::default())
+}
+
+/// Disassembles every function in the original executable to find sizes that are likely
+/// to be wrong, which the ELF cross-check cannot detect because the original executable
+/// has no symbol sizes. Returns at most one suspicion per function, sorted by address.
+pub fn verify_sizes_ex(
+    binary: &BaseBinary,
+    functions: &[Info],
+    options: &SizeCheckOptions,
+) -> Result<Vec<SizeSuspicion>> {
+    let mut sorted: Vec<&Info> = functions.iter().filter(|info| info.size != 0).collect();
+    sorted.par_sort_by_key(|info| info.addr);
+
+    let results: Vec<Option<SizeSuspicion>> = (0..sorted.len())
+        .into_par_iter()
+        .map_init(make_cs, |cs, i| {
+            let cs = cs.as_ref().map_err(|err| anyhow!("{}", err))?;
+            let info = sorted[i];
+            // Aliases share the address of the function and are not the next function.
+            let next_addr = sorted[i + 1..]
+                .iter()
+                .map(|next| next.addr)
+                .find(|&addr| addr > info.addr);
+            let kind = check_function_size(cs, binary, info, next_addr, options)?;
+            Ok(kind.map(|kind| SizeSuspicion {
+                addr: info.addr,
+                name: info.name.clone(),
+                size: info.size,
+                kind,
+            }))
+        })
+        .collect::<Result<_>>()?;
+
+    Ok(results.into_iter().flatten().collect())
+}
+
+/// Direct calls (`bl`) between functions.
+#[derive(Clone, Debug, Default)]
+pub struct CallGraph {
+    /// Sorted, deduplicated callees of each function that calls anything.
+    callees: FxHashMap<u64, Vec<u64>>,
+}
+
+impl CallGraph {
+    /// Builds a call graph from (caller, callee) address pairs.
+    pub fn from_edges<I: IntoIterator<Item = (u64, u64)>>(edges: I) -> Self {
+        let mut callees: FxHashMap<u64, Vec<u64>> = FxHashMap::default();
+        for (caller, callee) in edges {
+            callees.entry(caller).or_default().push(callee);
+        }
+        for callees in callees.values_mut() {
+            callees.sort_unstable();
+            callees.dedup();
+        }
+        Self { callees }
+    }
+
+    /// Finds the direct calls in every function by decoding `bl` instructions.
+    /// Calls to addresses that are not the start of a function in the list are ignored.
+    pub fn build(binary: &BaseBinary, functions: &[Info]) -> Self {
+        let known_functions = functions::make_known_function_index(functions);
+        let edges: Vec<(u64, u64)> = functions
+            .par_iter()
+            .flat_map_iter(|info| {
+                let code = binary
+                    .read(info.addr, info.size as usize)
+                    .unwrap_or_default();
+                let known_functions = &known_functions;
+                (0..code.len() / 4).filter_map(move |i| {
+                    let insn = read_u32(code, i * 4);
+                    if insn & 0xfc00_0000 != 0x9400_0000 {
+                        return None;
+                    }
+                    // imm26, sign-extended and multiplied by 4.
+                    let offset = ((insn << 6) as i32 >> 4) as i64;
+                    let callee = (info.addr + i as u64 * 4).wrapping_add(offset as u64);
+                    known_functions
+                        .contains_key(&callee)
+                        .then_some((info.addr, callee))
+                })
+            })
+            .collect();
+        Self::from_edges(edges)
+    }
+
+    /// Returns the addresses of the functions that are called by the function at `addr`.
+    pub fn callees(&self, addr: u64) -> &[u64] {
+        self.callees.get(&addr).map_or(&[], Vec::as_slice)
+    }
+}
+
+/// Call graph that is used to estimate how hard functions are to match.
+pub type FunctionGraph = CallGraph;
+
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 32;
+
+#[derive(Clone, Debug)]
+pub struct CallDepthOptions {
+    /// Call trees are not explored deeper than this, which bounds the runtime.
+    pub max_depth: usize,
+}
+
+impl Default for CallDepthOptions {
+    fn default() -> Self {
+        Self {
+            max_depth: DEFAULT_MAX_CALL_DEPTH,
+        }
+    }
+}
+
+impl CallDepthOptions {
+    /// Returns the options for the project. The maximum depth can be changed
+    /// with `max_call_depth` in the config.
+    pub fn from_config() -> Self {
+        Self {
+            max_depth: repo::CONFIG
+                .get("max_call_depth")
+                .and_then(toml::Value::as_integer)
+                .map(|depth| depth.max(0) as usize)
+                .unwrap_or(DEFAULT_MAX_CALL_DEPTH),
+        }
+    }
+}
+
+/// Returns whether a function still needs to be matched. Library functions don't.
+fn is_unmatched(info: &Info) -> bool {
+    !info.is_complete() && !info.is_skippable()
+}
+
+struct CallDepthSolver<'a> {
+    graph: &'a FunctionGraph,
+    unmatched: FxHashSet<u64>,
+    max_depth: usize,
+    /// Depths of functions whose call tree was fully explored.
+    depths: FxHashMap<u64, usize>,
+    /// Functions on the path from the root to the current function.
+    path: FxHashSet<u64>,
+}
+
+impl<'a> CallDepthSolver<'a> {
+    fn new(graph: &'a FunctionGraph, functions: &[Info], options: &CallDepthOptions) -> Self {
+        Self {
+            graph,
+            unmatched: functions
+                .iter()
+                .filter(|info| is_unmatched(info))
+                .map(|info| info.addr)
+                .collect(),
+            max_depth: options.max_depth,
+            depths: FxHashMap::default(),
+            path: FxHashSet::default(),
+        }
+    }
+
+    fn get_depth(&mut self, addr: u64) -> usize {
+        if let Some(&depth) = self.depths.get(&addr) {
+            return depth.min(self.max_depth - self.path.len());
+        }
+        // Functions at the maximum depth are treated as leaves.
+        if self.path.len() >= self.max_depth {
+            return 0;
+        }
+
+        self.path.insert(addr);
+        let mut depth = 0;
+        let mut truncated = false;
+        for &callee in self.graph.callees(addr) {
+            // Calls that would close a cycle are ignored.
+            if !self.unmatched.contains(&callee) || self.path.contains(&callee) {
+                continue;
+            }
+            let callee_depth = 1 + self.get_depth(callee);
+            depth = depth.max(callee_depth);
+            // The depth of the root is capped anyway.
+            if self.path.len() - 1 + callee_depth >= self.max_depth {
+                truncated = true;
+                break;
+            }
+        }
+        self.path.remove(&addr);
+
+        if !truncated {
+            self.depths.insert(addr, depth);
+        }
+        depth
+    }
+}
+
+/// Returns the depth of the call tree of the function at `addr`, only counting calls to
+/// functions that are not matching yet (library functions do not count either): 0 if it does
+/// not call any unmatched function, 1 if the unmatched functions it calls do not call any
+/// unmatched function themselves, etc.
+///
+/// Calls that would close a cycle are ignored (so the depths of functions in a cycle depend on
+/// where the cycle is entered), and the depth is capped at
+/// `DEFAULT_MAX_CALL_DEPTH` (see `get_call_depth_ex` to change it).
+pub fn get_call_depth(graph: &FunctionGraph, functions: &[Info], addr: u64) -> usize {
+    get_call_depth_ex(graph, functions, addr, &CallDepthOptions::default())
+}
+
+/// Same as `get_call_depth`, but with custom options (e.g. `CallDepthOptions::from_config`).
+pub fn get_call_depth_ex(
+    graph: &FunctionGraph,
+    functions: &[Info],
+    addr: u64,
+    options: &CallDepthOptions,
+) -> usize {
+    if options.max_depth == 0 {
+        return 0;
+    }
+    CallDepthSolver::new(graph, functions, options).get_depth(addr)
+}
+
+/// Returns the call depth (see `get_call_depth`) of every function that is not matching yet,
+/// sorted by depth (shallowest first, as those are the easiest to match) and then
+/// in list order.
+pub fn rank_by_call_depth<'a>(
+    graph: &FunctionGraph,
+    functions: &'a [Info],
+) -> Vec<(&'a Info, usize)> {
+    rank_by_call_depth_ex(graph, functions, &CallDepthOptions::default())
+}
+
+/// Same as `rank_by_call_depth`, but with custom options (e.g. `CallDepthOptions::from_config`).
+pub fn rank_by_call_depth_ex<'a>(
+    graph: &FunctionGraph,
+    functions: &'a [Info],
+    options: &CallDepthOptions,
+) -> Vec<(&'a Info, usize)> {
+    let mut ranking: Vec<(&'a Info, usize)> = if options.max_depth == 0 {
+        functions
+            .iter()
+            .filter(|info| is_unmatched(info))
+            .map(|info| (info, 0))
+            .collect()
+    } else {
+        let mut solver = CallDepthSolver::new(graph, functions, options);
+        functions
+            .iter()
+            .filter(|info| is_unmatched(info))
+            .map(|info| (info, solver.get_depth(info.addr)))
+            .collect()
+    };
+    ranking.sort_by_key(|&(_, depth)| depth);
+    ranking
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::Status;
+    use crate::testing::{self, make_function};
+
+    #[test]
+    fn density_buckets_must_not_be_empty() {
+        let functions = [
+            make_function(0x100, 0x10, "_Z1av", Status::Matching),
+            make_function(0x110, 0x30, "_Z1bv", Status::NotDecompiled),
+            make_function(0x250, 0x10, "_Z1cv", Status::NotDecompiled),
+        ];
+        let buckets = get_function_density_map(&functions, 0x100).unwrap();
+        let counts: Vec<(u64, usize, u64)> = buckets
+            .iter()
+            .map(|bucket| (bucket.addr, bucket.function_count, bucket.matched_bytes))
+            .collect();
+        assert_eq!(counts, [(0x100, 2, 0x10), (0x200, 1, 0)]);
+        assert_eq!(buckets[0].matched_fraction, 0.25);
+
+        assert!(get_function_density_map(&functions, 0).is_err());
+        assert!(get_function_density_map(&[], 0).is_err());
+    }
+
+    #[test]
+    fn aliases_do_not_overlap() {
+        let functions = [
+            make_function(0x100, 0x20, "_Z1av", Status::Matching),
+            make_function(0x100, 0x10, "_Z5aliasv", Status::Matching),
+            make_function(0x120, 0x20, "_Z1bv", Status::Matching),
+            // Both aliases overlap _Z1bv, which is reported once.
+            make_function(0x130, 0x8, "_Z1cv", Status::Matching),
+            make_function(0x130, 0x18, "_Z6alias2v", Status::Matching),
+        ];
+        let report = coverage(&functions, 0x100..0x150);
+        assert_eq!(
+            report.overlaps,
+            [CoverageOverlap {
+                addr: 0x130,
+                size: 0x10,
+                first: (0x120, "_Z1bv".to_string()),
+                second: (0x130, "_Z6alias2v".to_string()),
+            }]
+        );
+        assert_eq!(report.covered_bytes, 0x48);
+        assert_eq!(report.uncovered_bytes, 0x8);
+    }
+
+    fn group(functions: &[Info]) -> Vec<(String, ClassGroupKind, Vec<u64>)> {
+        testing::use_test_config();
+        group_by_class(functions)
+            .into_values()
+            .map(|group| (group.name, group.kind, group.functions))
+            .collect()
+    }
+
+    #[test]
+    fn template_instantiations_are_merged() {
+        let functions = vec![
+            // sead::Buffer<int>::size() const
+            make_function(0x10, 0x10, "_ZNK4sead6BufferIiE4sizeEv", Status::Matching),
+            // sead::Buffer<float>::size() const
+            make_function(0x20, 0x10, "_ZNK4sead6BufferIfE4sizeEv", Status::Wip),
+        ];
+        assert_eq!(
+            group(&functions),
+            [(
+                "sead::Buffer".to_string(),
+                ClassGroupKind::Class,
+                vec![0x10, 0x20]
+            )]
+        );
+
+        testing::use_test_config();
+        let groups = group_by_class(&functions);
+        let buffer = &groups["sead::Buffer"];
+        assert_eq!(buffer.stats.total.functions, 2);
+        assert_eq!(buffer.stats.matching.functions, 1);
+        assert_eq!(buffer.remaining_functions(), 1);
+        assert!(buffer.is_nearly_complete());
+    }
+
+    #[test]
+    fn nested_classes_have_their_own_group() {
+        let functions = vec![
+            // A::B::B()
+            make_function(0x10, 0x10, "_ZN1A1BC2Ev", Status::Matching),
+            // A::B::f()
+            make_function(0x20, 0x10, "_ZN1A1B1fEv", Status::Matching),
+            // A::g()
+            make_function(0x30, 0x10, "_ZN1A1gEv", Status::Matching),
+        ];
+        assert_eq!(
+            group(&functions),
+            [
+                ("A".to_string(), ClassGroupKind::Namespace, vec![0x30]),
+                ("A::B".to_string(), ClassGroupKind::Class, vec![0x10, 0x20]),
+            ]
+        );
+    }
+
+    #[test]
+    fn lambdas_are_grouped_with_their_enclosing_function() {
+        let functions = vec![
+            // A::f()::{lambda()#1}::operator()() const
+            make_function(0x10, 0x10, "_ZZN1A1fEvENKUlvE_clEv", Status::Matching),
+            // A::f()
+            make_function(0x20, 0x10, "_ZN1A1fEv", Status::Matching),
+        ];
+        // The lambda's const call operator does not make A a class.
+        assert_eq!(
+            group(&functions),
+            [("A".to_string(), ClassGroupKind::Namespace, vec![0x10, 0x20])]
+        );
+    }
+
+    #[test]
+    fn classes_are_told_apart_from_namespaces() {
+        let functions = vec![
+            // ksys::util::f()
+            make_function(0x10, 0x10, "_ZN4ksys4util1fEv", Status::Matching),
+            // ksys::Foo::Foo()
+            make_function(0x20, 0x10, "_ZN4ksys3FooC1Ev", Status::Matching),
+            // ksys::Foo::bar()
+            make_function(0x30, 0x10, "_ZN4ksys3Foo3barEv", Status::NotDecompiled),
+            // ksys::Bar::~Bar()
+            make_function(0x40, 0x10, "_ZN4ksys3BarD2Ev", Status::Matching),
+            // ksys::Vec::operator=(ksys::Vec const&)
+            make_function(0x50, 0x10, "_ZN4ksys3VecaSERKS0_", Status::Matching),
+            // ksys::Obj::get() const
+            make_function(0x60, 0x10, "_ZNK4ksys3Obj3getEv", Status::Matching),
+            make_function(0x70, 0x10, "main", Status::Matching),
+            make_function(0x80, 0x10, "OUTLINED_FUNCTION_12", Status::Matching),
+        ];
+        assert_eq!(
+            group(&functions),
+            [
+                (
+                    GLOBAL_CLASS_NAME.to_string(),
+                    ClassGroupKind::Namespace,
+                    vec![0x70]
+                ),
+                (
+                    OUTLINED_CLASS_NAME.to_string(),
+                    ClassGroupKind::Namespace,
+                    vec![0x80]
+                ),
+                ("ksys::Bar".to_string(), ClassGroupKind::Class, vec![0x40]),
+                (
+                    "ksys::Foo".to_string(),
+                    ClassGroupKind::Class,
+                    vec![0x20, 0x30]
+                ),
+                ("ksys::Obj".to_string(), ClassGroupKind::Class, vec![0x60]),
+                ("ksys::Vec".to_string(), ClassGroupKind::Class, vec![0x50]),
+                (
+                    "ksys::util".to_string(),
+                    ClassGroupKind::Namespace,
+                    vec![0x10]
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,544 @@
+use std::fmt;
+use std::ops::RangeInclusive;
+
+/// Controls which operands are masked by a `Normalizer`.
+///
+/// The default options don't mask anything, so two instruction sequences normalize to
+/// the same tokens if and only if their encodings are identical.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NormalizerOptions {
+    /// Mask targets of branches, ADR/ADRP and literal loads.
+    pub mask_pc_relative: bool,
+    /// Mask the low bits of addresses that are computed with an ADRP pair
+    /// (ADD or load/store offsets from a register that was set by ADRP).
+    pub mask_absolute_addresses: bool,
+    /// Mask immediates (including load/store offsets) that fall into one of these ranges.
+    pub masked_immediates: Vec<RangeInclusive<i64>>,
+    /// Renumber registers in order of first use, so that sequences that only differ
+    /// in register allocation normalize to the same tokens.
+    pub mask_registers: bool,
+}
+
+impl NormalizerOptions {
+    /// Options that make code independent of its location and of the data it references.
+    pub fn for_fingerprinting() -> Self {
+        Self {
+            mask_pc_relative: true,
+            mask_absolute_addresses: true,
+            masked_immediates: vec![i64::MIN..=i64::MAX],
+            mask_registers: false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MaskKind {
+    PcRelative,
+    AbsoluteAddress,
+    Immediate,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Token {
+    /// Start of an instruction: its encoding with all fields that are represented
+    /// by the following operand tokens cleared.
+    Insn(u32),
+    /// General-purpose register. 31 is SP or XZR/WZR depending on the instruction.
+    Reg(u8),
+    /// FP/SIMD register.
+    VReg(u8),
+    /// Immediate or offset from a register.
+    Imm(i64),
+    /// PC-relative offset in bytes.
+    PcRelative(i64),
+    Masked(MaskKind),
+}
+
+impl Token {
+    /// Returns a stable binary representation of the token, for hashing.
+    pub fn to_bytes(self) -> [u8; 9] {
+        let (tag, value): (u8, u64) = match self {
+            Token::Insn(bits) => (0, bits as u64),
+            Token::Reg(reg) => (1, reg as u64),
+            Token::VReg(reg) => (2, reg as u64),
+            Token::Imm(imm) => (3, imm as u64),
+            Token::PcRelative(offset) => (4, offset as u64),
+            Token::Masked(kind) => (5, kind as u64),
+        };
+        let mut bytes = [0u8; 9];
+        bytes[0] = tag;
+        bytes[1..].copy_from_slice(&value.to_le_bytes());
+        bytes
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Insn(bits) => write!(f, "{:08x}", bits),
+            Token::Reg(reg) => write!(f, "r{}", reg),
+            Token::VReg(reg) => write!(f, "v{}", reg),
+            Token::Imm(imm) if *imm < 0 => write!(f, "#-{:#x}", -(*imm as i128)),
+            Token::Imm(imm) => write!(f, "#{:#x}", imm),
+            Token::PcRelative(offset) if *offset < 0 => {
+                write!(f, "pc-{:#x}", -(*offset as i128))
+            }
+            Token::PcRelative(offset) => write!(f, "pc+{:#x}", offset),
+            Token::Masked(MaskKind::PcRelative) => write!(f, "<pc>"),
+            Token::Masked(MaskKind::AbsoluteAddress) => write!(f, "<addr>"),
+            Token::Masked(MaskKind::Immediate) => write!(f, "<imm>"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Operand {
+    /// 5-bit register field at the given bit offset.
+    Reg {
+        shift: u32,
+        vector: bool,
+        written: bool,
+    },
+    PcRelative(i64),
+    Imm(i64),
+    /// Offset from the base register at the given bit offset.
+    Offset {
+        value: i64,
+        base_shift: u32,
+    },
+}
+
+fn sign_extend(value: u32, bits: u32) -> i64 {
+    let shift = 64 - bits;
+    (((value as u64) << shift) as i64) >> shift
+}
+
+fn field(insn: u32, shift: u32, bits: u32) -> u32 {
+    (insn >> shift) & ((1 << bits) - 1)
+}
+
+fn gpr(shift: u32, written: bool) -> Operand {
+    Operand::Reg {
+        shift,
+        vector: false,
+        written,
+    }
+}
+
+fn is_load(insn: u32) -> bool {
+    let opc = field(insn, 22, 2);
+    if insn & (1 << 26) != 0 {
+        opc & 1 != 0
+    } else {
+        opc != 0
+    }
+}
+
+/// Returns the bits that are represented by operands and the operands of an instruction,
+/// or None if the instruction is not decoded (which means it is compared as a whole).
+fn decode(insn: u32) -> Option<(u32, Vec<Operand>)> {
+    let vector = insn & (1 << 26) != 0;
+    let rt = |written| Operand::Reg {
+        shift: 0,
+        vector,
+        written,
+    };
+
+    // B, BL
+    if insn & 0x7c00_0000 == 0x1400_0000 {
+        let offset = sign_extend(field(insn, 0, 26), 26) * 4;
+        return Some((0x03ff_ffff, vec![Operand::PcRelative(offset)]));
+    }
+    // B.cond
+    if insn & 0xff00_0010 == 0x5400_0000 {
+        let offset = sign_extend(field(insn, 5, 19), 19) * 4;
+        return Some((0x00ff_ffe0, vec![Operand::PcRelative(offset)]));
+    }
+    // CBZ, CBNZ
+    if insn & 0x7e00_0000 == 0x3400_0000 {
+        let offset = sign_extend(field(insn, 5, 19), 19) * 4;
+        return Some((
+            0x00ff_ffff,
+            vec![gpr(0, false), Operand::PcRelative(offset)],
+        ));
+    }
+    // TBZ, TBNZ
+    if insn & 0x7e00_0000 == 0x3600_0000 {
+        let bit = (field(insn, 31, 1) << 5) | field(insn, 19, 5);
+        let offset = sign_extend(field(insn, 5, 14), 14) * 4;
+        return Some((
+            0x80ff_ffff,
+            vec![
+                gpr(0, false),
+                Operand::Imm(bit as i64),
+                Operand::PcRelative(offset),
+            ],
+        ));
+    }
+    // ADR, ADRP
+    if insn & 0x1f00_0000 == 0x1000_0000 {
+        let imm = sign_extend((field(insn, 5, 19) << 2) | field(insn, 29, 2), 21);
+        let offset = if insn & 0x8000_0000 != 0 {
+            imm << 12
+        } else {
+            imm
+        };
+        return Some((0x60ff_ffff, vec![gpr(0, true), Operand::PcRelative(offset)]));
+    }
+    // LDR (literal)
+    if insn & 0x3b00_0000 == 0x1800_0000 {
+        let offset = sign_extend(field(insn, 5, 19), 19) * 4;
+        return Some((0x00ff_ffff, vec![rt(true), Operand::PcRelative(offset)]));
+    }
+    // ADD, SUB (immediate)
+    if insn & 0x1f00_0000 == 0x1100_0000 {
+        let value = (field(insn, 10, 12) << (12 * field(insn, 22, 1))) as i64;
+        return Some((
+            0x003f_ffff,
+            vec![
+                gpr(0, true),
+                gpr(5, false),
+                Operand::Offset {
+                    value,
+                    base_shift: 5,
+                },
+            ],
+        ));
+    }
+    // Loads and stores (unsigned immediate)
+    if insn & 0x3b00_0000 == 0x3900_0000 {
+        let scale = if vector && field(insn, 23, 1) != 0 {
+            4
+        } else {
+            field(insn, 30, 2)
+        };
+        let value = (field(insn, 10, 12) << scale) as i64;
+        return Some((
+            0x003f_ffff,
+            vec![
+                rt(is_load(insn)),
+                gpr(5, false),
+                Operand::Offset {
+                    value,
+                    base_shift: 5,
+                },
+            ],
+        ));
+    }
+    // MOVN, MOVZ, MOVK
+    if insn & 0x1f80_0000 == 0x1280_0000 {
+        let value = (field(insn, 5, 16) as i64) << (16 * field(insn, 21, 2));
+        return Some((0x001f_ffff, vec![gpr(0, true), Operand::Imm(value)]));
+    }
+    // Load/store pair
+    if insn & 0x3a00_0000 == 0x2800_0000 {
+        let opc = field(insn, 30, 2);
+        let scale = if vector { 2 + opc } else { 2 + (opc >> 1) };
+        let offset = sign_extend(field(insn, 15, 7), 7) << scale;
+        let load = insn & (1 << 22) != 0;
+        return Some((
+            0x003f_ffff,
+            vec![
+                rt(load),
+                Operand::Reg {
+                    shift: 10,
+                    vector,
+                    written: load,
+                },
+                gpr(5, false),
+                Operand::Imm(offset),
+            ],
+        ));
+    }
+    // Loads and stores (unscaled immediate, pre/post-indexed, unprivileged)
+    if insn & 0x3b20_0000 == 0x3800_0000 {
+        let offset = sign_extend(field(insn, 12, 9), 9);
+        return Some((
+            0x001f_f3ff,
+            vec![rt(is_load(insn)), gpr(5, false), Operand::Imm(offset)],
+        ));
+    }
+    // Loads and stores (register offset)
+    if insn & 0x3b20_0c00 == 0x3820_0800 {
+        return Some((
+            0x001f_03ff,
+            vec![rt(is_load(insn)), gpr(5, false), gpr(16, false)],
+        ));
+    }
+    // Logical (shifted register), ADD/SUB (shifted/extended register), conditional select
+    if insn & 0x1e00_0000 == 0x0a00_0000 || insn & 0x1fe0_0000 == 0x1a80_0000 {
+        return Some((
+            0x001f_03ff,
+            vec![gpr(0, true), gpr(5, false), gpr(16, false)],
+        ));
+    }
+    // Logical (immediate), bitfield
+    if insn & 0x1f80_0000 == 0x1200_0000 || insn & 0x1f80_0000 == 0x1300_0000 {
+        return Some((0x0000_03ff, vec![gpr(0, true), gpr(5, false)]));
+    }
+    // Data-processing (3 source)
+    if insn & 0x1f00_0000 == 0x1b00_0000 {
+        return Some((
+            0x001f_7fff,
+            vec![gpr(0, true), gpr(5, false), gpr(16, false), gpr(10, false)],
+        ));
+    }
+    // BR, BLR, RET
+    if insn & 0xff9f_fc1f == 0xd61f_0000 {
+        return Some((0x0000_03e0, vec![gpr(5, false)]));
+    }
+
+    None
+}
+
+/// Maps registers to their canonical numbers.
+#[derive(Default)]
+struct RegisterMap {
+    map: [Option<u8>; 31],
+    next: u8,
+}
+
+impl RegisterMap {
+    fn get(&mut self, reg: u8) -> u8 {
+        // Register 31 is special (SP or the zero register) so it is never renamed.
+        if reg == 31 {
+            return reg;
+        }
+        let next = &mut self.next;
+        *self.map[reg as usize].get_or_insert_with(|| {
+            *next += 1;
+            *next - 1
+        })
+    }
+}
+
+/// Turns AArch64 machine code into a canonical token stream in which some operands are masked
+/// (see `NormalizerOptions`). Normalized sequences can be hashed or diffed.
+///
+/// Only common integer and load/store instructions are decoded. Other instructions are
+/// represented by their encoding and are never masked.
+#[derive(Clone, Debug, Default)]
+pub struct Normalizer {
+    options: NormalizerOptions,
+}
+
+impl Normalizer {
+    pub fn new(options: NormalizerOptions) -> Self {
+        Self { options }
+    }
+
+    pub fn options(&self) -> &NormalizerOptions {
+        &self.options
+    }
+
+    fn is_immediate_masked(&self, imm: i64) -> bool {
+        self.options
+            .masked_immediates
+            .iter()
+            .any(|range| range.contains(&imm))
+    }
+
+    fn immediate(&self, imm: i64) -> Token {
+        if self.is_immediate_masked(imm) {
+            Token::Masked(MaskKind::Immediate)
+        } else {
+            Token::Imm(imm)
+        }
+    }
+
+    /// Normalizes a sequence of instructions. A trailing partial instruction is zero-padded.
+    pub fn normalize(&self, code: &[u8]) -> Vec<Token> {
+        let mut tokens = Vec::with_capacity(code.len() / 2);
+        let mut gprs = RegisterMap::default();
+        let mut vregs = RegisterMap::default();
+        // Registers that currently hold a page address computed by ADRP.
+        let mut adrp_regs: u32 = 0;
+
+        for chunk in code.chunks(4) {
+            let mut word = [0u8; 4];
+            word[..chunk.len()].copy_from_slice(chunk);
+            let insn = u32::from_le_bytes(word);
+
+            let (fields, operands) = match decode(insn) {
+                Some(decoded) => decoded,
+                None => {
+                    tokens.push(Token::Insn(insn));
+                    continue;
+                }
+            };
+
+            tokens.push(Token::Insn(insn & !fields));
+            let mut written_regs: u32 = 0;
+            for operand in operands {
+                let token = match operand {
+                    Operand::Reg {
+                        shift,
+                        vector,
+                        written,
+                    } => {
+                        let reg = field(insn, shift, 5) as u8;
+                        if written && !vector {
+                            written_regs |= 1 << reg;
+                        }
+                        match (vector, self.options.mask_registers) {
+                            (false, false) => Token::Reg(reg),
+                            (false, true) => Token::Reg(gprs.get(reg)),
+                            (true, false) => Token::VReg(reg),
+                            (true, true) => Token::VReg(vregs.get(reg)),
+                        }
+                    }
+                    Operand::PcRelative(_) if self.options.mask_pc_relative => {
+                        Token::Masked(MaskKind::PcRelative)
+                    }
+                    Operand::PcRelative(offset) => Token::PcRelative(offset),
+                    Operand::Imm(imm) => self.immediate(imm),
+                    Operand::Offset { value, base_shift } => {
+                        let base = field(insn, base_shift, 5);
+                        if self.options.mask_absolute_addresses && adrp_regs & (1 << base) != 0 {
+                            Token::Masked(MaskKind::AbsoluteAddress)
+                        } else {
+                            self.immediate(value)
+                        }
+                    }
+                };
+                tokens.push(token);
+            }
+
+            adrp_regs &= !written_regs;
+            if insn & 0x9f00_0000 == 0x9000_0000 {
+                adrp_regs |= 1 << field(insn, 0, 5);
+            }
+        }
+
+        tokens
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // adrp x8, #0x1000
+    const ADRP_X8: u32 = 0xb000_0008;
+    // add x8, x8, #0x10
+    const ADD_X8_X8: u32 = 0x9100_4108;
+    // ldr x0, [x8, #0x18]
+    const LDR_X0_X8: u32 = 0xf940_0d00;
+    // ldr x1, [x0, #0x8]
+    const LDR_X1_X0: u32 = 0xf940_0401;
+    // str w1, [sp, #0x8]
+    const STR_W1_SP: u32 = 0xb900_0be1;
+    // b #0x20
+    const B: u32 = 0x1400_0008;
+    // bl #-0x4
+    const BL: u32 = 0x97ff_ffff;
+
+    fn encode(insns: &[u32]) -> Vec<u8> {
+        insns.iter().flat_map(|insn| insn.to_le_bytes()).collect()
+    }
+
+    fn normalize(options: NormalizerOptions, insns: &[u32]) -> Vec<Token> {
+        Normalizer::new(options).normalize(&encode(insns))
+    }
+
+    #[test]
+    fn default_options_keep_every_operand() {
+        assert_eq!(
+            normalize(Default::default(), &[ADRP_X8, ADD_X8_X8, LDR_X0_X8]),
+            [
+                Token::Insn(0x9000_0000),
+                Token::Reg(8),
+                Token::PcRelative(0x1000),
+                Token::Insn(0x9100_0000),
+                Token::Reg(8),
+                Token::Reg(8),
+                Token::Imm(0x10),
+                Token::Insn(0xf940_0000),
+                Token::Reg(0),
+                Token::Reg(8),
+                Token::Imm(0x18),
+            ]
+        );
+    }
+
+    #[test]
+    fn adrp_pairs() {
+        let options = NormalizerOptions {
+            mask_absolute_addresses: true,
+            ..Default::default()
+        };
+        let adrp_add = normalize(options.clone(), &[ADRP_X8, ADD_X8_X8]);
+        assert_eq!(adrp_add[6], Token::Masked(MaskKind::AbsoluteAddress));
+        // ADD overwrites x8, so the load is no longer part of an ADRP pair.
+        let tokens = normalize(options.clone(), &[ADRP_X8, ADD_X8_X8, LDR_X0_X8]);
+        assert_eq!(tokens[10], Token::Imm(0x18));
+
+        let tokens = normalize(options, &[ADRP_X8, LDR_X0_X8, LDR_X1_X0]);
+        assert_eq!(tokens[2], Token::PcRelative(0x1000));
+        assert_eq!(tokens[6], Token::Masked(MaskKind::AbsoluteAddress));
+        assert_eq!(tokens[10], Token::Imm(0x8));
+    }
+
+    #[test]
+    fn branches() {
+        assert_eq!(
+            normalize(Default::default(), &[B, BL]),
+            [
+                Token::Insn(0x1400_0000),
+                Token::PcRelative(0x20),
+                Token::Insn(0x9400_0000),
+                Token::PcRelative(-0x4),
+            ]
+        );
+        let options = NormalizerOptions {
+            mask_pc_relative: true,
+            ..Default::default()
+        };
+        let tokens = normalize(options, &[B, BL, ADRP_X8]);
+        assert_eq!(tokens[1], Token::Masked(MaskKind::PcRelative));
+        assert_eq!(tokens[3], Token::Masked(MaskKind::PcRelative));
+        assert_eq!(tokens[6], Token::Masked(MaskKind::PcRelative));
+        assert_eq!(
+            tokens.iter().map(Token::to_string).collect::<Vec<_>>()[..2],
+            ["14000000", "<pc>"]
+        );
+    }
+
+    #[test]
+    fn load_store_offsets() {
+        let options = NormalizerOptions {
+            masked_immediates: vec![0..=0x8],
+            ..Default::default()
+        };
+        assert_eq!(
+            normalize(options, &[STR_W1_SP, LDR_X0_X8]),
+            [
+                Token::Insn(0xb900_0000),
+                Token::Reg(1),
+                Token::Reg(31),
+                Token::Masked(MaskKind::Immediate),
+                Token::Insn(0xf940_0000),
+                Token::Reg(0),
+                Token::Reg(8),
+                Token::Imm(0x18),
+            ]
+        );
+    }
+
+    #[test]
+    fn registers_are_renumbered() {
+        let options = NormalizerOptions {
+            mask_registers: true,
+            ..Default::default()
+        };
+        // add x1, x2, #4 / ldr x1, [x1] and add x3, x4, #4 / ldr x3, [x3]
+        let a = normalize(options.clone(), &[0x9100_1041, 0xf940_0021]);
+        let b = normalize(options, &[0x9100_1083, 0xf940_0063]);
+        assert_eq!(a, b);
+        assert_eq!(a[1..3], [Token::Reg(0), Token::Reg(1)]);
+        assert_ne!(
+            normalize(Default::default(), &[0x9100_1041]),
+            normalize(Default::default(), &[0x9100_1083])
+        );
+    }
+}
@@ -0,0 +1,163 @@
+use crate::functions::{self, Info, Status};
+use anyhow::{bail, Context, Result};
+use rustc_hash::FxHashMap;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const HEADER: &[&str] = &["Address", "Hash", "Timestamp", "Result"];
+
+/// Result of comparing a decompiled function against the original.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ComparisonResult {
+    Identical,
+    /// Only minor differences, e.g. register allocation.
+    NearlyIdentical,
+    Different,
+}
+
+impl ComparisonResult {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ComparisonResult::Identical => "identical",
+            ComparisonResult::NearlyIdentical => "nearly_identical",
+            ComparisonResult::Different => "different",
+        }
+    }
+
+    fn from_str(value: &str) -> Option<Self> {
+        match value {
+            "identical" => Some(ComparisonResult::Identical),
+            "nearly_identical" => Some(ComparisonResult::NearlyIdentical),
+            "different" => Some(ComparisonResult::Different),
+            _ => None,
+        }
+    }
+
+    /// Returns the function list status that corresponds to this result.
+    pub fn status(&self) -> Status {
+        match self {
+            ComparisonResult::Identical => Status::Matching,
+            ComparisonResult::NearlyIdentical => Status::NonMatchingMinor,
+            ComparisonResult::Different => Status::NonMatchingMajor,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AssemblyFingerprint {
+    /// Hash of the assembly of the decompiled function.
+    pub hash: u64,
+    /// When the comparison was made. Stored with a precision of one second.
+    pub timestamp: SystemTime,
+    pub status: ComparisonResult,
+}
+
+/// Cached comparison results, indexed by function address.
+///
+/// As long as the hash of a decompiled function doesn't change, its comparison result
+/// can be reused instead of comparing the function again.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AssemblyHashMap(pub FxHashMap<u64, AssemblyFingerprint>);
+
+impl AssemblyHashMap {
+    /// Returns the cached comparison result for a function, if its hash is still `hash`.
+    pub fn get_up_to_date(&self, addr: u64, hash: u64) -> Option<ComparisonResult> {
+        self.0
+            .get(&addr)
+            .filter(|fingerprint| fingerprint.hash == hash)
+            .map(|fingerprint| fingerprint.status)
+    }
+
+    /// Records a comparison result with the current time.
+    pub fn insert(&mut self, addr: u64, hash: u64, status: ComparisonResult) {
+        self.0.insert(
+            addr,
+            AssemblyFingerprint {
+                hash,
+                timestamp: SystemTime::now(),
+                status,
+            },
+        );
+    }
+}
+
+fn parse_entry(record: &csv::StringRecord) -> Result<(u64, AssemblyFingerprint)> {
+    if record.len() != HEADER.len() {
+        bail!("invalid record; expected {} fields", HEADER.len());
+    }
+
+    let status = match ComparisonResult::from_str(&record[3]) {
+        Some(status) => status,
+        None => bail!("invalid comparison result: {}", &record[3]),
+    };
+
+    Ok((
+        functions::parse_address(&record[0])?,
+        AssemblyFingerprint {
+            hash: functions::parse_hex_u64(&record[1])?,
+            timestamp: UNIX_EPOCH + Duration::from_secs(record[2].parse::<u64>()?),
+            status,
+        },
+    ))
+}
+
+/// Reads cached comparison results. A missing file is treated as an empty cache.
+pub fn load_assembly_hashes(path: &Path) -> Result<AssemblyHashMap> {
+    let mut map = AssemblyHashMap::default();
+    if !path.exists() {
+        return Ok(map);
+    }
+
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(true)
+        .from_path(path)
+        .with_context(|| format!("failed to open {:?}", path))?;
+    for (i, record) in reader.records().enumerate() {
+        let (addr, fingerprint) = parse_entry(&record?)
+            .with_context(|| format!("failed to parse entry at line {}", i + 2))?;
+        map.0.insert(addr, fingerprint);
+    }
+    Ok(map)
+}
+
+/// Writes cached comparison results, sorted by address.
+pub fn save_assembly_hashes(path: &Path, map: &AssemblyHashMap) -> Result<()> {
+    let mut entries: Vec<_> = map.0.iter().collect();
+    entries.sort_unstable_by_key(|(addr, _)| **addr);
+
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_path(path)
+        .with_context(|| format!("failed to create {:?}", path))?;
+    writer.write_record(HEADER)?;
+    for (addr, fingerprint) in entries {
+        let timestamp = fingerprint
+            .timestamp
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        writer.write_record(&[
+            functions::format_addr(*addr),
+            format!("0x{:016x}", fingerprint.hash),
+            timestamp.to_string(),
+            fingerprint.status.as_str().to_string(),
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Sets the status of every function that has a cached comparison result
+/// (see `ComparisonResult::status`). Library functions are left untouched.
+///
+/// *Note*: hashes are not checked against the current build; that is up to the caller.
+pub fn update_status_from_hashes(functions: &mut [Info], hashes: &AssemblyHashMap) {
+    for info in functions {
+        if info.status == Status::Library {
+            continue;
+        }
+        if let Some(fingerprint) = hashes.0.get(&info.addr) {
+            info.status = fingerprint.status.status();
+        }
+    }
+}
@@ -0,0 +1,351 @@
+use crate::file_utils;
+use crate::lock::{self, LockOptions};
+use crate::repo;
+use anyhow::{bail, Context, Result};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Returns the maximum number of function list backups to keep (see `backup_before_write`).
+/// Backups are disabled unless `max_csv_backups` is set in the config.
+pub(crate) fn get_max_backups() -> usize {
+    repo::CONFIG
+        .get("max_csv_backups")
+        .and_then(toml::Value::as_integer)
+        .map_or(0, |n| n.max(0) as usize)
+}
+
+fn get_backup_dir(repo_root: &Path) -> PathBuf {
+    file_utils::get_local_dir(repo_root).join("backups")
+}
+
+/// Creates the backup directory if it doesn't exist yet, and makes sure git ignores it.
+fn create_backup_dir(repo_root: &Path) -> Result<PathBuf> {
+    file_utils::create_untracked_dir(&file_utils::get_local_dir(repo_root))?;
+    let dir = get_backup_dir(repo_root);
+    std::fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+/// Returns the prefix of the names of the backups of a CSV, which is derived from the path
+/// of the CSV relative to the repo root (e.g. `data-functions-` for `data/functions.csv`),
+/// so that lists with the same name in different directories don't share backups.
+///
+/// Returns None for files that are not inside the repo, which are never backed up.
+fn get_backup_prefix(repo_root: &Path, csv_path: &Path) -> Option<String> {
+    let csv_path = csv_path.canonicalize().ok()?;
+    let repo_root = repo_root.canonicalize().ok()?;
+    let relative_path = csv_path.strip_prefix(&repo_root).ok()?.with_extension("");
+    let components: Vec<String> = relative_path
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy().to_string())
+        .collect();
+    Some(format!("{}-", components.join("-")))
+}
+
+/// Removes all but the newest `max_backups` of `backups`, which must be sorted from oldest
+/// to newest.
+fn remove_old_backups<T>(backups: &[(T, PathBuf)], max_backups: usize) -> Result<()> {
+    if backups.len() > max_backups {
+        for (_, old_backup) in &backups[..backups.len() - max_backups] {
+            std::fs::remove_file(old_backup)
+                .with_context(|| format!("failed to remove old backup {:?}", old_backup))?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns all backups with the specified prefix in the backup directory and their timestamps,
+/// sorted from oldest to newest.
+fn list_repo_backups(repo_root: &Path, prefix: &str) -> Result<Vec<(u128, PathBuf)>> {
+    let dir = get_backup_dir(repo_root);
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let mut backups: Vec<(u128, PathBuf)> = Vec::new();
+    for entry in std::fs::read_dir(&dir)? {
+        let path = entry?.path();
+        let timestamp = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_prefix(prefix))
+            .and_then(|name| name.strip_suffix(".csv"))
+            .and_then(|timestamp| timestamp.parse::<u128>().ok());
+        if let Some(timestamp) = timestamp {
+            backups.push((timestamp, path));
+        }
+    }
+    backups.sort();
+    Ok(backups)
+}
+
+/// Backs up the CSV at `csv_path` before it is overwritten with `new_contents`, keeping
+/// the newest `max_backups` backups. This is called by every function list writer in
+/// `functions` (see `WriteOptions::max_backups`) except `write_functions_with_backup`,
+/// which makes numbered backups instead (see `backup_to_numbered_file`).
+///
+/// Nothing is done if `max_backups` is 0, if the file doesn't exist yet, if it is not inside
+/// the repo or if the write wouldn't change anything. Returns the path to the backup,
+/// if one was made.
+pub(crate) fn backup_before_write(
+    csv_path: &Path,
+    new_contents: &[u8],
+    max_backups: usize,
+) -> Result<Option<PathBuf>> {
+    if max_backups == 0 || !csv_path.is_file() {
+        return Ok(None);
+    }
+    backup_to_repo_dir(&repo::get_repo_root()?, csv_path, new_contents, max_backups)
+}
+
+fn backup_to_repo_dir(
+    repo_root: &Path,
+    csv_path: &Path,
+    new_contents: &[u8],
+    max_backups: usize,
+) -> Result<Option<PathBuf>> {
+    let prefix = match get_backup_prefix(repo_root, csv_path) {
+        Some(prefix) => prefix,
+        None => return Ok(None),
+    };
+
+    let old_contents = std::fs::read(csv_path)?;
+    if old_contents == new_contents {
+        return Ok(None);
+    }
+
+    // Backups are named after the time in milliseconds. Several writes can happen within
+    // the same millisecond, so the next free timestamp is used. create_new makes sure that
+    // concurrent writers never overwrite each other's backups.
+    let dir = create_backup_dir(repo_root)?;
+    let mut timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+    if let Some((newest, _)) = list_repo_backups(repo_root, &prefix)?.last() {
+        timestamp = timestamp.max(newest + 1);
+    }
+    let (backup_path, mut file) = loop {
+        let backup_path = dir.join(format!("{}{}.csv", prefix, timestamp));
+        match std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&backup_path)
+        {
+            Ok(file) => break (backup_path, file),
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => timestamp += 1,
+            Err(err) => {
+                return Err(err)
+                    .with_context(|| format!("failed to create backup {:?}", &backup_path))
+            }
+        }
+    };
+    file.write_all(&old_contents)
+        .with_context(|| format!("failed to write backup to {:?}", &backup_path))?;
+
+    remove_old_backups(&list_repo_backups(repo_root, &prefix)?, max_backups)?;
+    Ok(Some(backup_path))
+}
+
+/// Restores the CSV at `csv_path` from its most recent backup, which is then removed
+/// so that calling this function again restores the previous backup.
+///
+/// The CSV is locked (see `lock::lock_exclusive`) and replaced atomically.
+pub fn restore_latest_backup(csv_path: &Path) -> Result<PathBuf> {
+    restore_latest_repo_backup(&repo::get_repo_root()?, csv_path)
+}
+
+fn restore_latest_repo_backup(repo_root: &Path, csv_path: &Path) -> Result<PathBuf> {
+    let _lock = lock::lock_exclusive(csv_path, &LockOptions::default())?;
+    let backup_path = match get_backup_prefix(repo_root, csv_path) {
+        Some(prefix) => list_repo_backups(repo_root, &prefix)?.pop(),
+        None => None,
+    };
+    let backup_path = match backup_path {
+        Some((_, path)) => path,
+        None => bail!("no backup found for {:?}", csv_path),
+    };
+
+    let backup_contents = std::fs::read(&backup_path)?;
+    if csv_path.is_file() && std::fs::read(csv_path)? == backup_contents {
+        bail!(
+            "{:?} is identical to its latest backup ({:?}); nothing to undo",
+            csv_path,
+            &backup_path
+        );
+    }
+
+    file_utils::write_atomic(csv_path, &backup_contents)?;
+    std::fs::remove_file(&backup_path)?;
+    Ok(backup_path)
+}
+
+/// Returns the numbered backups of the specified CSV (`<name>.csv.bak.N`, next to the CSV)
+/// and their numbers, sorted from oldest to newest.
+fn list_numbered_backups(csv_path: &Path) -> Vec<(u64, PathBuf)> {
+    let (dir, file_name) = match (csv_path.parent(), csv_path.file_name()) {
+        (Some(dir), Some(file_name)) => (dir, file_name.to_string_lossy()),
+        _ => return Vec::new(),
+    };
+    let dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    let prefix = format!("{}.bak.", file_name);
+    let mut backups: Vec<(u64, PathBuf)> = entries
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let number = entry
+                .file_name()
+                .to_str()?
+                .strip_prefix(&prefix)?
+                .parse::<u64>()
+                .ok()?;
+            Some((number, csv_path.with_file_name(entry.file_name())))
+        })
+        .collect();
+    backups.sort();
+    backups
+}
+
+/// Returns the numbered backups of the specified CSV that were made by
+/// `functions::write_functions_with_backup`, sorted from oldest to newest.
+pub fn list_backups(csv_path: &Path) -> Vec<PathBuf> {
+    list_numbered_backups(csv_path)
+        .into_iter()
+        .map(|(_, path)| path)
+        .collect()
+}
+
+/// Copies the CSV at `csv_path` to `<name>.csv.bak.N` before it is overwritten with
+/// `new_contents`, where N is one more than the number of the newest backup. Only the newest
+/// `max_backups` backups are kept.
+///
+/// Nothing is done if `max_backups` is 0, if the file doesn't exist yet or if the write
+/// wouldn't change anything. Returns the path to the backup, if one was made.
+pub(crate) fn backup_to_numbered_file(
+    csv_path: &Path,
+    new_contents: &[u8],
+    max_backups: usize,
+) -> Result<Option<PathBuf>> {
+    if max_backups == 0 || !csv_path.is_file() {
+        return Ok(None);
+    }
+
+    let old_contents = std::fs::read(csv_path)?;
+    if old_contents == new_contents {
+        return Ok(None);
+    }
+
+    let mut backups = list_numbered_backups(csv_path);
+    let number = backups.last().map_or(1, |(number, _)| number + 1);
+    let file_name = csv_path.file_name().unwrap().to_string_lossy();
+    let backup_path = csv_path.with_file_name(format!("{}.bak.{}", file_name, number));
+    file_utils::write_atomic(&backup_path, &old_contents)
+        .with_context(|| format!("failed to write backup to {:?}", &backup_path))?;
+    backups.push((number, backup_path.clone()));
+    remove_old_backups(&backups, max_backups)?;
+    Ok(Some(backup_path))
+}
+
+/// Replaces the CSV at `csv_path` with the contents of a backup (see `list_backups`).
+/// The file is locked and replaced atomically, and the backup is kept.
+pub fn restore_backup(backup_path: &Path, csv_path: &Path) -> Result<()> {
+    let _lock = lock::lock_exclusive(csv_path, &LockOptions::default())?;
+    let contents = std::fs::read(backup_path)
+        .with_context(|| format!("failed to read backup {:?}", backup_path))?;
+    file_utils::write_atomic(csv_path, &contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn backups_made_in_the_same_millisecond_are_kept() {
+        let repo = TempDir::new("backup_collisions");
+        std::fs::create_dir_all(repo.join("data")).unwrap();
+        let csv_path = repo.join("data").join("functions.csv");
+
+        let mut backups = Vec::new();
+        for i in 0..5 {
+            std::fs::write(&csv_path, format!("version {}", i)).unwrap();
+            let backup = backup_to_repo_dir(repo.path(), &csv_path, b"new", 10).unwrap();
+            backups.push(backup.unwrap());
+        }
+        let prefix = get_backup_prefix(repo.path(), &csv_path).unwrap();
+        assert_eq!(prefix, "data-functions-");
+        let listed: Vec<PathBuf> = list_repo_backups(repo.path(), &prefix)
+            .unwrap()
+            .into_iter()
+            .map(|(_, path)| path)
+            .collect();
+        assert_eq!(listed, backups);
+        for (i, backup) in backups.iter().enumerate() {
+            assert_eq!(
+                std::fs::read_to_string(backup).unwrap(),
+                format!("version {}", i)
+            );
+        }
+
+        // Only the newest backups are kept.
+        std::fs::write(&csv_path, "version 5").unwrap();
+        backup_to_repo_dir(repo.path(), &csv_path, b"new", 2).unwrap();
+        assert_eq!(list_repo_backups(repo.path(), &prefix).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn latest_backups_are_restored_in_order() {
+        let repo = TempDir::new("backup_restore");
+        let csv_path = repo.join("functions.csv");
+        for contents in ["version 0", "version 1"] {
+            std::fs::write(&csv_path, contents).unwrap();
+            backup_to_repo_dir(repo.path(), &csv_path, b"version 2", 10).unwrap();
+        }
+        std::fs::write(&csv_path, "version 2").unwrap();
+
+        for expected in ["version 1", "version 0"] {
+            restore_latest_repo_backup(repo.path(), &csv_path).unwrap();
+            assert_eq!(std::fs::read_to_string(&csv_path).unwrap(), expected);
+        }
+        assert!(restore_latest_repo_backup(repo.path(), &csv_path).is_err());
+        // The lock was released.
+        assert!(!lock::get_lock_path(&csv_path).exists());
+    }
+
+    #[test]
+    fn only_changed_files_in_the_repo_are_backed_up() {
+        let repo = TempDir::new("backup_repo");
+        let outside = TempDir::new("backup_outside");
+        let csv_path = repo.join("functions.csv");
+        std::fs::write(&csv_path, "old").unwrap();
+        assert!(backup_to_repo_dir(repo.path(), &csv_path, b"old", 10)
+            .unwrap()
+            .is_none());
+
+        let other_path = outside.join("functions.csv");
+        std::fs::write(&other_path, "old").unwrap();
+        assert!(backup_to_repo_dir(repo.path(), &other_path, b"new", 10)
+            .unwrap()
+            .is_none());
+        assert!(!get_backup_dir(repo.path()).exists());
+
+        // Lists with the same name in different directories don't share backups.
+        std::fs::create_dir_all(repo.join("data")).unwrap();
+        let data_path = repo.join("data").join("functions.csv");
+        std::fs::write(&data_path, "old").unwrap();
+        assert_eq!(
+            get_backup_prefix(repo.path(), &csv_path).as_deref(),
+            Some("functions-")
+        );
+        assert_eq!(
+            get_backup_prefix(repo.path(), &data_path).as_deref(),
+            Some("data-functions-")
+        );
+    }
+}
@@ -0,0 +1,201 @@
+use crate::elf::{self, OwnedElf};
+use crate::functions::{self, ADDRESS_BASE};
+use crate::nso;
+use anyhow::{bail, Context, Result};
+use goblin::elf::program_header::{PF_W, PF_X, PT_LOAD};
+use std::ops::Range;
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SegmentKind {
+    Text,
+    Ro,
+    Data,
+}
+
+impl SegmentKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SegmentKind::Text => "text",
+            SegmentKind::Ro => "ro",
+            SegmentKind::Data => "data",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Segment {
+    pub kind: SegmentKind,
+    /// Addresses of the segment contents.
+    /// *Note*: does not contain the IDA base (0x7100000000).
+    pub addr_range: Range<u64>,
+    /// Offset of the segment contents in the binary data.
+    data_offset: usize,
+}
+
+enum Data {
+    Elf(OwnedElf),
+    /// Decompressed segments, one after the other.
+    Owned(Vec<u8>),
+}
+
+/// The original executable, either as an ELF (converted from the NSO) or as an NSO.
+///
+/// Addresses may or may not include `ADDRESS_BASE`.
+pub struct BaseBinary {
+    data: Data,
+    /// Sorted by address.
+    segments: Vec<Segment>,
+    entry_point: u64,
+}
+
+fn strip_address_base(addr: u64) -> u64 {
+    addr.checked_sub(ADDRESS_BASE).unwrap_or(addr)
+}
+
+impl BaseBinary {
+    pub fn from_elf(path: &Path) -> Result<Self> {
+        let elf = elf::load_elf(path).with_context(|| format!("failed to load {:?}", path))?;
+
+        let mut segments: Vec<Segment> = elf
+            .program_headers
+            .iter()
+            .filter(|segment| segment.p_type == PT_LOAD)
+            .map(|segment| {
+                let kind = if segment.p_flags & PF_X != 0 {
+                    SegmentKind::Text
+                } else if segment.p_flags & PF_W != 0 {
+                    SegmentKind::Data
+                } else {
+                    SegmentKind::Ro
+                };
+                // Only the part of the segment that is backed by the file can be read.
+                Segment {
+                    kind,
+                    addr_range: segment.p_vaddr..segment.p_vaddr + segment.p_filesz,
+                    data_offset: segment.p_offset as usize,
+                }
+            })
+            .collect();
+        segments.sort_by_key(|segment| segment.addr_range.start);
+
+        let entry_point = strip_address_base(elf.header.e_entry);
+        Ok(Self {
+            data: Data::Elf(elf),
+            segments,
+            entry_point,
+        })
+    }
+
+    pub fn from_nso(path: &Path) -> Result<Self> {
+        let nso_bytes =
+            std::fs::read(path).with_context(|| format!("failed to read {:?}", path))?;
+        let header = nso::parse_nso_header(&nso_bytes)
+            .with_context(|| format!("failed to parse {:?}", path))?;
+
+        let mut data = Vec::new();
+        let mut segments = Vec::new();
+        for (kind, segment, flag) in [
+            (SegmentKind::Text, &header.text, nso::FLAG_TEXT_COMPRESSED),
+            (SegmentKind::Ro, &header.ro, nso::FLAG_RO_COMPRESSED),
+            (SegmentKind::Data, &header.data, nso::FLAG_DATA_COMPRESSED),
+        ] {
+            let contents = nso::get_segment_data(&nso_bytes, &header, segment, flag)
+                .with_context(|| format!("failed to read {} segment of {:?}", kind.name(), path))?;
+            let addr = segment.memory_offset as u64;
+            segments.push(Segment {
+                kind,
+                addr_range: addr..addr + contents.len() as u64,
+                data_offset: data.len(),
+            });
+            data.extend_from_slice(&contents);
+        }
+        segments.sort_by_key(|segment| segment.addr_range.start);
+
+        Ok(Self {
+            data: Data::Owned(data),
+            segments,
+            // Execution starts at the beginning of the text segment.
+            entry_point: header.text.memory_offset as u64,
+        })
+    }
+
+    fn data(&self) -> &[u8] {
+        match &self.data {
+            Data::Elf(elf) => &elf.as_owner().1,
+            Data::Owned(data) => data,
+        }
+    }
+
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    pub fn get_segment(&self, addr: u64) -> Option<&Segment> {
+        let addr = strip_address_base(addr);
+        self.segments
+            .iter()
+            .find(|segment| segment.addr_range.contains(&addr))
+    }
+
+    /// Returns `len` bytes starting at `addr`. The bytes must all be in the same segment.
+    pub fn read(&self, addr: u64, len: usize) -> Result<&[u8]> {
+        let addr = strip_address_base(addr);
+        let segment = match self.get_segment(addr) {
+            Some(segment) => segment,
+            None => bail!(
+                "{} is not in any segment (segments: {})",
+                functions::format_addr(addr),
+                self.describe_segments()
+            ),
+        };
+
+        let end = addr + len as u64;
+        if end > segment.addr_range.end {
+            bail!(
+                "cannot read {:#x} bytes at {}: the read goes past the end of the {} segment ({})",
+                len,
+                functions::format_addr(addr),
+                segment.kind.name(),
+                functions::format_addr(segment.addr_range.end)
+            );
+        }
+
+        let start = segment.data_offset + (addr - segment.addr_range.start) as usize;
+        self.data()
+            .get(start..start + len)
+            .with_context(|| format!("{} segment is truncated", segment.kind.name()))
+    }
+
+    /// Returns the address range of the code.
+    pub fn text_range(&self) -> Result<Range<u64>> {
+        let mut text = self
+            .segments
+            .iter()
+            .filter(|segment| segment.kind == SegmentKind::Text);
+        match (text.next(), text.next()) {
+            (Some(segment), None) => Ok(segment.addr_range.clone()),
+            (None, _) => bail!("no text segment"),
+            (Some(_), Some(_)) => bail!("several text segments"),
+        }
+    }
+
+    pub fn entry_point(&self) -> u64 {
+        self.entry_point
+    }
+
+    fn describe_segments(&self) -> String {
+        self.segments
+            .iter()
+            .map(|segment| {
+                format!(
+                    "{} {}..{}",
+                    segment.kind.name(),
+                    functions::format_addr(segment.addr_range.start),
+                    functions::format_addr(segment.addr_range.end)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
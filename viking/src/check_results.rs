@@ -0,0 +1,362 @@
+use crate::file_utils;
+use crate::functions;
+use crate::lock::{self, LockOptions};
+use crate::repo;
+use crate::stable_hash;
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Timelike, Utc};
+use rustc_hash::FxHashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Maximum number of lines that are stored for the diff of a failed check.
+pub const MAX_DIFF_EXCERPT_LINES: usize = 20;
+/// Maximum length (in bytes) that is stored for the diff of a failed check.
+pub const MAX_DIFF_EXCERPT_LEN: usize = 2000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CheckOutcome {
+    Match,
+    Mismatch,
+    /// The function could not be checked (e.g. because it is missing from the decomp executable).
+    Error,
+}
+
+impl CheckOutcome {
+    fn as_str(&self) -> &'static str {
+        match self {
+            CheckOutcome::Match => "match",
+            CheckOutcome::Mismatch => "mismatch",
+            CheckOutcome::Error => "error",
+        }
+    }
+
+    fn from_str(value: &str) -> Option<Self> {
+        match value {
+            "match" => Some(CheckOutcome::Match),
+            "mismatch" => Some(CheckOutcome::Mismatch),
+            "error" => Some(CheckOutcome::Error),
+            _ => None,
+        }
+    }
+}
+
+/// The result of the latest check of a function.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CheckRecord {
+    /// Mangled name of the function.
+    pub name: String,
+    /// Stored with a precision of one second.
+    pub timestamp: DateTime<Utc>,
+    pub outcome: CheckOutcome,
+    /// Hash of the decomp executable that was checked (see `hash_binary`).
+    pub binary_hash: u64,
+    /// Beginning of the mismatch description or error message, for failed checks.
+    /// See `make_diff_excerpt`.
+    pub diff: Option<String>,
+}
+
+impl CheckRecord {
+    /// Returns whether the record was made for a different build of the decomp executable,
+    /// in which case the function needs to be checked again for the result to be reliable.
+    pub fn is_stale(&self, binary_hash: u64) -> bool {
+        self.binary_hash != binary_hash
+    }
+}
+
+/// Hashes an executable to detect whether check results are still up to date.
+pub fn hash_binary(data: &[u8]) -> u64 {
+    stable_hash::hash(data)
+}
+
+/// Shortens a diff to at most `MAX_DIFF_EXCERPT_LINES` lines and `MAX_DIFF_EXCERPT_LEN` bytes
+/// so that the results of a full check stay small.
+pub(crate) fn make_diff_excerpt(diff: &str) -> String {
+    let mut excerpt = String::new();
+    for line in diff.lines().take(MAX_DIFF_EXCERPT_LINES) {
+        if excerpt.len() + line.len() + 1 > MAX_DIFF_EXCERPT_LEN {
+            if excerpt.is_empty() {
+                // Keep at least part of the first line.
+                let mut end = MAX_DIFF_EXCERPT_LEN;
+                while !line.is_char_boundary(end) {
+                    end -= 1;
+                }
+                excerpt.push_str(&line[..end]);
+            }
+            break;
+        }
+        if !excerpt.is_empty() {
+            excerpt.push('\n');
+        }
+        excerpt.push_str(line);
+    }
+    if excerpt.len() < diff.trim_end().len() {
+        excerpt.push_str("\n[...]");
+    }
+    excerpt
+}
+
+/// Check results, indexed by function name.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CheckResultStore(pub FxHashMap<String, CheckRecord>);
+
+impl CheckResultStore {
+    pub fn get(&self, name: &str) -> Option<&CheckRecord> {
+        self.0.get(name)
+    }
+
+    /// Replaces the previous record for the same function, if any.
+    pub fn insert(&mut self, record: CheckRecord) {
+        self.0.insert(record.name.clone(), record);
+    }
+}
+
+/// Collects check results from several threads.
+pub struct CheckResultBatch {
+    binary_hash: u64,
+    timestamp: DateTime<Utc>,
+    records: Mutex<Vec<CheckRecord>>,
+}
+
+impl CheckResultBatch {
+    pub fn new(binary_hash: u64) -> Self {
+        Self {
+            binary_hash,
+            timestamp: Utc::now().with_nanosecond(0).unwrap(),
+            records: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Adds a result. `diff` is shortened with `make_diff_excerpt`.
+    pub fn add(&self, name: &str, outcome: CheckOutcome, diff: Option<&str>) {
+        let record = CheckRecord {
+            name: name.to_string(),
+            timestamp: self.timestamp,
+            outcome,
+            binary_hash: self.binary_hash,
+            diff: diff.map(make_diff_excerpt),
+        };
+        self.records.lock().unwrap().push(record);
+    }
+
+    pub fn into_records(self) -> Vec<CheckRecord> {
+        self.records.into_inner().unwrap()
+    }
+}
+
+fn parse_entry(line: &str) -> Result<CheckRecord> {
+    let value: serde_json::Value = serde_json::from_str(line)?;
+    let get_str = |key: &str| -> Result<&str> {
+        match value.get(key).and_then(serde_json::Value::as_str) {
+            Some(value) => Ok(value),
+            None => bail!("missing or invalid {:?}", key),
+        }
+    };
+
+    let outcome = match CheckOutcome::from_str(get_str("outcome")?) {
+        Some(outcome) => outcome,
+        None => bail!("invalid check outcome: {}", get_str("outcome")?),
+    };
+    let diff = match value.get("diff") {
+        None | Some(serde_json::Value::Null) => None,
+        Some(_) => Some(get_str("diff")?.to_string()),
+    };
+
+    Ok(CheckRecord {
+        name: get_str("name")?.to_string(),
+        timestamp: DateTime::parse_from_rfc3339(get_str("timestamp")?)?.with_timezone(&Utc),
+        outcome,
+        binary_hash: functions::parse_hex_u64(get_str("binary_hash")?)?,
+        diff,
+    })
+}
+
+fn serialize_entry(record: &CheckRecord) -> String {
+    serde_json::json!({
+        "name": record.name,
+        "timestamp": record
+            .timestamp
+            .to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+        "outcome": record.outcome.as_str(),
+        "binary_hash": format!("{:016x}", record.binary_hash),
+        "diff": record.diff,
+    })
+    .to_string()
+}
+
+/// Reads check results (one JSON object per line). If there are several records for the
+/// same function, the last one wins. A missing file is treated as an empty store.
+pub fn load_check_results(path: &Path) -> Result<CheckResultStore> {
+    let mut store = CheckResultStore::default();
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(store),
+        Err(err) => return Err(err).with_context(|| format!("failed to read {:?}", path)),
+    };
+
+    for (i, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record = parse_entry(line)
+            .with_context(|| format!("failed to parse entry at line {}", i + 1))?;
+        store.insert(record);
+    }
+    Ok(store)
+}
+
+/// Writes check results, sorted by name so that the file is stable.
+pub fn save_check_results(path: &Path, store: &CheckResultStore) -> Result<()> {
+    let mut records: Vec<&CheckRecord> = store.0.values().collect();
+    records.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut contents = String::new();
+    for record in records {
+        contents.push_str(&serialize_entry(record));
+        contents.push('\n');
+    }
+    file_utils::write_atomic(path, contents.as_bytes())
+}
+
+/// Returns the path to the check result store, if check results should be kept.
+/// Results are not kept unless `check_results_jsonl` (relative to the repo root) is set in the
+/// config.
+pub fn get_check_results_path() -> Result<Option<PathBuf>> {
+    match repo::CONFIG
+        .get("check_results_jsonl")
+        .and_then(toml::Value::as_str)
+    {
+        Some(path) => Ok(Some(repo::get_repo_root()?.join(path))),
+        None => Ok(None),
+    }
+}
+
+/// Adds results to the store, replacing older results for the same functions.
+/// Nothing is done if the store is disabled.
+pub fn record_check_results(records: Vec<CheckRecord>) -> Result<()> {
+    let path = match get_check_results_path()? {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+    if records.is_empty() {
+        return Ok(());
+    }
+
+    let _lock = lock::lock_exclusive(&path, &LockOptions::default())?;
+    let mut store = load_check_results(&path)?;
+    for record in records {
+        store.insert(record);
+    }
+    save_check_results(&path, &store)
+}
+
+/// Returns the result of the latest check of the function called `name`.
+pub fn last_result(name: &str) -> Result<Option<CheckRecord>> {
+    let path = match get_check_results_path()? {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+    Ok(load_check_results(&path)?.0.remove(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use chrono::TimeZone;
+
+    fn record(name: &str, outcome: CheckOutcome, diff: Option<&str>) -> CheckRecord {
+        CheckRecord {
+            name: name.to_string(),
+            timestamp: Utc.timestamp_opt(1_700_000_000, 0).unwrap(),
+            outcome,
+            binary_hash: 0x0123_4567_89ab_cdef,
+            diff: diff.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn results_are_stored_as_json_lines() {
+        let dir = TempDir::new("check_results");
+        let path = dir.join("check_results.jsonl");
+        let mut store = CheckResultStore::default();
+        store.insert(record(
+            "_ZN4ksys3act8BaseProc4initEv",
+            CheckOutcome::Match,
+            None,
+        ));
+        store.insert(record(
+            "_ZN4ksys3act8BaseProc4calcEv",
+            CheckOutcome::Mismatch,
+            Some("mismatch at 0x10:\n  add x0, x0, #1, \"quoted\""),
+        ));
+        save_check_results(&path, &store).unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(contents.lines().count(), 2);
+        assert!(contents.starts_with("{\"binary_hash\":\"0123456789abcdef\""));
+        assert_eq!(load_check_results(&path).unwrap(), store);
+    }
+
+    #[test]
+    fn later_lines_replace_earlier_ones() {
+        let dir = TempDir::new("check_results_append");
+        let path = dir.join("check_results.jsonl");
+        let old = record("f", CheckOutcome::Error, Some("not found"));
+        let new = record("f", CheckOutcome::Match, None);
+        let contents = format!("{}\n\n{}\n", serialize_entry(&old), serialize_entry(&new));
+        std::fs::write(&path, contents).unwrap();
+
+        let store = load_check_results(&path).unwrap();
+        assert_eq!(store.get("f"), Some(&new));
+    }
+
+    #[test]
+    fn invalid_entries_are_rejected() {
+        let dir = TempDir::new("check_results_invalid");
+        let path = dir.join("check_results.jsonl");
+        let bad = serialize_entry(&record("f", CheckOutcome::Match, None))
+            .replace("\"match\"", "\"passed\"");
+        std::fs::write(
+            &path,
+            format!(
+                "{}\n{}\n",
+                serialize_entry(&record("g", CheckOutcome::Match, None)),
+                bad
+            ),
+        )
+        .unwrap();
+
+        let err = load_check_results(&path).unwrap_err();
+        assert_eq!(
+            format!("{:#}", err),
+            "failed to parse entry at line 2: invalid check outcome: passed"
+        );
+        assert!(load_check_results(&dir.join("missing.jsonl"))
+            .unwrap()
+            .0
+            .is_empty());
+    }
+
+    #[test]
+    fn records_for_other_builds_are_stale() {
+        let record = record("f", CheckOutcome::Match, None);
+        assert!(!record.is_stale(0x0123_4567_89ab_cdef));
+        assert!(record.is_stale(hash_binary(b"another build")));
+    }
+
+    #[test]
+    fn diff_excerpts_are_bounded() {
+        let long: String = (0..100).map(|i| format!("line {}\n", i)).collect();
+        let excerpt = make_diff_excerpt(&long);
+        assert_eq!(excerpt.lines().count(), MAX_DIFF_EXCERPT_LINES + 1);
+        assert!(excerpt.ends_with("line 19\n[...]"));
+
+        let wide = "é".repeat(MAX_DIFF_EXCERPT_LEN);
+        let excerpt = make_diff_excerpt(&wide);
+        assert!(excerpt.len() <= MAX_DIFF_EXCERPT_LEN + "\n[...]".len());
+        assert!(excerpt.ends_with("\n[...]"));
+
+        assert_eq!(make_diff_excerpt("short\n"), "short");
+    }
+}
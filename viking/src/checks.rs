@@ -1,14 +1,42 @@
-use anyhow::{ensure, Result};
+use anyhow::{bail, ensure, Context, Result};
 use capstone as cs;
 use cs::arch::arm64::{Arm64Insn, Arm64Operand, Arm64OperandType};
 use itertools::zip;
 use lazy_init::Lazy;
+use lazy_static::lazy_static;
 use rustc_hash::FxHashMap;
 use std::collections::{HashMap, HashSet};
 use std::convert::TryInto;
 use std::path::{Path, PathBuf};
 
-use crate::{capstone_utils::*, elf, functions, repo, ui};
+use crate::asm::{Normalizer, NormalizerOptions};
+use crate::{capstone_utils::*, elf, functions, object, repo, ui};
+
+lazy_static! {
+    static ref REGISTER_NORMALIZER: Normalizer = Normalizer::new(NormalizerOptions {
+        mask_registers: true,
+        ..Default::default()
+    });
+    static ref IMMEDIATE_NORMALIZER: Normalizer = Normalizer::new(NormalizerOptions {
+        masked_immediates: vec![i64::MIN..=i64::MAX],
+        ..Default::default()
+    });
+}
+
+/// Returns why two different instructions don't match: instructions that only differ in
+/// their registers or only in their immediates are reported as such (see `asm::Normalizer`).
+fn get_mismatch_cause(orig_insn: &[u8], decomp_insn: &[u8]) -> MismatchCause {
+    let same = |normalizer: &Normalizer| {
+        normalizer.normalize(orig_insn) == normalizer.normalize(decomp_insn)
+    };
+    if same(&REGISTER_NORMALIZER) {
+        MismatchCause::Register
+    } else if same(&IMMEDIATE_NORMALIZER) {
+        MismatchCause::Immediate
+    } else {
+        MismatchCause::Unknown
+    }
+}
 
 struct DataSymbol {
     /// Address of the symbol in the original executable.
@@ -132,6 +160,11 @@ pub enum MismatchCause {
     BranchTarget,
     FunctionCall(ReferenceDiff),
     DataReference(ReferenceDiff),
+    /// A relocation in an object file refers to the wrong symbol.
+    RelocationTarget {
+        expected: String,
+        actual: String,
+    },
     Immediate,
     Unknown,
 }
@@ -145,6 +178,14 @@ impl std::fmt::Display for MismatchCause {
             Self::BranchTarget => write!(f, "wrong branch target"),
             Self::FunctionCall(diff) => write!(f, "wrong function call\n{}", diff),
             Self::DataReference(diff) => write!(f, "wrong data reference\n{}", diff),
+            Self::RelocationTarget { expected, actual } => write!(
+                f,
+                "wrong relocation target\n\
+                --> object file is referencing {actual}\n\
+                --> expected to see {expected} to match original code",
+                actual = ui::format_symbol_name(actual),
+                expected = ui::format_symbol_name(expected),
+            ),
             Self::Immediate => write!(f, "wrong immediate"),
             Self::Unknown => write!(f, "unknown reason; check diff.py"),
         }
@@ -335,7 +376,8 @@ impl<'a, 'functions, 'orig_elf, 'decomp_elf>
                     }
 
                     if !diff_ok && i1.bytes() != i2.bytes() {
-                        return Self::make_mismatch(&i1, &i2, MismatchCause::Unknown);
+                        let cause = get_mismatch_cause(i1.bytes(), i2.bytes());
+                        return Self::make_mismatch(&i1, &i2, cause);
                     }
 
                     state.forget_modified_registers(&detail.0);
@@ -396,7 +438,8 @@ impl<'a, 'functions, 'orig_elf, 'decomp_elf>
                     }
 
                     if !diff_ok && i1.bytes() != i2.bytes() {
-                        return Self::make_mismatch(&i1, &i2, MismatchCause::Unknown);
+                        let cause = get_mismatch_cause(i1.bytes(), i2.bytes());
+                        return Self::make_mismatch(&i1, &i2, cause);
                     }
 
                     state.forget_modified_registers(&detail.0);
@@ -405,7 +448,8 @@ impl<'a, 'functions, 'orig_elf, 'decomp_elf>
                 // Anything else.
                 _ => {
                     if i1.bytes() != i2.bytes() {
-                        return Self::make_mismatch(&i1, &i2, MismatchCause::Unknown);
+                        let cause = get_mismatch_cause(i1.bytes(), i2.bytes());
+                        return Self::make_mismatch(&i1, &i2, cause);
                     }
 
                     state.forget_modified_registers(&detail.0);
@@ -516,3 +560,146 @@ impl<'a, 'functions, 'orig_elf, 'decomp_elf>
         map.get(&decomp_addr).copied()
     }
 }
+
+/// Returns the bits of an instruction that are filled in by a relocation.
+fn get_relocation_mask(r_type: u32) -> Result<u32> {
+    use goblin::elf::reloc::*;
+    Ok(match r_type {
+        R_AARCH64_CALL26 | R_AARCH64_JUMP26 => 0x03ff_ffff,
+        R_AARCH64_CONDBR19 | R_AARCH64_LD_PREL_LO19 => 0x00ff_ffe0,
+        R_AARCH64_TSTBR14 => 0x0007_ffe0,
+        R_AARCH64_ADR_PREL_LO21
+        | R_AARCH64_ADR_PREL_PG_HI21
+        | R_AARCH64_ADR_PREL_PG_HI21_NC
+        | R_AARCH64_ADR_GOT_PAGE => 0x60ff_ffe0,
+        R_AARCH64_ADD_ABS_LO12_NC
+        | R_AARCH64_LDST8_ABS_LO12_NC
+        | R_AARCH64_LDST16_ABS_LO12_NC
+        | R_AARCH64_LDST32_ABS_LO12_NC
+        | R_AARCH64_LDST64_ABS_LO12_NC
+        | R_AARCH64_LDST128_ABS_LO12_NC
+        | R_AARCH64_LD64_GOT_LO12_NC => 0x003f_fc00,
+        R_AARCH64_MOVW_UABS_G0
+        | R_AARCH64_MOVW_UABS_G0_NC
+        | R_AARCH64_MOVW_UABS_G1
+        | R_AARCH64_MOVW_UABS_G1_NC
+        | R_AARCH64_MOVW_UABS_G2
+        | R_AARCH64_MOVW_UABS_G2_NC
+        | R_AARCH64_MOVW_UABS_G3 => 0x001f_ffe0,
+        _ => bail!("unsupported relocation type: {}", r_type),
+    })
+}
+
+/// Returns the offset of the target of a branch, for relocations that apply to branches.
+fn get_branch_offset(insn: u32, r_type: u32) -> Option<i64> {
+    use goblin::elf::reloc::*;
+    let offset = match r_type {
+        R_AARCH64_CALL26 | R_AARCH64_JUMP26 => ((insn << 6) as i32) >> 6,
+        R_AARCH64_CONDBR19 => ((insn << 8) as i32) >> 13,
+        R_AARCH64_TSTBR14 => ((insn << 13) as i32) >> 18,
+        _ => return None,
+    };
+    Some(offset as i64 * 4)
+}
+
+/// Checks a function from a relocatable object file against the original.
+///
+/// Since the object file is not linked, relocations are compared symbolically: branches
+/// must refer to the function that the original branches to (if it is known), and data
+/// references are not checked at all. Non-relocated bits of every instruction must match.
+///
+/// *Note*: without a final link, section-anchored literals can differ from the linked
+/// executable, so a successful object-level check does not guarantee a match.
+pub fn check_object_function(
+    orig_fn: &elf::Function,
+    object_fn: &object::ObjectFunction,
+    known_functions: &FxHashMap<u64, &functions::Info>,
+) -> Result<Option<Mismatch>> {
+    if orig_fn.code.len() != object_fn.code.len() {
+        return Ok(Some(Mismatch {
+            addr_orig: orig_fn.addr,
+            addr_decomp: object_fn.offset,
+            cause: MismatchCause::FunctionSize,
+        }));
+    }
+
+    let mut relocs = object_fn.relocations.iter().peekable();
+    let words = orig_fn
+        .code
+        .chunks_exact(4)
+        .zip(object_fn.code.chunks_exact(4));
+    for (i, (orig_word, object_word)) in words.enumerate() {
+        let offset = i as u64 * 4;
+        let orig_insn = u32::from_le_bytes(orig_word.try_into()?);
+        let object_insn = u32::from_le_bytes(object_word.try_into()?);
+        let mismatch = |cause| {
+            Ok(Some(Mismatch {
+                addr_orig: orig_fn.addr + offset,
+                addr_decomp: object_fn.offset + offset,
+                cause,
+            }))
+        };
+
+        while relocs.next_if(|reloc| reloc.offset < offset).is_some() {}
+        let reloc = match relocs.peek() {
+            Some(reloc) if reloc.offset == offset => *reloc,
+            _ => {
+                if orig_insn != object_insn {
+                    return mismatch(get_mismatch_cause(orig_word, object_word));
+                }
+                continue;
+            }
+        };
+
+        let mask = get_relocation_mask(reloc.r_type)
+            .with_context(|| format!("at offset {:#x}", object_fn.offset + offset))?;
+        if orig_insn & !mask != object_insn & !mask {
+            return mismatch(MismatchCause::Unknown);
+        }
+
+        if let Some(branch_offset) = get_branch_offset(orig_insn, reloc.r_type) {
+            let target = (orig_fn.addr + offset).wrapping_add(branch_offset as u64);
+            // Branches to unknown functions cannot be checked.
+            if let Some(info) = known_functions.get(&target) {
+                if info.name != reloc.symbol || reloc.addend != 0 {
+                    let actual = if reloc.addend == 0 {
+                        reloc.symbol.clone()
+                    } else {
+                        format!("{}+{:#x}", reloc.symbol, reloc.addend)
+                    };
+                    return mismatch(MismatchCause::RelocationTarget {
+                        expected: info.name.clone(),
+                        actual,
+                    });
+                }
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mismatch_causes() {
+        let insn = |insn: u32| insn.to_le_bytes();
+        // add x1, x2, #4 vs add x3, x4, #4
+        assert!(matches!(
+            get_mismatch_cause(&insn(0x9100_1041), &insn(0x9100_1083)),
+            MismatchCause::Register
+        ));
+        // add x1, x2, #4 vs add x1, x2, #8
+        assert!(matches!(
+            get_mismatch_cause(&insn(0x9100_1041), &insn(0x9100_2041)),
+            MismatchCause::Immediate
+        ));
+        // add x1, x2, #4 vs sub x1, x2, #4
+        assert!(matches!(
+            get_mismatch_cause(&insn(0x9100_1041), &insn(0xd100_1041)),
+            MismatchCause::Unknown
+        ));
+    }
+}
@@ -0,0 +1,272 @@
+use crate::functions::{self, Info, Status};
+use crate::metadata::{self, Metadata};
+use crate::repo;
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Duration, Utc};
+
+/// Returns how long a claim stays active after it was made (or renewed by claiming
+/// the function again). This can be changed with the `claim_expiry_days` config key.
+fn get_claim_expiry() -> Duration {
+    let days = repo::CONFIG
+        .get("claim_expiry_days")
+        .and_then(toml::Value::as_integer)
+        .unwrap_or(30);
+    Duration::days(days)
+}
+
+/// An active or stale claim on a function. Claims are stored as the `owner` field
+/// of the metadata sidecar (see `metadata`) so that they can be shared through git.
+#[derive(Clone, Debug)]
+pub struct Claim {
+    pub info: Info,
+    pub owner: String,
+    pub url: Option<String>,
+    /// When the function was claimed by its current owner. Claims expire after
+    /// `claim_expiry_days`, even if the entry is updated in the meantime.
+    pub claimed_at: Option<DateTime<Utc>>,
+    pub updated_at: Option<DateTime<Utc>>,
+    /// Whether the claim has expired and can be taken over by someone else.
+    pub is_stale: bool,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ClaimFilter {
+    /// Only return claims by this owner.
+    pub owner: Option<String>,
+    /// Also return claims that have expired.
+    pub include_stale: bool,
+}
+
+fn parse_timestamp(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(value)
+        .ok()
+        .map(|date| date.with_timezone(&Utc))
+}
+
+/// Returns when the owner of `entry` claimed the function. Claims that were made before
+/// `claimed_at` was recorded fall back to `updated_at`.
+fn get_claim_date(entry: &metadata::Entry) -> Option<DateTime<Utc>> {
+    entry
+        .claimed_at()
+        .or_else(|| entry.updated_at())
+        .and_then(parse_timestamp)
+}
+
+fn is_stale(claimed_at: Option<DateTime<Utc>>, now: DateTime<Utc>) -> bool {
+    // Claims without a valid timestamp cannot expire.
+    claimed_at
+        .map(|date| now - date > get_claim_expiry())
+        .unwrap_or(false)
+}
+
+/// Finds a function by (possibly partial) name or by address (e.g. `0x7100012345`).
+fn resolve_function<'a>(functions: &'a [Info], name_or_addr: &str) -> Result<&'a Info> {
+    if name_or_addr.starts_with("0x") {
+        let addr = functions::parse_address_or_offset(name_or_addr)?;
+        functions::AddressIndex::build(functions).check_code_address(addr)?;
+        return functions
+            .iter()
+            .find(|info| info.addr == addr)
+            .with_context(|| format!("unknown function: {}", name_or_addr));
+    }
+
+    functions::find_function_fuzzy(functions, name_or_addr)
+        .with_context(|| format!("unknown function: {}", name_or_addr))
+}
+
+/// Claims a function in `metadata`. Fails if the function is already matching or if someone
+/// else has an active claim on it, unless `force` is set.
+pub fn claim_in(
+    functions: &[Info],
+    metadata: &mut Metadata,
+    name_or_addr: &str,
+    owner: &str,
+    force: bool,
+) -> Result<()> {
+    let info = resolve_function(functions, name_or_addr)?;
+    if info.name.is_empty() {
+        bail!("functions must be named before they can be claimed");
+    }
+    if info.status == Status::Matching && !force {
+        bail!("{} is already matching", info.name);
+    }
+    if info.status == Status::Library && !force {
+        bail!("{} is a library function", info.name);
+    }
+
+    let now = Utc::now();
+    let entry = metadata.entry(&info.name);
+    if let Some(current_owner) = entry.owner() {
+        if current_owner != owner && !force && !is_stale(get_claim_date(entry), now) {
+            bail!(
+                "{} is already claimed by {} (use force to take over the claim)",
+                info.name,
+                current_owner
+            );
+        }
+    }
+
+    entry.set(metadata::OWNER, owner.to_string());
+    // Claiming a function again renews the claim.
+    entry.set(metadata::CLAIMED_AT, metadata::format_timestamp(now));
+    Ok(())
+}
+
+/// Releases a claim in `metadata`. Only the owner can release a claim, unless `force` is set.
+pub fn release_in(
+    functions: &[Info],
+    metadata: &mut Metadata,
+    name_or_addr: &str,
+    owner: &str,
+    force: bool,
+) -> Result<()> {
+    let info = resolve_function(functions, name_or_addr)?;
+    let entry = match metadata.entries.get_mut(&info.name) {
+        Some(entry) => entry,
+        None => bail!("{} is not claimed", info.name),
+    };
+    match entry.owner() {
+        None => bail!("{} is not claimed", info.name),
+        Some(current_owner) if current_owner != owner && !force => {
+            bail!(
+                "{} is claimed by {}, not {}",
+                info.name,
+                current_owner,
+                owner
+            )
+        }
+        Some(_) => (),
+    }
+
+    entry.remove(metadata::OWNER);
+    entry.fields.remove(metadata::URL);
+    entry.fields.remove(metadata::CLAIMED_AT);
+    // Don't keep entries that only have a timestamp left.
+    if entry.fields.keys().all(|key| key == metadata::UPDATED_AT) {
+        metadata.remove(&info.name);
+    }
+    Ok(())
+}
+
+/// Returns claims from `metadata` that match the filter, sorted by address.
+pub fn list_in(functions: &[Info], metadata: &Metadata, filter: &ClaimFilter) -> Vec<Claim> {
+    let now = Utc::now();
+    metadata::join(functions, metadata)
+        .into_iter()
+        .filter_map(|annotated| {
+            let entry = annotated.metadata?;
+            let owner = entry.owner()?;
+            if let Some(wanted_owner) = &filter.owner {
+                if owner != wanted_owner {
+                    return None;
+                }
+            }
+
+            let claimed_at = get_claim_date(entry);
+            let is_stale = is_stale(claimed_at, now);
+            if is_stale && !filter.include_stale {
+                return None;
+            }
+
+            Some(Claim {
+                info: annotated.info.clone(),
+                owner: owner.to_string(),
+                url: entry.url().map(str::to_string),
+                claimed_at,
+                updated_at: entry.updated_at().and_then(parse_timestamp),
+                is_stale,
+            })
+        })
+        .collect()
+}
+
+/// Claims a function of the executable on behalf of `owner`. See `claim_in`.
+pub fn claim(name_or_addr: &str, owner: &str, force: bool) -> Result<()> {
+    let functions = functions::get_functions()?;
+    metadata::update(|metadata| claim_in(&functions, metadata, name_or_addr, owner, force))
+}
+
+/// Releases a claim on a function of the executable. See `release_in`.
+pub fn release(name_or_addr: &str, owner: &str, force: bool) -> Result<()> {
+    let functions = functions::get_functions()?;
+    metadata::update(|metadata| release_in(&functions, metadata, name_or_addr, owner, force))
+}
+
+/// Returns claims on functions of the executable. See `list_in`.
+pub fn list(filter: &ClaimFilter) -> Result<Vec<Claim>> {
+    let functions = functions::get_functions()?;
+    let metadata = metadata::load()?;
+    Ok(list_in(&functions, &metadata, filter))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, make_function};
+
+    fn make_functions() -> Vec<Info> {
+        vec![
+            make_function(0x100, 0x10, "_ZN3Foo1aEv", Status::NotDecompiled),
+            make_function(0x110, 0x10, "_ZN3Foo1bEv", Status::Matching),
+        ]
+    }
+
+    fn days_ago(days: i64) -> String {
+        metadata::format_timestamp(Utc::now() - Duration::days(days))
+    }
+
+    #[test]
+    fn claims_expire_after_they_were_made() {
+        testing::use_test_config();
+        let functions = make_functions();
+        let mut metadata = Metadata::default();
+        claim_in(&functions, &mut metadata, "_ZN3Foo1aEv", "alice", false).unwrap();
+        assert!(claim_in(&functions, &mut metadata, "_ZN3Foo1aEv", "bob", false).is_err());
+        assert!(claim_in(&functions, &mut metadata, "_ZN3Foo1bEv", "bob", false).is_err());
+
+        // Editing the entry doesn't extend the claim.
+        let entry = metadata.entry("_ZN3Foo1aEv");
+        entry.set(metadata::CLAIMED_AT, days_ago(31));
+        entry.set(metadata::NOTE, "still on it".to_string());
+        let claims = list_in(&functions, &metadata, &ClaimFilter::default());
+        assert!(claims.is_empty());
+        let filter = ClaimFilter {
+            include_stale: true,
+            ..Default::default()
+        };
+        let claims = list_in(&functions, &metadata, &filter);
+        assert_eq!(claims.len(), 1);
+        assert!(claims[0].is_stale);
+        assert!(claims[0].claimed_at < claims[0].updated_at);
+
+        claim_in(&functions, &mut metadata, "_ZN3Foo1aEv", "bob", false).unwrap();
+        let claims = list_in(&functions, &metadata, &ClaimFilter::default());
+        assert_eq!(claims[0].owner, "bob");
+        assert!(!claims[0].is_stale);
+    }
+
+    #[test]
+    fn claims_without_a_claim_date_use_the_update_date() {
+        testing::use_test_config();
+        let functions = make_functions();
+        let mut metadata = Metadata::default();
+        let entry = metadata.entry("_ZN3Foo1aEv");
+        entry
+            .fields
+            .insert(metadata::OWNER.to_string(), "alice".to_string());
+        entry
+            .fields
+            .insert(metadata::UPDATED_AT.to_string(), days_ago(10));
+        assert!(claim_in(&functions, &mut metadata, "_ZN3Foo1aEv", "bob", false).is_err());
+
+        let entry = metadata.entry("_ZN3Foo1aEv");
+        entry
+            .fields
+            .insert(metadata::UPDATED_AT.to_string(), days_ago(40));
+        claim_in(&functions, &mut metadata, "_ZN3Foo1aEv", "bob", false).unwrap();
+        assert!(metadata.get("_ZN3Foo1aEv").unwrap().claimed_at().is_some());
+
+        release_in(&functions, &mut metadata, "0x7100000100", "bob", false).unwrap();
+        assert!(metadata.get("_ZN3Foo1aEv").is_none());
+    }
+}
@@ -0,0 +1,416 @@
+use crate::edit::{self, Edit, WritePlan};
+use crate::functions::{self, Info, Status};
+use crate::lint::Issue;
+use crate::repo;
+use anyhow::{bail, Context, Result};
+use rayon::prelude::*;
+use regex::Regex;
+use std::ops::Range;
+
+const DEFAULT_LIBRARY_RULES: &str = include_str!("../data/library_rules.toml");
+const DEFAULT_COMPILER_ARTIFACT_RULES: &str = include_str!("../data/compiler_artifact_rules.toml");
+
+/// Rules that identify a group of functions, e.g. well-known library functions
+/// (libc, libstdc++, ...) or functions that are part of the project's own code.
+#[derive(Clone, Debug, Default)]
+pub struct Rules {
+    /// Prefixes of mangled names.
+    pub prefixes: Vec<String>,
+    /// Regular expressions that are matched against mangled names.
+    pub regexes: Vec<Regex>,
+    /// Namespaces, split into components (e.g. `["nn", "os"]`).
+    pub namespaces: Vec<Vec<String>>,
+    /// Address ranges.
+    /// *Note*: addresses do not contain the IDA base (0x7100000000).
+    pub address_ranges: Vec<Range<u64>>,
+}
+
+pub type LibraryRules = Rules;
+
+fn get_string_array<'a>(value: &'a toml::Value, key: &str) -> Result<Vec<&'a str>> {
+    match value.get(key) {
+        None => Ok(Vec::new()),
+        Some(array) => array
+            .as_array()
+            .with_context(|| format!("{} must be an array", key))?
+            .iter()
+            .map(|item| {
+                item.as_str()
+                    .with_context(|| format!("{} must only contain strings", key))
+            })
+            .collect(),
+    }
+}
+
+fn parse_address_range(value: &toml::Value) -> Result<Range<u64>> {
+    let bounds = match value.as_array().map(Vec::as_slice) {
+        Some([start, end]) => (start.as_str(), end.as_str()),
+        _ => bail!("address ranges must be [start, end] pairs"),
+    };
+    match bounds {
+        (Some(start), Some(end)) => Ok(
+            functions::parse_address_or_offset(start)?..functions::parse_address_or_offset(end)?
+        ),
+        _ => bail!("address range bounds must be strings (e.g. \"0x7100000000\")"),
+    }
+}
+
+impl Rules {
+    pub fn parse(contents: &str) -> Result<Self> {
+        Self::from_value(&toml::from_str(contents)?)
+    }
+
+    pub fn from_value(value: &toml::Value) -> Result<Self> {
+        let prefixes = get_string_array(value, "prefixes")?
+            .into_iter()
+            .map(str::to_string)
+            .collect();
+
+        let regexes = get_string_array(value, "regexes")?
+            .into_iter()
+            .map(|regex| Regex::new(regex).with_context(|| format!("invalid regex: {}", regex)))
+            .collect::<Result<_>>()?;
+
+        let namespaces = get_string_array(value, "namespaces")?
+            .into_iter()
+            .map(|namespace| namespace.split("::").map(str::to_string).collect())
+            .collect();
+
+        let address_ranges = match value.get("address_ranges") {
+            None => Vec::new(),
+            Some(ranges) => ranges
+                .as_array()
+                .context("address_ranges must be an array")?
+                .iter()
+                .map(parse_address_range)
+                .collect::<Result<_>>()?,
+        };
+
+        Ok(Self {
+            prefixes,
+            regexes,
+            namespaces,
+            address_ranges,
+        })
+    }
+
+    /// Reads rules from the config. `key` can either be set to the path of a rule file
+    /// (relative to the repo root) or to a table that contains the rules.
+    fn from_config(key: &str) -> Result<Option<Self>> {
+        match repo::CONFIG.get(key) {
+            None => Ok(None),
+            Some(toml::Value::String(path)) => {
+                let path = repo::get_repo_root()?.join(path);
+                let contents = std::fs::read_to_string(&path)
+                    .with_context(|| format!("failed to read {:?}", &path))?;
+                let rules = Self::parse(&contents)
+                    .with_context(|| format!("failed to parse {:?}", &path))?;
+                Ok(Some(rules))
+            }
+            Some(value) => Ok(Some(
+                Self::from_value(value).with_context(|| format!("invalid {} in config", key))?,
+            )),
+        }
+    }
+
+    /// Returns the library rules that ship with viking.
+    pub fn default_library_rules() -> Self {
+        Self::parse(DEFAULT_LIBRARY_RULES).expect("failed to parse default library rules")
+    }
+
+    /// Returns the project's library rules. The default rules can be replaced
+    /// with the `library_rules` config key.
+    pub fn load_library_rules() -> Result<Self> {
+        Ok(Self::from_config("library_rules")?.unwrap_or_else(Self::default_library_rules))
+    }
+
+    /// Returns the rules that identify the project's own code (`project_rules` in the config),
+    /// or empty rules if there are none.
+    pub fn load_project_rules() -> Result<Self> {
+        Ok(Self::from_config("project_rules")?.unwrap_or_default())
+    }
+
+    pub fn matches_name(&self, name: &str) -> bool {
+        if name.is_empty() {
+            return false;
+        }
+
+        if self
+            .prefixes
+            .iter()
+            .any(|prefix| name.starts_with(prefix.as_str()))
+        {
+            return true;
+        }
+
+        if self.regexes.iter().any(|regex| regex.is_match(name)) {
+            return true;
+        }
+
+        if self.namespaces.is_empty() {
+            return false;
+        }
+        match functions::demangle_str(name) {
+            Ok(demangled) => {
+                let components = functions::split_qualified_name(&demangled);
+                self.namespaces.iter().any(|namespace| {
+                    namespace.len() < components.len()
+                        && namespace.iter().zip(&components).all(|(a, b)| a == b)
+                })
+            }
+            Err(_) => false,
+        }
+    }
+
+    pub fn matches(&self, info: &Info) -> bool {
+        self.address_ranges
+            .iter()
+            .any(|range| range.contains(&info.addr))
+            || self.matches_name(&info.name)
+    }
+}
+
+/// Returns functions that look like library functions but are not marked as such.
+pub fn suggest_library<'a>(functions: &'a [Info], rules: &LibraryRules) -> Vec<&'a Info> {
+    functions
+        .par_iter()
+        .filter(|info| info.status != Status::Library && rules.matches(info))
+        .collect()
+}
+
+/// Returns functions that are marked as library functions but look like project code.
+pub fn find_misclassified_library_functions<'a>(
+    functions: &'a [Info],
+    project_rules: &Rules,
+) -> Vec<&'a Info> {
+    functions
+        .par_iter()
+        .filter(|info| info.status == Status::Library && project_rules.matches(info))
+        .collect()
+}
+
+/// Returns whether a suggestion can be applied without review. Functions that already have
+/// a name were named by someone (or imported from symbols) and are only ever suggested,
+/// so only unnamed functions (e.g. found through `Rules::address_ranges`) are auto-applied.
+pub fn can_apply_suggestion(info: &Info) -> bool {
+    info.name.is_empty()
+}
+
+/// Returns the edits that mark suggested functions as library functions.
+/// See `can_apply_suggestion`. Functions that match the project rules are left untouched.
+pub fn get_library_suggestion_edits(
+    functions: &[Info],
+    rules: &LibraryRules,
+    project_rules: &Rules,
+) -> Vec<Edit> {
+    suggest_library(functions, rules)
+        .into_iter()
+        .filter(|info| can_apply_suggestion(info) && !project_rules.matches(info))
+        .map(|info| Edit::SetStatus {
+            addr: info.addr,
+            status: Status::Library,
+        })
+        .collect()
+}
+
+/// Marks suggested functions of the executable as library functions.
+/// See `get_library_suggestion_edits` and `edit::update_functions`.
+pub fn apply_library_suggestions(rules: &LibraryRules, dry_run: bool) -> Result<WritePlan> {
+    let functions = functions::get_functions()?;
+    let edits = get_library_suggestion_edits(&functions, rules, &Rules::load_project_rules()?);
+    edit::update_functions(&edits, dry_run)
+}
+
+/// Reports functions whose status contradicts the library or project rules,
+/// together with the status they should probably have.
+pub fn check_with_rules(
+    functions: &[Info],
+    library_rules: &Rules,
+    project_rules: &Rules,
+) -> Vec<Issue> {
+    // Project code takes precedence: don't suggest marking it as library code.
+    let mut issues: Vec<Issue> = suggest_library(functions, library_rules)
+        .into_iter()
+        .filter(|info| !project_rules.matches(info))
+        .map(|info| {
+            Issue::warning(
+                Some(info.addr),
+                format!(
+                    "looks like a library function (suggested status: {})",
+                    Status::Library.code()
+                ),
+            )
+        })
+        .collect();
+
+    issues.extend(
+        find_misclassified_library_functions(functions, project_rules)
+            .into_iter()
+            .map(|info| {
+                Issue::warning(
+                    Some(info.addr),
+                    format!(
+                        "marked as a library function but looks like project code (suggested status: {})",
+                        Status::NotDecompiled.code()
+                    ),
+                )
+            }),
+    );
+
+    issues
+}
+
+/// Finds (and removes) compiler-generated functions, such as static initializers
+/// and exception handling personality routines, which clutter the function list.
+#[derive(Clone, Debug)]
+pub struct CompilerArtifactFilter {
+    pub rules: Rules,
+    /// Only return the functions that would be removed, without modifying the list.
+    pub dry_run: bool,
+}
+
+impl CompilerArtifactFilter {
+    pub fn new(rules: Rules) -> Self {
+        Self {
+            rules,
+            dry_run: false,
+        }
+    }
+
+    /// Returns a filter with the rules that ship with viking, which match the symbols that
+    /// are generated for the Itanium C++ ABI by GCC and Clang.
+    pub fn with_default_rules() -> Self {
+        Self::new(
+            Rules::parse(DEFAULT_COMPILER_ARTIFACT_RULES)
+                .expect("failed to parse default compiler artifact rules"),
+        )
+    }
+
+    /// Returns a filter with the project's rules. The default rules can be replaced
+    /// with the `compiler_artifact_rules` config key (see `Rules::load_library_rules`).
+    pub fn load() -> Result<Self> {
+        Ok(match Rules::from_config("compiler_artifact_rules")? {
+            Some(rules) => Self::new(rules),
+            None => Self::with_default_rules(),
+        })
+    }
+
+    pub fn is_artifact(&self, info: &Info) -> bool {
+        self.rules.matches(info)
+    }
+
+    /// Removes compiler artifacts from `functions` (unless this is a dry run)
+    /// and returns them in list order.
+    pub fn strip(&self, functions: &mut Vec<Info>) -> Vec<Info> {
+        let is_artifact: Vec<bool> = functions
+            .par_iter()
+            .map(|info| self.is_artifact(info))
+            .collect();
+
+        if self.dry_run {
+            return functions
+                .iter()
+                .zip(&is_artifact)
+                .filter(|(_, &is_artifact)| is_artifact)
+                .map(|(info, _)| info.clone())
+                .collect();
+        }
+
+        let mut removed = Vec::new();
+        let mut kept = Vec::with_capacity(functions.len());
+        for (info, is_artifact) in std::mem::take(functions).into_iter().zip(is_artifact) {
+            if is_artifact {
+                removed.push(info);
+            } else {
+                kept.push(info);
+            }
+        }
+        *functions = kept;
+        removed
+    }
+}
+
+/// Removes functions that match the default compiler artifact rules
+/// (see `CompilerArtifactFilter::with_default_rules`) and returns how many were removed.
+pub fn strip_compiler_artifacts(functions: &mut Vec<Info>) -> usize {
+    CompilerArtifactFilter::with_default_rules()
+        .strip(functions)
+        .len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::make_function;
+
+    fn make_rules() -> Rules {
+        Rules::parse(
+            r#"
+            prefixes = ["_ZNSt"]
+            regexes = ["^memcpy$"]
+            namespaces = ["nn::os"]
+            address_ranges = [["0x7100100000", "0x7100200000"]]
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn library_functions_are_suggested() {
+        let functions = vec![
+            // std::__1::mutex::lock()
+            make_function(0x10, 0x10, "_ZNSt3__15mutex4lockEv", Status::NotDecompiled),
+            make_function(0x20, 0x10, "memcpy", Status::Wip),
+            make_function(0x30, 0x10, "memcpy_s", Status::NotDecompiled),
+            // nn::os::SleepThread(nn::TimeSpan)
+            make_function(
+                0x40,
+                0x10,
+                "_ZN2nn2os11SleepThreadENS_8TimeSpanE",
+                Status::Matching,
+            ),
+            make_function(
+                0x50,
+                0x10,
+                "_ZN4ksys3act8BaseProc4initEv",
+                Status::NotDecompiled,
+            ),
+            make_function(0x100010, 0x10, "", Status::NotDecompiled),
+            make_function(0x100020, 0x10, "", Status::Library),
+        ];
+        let addrs: Vec<u64> = suggest_library(&functions, &make_rules())
+            .iter()
+            .map(|info| info.addr)
+            .collect();
+        assert_eq!(addrs, [0x10, 0x20, 0x40, 0x100010]);
+    }
+
+    #[test]
+    fn named_functions_are_never_auto_applied() {
+        let functions = vec![
+            make_function(0x10, 0x10, "_ZNSt3__15mutex4lockEv", Status::NotDecompiled),
+            make_function(0x20, 0x10, "memcpy", Status::NotDecompiled),
+            make_function(0x100010, 0x10, "", Status::NotDecompiled),
+            make_function(0x100020, 0x10, "", Status::Wip),
+            make_function(0x100030, 0x10, "", Status::NotDecompiled),
+        ];
+        assert!(!can_apply_suggestion(&functions[0]));
+        assert!(!can_apply_suggestion(&functions[1]));
+        assert!(can_apply_suggestion(&functions[2]));
+
+        let project_rules =
+            Rules::parse(r#"address_ranges = [["0x7100100030", "0x7100100040"]]"#).unwrap();
+        let edits = get_library_suggestion_edits(&functions, &make_rules(), &project_rules);
+        let addrs: Vec<u64> = edits
+            .iter()
+            .map(|edit| match edit {
+                Edit::SetStatus {
+                    addr,
+                    status: Status::Library,
+                } => *addr,
+                edit => panic!("unexpected edit: {:?}", edit),
+            })
+            .collect();
+        assert_eq!(addrs, [0x100010, 0x100020]);
+    }
+}
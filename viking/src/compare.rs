@@ -0,0 +1,211 @@
+use crate::asm_hashes::ComparisonResult;
+use crate::functions::{self, Info};
+use crate::repo;
+use anyhow::{bail, Context, Result};
+use rayon::prelude::*;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Functions whose diff score is at most this are considered to be nearly identical.
+pub const NEARLY_IDENTICAL_MAX_DIFF_SCORE: f64 = 0.05;
+
+/// Result of comparing a function in a built object against the original.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Comparison {
+    pub result: ComparisonResult,
+    /// Fraction of the function that differs (between 0 and 1, where 0 means that
+    /// the functions match).
+    pub diff_score: f64,
+}
+
+impl Comparison {
+    /// Classifies a diff score (see `NEARLY_IDENTICAL_MAX_DIFF_SCORE`).
+    pub fn from_diff_score(diff_score: f64) -> Self {
+        let result = if diff_score <= 0.0 {
+            ComparisonResult::Identical
+        } else if diff_score <= NEARLY_IDENTICAL_MAX_DIFF_SCORE {
+            ComparisonResult::NearlyIdentical
+        } else {
+            ComparisonResult::Different
+        };
+        Self { result, diff_score }
+    }
+}
+
+/// Compares a function in an object that was built from the decomp source against
+/// the same function in an object that was extracted from the original executable.
+pub trait AssemblyComparer: Send + Sync {
+    fn compare(
+        &self,
+        function: &Info,
+        built_obj_path: &Path,
+        original_obj_path: &Path,
+    ) -> Result<Comparison>;
+}
+
+/// Compares functions with [objdiff](https://github.com/encounter/objdiff)'s command line tool.
+#[derive(Clone, Debug)]
+pub struct ObjdiffComparer {
+    pub objdiff_path: PathBuf,
+}
+
+impl ObjdiffComparer {
+    pub fn new(objdiff_path: &Path) -> Self {
+        Self {
+            objdiff_path: objdiff_path.to_path_buf(),
+        }
+    }
+
+    /// Uses `objdiff_path` from the config (relative to the repo root) if it is set,
+    /// and `objdiff-cli` from the PATH otherwise.
+    pub fn from_config() -> Result<Self> {
+        let objdiff_path = match repo::CONFIG
+            .get("objdiff_path")
+            .and_then(toml::Value::as_str)
+        {
+            Some(path) => repo::get_repo_root()?.join(path),
+            None => PathBuf::from("objdiff-cli"),
+        };
+        Ok(Self { objdiff_path })
+    }
+}
+
+/// Returns the match percentage of the symbol called `name` in objdiff's JSON output.
+///
+/// The output is searched for objects that have a `match_percent` and the symbol name
+/// (either directly or in a `symbol` object), so that this does not depend on the exact
+/// layout of the output, which differs between objdiff versions.
+fn find_match_percent(value: &serde_json::Value, name: &str) -> Option<f64> {
+    match value {
+        serde_json::Value::Object(object) => {
+            let has_name = |object: &serde_json::Map<String, serde_json::Value>| {
+                object.get("name").and_then(serde_json::Value::as_str) == Some(name)
+            };
+            let is_symbol = match object.get("symbol") {
+                Some(serde_json::Value::Object(symbol)) => has_name(symbol),
+                _ => has_name(object),
+            };
+            if is_symbol {
+                if let Some(percent) = object
+                    .get("match_percent")
+                    .and_then(serde_json::Value::as_f64)
+                {
+                    return Some(percent);
+                }
+            }
+            object
+                .values()
+                .find_map(|value| find_match_percent(value, name))
+        }
+        serde_json::Value::Array(array) => array
+            .iter()
+            .find_map(|value| find_match_percent(value, name)),
+        _ => None,
+    }
+}
+
+impl AssemblyComparer for ObjdiffComparer {
+    fn compare(
+        &self,
+        function: &Info,
+        built_obj_path: &Path,
+        original_obj_path: &Path,
+    ) -> Result<Comparison> {
+        let output = Command::new(&self.objdiff_path)
+            .arg("diff")
+            .arg("--target")
+            .arg(original_obj_path)
+            .arg("--base")
+            .arg(built_obj_path)
+            .arg("--format")
+            .arg("json")
+            .arg("--output")
+            .arg("-")
+            .arg(&function.name)
+            .output()
+            .with_context(|| format!("failed to launch {:?}", self.objdiff_path))?;
+
+        if !output.status.success() {
+            bail!(
+                "objdiff failed for {} ({}): {}",
+                function.name,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+
+        let value: serde_json::Value = serde_json::from_slice(&output.stdout)
+            .with_context(|| format!("failed to parse objdiff output for {}", function.name))?;
+        let percent = match find_match_percent(&value, &function.name) {
+            Some(percent) => percent,
+            None => bail!(
+                "objdiff did not report a match percentage for {}",
+                function.name
+            ),
+        };
+
+        let diff_score = (1.0 - percent / 100.0).clamp(0.0, 1.0);
+        Ok(Comparison::from_diff_score(diff_score))
+    }
+}
+
+/// Compares functions in parallel. `get_obj_paths` returns the paths to the built object
+/// and to the original object for a function, or None to skip the function.
+///
+/// Results are returned in the same order as `functions`, with function addresses.
+pub fn compare_all_functions(
+    comparer: &dyn AssemblyComparer,
+    functions: &[Info],
+    get_obj_paths: &(dyn Fn(&Info) -> Option<(PathBuf, PathBuf)> + Sync),
+) -> Vec<(u64, Result<Comparison>)> {
+    functions
+        .par_iter()
+        .filter_map(|function| {
+            let (built_obj_path, original_obj_path) = get_obj_paths(function)?;
+            let result = comparer
+                .compare(function, &built_obj_path, &original_obj_path)
+                .with_context(|| {
+                    format!(
+                        "failed to compare {} ({})",
+                        function.name,
+                        functions::format_addr(function.addr)
+                    )
+                });
+            Some((function.addr, result))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diff_scores_are_classified() {
+        let classify = |diff_score| Comparison::from_diff_score(diff_score).result;
+        assert_eq!(classify(0.0), ComparisonResult::Identical);
+        assert_eq!(classify(0.01), ComparisonResult::NearlyIdentical);
+        assert_eq!(
+            classify(NEARLY_IDENTICAL_MAX_DIFF_SCORE),
+            ComparisonResult::NearlyIdentical
+        );
+        assert_eq!(classify(0.5), ComparisonResult::Different);
+    }
+
+    #[test]
+    fn match_percent_is_found() {
+        let output = serde_json::json!({
+            "left": {
+                "sections": [{
+                    "symbols": [
+                        {"symbol": {"name": "_Z1av"}, "match_percent": 50.0},
+                        {"name": "_Z1bv", "match_percent": 99.5},
+                    ],
+                }],
+            },
+        });
+        assert_eq!(find_match_percent(&output, "_Z1av"), Some(50.0));
+        assert_eq!(find_match_percent(&output, "_Z1bv"), Some(99.5));
+        assert_eq!(find_match_percent(&output, "_Z1cv"), None);
+    }
+}
@@ -0,0 +1,271 @@
+use crate::functions::{self, CsvFormatVersion, Info, Status, WriteOptions};
+use crate::lock::{self, LockOptions};
+use anyhow::{bail, ensure, Context, Result};
+use std::path::Path;
+
+/// Column order of legacy lists that have no header.
+const DEFAULT_COLUMNS: Columns = Columns {
+    addr: 0,
+    status: 1,
+    size: 2,
+    name: 3,
+};
+
+#[derive(Clone, Copy, Debug)]
+struct Columns {
+    addr: usize,
+    status: usize,
+    size: usize,
+    name: usize,
+}
+
+/// Returns the column layout described by a header row, or None if the row is not a header.
+fn parse_header(record: &csv::StringRecord) -> Result<Option<Columns>> {
+    let find = |names: &[&str]| {
+        record.iter().position(|field| {
+            names
+                .iter()
+                .any(|name| field.trim().eq_ignore_ascii_case(name))
+        })
+    };
+
+    let addr = find(&["Address", "Addr", "EA"]);
+    let status = find(&["Quality", "Status"]);
+    let size = find(&["Size"]);
+    let name = find(&["Name"]);
+
+    match (addr, status, size, name) {
+        (None, None, None, None) => Ok(None),
+        (Some(addr), Some(status), Some(size), Some(name)) => Ok(Some(Columns {
+            addr,
+            status,
+            size,
+            name,
+        })),
+        _ => bail!("unrecognised header: {:?}", record),
+    }
+}
+
+/// Returns whether the first record of a file is a legacy header or a legacy entry
+/// (a record with at least 4 fields that starts with an address).
+pub(crate) fn is_legacy_header_or_entry(record: &csv::StringRecord) -> bool {
+    match parse_header(record) {
+        Ok(Some(_)) => true,
+        Ok(None) => {
+            let addr = record.get(DEFAULT_COLUMNS.addr).unwrap_or_default();
+            record.len() >= 4 && functions::parse_address_or_offset(addr.trim()).is_ok()
+        }
+        Err(_) => false,
+    }
+}
+
+/// Maps a legacy status marker onto the current `Status` enum.
+///
+/// The legacy markers are the ones that the Python tools use (see `_markers` in util/utils.py):
+/// `O` (matching), `m` (equivalent), `M` (non-matching), `W` (WIP), `U` (not decompiled) and
+/// `L` (library). The only lossy mapping is `m`: the legacy tools called these functions
+/// "semantically equivalent", and they become `NonMatchingMinor`.
+fn parse_legacy_status(marker: &str) -> Result<Status> {
+    Ok(match marker.trim() {
+        "O" => Status::Matching,
+        "m" => Status::NonMatchingMinor,
+        "M" => Status::NonMatchingMajor,
+        "W" => Status::Wip,
+        "U" => Status::NotDecompiled,
+        "L" => Status::Library,
+        "" => bail!("missing status marker"),
+        marker => bail!("unknown status marker: {:?}", marker),
+    })
+}
+
+fn parse_legacy_size(value: &str) -> Result<u32> {
+    let value = value.trim();
+    match value.strip_prefix("0x") {
+        Some(hex) => Ok(u32::from_str_radix(hex, 16)?),
+        None => Ok(value.parse::<u32>()?),
+    }
+}
+
+fn parse_legacy_entry(record: &csv::StringRecord, columns: Columns) -> Result<Info> {
+    let get = |i: usize| record.get(i).context("missing field");
+
+    Ok(Info {
+        addr: functions::parse_address_or_offset(get(columns.addr)?.trim())?,
+        size: parse_legacy_size(get(columns.size)?)?,
+        name: get(columns.name)?.trim().to_string(),
+        status: parse_legacy_status(get(columns.status)?)?,
+        extra: Default::default(),
+    })
+}
+
+/// Reads a function list in the legacy format (from before commit 1d4c815fbae3).
+///
+/// Columns are found by name if the file has a header (Address, Quality, Size, Name in any
+/// order) and are assumed to be in that order otherwise. Addresses may or may not include
+/// `ADDRESS_BASE`, and sizes may be decimal or hexadecimal. See `parse_legacy_status`
+/// for how statuses are mapped.
+///
+/// The result is in canonical order and can be written in the current format with
+/// `functions::write_functions_to_path`.
+pub fn from_legacy(path: &Path) -> Result<Vec<Info>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .comment(Some(b'#'))
+        .from_path(path)
+        .with_context(|| format!("failed to open {:?}", path))?;
+
+    let mut columns = DEFAULT_COLUMNS;
+    let mut functions = Vec::new();
+    for (i, record) in reader.records().enumerate() {
+        let record = record?;
+        let line = record.position().map_or(0, |position| position.line());
+        if i == 0 {
+            if let Some(header) = parse_header(&record)? {
+                columns = header;
+                continue;
+            }
+        }
+
+        let info = parse_legacy_entry(&record, columns)
+            .with_context(|| format!("failed to parse record at line {}", line))?;
+        functions.push(info);
+    }
+
+    functions::canonicalize(&mut functions);
+    for pair in functions.windows(2) {
+        ensure!(
+            pair[0].addr != pair[1].addr,
+            "found several functions at {}",
+            functions::format_addr(pair[0].addr)
+        );
+    }
+
+    Ok(functions)
+}
+
+/// Converts a function list in the legacy format (`functions::CsvFormatVersion::V1`) to the
+/// current format, in place. Entries are converted as well as the header because legacy lists
+/// can use another column order, other status markers and hexadecimal sizes (see `from_legacy`).
+///
+/// The legacy file is kept as `<name>.csv.bak.N` (see `backup::list_backups`). Lists that are
+/// already in the current format are left untouched. The list is locked during the migration
+/// (see `lock::lock_exclusive`).
+pub fn migrate_csv_v1_header(csv_path: &Path) -> Result<()> {
+    let _lock = lock::lock_exclusive(csv_path, &LockOptions::default())?;
+    match functions::detect_csv_format_version(csv_path)? {
+        CsvFormatVersion::V1 => {}
+        CsvFormatVersion::V2 => return Ok(()),
+        CsvFormatVersion::Unknown => bail!("{:?} is not a function list", csv_path),
+    }
+    let functions = from_legacy(csv_path)
+        .with_context(|| format!("failed to read legacy function list {:?}", csv_path))?;
+    // The current reader cannot compare against the legacy file, and no rows are removed
+    // anyway. Existing backups are kept: the legacy file might be the only copy of the old data.
+    let options = WriteOptions {
+        allow_shrink: true,
+        ..WriteOptions::from_config()
+    };
+    functions::write_functions_with_backup_ex(csv_path, &functions, usize::MAX, &options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, TempDir};
+
+    /// Converts a legacy list and returns the result in the current format.
+    fn convert(legacy: &str) -> Result<String> {
+        let dir = TempDir::new("convert_legacy");
+        let path = dir.join("legacy.csv");
+        std::fs::write(&path, legacy).unwrap();
+        let functions = from_legacy(&path)?;
+        let converted_path = dir.join("functions.csv");
+        functions::write_functions_to_path_ex(
+            &converted_path,
+            &functions,
+            &WriteOptions::default(),
+        )?;
+        Ok(std::fs::read_to_string(&converted_path).unwrap())
+    }
+
+    #[test]
+    fn legacy_lists_are_converted() {
+        let expected = include_str!("../tests/snapshots/legacy_functions_converted.csv");
+        assert_eq!(
+            convert(include_str!("../tests/fixtures/legacy_functions.csv")).unwrap(),
+            expected
+        );
+        assert_eq!(
+            convert(include_str!(
+                "../tests/fixtures/legacy_functions_with_header.csv"
+            ))
+            .unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn legacy_status_markers() {
+        assert_eq!(parse_legacy_status("O").unwrap(), Status::Matching);
+        assert_eq!(parse_legacy_status("m").unwrap(), Status::NonMatchingMinor);
+        assert_eq!(parse_legacy_status("M").unwrap(), Status::NonMatchingMajor);
+        assert_eq!(parse_legacy_status(" W ").unwrap(), Status::Wip);
+        assert_eq!(parse_legacy_status("U").unwrap(), Status::NotDecompiled);
+        assert_eq!(parse_legacy_status("L").unwrap(), Status::Library);
+        assert!(parse_legacy_status("").is_err());
+        assert!(parse_legacy_status("?").is_err());
+    }
+
+    #[test]
+    fn unknown_legacy_markers_are_rejected() {
+        let err =
+            convert("0x0000007100000010,O,16,_Z1av\n0x0000007100000020,E,16,_Z1bv\n").unwrap_err();
+        assert_eq!(
+            format!("{:#}", err),
+            "failed to parse record at line 2: unknown status marker: \"E\""
+        );
+    }
+
+    const LEGACY_LIST: &str = "0x0000007100000010,O,16,_Z1av\n0x0000007100000020,U,0x20,\n";
+
+    #[test]
+    fn legacy_lists_are_detected_when_reading() {
+        let dir = TempDir::new("convert_detect");
+        let path = dir.join("functions.csv");
+        std::fs::write(&path, LEGACY_LIST).unwrap();
+        assert_eq!(
+            functions::detect_csv_format_version(&path).unwrap(),
+            CsvFormatVersion::V1
+        );
+        let err = functions::get_functions_for_path(&path).unwrap_err();
+        assert!(
+            format!("{:#}", err).contains("migrate_csv_v1_header"),
+            "{:#}",
+            err
+        );
+    }
+
+    #[test]
+    fn migration_keeps_existing_backups() {
+        testing::use_test_config();
+        let dir = TempDir::new("convert_migrate");
+        let path = dir.join("functions.csv");
+        std::fs::write(dir.join("functions.csv.bak.1"), "old 1").unwrap();
+        std::fs::write(dir.join("functions.csv.bak.2"), "old 2").unwrap();
+        std::fs::write(&path, LEGACY_LIST).unwrap();
+
+        migrate_csv_v1_header(&path).unwrap();
+        let functions = functions::get_functions_for_path(&path).unwrap();
+        assert_eq!(functions.len(), 2);
+        assert_eq!(functions[1].size, 0x20);
+
+        let backups = crate::backup::list_backups(&path);
+        assert_eq!(backups.len(), 3);
+        assert_eq!(std::fs::read_to_string(&backups[2]).unwrap(), LEGACY_LIST);
+
+        // Lists in the current format are left untouched.
+        migrate_csv_v1_header(&path).unwrap();
+        assert_eq!(crate::backup::list_backups(&path).len(), 3);
+    }
+}
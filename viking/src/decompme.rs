@@ -0,0 +1,529 @@
+use crate::functions::{self, DemangledIndex, Info, Status};
+use crate::remote::RemoteError;
+use anyhow::{bail, Context, Result};
+use rustc_hash::FxHashMap;
+use std::time::Duration;
+
+/// Scratches that are at least this close to matching are worth porting.
+pub const DEFAULT_MIN_MATCH_PERCENT: f64 = 95.0;
+
+/// A scratch on decomp.me.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Scratch {
+    pub slug: String,
+    /// Name of the function, as entered by the owner of the scratch (mangled or not).
+    pub name: String,
+    /// Number of differences (0 for a match, negative if the scratch does not compile).
+    pub score: i64,
+    /// Score of an empty function.
+    pub max_score: i64,
+}
+
+impl Scratch {
+    /// Returns how close the scratch is to matching, from 0 to 100.
+    pub fn match_percent(&self) -> f64 {
+        if self.score < 0 {
+            return 0.0;
+        }
+        if self.max_score <= 0 {
+            return if self.score == 0 { 100.0 } else { 0.0 };
+        }
+        ((1.0 - self.score as f64 / self.max_score as f64) * 100.0).max(0.0)
+    }
+}
+
+/// A page of scratches, as returned by the decomp.me API.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ScratchPage {
+    pub scratches: Vec<Scratch>,
+    /// URL of the next page, if there is one.
+    pub next: Option<String>,
+}
+
+/// Parses a page of the scratch list of the decomp.me API:
+///
+/// ```json
+/// {"next": "https://decomp.me/api/scratch?page=2", "results": [
+///     {"slug": "AbCdE", "name": "_ZN4ksys3act8BaseProc4initEv", "score": 12, "max_score": 400}
+/// ]}
+/// ```
+pub fn parse_scratch_page(json: &str) -> Result<ScratchPage> {
+    let value: serde_json::Value = serde_json::from_str(json)?;
+
+    let next = match value.get("next") {
+        None | Some(serde_json::Value::Null) => None,
+        Some(next) => Some(next.as_str().context("next must be a string")?.to_string()),
+    };
+
+    let results = value
+        .get("results")
+        .and_then(serde_json::Value::as_array)
+        .context("missing results")?;
+    let scratches = results
+        .iter()
+        .map(|result| {
+            let get_str = |key: &str| {
+                result
+                    .get(key)
+                    .and_then(serde_json::Value::as_str)
+                    .with_context(|| format!("scratch {} must be a string", key))
+            };
+            let get_int = |key: &str| {
+                result
+                    .get(key)
+                    .and_then(serde_json::Value::as_i64)
+                    .with_context(|| format!("scratch {} must be an integer", key))
+            };
+            Ok(Scratch {
+                slug: get_str("slug")?.to_string(),
+                name: get_str("name")?.to_string(),
+                score: get_int("score")?,
+                max_score: get_int("max_score")?,
+            })
+        })
+        .collect::<Result<_>>()?;
+
+    Ok(ScratchPage { scratches, next })
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct AuditReport<'a> {
+    /// Scratches that match at least `AuditOptions::min_match_percent` while the function
+    /// is still undecompiled or WIP, sorted by match percentage (highest first).
+    pub unported: Vec<(Scratch, &'a Info)>,
+    /// WIP functions that no scratch was found for, in list order.
+    pub wip_without_scratch: Vec<&'a Info>,
+    /// Scratches that could not be matched to exactly one function.
+    pub unknown_scratches: Vec<Scratch>,
+}
+
+#[derive(Clone, Debug)]
+pub struct AuditOptions {
+    pub min_match_percent: f64,
+    /// Maximum number of pages to fetch, as a safeguard against pagination loops.
+    pub max_pages: usize,
+    /// Minimum delay between two requests.
+    pub request_interval: Duration,
+    /// How many times a request is retried after a rate limiting response (HTTP 429).
+    pub max_retries: u32,
+    /// How long to wait after a rate limiting response without a `Retry-After` header.
+    pub default_retry_delay: Duration,
+    pub timeout: Duration,
+}
+
+impl Default for AuditOptions {
+    fn default() -> Self {
+        Self {
+            min_match_percent: DEFAULT_MIN_MATCH_PERCENT,
+            max_pages: 1000,
+            request_interval: Duration::from_millis(500),
+            max_retries: 5,
+            default_retry_delay: Duration::from_secs(10),
+            timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Finds the functions that scratches are for. Built once per audit so that looking up
+/// a scratch does not scan the whole function list.
+struct ScratchFunctionMap<'a> {
+    by_name: FxHashMap<&'a str, usize>,
+    /// Every trailing sequence of qualified name components of each demangled name
+    /// (e.g. `calc`, `BaseProc::calc` and `act::BaseProc::calc`), or None if several
+    /// functions share it.
+    by_components: FxHashMap<Vec<&'a str>, Option<usize>>,
+}
+
+impl<'a> ScratchFunctionMap<'a> {
+    fn build(functions: &'a [Info], index: &'a DemangledIndex) -> Self {
+        let by_name = functions
+            .iter()
+            .enumerate()
+            .filter(|(_, info)| !info.name.is_empty())
+            .map(|(i, info)| (info.name.as_str(), i))
+            .collect();
+
+        let mut by_components: FxHashMap<Vec<&str>, Option<usize>> = FxHashMap::default();
+        for i in 0..functions.len() {
+            let components = match index.get_demangled(i) {
+                Some(demangled) => functions::split_qualified_name(demangled),
+                None => continue,
+            };
+            for start in 0..components.len() {
+                by_components
+                    .entry(components[start..].to_vec())
+                    .and_modify(|entry| *entry = None)
+                    .or_insert(Some(i));
+            }
+        }
+
+        Self {
+            by_name,
+            by_components,
+        }
+    }
+
+    /// Returns the index of the function that a scratch is for: the function with the same
+    /// name, or else the only function whose demangled name ends with the scratch name
+    /// (see `functions::is_anchored_name_match`).
+    fn find(&self, name: &str) -> Option<usize> {
+        if let Some(&i) = self.by_name.get(name) {
+            return Some(i);
+        }
+        let components = functions::split_qualified_name(name);
+        self.by_components.get(&components).copied().flatten()
+    }
+}
+
+/// Cross-references scratches with the function list. This does not access the network.
+pub fn audit_scratches<'a>(
+    functions: &'a [Info],
+    scratches: &[Scratch],
+    options: &AuditOptions,
+) -> AuditReport<'a> {
+    let index = DemangledIndex::build(functions);
+    let function_map = ScratchFunctionMap::build(functions, &index);
+
+    let mut report = AuditReport::default();
+    let mut has_scratch = vec![false; functions.len()];
+    for scratch in scratches {
+        let i = match function_map.find(&scratch.name) {
+            Some(i) => i,
+            None => {
+                report.unknown_scratches.push(scratch.clone());
+                continue;
+            }
+        };
+        has_scratch[i] = true;
+
+        let info = &functions[i];
+        let is_unported = matches!(info.status, Status::NotDecompiled | Status::Wip);
+        if is_unported && scratch.match_percent() >= options.min_match_percent {
+            report.unported.push((scratch.clone(), info));
+        }
+    }
+
+    report.unported.sort_by(|(a, _), (b, _)| {
+        b.match_percent()
+            .partial_cmp(&a.match_percent())
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+    report.wip_without_scratch = functions
+        .iter()
+        .zip(&has_scratch)
+        .filter(|(info, &has_scratch)| info.status == Status::Wip && !has_scratch)
+        .map(|(info, _)| info)
+        .collect();
+    report
+}
+
+/// Percent-encodes a query string value (everything but unreserved characters is escaped).
+fn encode_query_value(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for &byte in value.as_bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~') {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+/// Returns the URL of the first page of the scratches of a project.
+fn get_scratch_list_url(api_base: &str, project_slug: &str) -> String {
+    format!(
+        "{}/scratch?project={}&page_size=100",
+        api_base.trim_end_matches('/'),
+        encode_query_value(project_slug)
+    )
+}
+
+/// Fetches `url`, waiting and retrying if the server asks to slow down.
+fn fetch_page(agent: &ureq::Agent, url: &str, options: &AuditOptions) -> Result<String> {
+    let mut retries = 0;
+    loop {
+        match agent.get(url).call() {
+            Ok(response) => {
+                return response.into_string().map_err(|err| {
+                    RemoteError::Network {
+                        url: url.to_string(),
+                        message: err.to_string(),
+                    }
+                    .into()
+                })
+            }
+            Err(ureq::Error::Status(429, response)) if retries < options.max_retries => {
+                let delay = response
+                    .header("Retry-After")
+                    .and_then(|value| value.trim().parse::<u64>().ok())
+                    .map(Duration::from_secs)
+                    .unwrap_or(options.default_retry_delay);
+                std::thread::sleep(delay);
+                retries += 1;
+            }
+            Err(ureq::Error::Status(status, _)) => {
+                return Err(RemoteError::Http {
+                    url: url.to_string(),
+                    status,
+                }
+                .into())
+            }
+            Err(ureq::Error::Transport(err)) => {
+                return Err(RemoteError::Network {
+                    url: url.to_string(),
+                    message: err.to_string(),
+                }
+                .into())
+            }
+        }
+    }
+}
+
+/// Fetches every scratch of a project from the decomp.me API at `api_base`
+/// (e.g. `https://decomp.me/api`), following pagination.
+///
+/// Download failures are reported as `remote::RemoteError`s.
+pub fn fetch_scratches(
+    api_base: &str,
+    project_slug: &str,
+    options: &AuditOptions,
+) -> Result<Vec<Scratch>> {
+    let agent = ureq::AgentBuilder::new().timeout(options.timeout).build();
+    fetch_all_pages(
+        &get_scratch_list_url(api_base, project_slug),
+        project_slug,
+        options,
+        |url| fetch_page(&agent, url, options),
+    )
+}
+
+/// Fetches the page at `first_url` and every page after it with `fetch`.
+fn fetch_all_pages(
+    first_url: &str,
+    project_slug: &str,
+    options: &AuditOptions,
+    mut fetch: impl FnMut(&str) -> Result<String>,
+) -> Result<Vec<Scratch>> {
+    let mut scratches = Vec::new();
+    let mut url = Some(first_url.to_string());
+    let mut num_pages = 0;
+    while let Some(page_url) = url {
+        if num_pages == options.max_pages {
+            bail!(
+                "{} has more than {} pages of scratches",
+                project_slug,
+                options.max_pages
+            );
+        }
+        if num_pages != 0 {
+            std::thread::sleep(options.request_interval);
+        }
+
+        let page = parse_scratch_page(&fetch(&page_url)?)
+            .with_context(|| format!("failed to parse scratch list from {}", page_url))?;
+        scratches.extend(page.scratches);
+        url = page.next;
+        num_pages += 1;
+    }
+    Ok(scratches)
+}
+
+/// Same as `audit_ex`, with default options.
+pub fn audit<'a>(
+    functions: &'a [Info],
+    api_base: &str,
+    project_slug: &str,
+) -> Result<AuditReport<'a>> {
+    audit_ex(functions, api_base, project_slug, &AuditOptions::default())
+}
+
+/// Fetches the scratches of a project from decomp.me (see `fetch_scratches`) and
+/// cross-references them with the function list (see `audit_scratches`).
+pub fn audit_ex<'a>(
+    functions: &'a [Info],
+    api_base: &str,
+    project_slug: &str,
+    options: &AuditOptions,
+) -> Result<AuditReport<'a>> {
+    let scratches = fetch_scratches(api_base, project_slug, options)?;
+    Ok(audit_scratches(functions, &scratches, options))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::make_function;
+
+    fn make_scratch(slug: &str, name: &str, score: i64) -> Scratch {
+        Scratch {
+            slug: slug.to_string(),
+            name: name.to_string(),
+            score,
+            max_score: 400,
+        }
+    }
+
+    fn get_names<'a>(functions: impl IntoIterator<Item = &'a Info>) -> Vec<&'a str> {
+        functions
+            .into_iter()
+            .map(|info| info.name.as_str())
+            .collect()
+    }
+
+    fn get_slugs(scratches: &[Scratch]) -> Vec<&str> {
+        scratches
+            .iter()
+            .map(|scratch| scratch.slug.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn pages_are_followed() {
+        let pages = [
+            (
+                "https://decomp.me/api/scratch?project=botw&page_size=100",
+                r#"{"next": "https://decomp.me/api/scratch?page=2", "results": [
+                    {"slug": "aaaaa", "name": "_ZN4ksys3act8BaseProc4initEv", "score": 0, "max_score": 400}
+                ]}"#,
+            ),
+            (
+                "https://decomp.me/api/scratch?page=2",
+                r#"{"next": null, "results": [
+                    {"slug": "bbbbb", "name": "BaseProc::calc", "score": 20, "max_score": 400}
+                ]}"#,
+            ),
+        ];
+        let options = AuditOptions {
+            request_interval: Duration::from_secs(0),
+            ..Default::default()
+        };
+
+        let mut requested = Vec::new();
+        let url = get_scratch_list_url("https://decomp.me/api/", "botw");
+        let scratches = fetch_all_pages(&url, "botw", &options, |url| {
+            requested.push(url.to_string());
+            let (_, json) = pages.iter().find(|(page_url, _)| *page_url == url).unwrap();
+            Ok(json.to_string())
+        })
+        .unwrap();
+
+        assert_eq!(requested, [pages[0].0, pages[1].0]);
+        assert_eq!(get_slugs(&scratches), ["aaaaa", "bbbbb"]);
+        assert_eq!(scratches[1].match_percent(), 95.0);
+    }
+
+    #[test]
+    fn pagination_loops_are_stopped() {
+        let options = AuditOptions {
+            request_interval: Duration::from_secs(0),
+            max_pages: 3,
+            ..Default::default()
+        };
+        let mut num_requests = 0;
+        let result = fetch_all_pages("https://decomp.me/api/loop", "botw", &options, |_| {
+            num_requests += 1;
+            Ok(r#"{"next": "https://decomp.me/api/loop", "results": []}"#.to_string())
+        });
+        assert!(result.is_err());
+        assert_eq!(num_requests, 3);
+    }
+
+    #[test]
+    fn project_slugs_are_encoded() {
+        assert_eq!(
+            get_scratch_list_url("https://decomp.me/api", "a b&c=d/é"),
+            "https://decomp.me/api/scratch?project=a%20b%26c%3Dd%2F%C3%A9&page_size=100"
+        );
+        assert_eq!(
+            get_scratch_list_url("https://decomp.me/api", "botw-1.5.0_x~y"),
+            "https://decomp.me/api/scratch?project=botw-1.5.0_x~y&page_size=100"
+        );
+    }
+
+    #[test]
+    fn scratches_are_matched_to_functions() {
+        let functions = vec![
+            make_function(
+                0x10,
+                0x10,
+                "_ZN4ksys3act8BaseProc4initEv",
+                Status::NotDecompiled,
+            ),
+            make_function(
+                0x20,
+                0x10,
+                "_ZN4ksys3act19BaseProcInitializer8initImplEv",
+                Status::Wip,
+            ),
+            make_function(
+                0x30,
+                0x10,
+                "_ZN4ksys3act8BaseProc4calcEv",
+                Status::NotDecompiled,
+            ),
+            make_function(
+                0x40,
+                0x10,
+                "_ZN4ksys3gdt8BaseProc4calcEv",
+                Status::NotDecompiled,
+            ),
+            make_function(0x50, 0x10, "_ZN4ksys3act8BaseProc5startEv", Status::Wip),
+            make_function(0x60, 0x10, "_ZN4ksys3act8BaseProc4stopEv", Status::Matching),
+            make_function(0x70, 0x10, "_ZN4ksys3act8BaseProc6resumeEv", Status::Wip),
+        ];
+        let scratches = vec![
+            // Exact match.
+            make_scratch("exact", "_ZN4ksys3act8BaseProc4initEv", 0),
+            // Anchored match: BaseProcInitializer::initImpl is not a candidate.
+            make_scratch("anchored", "act::BaseProc::start", 8),
+            // Ambiguous: act::BaseProc::calc and gdt::BaseProc::calc.
+            make_scratch("ambiguous", "BaseProc::calc", 0),
+            make_scratch("missing", "BaseProc::pause", 0),
+            // Already matching.
+            make_scratch("matching", "BaseProc::stop", 0),
+            // Too far from matching to be listed, but the function has a scratch.
+            make_scratch("far", "BaseProc::resume", 200),
+        ];
+
+        let report = audit_scratches(&functions, &scratches, &AuditOptions::default());
+        let unported: Vec<(&str, &str)> = report
+            .unported
+            .iter()
+            .map(|(scratch, info)| (scratch.slug.as_str(), info.name.as_str()))
+            .collect();
+        assert_eq!(
+            unported,
+            [
+                ("exact", "_ZN4ksys3act8BaseProc4initEv"),
+                ("anchored", "_ZN4ksys3act8BaseProc5startEv"),
+            ]
+        );
+        assert_eq!(
+            get_slugs(&report.unknown_scratches),
+            ["ambiguous", "missing"]
+        );
+        assert_eq!(
+            get_names(report.wip_without_scratch.iter().copied()),
+            ["_ZN4ksys3act19BaseProcInitializer8initImplEv"]
+        );
+    }
+
+    #[test]
+    fn anchored_matches_do_not_match_partial_components() {
+        let functions = vec![make_function(
+            0x10,
+            0x10,
+            "_ZN4ksys3act19BaseProcInitializer8initImplEv",
+            Status::Wip,
+        )];
+        let scratches = vec![make_scratch("partial", "BaseProc::init", 0)];
+        let report = audit_scratches(&functions, &scratches, &AuditOptions::default());
+        assert!(report.unported.is_empty());
+        assert_eq!(get_slugs(&report.unknown_scratches), ["partial"]);
+        assert_eq!(
+            get_names(report.wip_without_scratch.iter().copied()),
+            ["_ZN4ksys3act19BaseProcInitializer8initImplEv"]
+        );
+    }
+}
@@ -0,0 +1,436 @@
+use crate::check_results;
+use crate::elf;
+use crate::file_utils;
+use crate::functions::{self, Info, Status};
+use crate::lint::{self, Severity};
+use crate::repo;
+use std::path::{Path, PathBuf};
+
+/// Maximum number of matching functions whose symbols are looked up in the decomp executable.
+pub const DOCTOR_SAMPLE_SIZE: usize = 200;
+
+/// Keys that must be present in the config for the tools to work at all.
+const REQUIRED_CONFIG_KEYS: &[&str] = &["functions_csv", "build_target"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FindingStatus {
+    Pass,
+    Warn,
+    Fail,
+    /// The check could not run because an earlier check failed.
+    Skip,
+}
+
+impl FindingStatus {
+    pub fn description(&self) -> &'static str {
+        match self {
+            FindingStatus::Pass => "pass",
+            FindingStatus::Warn => "warn",
+            FindingStatus::Fail => "fail",
+            FindingStatus::Skip => "skip",
+        }
+    }
+}
+
+/// Result of a single `doctor` check.
+#[derive(Clone, Debug)]
+pub struct Finding {
+    /// Short name of the check, e.g. "config".
+    pub check: &'static str,
+    pub status: FindingStatus,
+    pub message: String,
+    /// How to fix the problem, for warnings and failures.
+    pub hint: Option<String>,
+}
+
+impl Finding {
+    fn pass(check: &'static str, message: String) -> Self {
+        Self {
+            check,
+            status: FindingStatus::Pass,
+            message,
+            hint: None,
+        }
+    }
+
+    fn warn(check: &'static str, message: String, hint: &str) -> Self {
+        Self {
+            check,
+            status: FindingStatus::Warn,
+            message,
+            hint: Some(hint.to_string()),
+        }
+    }
+
+    fn fail(check: &'static str, message: String, hint: &str) -> Self {
+        Self {
+            check,
+            status: FindingStatus::Fail,
+            message,
+            hint: Some(hint.to_string()),
+        }
+    }
+
+    fn skip(check: &'static str, reason: &str) -> Self {
+        Self {
+            check,
+            status: FindingStatus::Skip,
+            message: format!("skipped because {}", reason),
+            hint: None,
+        }
+    }
+}
+
+impl std::fmt::Display for Finding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "[{}] {}: {}",
+            self.status.description(),
+            self.check,
+            self.message
+        )?;
+        if let Some(hint) = &self.hint {
+            write!(f, "\n       hint: {}", hint)?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns whether any finding is a failure (or a check that was skipped because of one).
+pub fn has_failures(findings: &[Finding]) -> bool {
+    findings
+        .iter()
+        .any(|finding| finding.status >= FindingStatus::Fail)
+}
+
+/// Renders findings as a JSON array, for setup scripts.
+pub fn render_findings_json(findings: &[Finding]) -> String {
+    let findings: Vec<serde_json::Value> = findings
+        .iter()
+        .map(|finding| {
+            serde_json::json!({
+                "check": finding.check,
+                "status": finding.status.description(),
+                "message": finding.message,
+                "hint": finding.hint,
+            })
+        })
+        .collect();
+    serde_json::Value::Array(findings).to_string()
+}
+
+/// Checks that the project is set up correctly, in order: repo root, config, function list,
+/// base executable, decomp executable and the cache directory. Checks that depend on a check
+/// that failed are skipped.
+///
+/// Nothing is modified, and expensive checks only look at a sample of the functions
+/// (see `DOCTOR_SAMPLE_SIZE`) so that this finishes in a few seconds.
+pub fn run() -> Vec<Finding> {
+    let mut findings = Vec::new();
+
+    let root = match repo::get_repo_root() {
+        Ok(root) => {
+            findings.push(Finding::pass("repo root", format!("{:?}", root)));
+            root
+        }
+        Err(err) => {
+            findings.push(Finding::fail(
+                "repo root",
+                err.to_string(),
+                "run this command inside the decomp repo (the directory that contains data/ and src/)",
+            ));
+            for check in &[
+                "config",
+                "function list",
+                "base executable",
+                "decomp executable",
+                "cache directory",
+            ] {
+                findings.push(Finding::skip(check, "the repo root was not found"));
+            }
+            return findings;
+        }
+    };
+
+    // The config is parsed here rather than through repo::CONFIG, which panics on errors.
+    let config = check_config(&root, &mut findings);
+
+    let functions = match &config {
+        Some(config) => check_function_list(&root, config, &mut findings),
+        None => {
+            findings.push(Finding::skip("function list", "the config is invalid"));
+            None
+        }
+    };
+
+    check_base_executable(&root, config.as_ref(), &mut findings);
+
+    match (&config, &functions) {
+        (Some(config), Some(functions)) => {
+            check_decomp_executable(&root, config, functions, &mut findings)
+        }
+        _ => findings.push(Finding::skip(
+            "decomp executable",
+            "the config or the function list is invalid",
+        )),
+    }
+
+    check_cache_directory(&root, &mut findings);
+    findings
+}
+
+fn check_config(root: &Path, findings: &mut Vec<Finding>) -> Option<toml::Value> {
+    const CHECK: &str = "config";
+    let path = root.join("tools/config.toml");
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(err) => {
+            findings.push(Finding::fail(
+                CHECK,
+                format!("failed to read {:?}: {}", path, err),
+                "create tools/config.toml (see the project's setup instructions)",
+            ));
+            return None;
+        }
+    };
+    let config: toml::Value = match contents.parse() {
+        Ok(config) => config,
+        Err(err) => {
+            findings.push(Finding::fail(
+                CHECK,
+                format!("failed to parse {:?}: {}", path, err),
+                "fix the TOML syntax error",
+            ));
+            return None;
+        }
+    };
+
+    let missing: Vec<&str> = REQUIRED_CONFIG_KEYS
+        .iter()
+        .copied()
+        .filter(|key| config.get(key).and_then(toml::Value::as_str).is_none())
+        .collect();
+    if !missing.is_empty() {
+        findings.push(Finding::fail(
+            CHECK,
+            format!("missing or invalid keys: {}", missing.join(", ")),
+            "set these keys to strings in tools/config.toml",
+        ));
+        return None;
+    }
+
+    findings.push(Finding::pass(CHECK, format!("{:?}", path)));
+    Some(config)
+}
+
+fn get_config_str<'a>(config: &'a toml::Value, key: &str) -> &'a str {
+    // Required keys have already been checked.
+    config[key].as_str().unwrap()
+}
+
+fn check_function_list(
+    root: &Path,
+    config: &toml::Value,
+    findings: &mut Vec<Finding>,
+) -> Option<Vec<Info>> {
+    const CHECK: &str = "function list";
+    let path = root.join(get_config_str(config, "functions_csv"));
+    if !path.is_file() {
+        findings.push(Finding::fail(
+            CHECK,
+            format!("{:?} does not exist", path),
+            "check functions_csv in the config",
+        ));
+        return None;
+    }
+
+    let functions = match functions::get_functions_for_path(&path) {
+        Ok(functions) => functions,
+        Err(err) => {
+            findings.push(Finding::fail(
+                CHECK,
+                format!("failed to parse {:?}: {:#}", path, err),
+                "fix the entry that is mentioned in the error (or restore the file from git)",
+            ));
+            return None;
+        }
+    };
+
+    let issues = lint::validate_all(&functions);
+    let num_errors = issues
+        .iter()
+        .filter(|issue| issue.severity == Severity::Error)
+        .count();
+    let num_warnings = issues.len() - num_errors;
+    let message = format!(
+        "{} functions, {} lint errors, {} lint warnings",
+        functions.len(),
+        num_errors,
+        num_warnings
+    );
+    findings.push(if num_errors != 0 {
+        Finding::fail(CHECK, message, "see lint::validate_all for details")
+    } else if num_warnings != 0 {
+        Finding::warn(CHECK, message, "see lint::validate_all for details")
+    } else {
+        Finding::pass(CHECK, message)
+    });
+    Some(functions)
+}
+
+fn check_base_executable(root: &Path, config: Option<&toml::Value>, findings: &mut Vec<Finding>) {
+    const CHECK: &str = "base executable";
+    let path = root.join("data").join("main.elf");
+    let bytes = match std::fs::read(&path) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            findings.push(Finding::fail(
+                CHECK,
+                format!("failed to read {:?}: {}", path, err),
+                "extract the original executable and convert it to an ELF (data/main.elf)",
+            ));
+            return;
+        }
+    };
+    if let Err(err) = elf::load_elf(&path) {
+        findings.push(Finding::fail(
+            CHECK,
+            format!("failed to load {:?}: {:#}", path, err),
+            "data/main.elf must be the original executable converted to an ELF",
+        ));
+        return;
+    }
+
+    let hash = check_results::hash_binary(&bytes);
+    let expected = config
+        .and_then(|config| config.get("base_binary_hash"))
+        .and_then(toml::Value::as_str);
+    findings.push(match expected {
+        None => Finding::warn(
+            CHECK,
+            format!("{:?} has hash {:#018x}", path, hash),
+            "set base_binary_hash in the config to verify the executable",
+        ),
+        Some(expected) => match functions::parse_hex_u64(expected) {
+            Ok(expected) if expected == hash => {
+                Finding::pass(CHECK, format!("{:?} (hash {:#018x})", path, hash))
+            }
+            Ok(expected) => Finding::fail(
+                CHECK,
+                format!(
+                    "{:?} has hash {:#018x}, expected {:#018x}",
+                    path, hash, expected
+                ),
+                "the executable is for a different version; dump the version the project targets",
+            ),
+            Err(_) => Finding::fail(
+                CHECK,
+                format!("invalid base_binary_hash in the config: {}", expected),
+                "base_binary_hash must be a hexadecimal string",
+            ),
+        },
+    });
+}
+
+/// Returns up to `n` evenly spaced matching functions.
+fn sample_matching_functions(functions: &[Info], n: usize) -> Vec<&Info> {
+    let matching: Vec<&Info> = functions
+        .iter()
+        .filter(|info| info.status == Status::Matching && !info.name.is_empty())
+        .collect();
+    if matching.len() <= n {
+        return matching;
+    }
+    (0..n).map(|i| matching[i * matching.len() / n]).collect()
+}
+
+fn check_decomp_executable(
+    root: &Path,
+    config: &toml::Value,
+    functions: &[Info],
+    findings: &mut Vec<Finding>,
+) {
+    const CHECK: &str = "decomp executable";
+    let path = root
+        .join("build")
+        .join(get_config_str(config, "build_target"));
+    let elf = match elf::load_elf(&path) {
+        Ok(elf) => elf,
+        Err(err) => {
+            findings.push(Finding::fail(
+                CHECK,
+                format!("failed to load {:?}: {:#}", path, err),
+                "build the project (and check build_target in the config)",
+            ));
+            return;
+        }
+    };
+    let symbols = match elf::make_symbol_map_by_name(&elf) {
+        Ok(symbols) => symbols,
+        Err(err) => {
+            findings.push(Finding::fail(
+                CHECK,
+                format!("failed to read the symbols of {:?}: {:#}", path, err),
+                "rebuild the project",
+            ));
+            return;
+        }
+    };
+
+    let sample = sample_matching_functions(functions, DOCTOR_SAMPLE_SIZE);
+    let missing: Vec<&str> = sample
+        .iter()
+        .filter(|info| !symbols.contains_key(info.name.as_str()))
+        .map(|info| info.name.as_str())
+        .collect();
+    findings.push(if missing.is_empty() {
+        Finding::pass(
+            CHECK,
+            format!(
+                "{:?} ({} sampled matching functions found)",
+                path,
+                sample.len()
+            ),
+        )
+    } else {
+        Finding::fail(
+            CHECK,
+            format!(
+                "{} of {} sampled matching functions are missing from {:?}, e.g. {}",
+                missing.len(),
+                sample.len(),
+                path,
+                missing[0]
+            ),
+            "rebuild the project; if that does not help, the function list or the build is out of date",
+        )
+    });
+}
+
+fn check_cache_directory(root: &Path, findings: &mut Vec<Finding>) {
+    const CHECK: &str = "cache directory";
+    let dir = file_utils::get_local_dir(root);
+    // The directory is created on demand, so check the closest directory that exists.
+    // Only the permission bits are checked: nothing is written.
+    let existing: PathBuf = if dir.is_dir() {
+        dir.clone()
+    } else {
+        root.to_path_buf()
+    };
+    findings.push(match std::fs::metadata(&existing) {
+        Ok(metadata) if !metadata.permissions().readonly() => {
+            Finding::pass(CHECK, format!("{:?} is writable", dir))
+        }
+        Ok(_) => Finding::fail(
+            CHECK,
+            format!("{:?} is read-only", existing),
+            "make the directory writable (backups and caches are stored there)",
+        ),
+        Err(err) => Finding::fail(
+            CHECK,
+            format!("failed to access {:?}: {}", existing, err),
+            "make sure the repo is accessible",
+        ),
+    });
+}
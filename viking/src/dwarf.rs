@@ -0,0 +1,156 @@
+use crate::functions::Info;
+use anyhow::Result;
+use gimli::{AttributeValue, EndianSlice, LittleEndian};
+use goblin::elf::Elf;
+use rustc_hash::FxHashMap;
+
+type Reader<'a> = EndianSlice<'a, LittleEndian>;
+
+/// A function (`DW_TAG_subprogram`) from DWARF debug info.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DwarfFunctionRange {
+    pub low_pc: u64,
+    pub high_pc: u64,
+    /// Linkage (mangled) name if available, plain name otherwise.
+    pub name: Option<String>,
+}
+
+impl DwarfFunctionRange {
+    pub fn size(&self) -> u64 {
+        self.high_pc.saturating_sub(self.low_pc)
+    }
+}
+
+fn get_attr_string(
+    dwarf: &gimli::Dwarf<Reader>,
+    unit: &gimli::Unit<Reader>,
+    value: AttributeValue<Reader>,
+) -> Result<String> {
+    Ok(dwarf
+        .attr_string(unit, value)?
+        .to_string_lossy()
+        .into_owned())
+}
+
+/// Returns the name of a subprogram, following declarations for out-of-line definitions
+/// and abstract origins for concrete instances of inline functions.
+fn get_function_name(
+    dwarf: &gimli::Dwarf<Reader>,
+    unit: &gimli::Unit<Reader>,
+    entry: &gimli::DebuggingInformationEntry<Reader>,
+    depth: usize,
+) -> Result<Option<String>> {
+    for attr in [gimli::DW_AT_linkage_name, gimli::DW_AT_MIPS_linkage_name] {
+        if let Some(value) = entry.attr_value(attr)? {
+            return Ok(Some(get_attr_string(dwarf, unit, value)?));
+        }
+    }
+
+    if depth < 8 {
+        for attr in [gimli::DW_AT_specification, gimli::DW_AT_abstract_origin] {
+            if let Some(AttributeValue::UnitRef(offset)) = entry.attr_value(attr)? {
+                let origin = unit.entry(offset)?;
+                if let Some(name) = get_function_name(dwarf, unit, &origin, depth + 1)? {
+                    return Ok(Some(name));
+                }
+            }
+        }
+    }
+
+    match entry.attr_value(gimli::DW_AT_name)? {
+        Some(value) => Ok(Some(get_attr_string(dwarf, unit, value)?)),
+        None => Ok(None),
+    }
+}
+
+/// Extracts the address ranges of all functions from the DWARF debug info of an ELF.
+///
+/// Functions that were discarded by the linker (whose low PC is 0) are skipped.
+pub fn parse_dwarf_function_ranges(elf_bytes: &[u8]) -> Result<Vec<DwarfFunctionRange>> {
+    let elf = Elf::parse(elf_bytes)?;
+
+    let load_section = |id: gimli::SectionId| -> Result<Reader, gimli::Error> {
+        let data = elf
+            .section_headers
+            .iter()
+            .find(|shdr| elf.shdr_strtab.get_at(shdr.sh_name) == Some(id.name()))
+            .and_then(|shdr| {
+                let start = shdr.sh_offset as usize;
+                elf_bytes.get(start..start + shdr.sh_size as usize)
+            })
+            .unwrap_or(&[]);
+        Ok(EndianSlice::new(data, LittleEndian))
+    };
+    let dwarf = gimli::Dwarf::load(load_section)?;
+
+    let mut ranges = Vec::new();
+    let mut units = dwarf.units();
+    while let Some(header) = units.next()? {
+        let unit = dwarf.unit(header)?;
+        let mut entries = unit.entries();
+        while let Some((_, entry)) = entries.next_dfs()? {
+            if entry.tag() != gimli::DW_TAG_subprogram {
+                continue;
+            }
+
+            let low_pc = match entry.attr_value(gimli::DW_AT_low_pc)? {
+                Some(AttributeValue::Addr(addr)) if addr != 0 => addr,
+                _ => continue,
+            };
+            let high_pc = match entry.attr_value(gimli::DW_AT_high_pc)? {
+                Some(AttributeValue::Addr(addr)) => addr,
+                Some(AttributeValue::Udata(size)) => low_pc + size,
+                _ => continue,
+            };
+
+            ranges.push(DwarfFunctionRange {
+                low_pc,
+                high_pc,
+                name: get_function_name(&dwarf, &unit, entry, 0)?,
+            });
+        }
+    }
+
+    ranges.sort_by_key(|range| range.low_pc);
+    Ok(ranges)
+}
+
+/// A function whose size in the function list doesn't match its DWARF size.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DwarfMismatch {
+    /// *Note*: does not contain the IDA base (0x7100000000).
+    pub addr: u64,
+    pub name: String,
+    pub csv_size: u32,
+    pub dwarf_size: u64,
+    pub dwarf_name: Option<String>,
+}
+
+/// Reports functions whose size differs from the size in the DWARF debug info.
+/// Functions that have no DWARF entry at the same address are ignored.
+pub fn cross_check_with_dwarf(
+    functions: &[Info],
+    dwarf_ranges: &[DwarfFunctionRange],
+) -> Vec<DwarfMismatch> {
+    let ranges_by_addr: FxHashMap<u64, &DwarfFunctionRange> = dwarf_ranges
+        .iter()
+        .map(|range| (range.low_pc, range))
+        .collect();
+
+    functions
+        .iter()
+        .filter_map(|info| {
+            let range = ranges_by_addr.get(&info.addr)?;
+            if range.size() == info.size as u64 {
+                return None;
+            }
+            Some(DwarfMismatch {
+                addr: info.addr,
+                name: info.name.clone(),
+                csv_size: info.size,
+                dwarf_size: range.size(),
+                dwarf_name: range.name.clone(),
+            })
+        })
+        .collect()
+}
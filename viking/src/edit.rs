@@ -0,0 +1,709 @@
+use crate::functions::{self, AddressDiff, Info, Status};
+use crate::history;
+use crate::lock::{self, FileLock, LockOptions};
+use crate::outlined;
+use crate::schema::CsvSchema;
+use crate::tombstones;
+use anyhow::{bail, ensure, Context, Result};
+use regex::Regex;
+use rustc_hash::FxHashMap;
+use similar::udiff::UnifiedHunkHeader;
+use similar::DiffOp;
+use std::path::Path;
+
+/// A change to the function list.
+#[derive(Clone, Debug)]
+pub enum Edit {
+    SetStatus { addr: u64, status: Status },
+    Rename { addr: u64, name: String },
+    Insert(Info),
+    Remove { addr: u64 },
+}
+
+/// Returns the index of the function at `addr`. The address index is built on first use
+/// and must be reset (set to None) whenever entries are added, removed or moved.
+fn find_index(
+    functions: &[Info],
+    index: &mut Option<FxHashMap<u64, usize>>,
+    addr: u64,
+) -> Result<usize> {
+    let index = index.get_or_insert_with(|| functions::make_known_function_index(functions));
+    match index.get(&addr) {
+        Some(i) => Ok(*i),
+        None => bail!("unknown function at {}", functions::format_addr(addr)),
+    }
+}
+
+/// Applies edits to a function list. A list that is in canonical order stays in canonical order
+/// (see `functions::canonicalize`).
+pub fn apply_edits(functions: &mut Vec<Info>, edits: &[Edit]) -> Result<()> {
+    let mut index = None;
+    for edit in edits {
+        match edit {
+            Edit::SetStatus { addr, status } => {
+                let i = find_index(functions, &mut index, *addr)?;
+                functions[i].status = status.clone();
+            }
+            Edit::Rename { addr, name } => {
+                let i = find_index(functions, &mut index, *addr)?;
+                functions[i].name = name.clone();
+                // Aliases are sorted by name.
+                let start = functions.partition_point(|function| function.addr < *addr);
+                let len = functions[start..].partition_point(|function| function.addr == *addr);
+                if len > 1 {
+                    functions[start..start + len].sort_by(functions::compare_canonical);
+                    index = None;
+                }
+            }
+            Edit::Insert(info) => {
+                if find_index(functions, &mut index, info.addr).is_ok() {
+                    bail!(
+                        "there is already a function at {}",
+                        functions::format_addr(info.addr)
+                    );
+                }
+                let insert_index = functions.partition_point(|function| {
+                    functions::compare_canonical(function, info) == std::cmp::Ordering::Less
+                });
+                functions.insert(insert_index, info.clone());
+                index = None;
+            }
+            Edit::Remove { addr } => {
+                let i = find_index(functions, &mut index, *addr)?;
+                functions.remove(i);
+                index = None;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Renames all decompiled functions whose name matches the regex `pattern`, replacing the first
+/// match with `replacement` (see `regex::Regex::replace`; `$1` etc. refer to capture groups).
+/// Returns the number of renamed functions.
+///
+/// Fails without changing anything if a new name would be the same as the name of another
+/// function.
+pub fn batch_rename_functions(
+    functions: &mut [Info],
+    pattern: &str,
+    replacement: &str,
+) -> Result<usize> {
+    let regex = Regex::new(pattern).with_context(|| format!("invalid regex: {}", pattern))?;
+
+    let renames: Vec<(usize, String)> = functions
+        .iter()
+        .enumerate()
+        .filter(|(_, function)| function.is_decompiled())
+        .filter_map(|(i, function)| {
+            let new_name = regex.replace(&function.name, replacement);
+            if new_name == function.name {
+                return None;
+            }
+            Some((i, new_name.into_owned()))
+        })
+        .collect();
+
+    let mut names: FxHashMap<&str, usize> = functions
+        .iter()
+        .enumerate()
+        .filter(|(_, function)| !function.name.is_empty())
+        .map(|(i, function)| (function.name.as_str(), i))
+        .collect();
+    for (i, _) in &renames {
+        names.remove(functions[*i].name.as_str());
+    }
+    let mut conflicts = Vec::new();
+    for (i, new_name) in &renames {
+        if let Some(other) = names.insert(new_name.as_str(), *i) {
+            conflicts.push(format!(
+                "{} and {} would both be named {}",
+                functions::format_addr(functions[*i].addr),
+                functions::format_addr(functions[other].addr),
+                new_name
+            ));
+        }
+    }
+    if !conflicts.is_empty() {
+        bail!("name conflicts after renaming:\n{}", conflicts.join("\n"));
+    }
+
+    for (i, new_name) in &renames {
+        functions[*i].name = new_name.clone();
+    }
+
+    // Aliases are sorted by name.
+    for (i, _) in &renames {
+        let addr = functions[*i].addr;
+        let start = functions.partition_point(|function| function.addr < addr);
+        let len = functions[start..].partition_point(|function| function.addr == addr);
+        if len > 1 {
+            functions[start..start + len].sort_by(functions::compare_canonical);
+        }
+    }
+
+    Ok(renames.len())
+}
+
+/// Describes what would change if a function list were written to disk.
+#[derive(Clone, Debug, Default)]
+pub struct WritePlan {
+    /// Unified diff between the current and the new file contents.
+    pub diff: String,
+    pub rows_added: usize,
+    pub rows_removed: usize,
+    pub rows_modified: usize,
+    /// Outlined functions that were only renamed. These renames are left out of the diff
+    /// and of `rows_modified` because outlined functions are renumbered by every build.
+    pub outlined_renames: usize,
+}
+
+impl WritePlan {
+    pub fn is_empty(&self) -> bool {
+        self.diff.is_empty() && self.outlined_renames == 0
+    }
+}
+
+impl std::fmt::Display for WritePlan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}{} added, {} removed, {} modified",
+            self.diff, self.rows_added, self.rows_removed, self.rows_modified
+        )?;
+        if self.outlined_renames != 0 {
+            write!(f, " ({} outlined functions renamed)", self.outlined_renames)?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns whether the only change is that an outlined function was renumbered.
+fn is_outlined_rename(old: &Info, new: &Info) -> bool {
+    old.name != new.name
+        && old.size == new.size
+        && old.status == new.status
+        && outlined::is_outlined_function(&old.name)
+        && outlined::is_outlined_function(&new.name)
+}
+
+/// Number of unchanged lines that are shown around changes in `WritePlan::diff`.
+const DIFF_CONTEXT_LINES: usize = 3;
+
+/// Computes what writing `new_functions` to `csv_path` would change, without writing anything.
+///
+/// If the file and `new_functions` are both sorted by address, the lists are compared entry by
+/// entry and only the entries that changed are serialized and diffed, so that dry runs stay
+/// fast for large lists. The lines of the other entries are assumed to be written exactly as
+/// they are in the file. Otherwise, the whole new list is serialized and diffed with the file.
+pub fn plan_write(csv_path: &Path, new_functions: &[Info]) -> Result<WritePlan> {
+    let old_contents = if csv_path.is_file() {
+        std::fs::read_to_string(csv_path)?
+    } else {
+        String::new()
+    };
+    let old_functions = if old_contents.is_empty() {
+        Vec::new()
+    } else {
+        functions::get_functions_for_path(csv_path)?
+    };
+
+    let name = csv_path.to_string_lossy();
+    let schema = functions::get_schema_for_path(csv_path, new_functions)?;
+    let old_lines: Vec<&str> = old_contents.lines().collect();
+    let header = functions::serialize_function_lines(&[], &schema, true)?;
+    let is_sorted_by_addr =
+        |functions: &[Info]| functions.windows(2).all(|w| w[0].addr <= w[1].addr);
+    let lines_match_entries = old_contents.ends_with('\n')
+        && old_lines.len() == old_functions.len() + 1
+        && old_lines[0] == header[0];
+
+    if lines_match_entries && is_sorted_by_addr(&old_functions) && is_sorted_by_addr(new_functions)
+    {
+        plan_changed_regions(&name, &old_lines, &old_functions, new_functions, &schema)
+    } else {
+        plan_full_write(&name, &old_contents, &old_functions, new_functions, &schema)
+    }
+}
+
+/// Appends an operation to `ops`. Adjacent operations of the same kind (unchanged lines or
+/// changed lines) are merged, so that changes are shown like in a diff of the whole file.
+fn push_diff_op(ops: &mut Vec<DiffOp>, op: DiffOp) {
+    let last = match ops.last_mut() {
+        Some(last) => last,
+        None => return ops.push(op),
+    };
+    let is_equal = |op: &DiffOp| matches!(op, DiffOp::Equal { .. });
+    if is_equal(last) != is_equal(&op)
+        || last.old_range().end != op.old_range().start
+        || last.new_range().end != op.new_range().start
+    {
+        return ops.push(op);
+    }
+
+    let old_index = last.old_range().start;
+    let new_index = last.new_range().start;
+    let old_len = op.old_range().end - old_index;
+    let new_len = op.new_range().end - new_index;
+    *last = if is_equal(&op) {
+        DiffOp::Equal {
+            old_index,
+            new_index,
+            len: old_len,
+        }
+    } else if old_len == 0 {
+        DiffOp::Insert {
+            old_index,
+            new_index,
+            new_len,
+        }
+    } else if new_len == 0 {
+        DiffOp::Delete {
+            old_index,
+            old_len,
+            new_index,
+        }
+    } else {
+        DiffOp::Replace {
+            old_index,
+            old_len,
+            new_index,
+            new_len,
+        }
+    };
+}
+
+/// See `plan_write`. Both lists must be sorted by address, and `old_lines` must be the header
+/// followed by one line per entry of `old_functions`.
+fn plan_changed_regions(
+    name: &str,
+    old_lines: &[&str],
+    old_functions: &[Info],
+    new_functions: &[Info],
+    schema: &CsvSchema,
+) -> Result<WritePlan> {
+    let mut plan = WritePlan::default();
+    // Line indices are offset by 1 because of the header.
+    let mut ops = vec![DiffOp::Equal {
+        old_index: 0,
+        new_index: 0,
+        len: 1,
+    }];
+    // Lines in the new file that are not in the old file, by line index.
+    let mut new_lines: FxHashMap<usize, String> = FxHashMap::default();
+
+    let (mut i, mut j) = (0, 0);
+    while i < old_functions.len() || j < new_functions.len() {
+        // Compare all entries at the next address at once, so that aliases are handled.
+        let addr = match (old_functions.get(i), new_functions.get(j)) {
+            (Some(old), Some(new)) => old.addr.min(new.addr),
+            (Some(old), None) => old.addr,
+            (None, Some(new)) => new.addr,
+            (None, None) => unreachable!(),
+        };
+        let old_end = i + old_functions[i..].partition_point(|info| info.addr == addr);
+        let new_end = j + new_functions[j..].partition_point(|info| info.addr == addr);
+        let old_group = &old_functions[i..old_end];
+        let new_group = &new_functions[j..new_end];
+
+        let is_unchanged = old_group == new_group
+            || matches!((old_group, new_group), ([old], [new]) if is_outlined_rename(old, new));
+        if is_unchanged {
+            if old_group != new_group {
+                // Show outlined functions that were only renamed with their old names.
+                plan.outlined_renames += 1;
+            }
+            push_diff_op(
+                &mut ops,
+                DiffOp::Equal {
+                    old_index: i + 1,
+                    new_index: j + 1,
+                    len: old_group.len(),
+                },
+            );
+        } else {
+            let num_unchanged = old_group
+                .iter()
+                .filter(|info| new_group.contains(info))
+                .count();
+            let num_common = old_group.len().min(new_group.len());
+            plan.rows_modified += num_common - num_unchanged.min(num_common);
+            plan.rows_added += new_group.len() - num_common;
+            plan.rows_removed += old_group.len() - num_common;
+
+            let group_lines = functions::serialize_function_lines(new_group, schema, false)?;
+            let group_lines: Vec<&str> = group_lines.iter().map(String::as_str).collect();
+            let group_ops = similar::capture_diff_slices(
+                similar::Algorithm::Myers,
+                &old_lines[i + 1..old_end + 1],
+                &group_lines,
+            );
+            for op in group_ops {
+                let op = shift_diff_op(op, i + 1, j + 1);
+                let new_range = op.new_range();
+                if !matches!(op, DiffOp::Equal { .. }) {
+                    for index in new_range {
+                        new_lines.insert(index, group_lines[index - j - 1].to_string());
+                    }
+                }
+                push_diff_op(&mut ops, op);
+            }
+        }
+
+        i = old_end;
+        j = new_end;
+    }
+
+    if new_lines.is_empty() && ops.len() == 1 {
+        return Ok(plan);
+    }
+
+    let mut diff = format!("--- {}\n+++ {}\n", name, name);
+    for group in similar::group_diff_ops(ops, DIFF_CONTEXT_LINES) {
+        diff.push_str(&format!("{}\n", UnifiedHunkHeader::new(&group)));
+        for op in &group {
+            for index in op.old_range() {
+                let tag = if matches!(op, DiffOp::Equal { .. }) {
+                    ' '
+                } else {
+                    '-'
+                };
+                diff.push_str(&format!("{}{}\n", tag, old_lines[index]));
+            }
+            if !matches!(op, DiffOp::Equal { .. }) {
+                for index in op.new_range() {
+                    diff.push_str(&format!("+{}\n", new_lines[&index]));
+                }
+            }
+        }
+    }
+    plan.diff = diff;
+    Ok(plan)
+}
+
+fn shift_diff_op(op: DiffOp, old_offset: usize, new_offset: usize) -> DiffOp {
+    match op {
+        DiffOp::Equal {
+            old_index,
+            new_index,
+            len,
+        } => DiffOp::Equal {
+            old_index: old_index + old_offset,
+            new_index: new_index + new_offset,
+            len,
+        },
+        DiffOp::Delete {
+            old_index,
+            old_len,
+            new_index,
+        } => DiffOp::Delete {
+            old_index: old_index + old_offset,
+            old_len,
+            new_index: new_index + new_offset,
+        },
+        DiffOp::Insert {
+            old_index,
+            new_index,
+            new_len,
+        } => DiffOp::Insert {
+            old_index: old_index + old_offset,
+            new_index: new_index + new_offset,
+            new_len,
+        },
+        DiffOp::Replace {
+            old_index,
+            old_len,
+            new_index,
+            new_len,
+        } => DiffOp::Replace {
+            old_index: old_index + old_offset,
+            old_len,
+            new_index: new_index + new_offset,
+            new_len,
+        },
+    }
+}
+
+/// See `plan_write`.
+fn plan_full_write(
+    name: &str,
+    old_contents: &str,
+    old_functions: &[Info],
+    new_functions: &[Info],
+    schema: &CsvSchema,
+) -> Result<WritePlan> {
+    let mut plan = WritePlan::default();
+
+    let old_by_addr: FxHashMap<u64, &Info> =
+        old_functions.iter().map(|info| (info.addr, info)).collect();
+
+    // Show outlined functions that were only renamed with their old names.
+    let new_functions: Vec<Info> = new_functions
+        .iter()
+        .map(|info| match old_by_addr.get(&info.addr) {
+            Some(old_info) if is_outlined_rename(old_info, info) => {
+                plan.outlined_renames += 1;
+                (*old_info).clone()
+            }
+            _ => info.clone(),
+        })
+        .collect();
+
+    let new_contents = String::from_utf8(functions::serialize_functions(&new_functions, schema)?)?;
+
+    for entry in functions::diff_by_address(old_functions, &new_functions) {
+        match entry {
+            AddressDiff::Added(_) => plan.rows_added += 1,
+            AddressDiff::Removed(_) => plan.rows_removed += 1,
+            AddressDiff::Kept { old, new } if old != new => plan.rows_modified += 1,
+            AddressDiff::Kept { .. } => (),
+        }
+    }
+
+    if old_contents != new_contents {
+        plan.diff = similar::TextDiff::from_lines(old_contents, &new_contents)
+            .unified_diff()
+            .context_radius(DIFF_CONTEXT_LINES)
+            .header(name, name)
+            .to_string();
+    }
+
+    Ok(plan)
+}
+
+/// Takes the lock for a read-modify-write cycle. Dry runs do not write anything
+/// and do not need to exclude other writers.
+fn lock_for_edit(csv_path: &Path, dry_run: bool) -> Result<Option<FileLock>> {
+    if dry_run {
+        return Ok(None);
+    }
+    lock::lock_exclusive(csv_path, &LockOptions::default()).map(Some)
+}
+
+/// Applies edits to the function list at `csv_path`.
+///
+/// If `dry_run` is set, the file is left untouched. In both cases, a description of the
+/// changes is returned.
+/// The file is locked (see `lock::lock_exclusive`) until the changes have been written,
+/// so that concurrent edits are not lost.
+pub fn apply_edits_to_path(csv_path: &Path, edits: &[Edit], dry_run: bool) -> Result<WritePlan> {
+    let _lock = lock_for_edit(csv_path, dry_run)?;
+    let mut functions = functions::get_functions_for_path(csv_path)?;
+    apply_edits(&mut functions, edits)?;
+
+    let plan = plan_write(csv_path, &functions)?;
+    if !dry_run && !plan.is_empty() {
+        functions::write_functions_to_path(csv_path, &functions)?;
+    }
+    Ok(plan)
+}
+
+/// Computes what writing `new_functions` to the function list of the executable would change.
+pub fn plan_write_functions(new_functions: &[Info]) -> Result<WritePlan> {
+    plan_write(functions::get_functions_csv_path(), new_functions)
+}
+
+/// Applies edits to the function list of the executable. See `apply_edits_to_path`.
+/// Status changes are recorded in the status log (see `history`).
+pub fn update_functions(edits: &[Edit], dry_run: bool) -> Result<WritePlan> {
+    update_functions_ex(edits, dry_run, None)
+}
+
+/// Same as `update_functions`, with a reason for the status log.
+pub fn update_functions_ex(
+    edits: &[Edit],
+    dry_run: bool,
+    reason: Option<&str>,
+) -> Result<WritePlan> {
+    let _lock = lock_for_edit(functions::get_functions_csv_path(), dry_run)?;
+    let old_functions = functions::get_functions()?;
+    let mut functions = old_functions.clone();
+    apply_edits(&mut functions, edits)?;
+
+    let plan = plan_write_functions(&functions)?;
+    if !dry_run && !plan.is_empty() {
+        tombstones::write_with_tombstones(
+            &old_functions,
+            &functions,
+            reason.unwrap_or("removed"),
+            || functions::write_functions(&functions),
+        )?;
+        history::record_status_changes(&old_functions, &functions, reason)?;
+    }
+    Ok(plan)
+}
+
+/// Removes the function at `addr` from the function list of the executable and records
+/// a tombstone with `reason`, so that the entry is reported if it comes back
+/// (see `tombstones::check_tombstones`). Fails if no tombstone file is configured.
+pub fn remove_function(addr: u64, reason: &str) -> Result<WritePlan> {
+    ensure!(
+        tombstones::get_tombstone_path()?.is_some(),
+        "cannot record the removal: removed_functions is not set in the config"
+    );
+    update_functions_ex(&[Edit::Remove { addr }], false, Some(reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::WriteOptions;
+    use crate::testing::{self, make_function, TempDir};
+
+    #[test]
+    fn outlined_renames_are_left_out_of_write_plans() {
+        testing::use_test_config();
+        let dir = TempDir::new("edit_outlined");
+        let path = dir.join("functions.csv");
+        let old = vec![
+            make_function(0x100, 0x10, "OUTLINED_FUNCTION_1", Status::NotDecompiled),
+            make_function(0x110, 0x10, "OUTLINED_FUNCTION_2", Status::NotDecompiled),
+            make_function(0x120, 0x10, "OUTLINED_FUNCTION_3", Status::NotDecompiled),
+        ];
+        functions::write_functions_to_path_ex(&path, &old, &WriteOptions::default()).unwrap();
+
+        // A new build renumbered every outlined function.
+        let mut new = vec![
+            make_function(0x100, 0x10, "OUTLINED_FUNCTION_4", Status::NotDecompiled),
+            make_function(0x110, 0x10, "OUTLINED_FUNCTION_5", Status::NotDecompiled),
+            make_function(0x120, 0x10, "OUTLINED_FUNCTION_6", Status::NotDecompiled),
+        ];
+        let plan = plan_write(&path, &new).unwrap();
+        assert_eq!(plan.outlined_renames, 3);
+        assert_eq!(plan.rows_modified, 0);
+        assert!(plan.diff.is_empty());
+
+        // Other changes to outlined functions are still shown.
+        new[1].status = Status::Matching;
+        new[2].name = "_ZN3Foo1gEv".to_string();
+        let plan = plan_write(&path, &new).unwrap();
+        assert_eq!(plan.outlined_renames, 1);
+        assert_eq!(plan.rows_modified, 2);
+        assert!(plan
+            .diff
+            .contains("+0x0000007100000110,O,000016,OUTLINED_FUNCTION_5"));
+        assert!(plan
+            .diff
+            .contains("+0x0000007100000120,U,000016,_ZN3Foo1gEv"));
+        assert!(!plan.diff.contains("OUTLINED_FUNCTION_4"));
+    }
+
+    /// Returns `plan_write` and the plan that serializes and diffs the whole list.
+    fn plan_both_ways(path: &Path, new: &[Info]) -> (WritePlan, WritePlan) {
+        let old_contents = std::fs::read_to_string(path).unwrap();
+        let old = functions::get_functions_for_path(path).unwrap();
+        let name = path.to_string_lossy();
+        (
+            plan_write(path, new).unwrap(),
+            plan_full_write(
+                &name,
+                &old_contents,
+                &old,
+                new,
+                &functions::get_schema_for_path(path, new).unwrap(),
+            )
+            .unwrap(),
+        )
+    }
+
+    #[test]
+    fn write_plans_only_diff_changed_regions() {
+        testing::use_test_config();
+        let dir = TempDir::new("edit_plan_regions");
+        let path = dir.join("functions.csv");
+        let old: Vec<Info> = (0..200)
+            .map(|i| {
+                let addr = 0x100 + i * 0x10;
+                make_function(
+                    addr,
+                    0x10,
+                    &format!("func_{:x}", addr),
+                    Status::NotDecompiled,
+                )
+            })
+            .collect();
+        functions::write_functions_to_path_ex(&path, &old, &WriteOptions::default()).unwrap();
+
+        let mut rng = testing::Rng::new(0x105);
+        for iteration in 0..200 {
+            let mut new = old.clone();
+            let mut edits = Vec::new();
+            for _ in 0..1 + rng.below(6) {
+                let addr = 0x100 + rng.below(200) as u64 * 0x10;
+                edits.push(match rng.below(4) {
+                    0 => Edit::SetStatus {
+                        addr,
+                        status: rng.choose(&testing::ALL_STATUSES).clone(),
+                    },
+                    1 => Edit::Rename {
+                        addr,
+                        name: format!("renamed_{}", edits.len()),
+                    },
+                    2 => Edit::Insert(make_function(
+                        addr + 8,
+                        0x10,
+                        "inserted",
+                        Status::NotDecompiled,
+                    )),
+                    _ => Edit::Remove { addr },
+                });
+            }
+            // Skip edits that conflict with earlier ones. Removed functions cannot be edited.
+            for edit in &edits {
+                let _ = apply_edits(&mut new, std::slice::from_ref(edit));
+            }
+
+            let (plan, expected) = plan_both_ways(&path, &new);
+            assert_eq!(plan.diff, expected.diff, "iteration {}", iteration);
+            assert_eq!(
+                (plan.rows_added, plan.rows_removed, plan.rows_modified),
+                (
+                    expected.rows_added,
+                    expected.rows_removed,
+                    expected.rows_modified
+                ),
+                "iteration {}",
+                iteration
+            );
+        }
+
+        let (plan, _) = plan_both_ways(&path, &old);
+        assert!(plan.is_empty());
+    }
+
+    #[test]
+    fn write_plans_handle_aliases() {
+        testing::use_test_config();
+        let dir = TempDir::new("edit_plan_aliases");
+        let path = dir.join("functions.csv");
+        let old = vec![
+            make_function(0x100, 0x10, "f", Status::NotDecompiled),
+            make_function(0x110, 0x10, "alias_a", Status::NotDecompiled),
+            make_function(0x110, 0x10, "alias_b", Status::NotDecompiled),
+            make_function(0x120, 0x10, "g", Status::NotDecompiled),
+        ];
+        functions::write_functions_to_path_ex(&path, &old, &WriteOptions::default()).unwrap();
+
+        let mut new = old.clone();
+        apply_edits(
+            &mut new,
+            &[
+                Edit::Insert(make_function(0x118, 0x10, "h", Status::NotDecompiled)),
+                Edit::Remove { addr: 0x120 },
+            ],
+        )
+        .unwrap();
+        new[1].name = "alias_c".to_string();
+        new[1..3].sort_by(functions::compare_canonical);
+
+        let (plan, expected) = plan_both_ways(&path, &new);
+        assert_eq!(plan.diff, expected.diff);
+        assert!(plan.diff.contains("-0x0000007100000110,U,000016,alias_a\n"));
+        assert!(plan.diff.contains(" 0x0000007100000110,U,000016,alias_b\n"));
+        assert!(plan.diff.contains("+0x0000007100000110,U,000016,alias_c\n"));
+        assert_eq!(
+            (plan.rows_added, plan.rows_removed, plan.rows_modified),
+            (1, 1, 1)
+        );
+    }
+}
@@ -209,6 +209,44 @@ pub fn make_addr_to_name_map(elf: &OwnedElf) -> Result<AddrToNameMap> {
     Ok(map)
 }
 
+/// Returns the names of all symbols, in symbol table order. Unlike `make_symbol_map_by_name`,
+/// this includes undefined and section symbols, which relocations in object files refer to.
+///
+/// *Note*: section symbols have no name of their own; see `get_section_name`.
+pub fn get_all_symbol_names(elf: &OwnedElf) -> Result<Vec<&str>> {
+    let strtab = SymbolStringTable::from_elf(elf)?;
+    Ok(elf
+        .syms
+        .iter()
+        .map(|symbol| strtab.get_string(symbol.st_name))
+        .collect())
+}
+
+pub fn get_section_name<'a>(elf: &'a OwnedElf, shdr: &SectionHeader) -> &'a str {
+    &elf.shdr_strtab[shdr.sh_name]
+}
+
+/// Returns the relocations that apply to the section at `section_index`.
+/// Only RELA sections are supported, since that is what AArch64 uses.
+pub fn get_section_relocations(elf: &OwnedElf, section_index: usize) -> Result<Vec<reloc::Reloc>> {
+    let bytes = &elf.as_owner().1;
+    let mut relocs = Vec::new();
+    for shdr in &elf.section_headers {
+        if shdr.sh_type != section_header::SHT_RELA || shdr.sh_info as usize != section_index {
+            continue;
+        }
+        let section = RelocSection::parse(
+            bytes,
+            shdr.sh_offset as usize,
+            shdr.sh_size as usize,
+            true,
+            make_goblin_ctx(),
+        )?;
+        relocs.extend(section.iter());
+    }
+    Ok(relocs)
+}
+
 fn parse_symtab<'a>(elf: &'a OwnedElf, shdr: &'a SectionHeader) -> Result<Symtab<'a>> {
     let bytes = &elf.as_owner().1;
     let size = shdr.sh_entsize;
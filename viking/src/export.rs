@@ -0,0 +1,599 @@
+use crate::functions::{self, Info, Status};
+use crate::sources::{self, SourceMapping};
+use anyhow::{bail, Result};
+use itertools::Itertools;
+use std::collections::BTreeSet;
+use std::io::Write;
+use std::path::Path;
+
+pub struct LinkerScriptOptions {
+    /// Emit `. = . + size;` after each symbol so that GNU ld errors out if two functions overlap
+    /// (the location counter cannot move backwards). Only used if `wrap_in_sections` is set.
+    pub include_size_assertions: bool,
+    /// Place the symbol definitions inside a `SECTIONS` command rather than at the top level.
+    pub wrap_in_sections: bool,
+    /// Name of the output section to use if `wrap_in_sections` is set.
+    pub section_name: String,
+}
+
+impl Default for LinkerScriptOptions {
+    fn default() -> Self {
+        Self {
+            include_size_assertions: false,
+            wrap_in_sections: false,
+            section_name: ".text".to_string(),
+        }
+    }
+}
+
+/// Returns the name as it should be written in a linker script.
+/// Names that contain characters other than [A-Za-z0-9_.$] need to be quoted.
+fn quote_linker_script_symbol(name: &str) -> String {
+    if name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '$'))
+    {
+        name.to_string()
+    } else {
+        format!("\"{}\"", name)
+    }
+}
+
+/// Writes a GNU ld script that defines a symbol for every matching function
+/// at its address in the original executable.
+pub fn generate_linker_script(
+    functions: &[Info],
+    output_section: &str,
+    writer: &mut dyn Write,
+) -> Result<()> {
+    let options = LinkerScriptOptions {
+        section_name: output_section.to_string(),
+        ..Default::default()
+    };
+    generate_linker_script_with_options(functions, &options, writer)
+}
+
+pub fn generate_linker_script_with_options(
+    functions: &[Info],
+    options: &LinkerScriptOptions,
+    writer: &mut dyn Write,
+) -> Result<()> {
+    let matched = functions
+        .iter()
+        .filter(|info| info.status == Status::Matching && !info.name.is_empty())
+        .sorted_by_key(|info| info.addr)
+        .collect_vec();
+
+    writeln!(writer, "/* Generated by viking. Do not edit. */")?;
+
+    if !options.wrap_in_sections {
+        for info in matched {
+            writeln!(
+                writer,
+                "PROVIDE_HIDDEN({} = {:#x});",
+                quote_linker_script_symbol(&info.name),
+                info.addr
+            )?;
+        }
+        return Ok(());
+    }
+
+    // Inside an output section description, `.` is an offset from the start of the section.
+    let base = matched.first().map_or(0, |info| info.addr);
+
+    writeln!(writer, "SECTIONS")?;
+    writeln!(writer, "{{")?;
+    writeln!(writer, "  {} {:#x} :", options.section_name, base)?;
+    writeln!(writer, "  {{")?;
+    for info in matched {
+        writeln!(writer, "    . = {:#x};", info.addr - base)?;
+        writeln!(
+            writer,
+            "    PROVIDE_HIDDEN({} = ABSOLUTE(.));",
+            quote_linker_script_symbol(&info.name)
+        )?;
+        if options.include_size_assertions {
+            writeln!(writer, "    . = . + {:#x};", info.size)?;
+        }
+    }
+    writeln!(writer, "  }}")?;
+    writeln!(writer, "}}")?;
+
+    Ok(())
+}
+
+/// Derives an IDA function declaration from a mangled name.
+///
+/// Mangled names only have part of the type information, and nothing is guessed: names are
+/// rejected if the return type is not part of the name (it is only known for function
+/// templates and constructors/destructors), or if it is not known whether the function has
+/// a `this` parameter (for scoped functions that are not constructors or destructors and have
+/// no cv-qualifiers).
+fn make_ida_function_decl(name: &str) -> Result<String> {
+    // Special names (vtables, thunks, guard variables, ...)
+    if name.starts_with("_ZT") || name.starts_with("_ZG") {
+        bail!("not a regular function");
+    }
+
+    if !name.starts_with("_Z") {
+        bail!("no type information in the name");
+    }
+    let demangled = match functions::demangle_str(name) {
+        Ok(demangled) => demangled,
+        Err(_) => bail!("failed to demangle name"),
+    };
+    for unsupported in &["(anonymous namespace)", "{lambda", "{unnamed", "decltype"] {
+        if demangled.contains(unsupported) {
+            bail!("unsupported construct: {}", unsupported);
+        }
+    }
+
+    let signature = match functions::parse_demangled_signature(&demangled) {
+        Some(signature) => signature,
+        None => bail!("failed to parse the demangled name"),
+    };
+
+    let function_name = signature.components.last().copied().unwrap_or_default();
+    let class_name = signature.components.iter().rev().nth(1).copied();
+    let is_ctor_or_dtor = class_name
+        .map(|class_name| {
+            function_name == class_name || function_name.strip_prefix('~') == Some(class_name)
+        })
+        .unwrap_or(false);
+
+    let return_type = match signature.return_type {
+        Some(return_type) => return_type,
+        None if is_ctor_or_dtor => "void",
+        None => bail!("the return type is not part of the name"),
+    };
+
+    let mut params = Vec::new();
+    if let Some(scope) = signature.scope {
+        let qualifiers = signature.qualifiers;
+        // Without qualifiers, the scope could be a namespace or the function could be a static
+        // member function.
+        if qualifiers.is_empty() && !is_ctor_or_dtor {
+            bail!("cannot tell whether the function has a this parameter");
+        }
+        // Reference qualifiers don't matter for the type of `this`.
+        let cv = qualifiers.trim_end_matches('&').trim();
+        let this_type = if cv.is_empty() {
+            scope.to_string()
+        } else {
+            format!("{} {}", cv, scope)
+        };
+        params.push(format!("{} *__hidden this", this_type));
+    }
+    params.extend(signature.params.iter().map(|param| param.to_string()));
+
+    Ok(format!(
+        "{} __fastcall {}({});",
+        return_type,
+        name,
+        params.join(", ")
+    ))
+}
+
+fn escape_python_string(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Writes an IDAPython script that sets the prototype of every named function.
+/// Prototypes are derived from the mangled names (see `make_ida_function_decl`);
+/// functions whose prototype cannot be determined are listed in comments.
+pub fn generate_ida_til_script(functions: &[Info], writer: &mut dyn Write) -> Result<()> {
+    writeln!(writer, "# Generated by viking. Do not edit.")?;
+    writeln!(writer, "import idc")?;
+    writeln!(writer)?;
+    writeln!(writer, "def set_type(ea, decl):")?;
+    writeln!(writer, "    if not idc.SetType(ea, decl):")?;
+    writeln!(
+        writer,
+        "        print(\"failed to set type for 0x%016x: %s\" % (ea, decl))"
+    )?;
+    writeln!(writer)?;
+
+    for info in functions.iter().sorted_by_key(|info| info.addr) {
+        if info.name.is_empty() {
+            continue;
+        }
+
+        let addr = functions::format_addr(info.addr);
+        match make_ida_function_decl(&info.name) {
+            Ok(decl) => writeln!(
+                writer,
+                "set_type({}, \"{}\")",
+                addr,
+                escape_python_string(&decl)
+            )?,
+            Err(err) => writeln!(writer, "# {} {}: {}", addr, info.name, err)?,
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns a colour-coded label for a status, for tools that display tags with text only.
+fn get_status_badge(status: &Status) -> String {
+    let icon = match status {
+        Status::Matching => "🟢",
+        Status::NonMatchingMinor => "🟡",
+        Status::NonMatchingMajor => "🟠",
+        Status::Wip => "🔵",
+        Status::NotDecompiled => "⚪",
+        Status::Library => "🟣",
+    };
+    format!("{} {}", icon, status.description())
+}
+
+/// Writes a Binary Ninja Python script that renames functions and tags them with their status.
+/// The script must be run with `bv` set to the analysis session to import into
+/// (e.g. from the Python console). Unknown addresses are logged and skipped.
+pub fn export_for_binary_ninja(functions: &[Info], writer: &mut dyn Write) -> Result<()> {
+    writeln!(writer, "# Generated by viking. Do not edit.")?;
+    writeln!(writer, "from binaryninja import log_warn")?;
+    writeln!(writer)?;
+    writeln!(writer, "STATUS_BADGES = {{")?;
+    for status in [
+        Status::Matching,
+        Status::NonMatchingMinor,
+        Status::NonMatchingMajor,
+        Status::Wip,
+        Status::NotDecompiled,
+        Status::Library,
+    ] {
+        writeln!(
+            writer,
+            "    \"{}\": \"{}\",",
+            status.code(),
+            get_status_badge(&status)
+        )?;
+    }
+    writeln!(writer, "}}")?;
+    writeln!(writer)?;
+    writeln!(writer, "ENTRIES = [")?;
+    for info in functions.iter().sorted_by_key(|info| info.addr) {
+        writeln!(
+            writer,
+            "    ({}, \"{}\", \"{}\"),",
+            functions::format_addr(info.addr),
+            escape_python_string(&info.name),
+            info.status.code()
+        )?;
+    }
+    writeln!(writer, "]")?;
+    writeln!(writer)?;
+    writeln!(writer, "def import_entries(bv, entries):")?;
+    writeln!(writer, "    if \"DecompStatus\" not in bv.tag_types:")?;
+    writeln!(
+        writer,
+        "        bv.create_tag_type(\"DecompStatus\", \"🏷\")"
+    )?;
+    writeln!(writer, "    for addr, name, status in entries:")?;
+    writeln!(writer, "        func = bv.get_function_at(addr)")?;
+    writeln!(writer, "        if func is None:")?;
+    writeln!(
+        writer,
+        "            log_warn(\"viking: no function at 0x%016x (%s)\" % (addr, name))"
+    )?;
+    writeln!(writer, "            continue")?;
+    writeln!(writer, "        if name:")?;
+    writeln!(writer, "            func.name = name")?;
+    writeln!(
+        writer,
+        "        func.add_tag(\"DecompStatus\", STATUS_BADGES[status])"
+    )?;
+    writeln!(writer)?;
+    writeln!(writer, "import_entries(bv, ENTRIES)")?;
+
+    Ok(())
+}
+
+pub struct GhidraScriptOptions {
+    /// Calling convention to set on every function, or None to leave calling conventions alone.
+    pub calling_convention: Option<String>,
+}
+
+impl Default for GhidraScriptOptions {
+    fn default() -> Self {
+        Self {
+            calling_convention: Some("__fastcall".to_string()),
+        }
+    }
+}
+
+/// Returns the background colour for a status, as RGB.
+fn get_status_color(status: &Status) -> (u8, u8, u8) {
+    match status {
+        Status::Matching => (0xc8, 0xf0, 0xc8),
+        Status::NonMatchingMinor => (0xf0, 0xf0, 0xb4),
+        Status::NonMatchingMajor => (0xf8, 0xd8, 0xb0),
+        Status::Wip => (0xc8, 0xdc, 0xf8),
+        Status::NotDecompiled => (0xff, 0xff, 0xff),
+        Status::Library => (0xe0, 0xd0, 0xf0),
+    }
+}
+
+/// Same as `export_ghidra_analysis_script_with_options`, with default options.
+pub fn export_ghidra_analysis_script(functions: &[Info], writer: &mut dyn Write) -> Result<()> {
+    export_ghidra_analysis_script_with_options(functions, &GhidraScriptOptions::default(), writer)
+}
+
+/// Writes a Ghidra Python script (for the Script Manager) that creates a function at every
+/// address that does not have one yet, renames functions, sets calling conventions and colours
+/// functions by status. Functions that already have the right name or calling convention are
+/// left alone, so the script can be run again after the function list is updated. A summary
+/// of the changes is printed at the end.
+pub fn export_ghidra_analysis_script_with_options(
+    functions: &[Info],
+    options: &GhidraScriptOptions,
+    writer: &mut dyn Write,
+) -> Result<()> {
+    writeln!(writer, "# Generated by viking. Do not edit.")?;
+    writeln!(
+        writer,
+        "# Run from Ghidra's Script Manager with the program to analyse open."
+    )?;
+    writeln!(writer, "from ghidra.program.model.symbol import SourceType")?;
+    writeln!(writer, "from java.awt import Color")?;
+    writeln!(writer)?;
+    match &options.calling_convention {
+        Some(convention) => writeln!(
+            writer,
+            "CALLING_CONVENTION = \"{}\"",
+            escape_python_string(convention)
+        )?,
+        None => writeln!(writer, "CALLING_CONVENTION = None")?,
+    }
+    writeln!(writer)?;
+    writeln!(writer, "STATUS_COLORS = {{")?;
+    for status in [
+        Status::Matching,
+        Status::NonMatchingMinor,
+        Status::NonMatchingMajor,
+        Status::Wip,
+        Status::NotDecompiled,
+        Status::Library,
+    ] {
+        let (r, g, b) = get_status_color(&status);
+        writeln!(
+            writer,
+            "    \"{}\": Color({:#04x}, {:#04x}, {:#04x}),",
+            status.code(),
+            r,
+            g,
+            b
+        )?;
+    }
+    writeln!(writer, "}}")?;
+    writeln!(writer)?;
+    writeln!(writer, "ENTRIES = [")?;
+    for info in functions.iter().sorted_by_key(|info| info.addr) {
+        writeln!(
+            writer,
+            "    ({}, \"{}\", \"{}\"),",
+            functions::format_addr(info.addr),
+            escape_python_string(&info.name),
+            info.status.code()
+        )?;
+    }
+    writeln!(writer, "]")?;
+    writeln!(writer)?;
+    for line in &[
+        "def apply_entries(entries):",
+        "    counts = {\"created\": 0, \"renamed\": 0, \"calling conventions set\": 0, \"failed\": 0}",
+        "    for addr, name, status in entries:",
+        "        ea = toAddr(addr)",
+        "        func = getFunctionAt(ea)",
+        "        try:",
+        "            if func is None:",
+        "                func = createFunction(ea, name or None)",
+        "                if func is None:",
+        "                    raise Exception(\"createFunction failed\")",
+        "                counts[\"created\"] += 1",
+        "            elif name and func.getName() != name:",
+        "                func.setName(name, SourceType.USER_DEFINED)",
+        "                counts[\"renamed\"] += 1",
+        "            if CALLING_CONVENTION and func.getCallingConventionName() != CALLING_CONVENTION:",
+        "                func.setCallingConvention(CALLING_CONVENTION)",
+        "                counts[\"calling conventions set\"] += 1",
+        "            setBackgroundColor(func.getBody(), STATUS_COLORS[status])",
+        "        except Exception as e:",
+        "            printerr(\"viking: 0x%016x (%s): %s\" % (addr, name, e))",
+        "            counts[\"failed\"] += 1",
+        "    return counts",
+        "",
+        "counts = apply_entries(ENTRIES)",
+        "print(\"viking: %d entries: %s\" % (len(ENTRIES), \", \".join(\"%d %s\" % (counts[k], k) for k in sorted(counts))))",
+    ] {
+        writeln!(writer, "{}", line)?;
+    }
+
+    Ok(())
+}
+
+/// Returns a path as it should be written in a Makefile.
+fn quote_makefile_path(path: &Path) -> Result<String> {
+    let path = match path.to_str() {
+        Some(path) => path,
+        None => bail!("{:?} is not valid UTF-8", path),
+    };
+    // Make has no way of quoting whitespace in target and prerequisite names.
+    if path.chars().any(char::is_whitespace) {
+        bail!(
+            "{:?} cannot be used in a Makefile because it contains whitespace",
+            path
+        );
+    }
+    Ok(path.replace('$', "$$").replace('#', "\\#"))
+}
+
+/// Writes GNU Makefile rules that build one object per translation unit (see
+/// `sources::get_functions_by_translation_unit`). Functions that are not mapped to a source file
+/// are skipped.
+///
+/// Source paths in the mapping are relative to `source_root`; objects are written to the same
+/// relative path in `object_dir`. The recipes are generated from pattern rules that use `CXX`
+/// and `CXXFLAGS` (or `CC` and `CFLAGS` for C files), so that projects can set their own flags.
+///
+/// `OBJECTS` lists every object and `MATCHED_OBJECTS` lists the objects in which every function
+/// is matching.
+pub fn generate_makefile_rules(
+    functions: &[Info],
+    source_root: &Path,
+    object_dir: &Path,
+    source_mapping: &SourceMapping,
+    writer: &mut dyn Write,
+) -> Result<()> {
+    let functions_by_file = sources::get_functions_by_translation_unit(functions, source_mapping);
+    let mut units = Vec::new();
+    for (source_path, functions) in functions_by_file {
+        let source_path = match source_path {
+            Some(source_path) => source_path,
+            None => continue,
+        };
+        let relative_path = source_path
+            .strip_prefix(source_root)
+            .unwrap_or(&source_path)
+            .to_path_buf();
+        if relative_path.is_absolute() {
+            bail!("{:?} is not in {:?}", source_path, source_root);
+        }
+        units.push((relative_path, functions));
+    }
+    units.sort_by(|a, b| a.0.cmp(&b.0));
+
+    writeln!(writer, "# Generated by viking. Do not edit.")?;
+    writeln!(writer)?;
+    writeln!(
+        writer,
+        "SOURCE_ROOT := {}",
+        quote_makefile_path(source_root)?
+    )?;
+    writeln!(writer, "OBJECT_DIR := {}", quote_makefile_path(object_dir)?)?;
+    writeln!(writer)?;
+
+    let extensions: BTreeSet<&str> = units
+        .iter()
+        .filter_map(|(path, _)| path.extension().and_then(|extension| extension.to_str()))
+        .collect();
+    for extension in extensions {
+        let (compiler, flags) = if extension == "c" {
+            ("CC", "CFLAGS")
+        } else {
+            ("CXX", "CXXFLAGS")
+        };
+        writeln!(writer, "$(OBJECT_DIR)/%.o: $(SOURCE_ROOT)/%.{}", extension)?;
+        writeln!(writer, "\t@mkdir -p $(@D)")?;
+        writeln!(writer, "\t$({}) $({}) -c -o $@ $<", compiler, flags)?;
+        writeln!(writer)?;
+    }
+
+    let mut objects = Vec::with_capacity(units.len());
+    let mut matched_objects = Vec::new();
+    for (relative_path, functions) in &units {
+        let source = format!("$(SOURCE_ROOT)/{}", quote_makefile_path(relative_path)?);
+        let object = format!(
+            "$(OBJECT_DIR)/{}",
+            quote_makefile_path(&relative_path.with_extension("o"))?
+        );
+        let num_matching = functions
+            .iter()
+            .filter(|info| info.status == Status::Matching)
+            .count();
+
+        writeln!(
+            writer,
+            "# {}/{} functions matching",
+            num_matching,
+            functions.len()
+        )?;
+        writeln!(writer, "{}: {}", object, source)?;
+        writeln!(writer)?;
+
+        if num_matching == functions.len() {
+            matched_objects.push(object.clone());
+        }
+        objects.push(object);
+    }
+
+    let write_variable = |writer: &mut dyn Write, name: &str, values: &[String]| -> Result<()> {
+        write!(writer, "{} :=", name)?;
+        for value in values {
+            write!(writer, " \\\n\t{}", value)?;
+        }
+        writeln!(writer)?;
+        Ok(())
+    };
+    write_variable(writer, "OBJECTS", &objects)?;
+    writeln!(writer)?;
+    write_variable(writer, "MATCHED_OBJECTS", &matched_objects)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::make_function;
+
+    #[test]
+    fn ida_declarations_only_use_known_types() {
+        let cases = [
+            (
+                "_ZN4sead4HeapC2Ev",
+                Some("void __fastcall _ZN4sead4HeapC2Ev(sead::Heap *__hidden this);"),
+            ),
+            (
+                "_ZN4sead4HeapD0Ev",
+                Some("void __fastcall _ZN4sead4HeapD0Ev(sead::Heap *__hidden this);"),
+            ),
+            (
+                "_Z3maxIiET_S0_S0_",
+                Some("int __fastcall _Z3maxIiET_S0_S0_(int, int);"),
+            ),
+            (
+                "_ZNK4sead6BufferIiE3getIfEET_i",
+                Some(
+                    "float __fastcall _ZNK4sead6BufferIiE3getIfEET_i(\
+                     const sead::Buffer<int> *__hidden this, int);",
+                ),
+            ),
+            // No return type.
+            ("_Z3fooi", None),
+            ("_ZNK4sead4Heap7getSizeEv", None),
+            // The scope could be a namespace or a class.
+            ("_ZN4sead3maxIiEET_S1_S1_", None),
+            // Static member function or member function without qualifiers?
+            ("_ZN4sead6BufferIiE3getIfEET_i", None),
+            ("_ZTVN4sead4HeapE", None),
+            ("memcpy", None),
+        ];
+        for (name, expected) in cases {
+            assert_eq!(
+                make_ida_function_decl(name).ok().as_deref(),
+                expected,
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn ida_script_lists_functions_without_prototypes() {
+        let functions = [
+            make_function(0x10, 0x20, "_ZN4sead4HeapC2Ev", Status::Matching),
+            make_function(0x30, 0x20, "_Z3fooi", Status::NotDecompiled),
+        ];
+        let mut script = Vec::new();
+        generate_ida_til_script(&functions, &mut script).unwrap();
+        let script = String::from_utf8(script).unwrap();
+        assert!(script.contains(
+            "set_type(0x0000007100000010, \
+             \"void __fastcall _ZN4sead4HeapC2Ev(sead::Heap *__hidden this);\")\n"
+        ));
+        assert!(script
+            .contains("# 0x0000007100000030 _Z3fooi: the return type is not part of the name\n"));
+        assert!(!script.contains("__int64"));
+    }
+}
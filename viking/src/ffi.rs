@@ -0,0 +1,202 @@
+use crate::functions::{self, Info};
+use rustc_hash::FxHashMap;
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+/// A loaded function list, for C code (e.g. IDA plugins). Opaque to C code.
+///
+/// See `include/viking.h` (generated with `cbindgen --config cbindgen.toml`) for thread safety
+/// and ownership rules. To build a shared library:
+/// `cargo rustc --release --lib --features ffi --crate-type cdylib`
+pub struct ViKingHandle {
+    /// Sorted by address.
+    functions: Vec<Info>,
+    /// Names of `functions`, as C strings.
+    names: Vec<CString>,
+    by_name: FxHashMap<String, usize>,
+}
+
+/// A function entry.
+#[repr(C)]
+pub struct ViKingInfo {
+    /// Address of the function, including the base address of the executable (0x7100000000).
+    pub address: u64,
+    pub size: u32,
+    /// Status code, as in the function list (e.g. 'O' for matching functions).
+    pub status: c_char,
+    /// Mangled name of the function (empty for unnamed functions).
+    /// Owned by the handle: valid until `viking_free` is called.
+    pub name: *const c_char,
+}
+
+impl ViKingHandle {
+    fn load(path: &CStr) -> Option<Self> {
+        let path = std::path::Path::new(path.to_str().ok()?);
+        let mut functions = functions::get_functions_for_path(path).ok()?;
+        functions.sort_by_key(|info| info.addr);
+        let names = functions
+            .iter()
+            .map(|info| CString::new(info.name.as_str()).ok())
+            .collect::<Option<Vec<_>>>()?;
+        let mut by_name = FxHashMap::default();
+        for (i, info) in functions.iter().enumerate() {
+            if !info.name.is_empty() {
+                by_name.entry(info.name.clone()).or_insert(i);
+            }
+        }
+        Some(Self {
+            functions,
+            names,
+            by_name,
+        })
+    }
+
+    /// Returns the index of the function that contains `addr` (without the base address).
+    fn find_by_addr(&self, addr: u64) -> Option<usize> {
+        let i = self
+            .functions
+            .partition_point(|info| info.addr <= addr)
+            .checked_sub(1)?;
+        // Aliases share the same address; prefer the first entry.
+        let i = self.functions[..=i].partition_point(|info| info.addr < self.functions[i].addr);
+        let info = &self.functions[i];
+        if addr == info.addr || addr < info.addr + info.size as u64 {
+            Some(i)
+        } else {
+            None
+        }
+    }
+
+    fn get_info(&self, i: usize) -> ViKingInfo {
+        let info = &self.functions[i];
+        ViKingInfo {
+            address: info.addr + functions::ADDRESS_BASE,
+            size: info.size,
+            status: info.status.code() as c_char,
+            name: self.names[i].as_ptr(),
+        }
+    }
+}
+
+/// Returns 1 if `out_info` was filled and 0 if there is no result.
+unsafe fn write_result(handle: &ViKingHandle, i: Option<usize>, out_info: *mut ViKingInfo) -> i32 {
+    match i {
+        Some(i) => {
+            *out_info = handle.get_info(i);
+            1
+        }
+        None => 0,
+    }
+}
+
+/// Loads a function list. Returns null on failure.
+///
+/// # Safety
+/// `path` must be a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn viking_load(path: *const c_char) -> *mut ViKingHandle {
+    if path.is_null() {
+        return std::ptr::null_mut();
+    }
+    let path = CStr::from_ptr(path);
+    match catch_unwind(|| ViKingHandle::load(path)) {
+        Ok(Some(handle)) => Box::into_raw(Box::new(handle)),
+        _ => std::ptr::null_mut(),
+    }
+}
+
+/// Looks up the function that contains `addr` (including the base address).
+///
+/// Returns 1 and fills `out_info` if the function was found, 0 if it was not found
+/// and -1 if a pointer is null.
+///
+/// # Safety
+/// `handle` must have been returned by `viking_load` and not freed; `out_info` must be
+/// valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn viking_lookup_addr(
+    handle: *const ViKingHandle,
+    addr: u64,
+    out_info: *mut ViKingInfo,
+) -> i32 {
+    if handle.is_null() || out_info.is_null() {
+        return -1;
+    }
+    let handle = &*handle;
+    let i = match addr.checked_sub(functions::ADDRESS_BASE) {
+        Some(addr) => catch_unwind(|| handle.find_by_addr(addr)).unwrap_or(None),
+        None => None,
+    };
+    write_result(handle, i, out_info)
+}
+
+/// Looks up a function by mangled name.
+///
+/// Returns 1 and fills `out_info` if the function was found, 0 if it was not found
+/// and -1 if a pointer is null.
+///
+/// # Safety
+/// `handle` must have been returned by `viking_load` and not freed; `name` must be a valid
+/// NUL-terminated string; `out_info` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn viking_lookup_name(
+    handle: *const ViKingHandle,
+    name: *const c_char,
+    out_info: *mut ViKingInfo,
+) -> i32 {
+    if handle.is_null() || name.is_null() || out_info.is_null() {
+        return -1;
+    }
+    let handle = &*handle;
+    let i = match CStr::from_ptr(name).to_str() {
+        Ok(name) => handle.by_name.get(name).copied(),
+        Err(_) => None,
+    };
+    write_result(handle, i, out_info)
+}
+
+/// Demangles a C++ symbol into `buf` (NUL-terminated, truncated to `buflen - 1` bytes).
+///
+/// Returns the length of the full demangled name (excluding the NUL terminator), like
+/// `snprintf`, or 0 if the name could not be demangled.
+///
+/// # Safety
+/// `name` must be a valid NUL-terminated string; `buf` must be valid for `buflen` bytes
+/// of writes (or null if `buflen` is 0).
+#[no_mangle]
+pub unsafe extern "C" fn viking_demangle(
+    name: *const c_char,
+    buf: *mut c_char,
+    buflen: usize,
+) -> usize {
+    if name.is_null() {
+        return 0;
+    }
+    let name = match CStr::from_ptr(name).to_str() {
+        Ok(name) => name,
+        Err(_) => return 0,
+    };
+    let demangled = match catch_unwind(AssertUnwindSafe(|| functions::demangle_str(name))) {
+        Ok(Ok(demangled)) => demangled,
+        _ => return 0,
+    };
+
+    if !buf.is_null() && buflen != 0 {
+        let len = demangled.len().min(buflen - 1);
+        std::ptr::copy_nonoverlapping(demangled.as_ptr() as *const c_char, buf, len);
+        *buf.add(len) = 0;
+    }
+    demangled.len()
+}
+
+/// Frees a handle. Names that were returned for this handle become invalid.
+///
+/// # Safety
+/// `handle` must be null or have been returned by `viking_load` and not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn viking_free(handle: *mut ViKingHandle) {
+    if !handle.is_null() {
+        drop(Box::from_raw(handle));
+    }
+}
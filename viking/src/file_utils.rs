@@ -0,0 +1,37 @@
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+/// Writes `contents` to a temporary file next to `path`, then renames it to `path`,
+/// so that readers never see a partially written file.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    let file_name = path
+        .file_name()
+        .with_context(|| format!("invalid path: {:?}", path))?
+        .to_string_lossy();
+    let tmp_path = path.with_file_name(format!(".{}.{}.tmp", file_name, std::process::id()));
+
+    std::fs::write(&tmp_path, contents)
+        .with_context(|| format!("failed to write {:?}", &tmp_path))?;
+    if let Err(err) = std::fs::rename(&tmp_path, path) {
+        let _ = std::fs::remove_file(&tmp_path);
+        return Err(err).with_context(|| format!("failed to replace {:?}", path));
+    }
+    Ok(())
+}
+
+/// Returns the directory for files that the tools keep in a repo (backups, caches...),
+/// which git ignores (see `create_untracked_dir`).
+pub(crate) fn get_local_dir(repo_root: &Path) -> PathBuf {
+    repo_root.join(".viking")
+}
+
+/// Creates `dir` if it doesn't exist yet, and makes sure that git ignores everything in it.
+pub(crate) fn create_untracked_dir(dir: &Path) -> Result<()> {
+    std::fs::create_dir_all(dir).with_context(|| format!("failed to create {:?}", dir))?;
+    let gitignore = dir.join(".gitignore");
+    if !gitignore.exists() {
+        std::fs::write(&gitignore, "*\n")
+            .with_context(|| format!("failed to write {:?}", gitignore))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,214 @@
+use crate::asm::{Normalizer, NormalizerOptions};
+use crate::elf;
+use crate::functions::{self, Info};
+use crate::stable_hash::StableHasher;
+use anyhow::{ensure, Context, Result};
+use lazy_static::lazy_static;
+use rayon::prelude::*;
+use rustc_hash::FxHashMap;
+use std::io::{Read, Write};
+use std::path::Path;
+
+const DB_MAGIC: &[u8; 4] = b"VKFP";
+const DB_VERSION: u32 = 2;
+
+lazy_static! {
+    /// Moving a function or the data it references must not change its fingerprint.
+    static ref NORMALIZER: Normalizer = Normalizer::new(NormalizerOptions::for_fingerprinting());
+}
+
+/// Computes a fingerprint for the code of a function. See `NormalizerOptions::for_fingerprinting`.
+pub fn fingerprint_function(code: &[u8]) -> u64 {
+    let mut hasher = StableHasher::new();
+    for token in NORMALIZER.normalize(code) {
+        hasher.write(&token.to_bytes());
+    }
+    hasher.finish()
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FingerprintEntry {
+    pub fingerprint: u64,
+    pub size: u32,
+    pub name: String,
+}
+
+/// Function names indexed by fingerprint, for carrying names over to another version of
+/// an executable.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FingerprintDb {
+    /// Sorted by fingerprint.
+    entries: Vec<FingerprintEntry>,
+}
+
+impl FingerprintDb {
+    pub fn from_entries(mut entries: Vec<FingerprintEntry>) -> Self {
+        entries.sort_by(|a, b| {
+            (a.fingerprint, a.size, &a.name).cmp(&(b.fingerprint, b.size, &b.name))
+        });
+        Self { entries }
+    }
+
+    pub fn entries(&self) -> &[FingerprintEntry] {
+        &self.entries
+    }
+
+    /// Returns all entries with the specified fingerprint.
+    pub fn get(&self, fingerprint: u64) -> &[FingerprintEntry] {
+        let start = self
+            .entries
+            .partition_point(|entry| entry.fingerprint < fingerprint);
+        let len = self.entries[start..].partition_point(|entry| entry.fingerprint == fingerprint);
+        &self.entries[start..start + len]
+    }
+
+    pub fn write(&self, writer: &mut dyn Write) -> Result<()> {
+        writer.write_all(DB_MAGIC)?;
+        writer.write_all(&DB_VERSION.to_le_bytes())?;
+        writer.write_all(&(self.entries.len() as u32).to_le_bytes())?;
+        for entry in &self.entries {
+            writer.write_all(&entry.fingerprint.to_le_bytes())?;
+            writer.write_all(&entry.size.to_le_bytes())?;
+            writer.write_all(&(entry.name.len() as u32).to_le_bytes())?;
+            writer.write_all(entry.name.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    pub fn read(reader: &mut dyn Read) -> Result<Self> {
+        fn read_u32(reader: &mut dyn Read) -> Result<u32> {
+            let mut buf = [0u8; 4];
+            reader.read_exact(&mut buf)?;
+            Ok(u32::from_le_bytes(buf))
+        }
+
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        ensure!(&magic == DB_MAGIC, "not a fingerprint database");
+        let version = read_u32(reader)?;
+        ensure!(
+            version == DB_VERSION,
+            "unsupported fingerprint database version: {}",
+            version
+        );
+
+        let count = read_u32(reader)? as usize;
+        let mut entries = Vec::with_capacity(count);
+        for _ in 0..count {
+            let mut fingerprint = [0u8; 8];
+            reader.read_exact(&mut fingerprint)?;
+            let size = read_u32(reader)?;
+            let mut name = vec![0u8; read_u32(reader)? as usize];
+            reader.read_exact(&mut name)?;
+            entries.push(FingerprintEntry {
+                fingerprint: u64::from_le_bytes(fingerprint),
+                size,
+                name: String::from_utf8(name)?,
+            });
+        }
+        Ok(Self::from_entries(entries))
+    }
+
+    pub fn write_to_path(&self, path: &Path) -> Result<()> {
+        let mut contents = Vec::new();
+        self.write(&mut contents)?;
+        std::fs::write(path, contents).with_context(|| format!("failed to write {:?}", path))
+    }
+
+    pub fn read_from_path(path: &Path) -> Result<Self> {
+        let mut file =
+            std::fs::File::open(path).with_context(|| format!("failed to open {:?}", path))?;
+        Self::read(&mut std::io::BufReader::new(&mut file))
+            .with_context(|| format!("failed to read {:?}", path))
+    }
+}
+
+/// Fingerprints all named functions in `functions`, using the code from `base_binary`.
+pub fn build(base_binary: &elf::OwnedElf, functions: &[Info]) -> Result<FingerprintDb> {
+    let entries = functions
+        .par_iter()
+        .filter(|info| !info.name.is_empty())
+        .map(|info| {
+            let function = elf::get_function(base_binary, info.addr, info.size as u64)
+                .with_context(|| {
+                    format!(
+                        "failed to get code for {} ({})",
+                        info.name,
+                        functions::format_addr(info.addr)
+                    )
+                })?;
+            Ok(FingerprintEntry {
+                fingerprint: fingerprint_function(function.code),
+                size: info.size,
+                name: info.name.clone(),
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(FingerprintDb::from_entries(entries))
+}
+
+/// A name proposal for a function in another version of the executable.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NameTransfer {
+    /// Exactly one function in each executable has this fingerprint.
+    Unique { addr: u64, name: String },
+    /// The fingerprint is shared by several named functions in the database
+    /// or by several functions in the new executable, so no name can be picked.
+    Ambiguous { addr: u64, candidates: Vec<String> },
+}
+
+impl NameTransfer {
+    pub fn addr(&self) -> u64 {
+        match self {
+            NameTransfer::Unique { addr, .. } | NameTransfer::Ambiguous { addr, .. } => *addr,
+        }
+    }
+}
+
+/// Proposes names for functions of another version of the executable, based on their
+/// fingerprints. Functions whose fingerprint is not in the database are not returned.
+pub fn match_against(
+    new_binary_functions: &[elf::Function],
+    db: &FingerprintDb,
+) -> Vec<NameTransfer> {
+    let fingerprints: Vec<(u64, u32)> = new_binary_functions
+        .par_iter()
+        .map(|function| {
+            (
+                fingerprint_function(function.code),
+                function.code.len() as u32,
+            )
+        })
+        .collect();
+
+    let mut counts: FxHashMap<(u64, u32), usize> = FxHashMap::default();
+    for key in &fingerprints {
+        *counts.entry(*key).or_default() += 1;
+    }
+
+    new_binary_functions
+        .iter()
+        .zip(&fingerprints)
+        .filter_map(|(function, &(fingerprint, size))| {
+            let candidates: Vec<String> = db
+                .get(fingerprint)
+                .iter()
+                .filter(|entry| entry.size == size)
+                .map(|entry| entry.name.clone())
+                .collect();
+
+            match candidates.len() {
+                0 => None,
+                1 if counts[&(fingerprint, size)] == 1 => Some(NameTransfer::Unique {
+                    addr: function.addr,
+                    name: candidates.into_iter().next().unwrap(),
+                }),
+                _ => Some(NameTransfer::Ambiguous {
+                    addr: function.addr,
+                    candidates,
+                }),
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,313 @@
+use crate::functions::{self, Info};
+use crate::lock::{self, LockOptions};
+use anyhow::Result;
+use rustc_hash::FxHashMap;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, RwLock};
+
+/// A place function lists can be loaded from and stored to.
+///
+/// The CSV in the repo is the canonical store, but other backends (e.g. `MemorySource` in a
+/// long-running process) can implement this trait to serve queries faster. Stats, searches and
+/// the check tool accept any source (see e.g. `stats::compute_stats_for_source`).
+/// Backends that can look up functions without loading the whole list should override
+/// `lookup_by_name` and `lookup_by_addr`.
+pub trait FunctionSource {
+    fn load(&self) -> Result<Vec<Info>>;
+
+    /// Returns the function with exactly this (mangled) name.
+    fn lookup_by_name(&self, name: &str) -> Result<Option<Info>> {
+        Ok(self.load()?.into_iter().find(|info| info.name == name))
+    }
+
+    /// Returns the function at `addr` (without `functions::ADDRESS_BASE`).
+    fn lookup_by_addr(&self, addr: u64) -> Result<Option<Info>> {
+        Ok(self.load()?.into_iter().find(|info| info.addr == addr))
+    }
+
+    /// Replaces the whole function list.
+    fn store(&self, functions: &[Info]) -> Result<()>;
+
+    /// Loads the function list, lets `update` change it and stores the result.
+    /// Nothing is stored if `update` fails.
+    ///
+    /// Backends that other processes can change hold a lock for the whole cycle, so that
+    /// concurrent updates are not lost. Loading and storing separately gives no such guarantee.
+    fn update(&self, update: &mut dyn FnMut(&mut Vec<Info>) -> Result<()>) -> Result<()> {
+        let mut functions = self.load()?;
+        update(&mut functions)?;
+        self.store(&functions)
+    }
+}
+
+/// The function list of the executable, as specified in the config.
+///
+/// This behaves like `functions::get_functions` and `functions::write_functions`, except that
+/// reads wait for edits that are in progress and writes lock the list (see `lock`).
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ProjectFunctions;
+
+impl FunctionSource for ProjectFunctions {
+    fn load(&self) -> Result<Vec<Info>> {
+        functions::get_functions_for_path_shared(
+            functions::get_functions_csv_path(),
+            &LockOptions::default(),
+        )
+    }
+
+    fn store(&self, functions: &[Info]) -> Result<()> {
+        let _lock =
+            lock::lock_exclusive(functions::get_functions_csv_path(), &LockOptions::default())?;
+        functions::write_functions(functions)
+    }
+
+    fn update(&self, update: &mut dyn FnMut(&mut Vec<Info>) -> Result<()>) -> Result<()> {
+        let _lock =
+            lock::lock_exclusive(functions::get_functions_csv_path(), &LockOptions::default())?;
+        // Not `load`: waiting for the lock that is held here would never finish.
+        let mut functions = functions::get_functions()?;
+        update(&mut functions)?;
+        functions::write_functions(&functions)
+    }
+}
+
+/// A function list in an arbitrary CSV file (e.g. for another executable).
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct CsvFile {
+    pub path: PathBuf,
+}
+
+impl CsvFile {
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+        }
+    }
+}
+
+impl FunctionSource for CsvFile {
+    fn load(&self) -> Result<Vec<Info>> {
+        functions::get_functions_for_path_shared(&self.path, &LockOptions::default())
+    }
+
+    fn store(&self, functions: &[Info]) -> Result<()> {
+        let _lock = lock::lock_exclusive(&self.path, &LockOptions::default())?;
+        functions::write_functions_atomic(&self.path, functions)
+    }
+
+    fn update(&self, update: &mut dyn FnMut(&mut Vec<Info>) -> Result<()>) -> Result<()> {
+        functions::update_functions_at_path(&self.path, update)
+    }
+}
+
+/// A function list that is read from a reader and written to a writer
+/// (see `functions::get_functions_for_reader` and `functions::write_functions_to_writer`).
+///
+/// *Note*: each `load` parses whatever is left in the reader, so for most readers only the
+/// first call returns the list.
+pub struct StreamSource<R: Read, W: Write> {
+    reader: Mutex<R>,
+    writer: Mutex<W>,
+}
+
+impl<R: Read, W: Write> StreamSource<R, W> {
+    pub fn new(reader: R, writer: W) -> Self {
+        Self {
+            reader: Mutex::new(reader),
+            writer: Mutex::new(writer),
+        }
+    }
+
+    pub fn into_inner(self) -> (R, W) {
+        (
+            self.reader.into_inner().unwrap(),
+            self.writer.into_inner().unwrap(),
+        )
+    }
+}
+
+impl<R: Read, W: Write> FunctionSource for StreamSource<R, W> {
+    fn load(&self) -> Result<Vec<Info>> {
+        functions::get_functions_for_reader(&mut *self.reader.lock().unwrap())
+    }
+
+    fn store(&self, functions: &[Info]) -> Result<()> {
+        functions::write_functions_to_writer(&mut *self.writer.lock().unwrap(), functions)
+    }
+}
+
+/// A function list that is kept in memory, with indexes for lookups by name and address.
+#[derive(Debug, Default)]
+pub struct MemorySource {
+    state: RwLock<MemoryState>,
+}
+
+#[derive(Debug, Default)]
+struct MemoryState {
+    functions: Vec<Info>,
+    /// Indices into `functions`.
+    by_name: FxHashMap<String, usize>,
+    /// Indices into `functions`.
+    by_addr: FxHashMap<u64, usize>,
+}
+
+impl MemoryState {
+    fn new(functions: Vec<Info>) -> Self {
+        let mut state = Self {
+            by_name: FxHashMap::default(),
+            by_addr: FxHashMap::default(),
+            functions,
+        };
+        for (i, info) in state.functions.iter().enumerate() {
+            if !info.name.is_empty() {
+                state.by_name.entry(info.name.clone()).or_insert(i);
+            }
+            state.by_addr.entry(info.addr).or_insert(i);
+        }
+        state
+    }
+}
+
+impl MemorySource {
+    pub fn new(functions: Vec<Info>) -> Self {
+        Self {
+            state: RwLock::new(MemoryState::new(functions)),
+        }
+    }
+
+    /// Loads all functions from `source` into memory.
+    pub fn load_from(source: &dyn FunctionSource) -> Result<Self> {
+        Ok(Self::new(source.load()?))
+    }
+}
+
+impl FunctionSource for MemorySource {
+    fn load(&self) -> Result<Vec<Info>> {
+        Ok(self.state.read().unwrap().functions.clone())
+    }
+
+    fn lookup_by_name(&self, name: &str) -> Result<Option<Info>> {
+        let state = self.state.read().unwrap();
+        Ok(state.by_name.get(name).map(|&i| state.functions[i].clone()))
+    }
+
+    fn lookup_by_addr(&self, addr: u64) -> Result<Option<Info>> {
+        let state = self.state.read().unwrap();
+        Ok(state
+            .by_addr
+            .get(&addr)
+            .map(|&i| state.functions[i].clone()))
+    }
+
+    fn store(&self, functions: &[Info]) -> Result<()> {
+        *self.state.write().unwrap() = MemoryState::new(functions.to_vec());
+        Ok(())
+    }
+
+    fn update(&self, update: &mut dyn FnMut(&mut Vec<Info>) -> Result<()>) -> Result<()> {
+        let mut state = self.state.write().unwrap();
+        let mut functions = state.functions.clone();
+        update(&mut functions)?;
+        *state = MemoryState::new(functions);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::Status;
+    use crate::search;
+    use crate::stats;
+    use crate::testing::{make_function, TempDir};
+
+    fn make_functions() -> Vec<Info> {
+        [
+            (0x10, "_ZN4ksys3act8BaseProc4initEv", Status::Matching),
+            (
+                0x20,
+                "_ZN4ksys3act8BaseProc4calcEv",
+                Status::NonMatchingMinor,
+            ),
+            (0x30, "", Status::NotDecompiled),
+        ]
+        .iter()
+        .map(|(addr, name, status)| make_function(*addr, 0x10, name, status.clone()))
+        .collect()
+    }
+
+    /// Checks that `source` (which must be empty) stores and looks up functions correctly.
+    fn check_source(source: &dyn FunctionSource) {
+        let functions = make_functions();
+        source.store(&functions).unwrap();
+        assert_eq!(source.load().unwrap(), functions);
+        assert_eq!(
+            source
+                .lookup_by_name("_ZN4ksys3act8BaseProc4calcEv")
+                .unwrap(),
+            Some(functions[1].clone())
+        );
+        assert_eq!(
+            source
+                .lookup_by_name("_ZN4ksys3act8BaseProc4stopEv")
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            source.lookup_by_addr(0x30).unwrap(),
+            Some(functions[2].clone())
+        );
+        assert_eq!(source.lookup_by_addr(0x40).unwrap(), None);
+    }
+
+    #[test]
+    fn csv_files_are_sources() {
+        let dir = TempDir::new("function_source_csv");
+        check_source(&CsvFile::new(&dir.join("functions.csv")));
+    }
+
+    #[test]
+    fn memory_sources_are_indexed() {
+        let source = MemorySource::default();
+        check_source(&source);
+
+        // Lookups must not see stale indexes after a store.
+        source.store(&make_functions()[..1]).unwrap();
+        assert_eq!(source.lookup_by_addr(0x20).unwrap(), None);
+        assert!(source
+            .lookup_by_name("_ZN4ksys3act8BaseProc4initEv")
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn streams_are_sources() {
+        let mut written = Vec::new();
+        StreamSource::new(std::io::empty(), &mut written)
+            .store(&make_functions())
+            .unwrap();
+        let source =
+            MemorySource::load_from(&StreamSource::new(&written[..], std::io::sink())).unwrap();
+        assert_eq!(source.load().unwrap(), make_functions());
+    }
+
+    #[test]
+    fn queries_accept_any_source() {
+        let functions = make_functions();
+        let source = MemorySource::new(functions.clone());
+        assert_eq!(
+            stats::compute_stats_for_source(&source).unwrap(),
+            stats::compute_stats(&functions)
+        );
+        assert_eq!(
+            search::get_functions_with_pattern_in_source(&source, "ksys::act::BaseProc::calc*")
+                .unwrap()
+                .iter()
+                .map(|info| info.addr)
+                .collect::<Vec<_>>(),
+            [0x20]
+        );
+    }
+}
@@ -1,12 +1,23 @@
-use crate::repo;
-use anyhow::{bail, ensure, Context, Result};
+use crate::lock::{self, LockOptions};
+use crate::schema::{self, CsvSchema};
+use crate::stats::Stats;
+use crate::{backup, convert, file_utils, nso, repo, ui};
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use chrono::{NaiveDate, Utc};
+use indexmap::IndexMap;
+use lazy_init::Lazy;
 use lazy_static::lazy_static;
 use rayon::prelude::*;
 use rustc_hash::FxHashMap;
 use std::{
-    collections::HashSet,
+    collections::{BTreeMap, HashSet},
+    convert::TryFrom,
+    fs::File,
+    io::{self, BufRead, BufReader, Read, Write},
+    ops::Range,
     path::{Path, PathBuf},
 };
+use xxhash_rust::xxh64::Xxh64;
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Status {
@@ -29,20 +40,280 @@ impl Status {
             Status::Library => "library function",
         }
     }
+
+    /// Returns the status code that is used in the function list.
+    pub fn code(&self) -> char {
+        match &self {
+            Status::Matching => 'O',
+            Status::NonMatchingMinor => 'm',
+            Status::NonMatchingMajor => 'M',
+            Status::NotDecompiled => 'U',
+            Status::Wip => 'W',
+            Status::Library => 'L',
+        }
+    }
+
+    pub fn from_code(code: char) -> Option<Status> {
+        match code {
+            'm' => Some(Status::NonMatchingMinor),
+            'M' => Some(Status::NonMatchingMajor),
+            'O' => Some(Status::Matching),
+            'U' => Some(Status::NotDecompiled),
+            'W' => Some(Status::Wip),
+            'L' => Some(Status::Library),
+            _ => None,
+        }
+    }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
 pub struct Info {
     pub addr: u64,
     pub size: u32,
     pub name: String,
     pub status: Status,
+    /// Values of the extra columns that are declared in the config (see `schema::CsvSchema`),
+    /// in declaration order. Empty fields are left out.
+    pub extra: IndexMap<String, schema::Value>,
 }
 
 impl Info {
+    /// Returns an entry without any extra columns.
+    pub fn new(addr: u64, size: u32, name: String, status: Status) -> Self {
+        Self {
+            addr,
+            size,
+            name,
+            status,
+            extra: Default::default(),
+        }
+    }
+
     pub fn is_decompiled(&self) -> bool {
         !matches!(self.status, Status::NotDecompiled | Status::Library)
     }
+
+    /// Returns whether the function is being worked on (WIP or minor non-matching).
+    pub fn is_in_progress(&self) -> bool {
+        matches!(self.status, Status::Wip | Status::NonMatchingMinor)
+    }
+
+    /// Returns whether the function has been decompiled but does not match yet.
+    pub fn needs_verification(&self) -> bool {
+        matches!(
+            self.status,
+            Status::NonMatchingMinor | Status::NonMatchingMajor
+        )
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.status == Status::Matching
+    }
+
+    /// Returns whether no work is needed for the function (library functions).
+    pub fn is_skippable(&self) -> bool {
+        self.status == Status::Library
+    }
+
+    /// Returns a fast non-cryptographic hash (xxHash) of every field, for detecting
+    /// changed entries without keeping a copy of the old list. The hash is stable across runs
+    /// and platforms, so it can be stored by incremental tools.
+    pub fn content_hash(&self) -> u64 {
+        let mut hasher = Xxh64::new(0);
+        self.hash_fields(&mut hasher);
+        hasher.digest()
+    }
+
+    fn hash_fields(&self, hasher: &mut Xxh64) {
+        // Variable-length fields are prefixed with their length so that moving bytes
+        // from one field to the next changes the hash.
+        let mut write_str = |value: &str| {
+            hasher.update(&(value.len() as u64).to_le_bytes());
+            hasher.update(value.as_bytes());
+        };
+        write_str(&self.name);
+        for (column, value) in &self.extra {
+            write_str(column);
+            write_str(&value.to_string());
+        }
+        hasher.update(&self.addr.to_le_bytes());
+        hasher.update(&self.size.to_le_bytes());
+        hasher.update(&[self.status.code() as u8]);
+    }
+}
+
+/// Compares entries in canonical function list order: by address, then entries that share
+/// an address (aliases) by name, size and status code.
+pub fn compare_canonical(a: &Info, b: &Info) -> std::cmp::Ordering {
+    (a.addr, &a.name, a.size, a.status.code()).cmp(&(b.addr, &b.name, b.size, b.status.code()))
+}
+
+/// Returns the index of the first entry that is not in canonical order, if any.
+pub fn find_non_canonical_entry(functions: &[Info]) -> Option<usize> {
+    functions
+        .windows(2)
+        .position(|pair| compare_canonical(&pair[0], &pair[1]) == std::cmp::Ordering::Greater)
+        .map(|i| i + 1)
+}
+
+pub fn is_canonical(functions: &[Info]) -> bool {
+    find_non_canonical_entry(functions).is_none()
+}
+
+/// Sorts functions in canonical order (see `compare_canonical`) and removes identical entries.
+pub fn canonicalize(functions: &mut Vec<Info>) {
+    functions.par_sort_by(compare_canonical);
+    functions.dedup();
+}
+
+/// Returns whether a mangled name is a virtual dispatch thunk (`_ZTh`, `_ZTv` or `_ZTc`,
+/// which includes negative offsets such as `_ZThn8_`).
+pub fn is_virtual_thunk(name: &str) -> bool {
+    ["_ZTh", "_ZTv", "_ZTc"]
+        .iter()
+        .any(|prefix| name.starts_with(prefix))
+}
+
+/// Removes virtual dispatch thunks (see `is_virtual_thunk`).
+pub fn strip_virtual_thunks(functions: &mut Vec<Info>) {
+    functions.retain(|info| !is_virtual_thunk(&info.name));
+}
+
+pub fn count_virtual_thunks(functions: &[Info]) -> usize {
+    functions
+        .par_iter()
+        .filter(|info| is_virtual_thunk(&info.name))
+        .count()
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct NormalizationOptions {
+    /// Remove virtual dispatch thunks, which are trivially generated by the compiler.
+    pub strip_virtual_thunks: bool,
+}
+
+impl NormalizationOptions {
+    /// Returns the options for the project's function list. Thunks are stripped
+    /// if `strip_virtual_thunks` is set in the config.
+    pub fn from_config() -> Self {
+        Self {
+            strip_virtual_thunks: repo::CONFIG
+                .get("strip_virtual_thunks")
+                .and_then(toml::Value::as_bool)
+                .unwrap_or(false),
+        }
+    }
+}
+
+/// Canonicalizes a function list (see `canonicalize`) and applies the optional normalization
+/// steps that are enabled in `options`.
+pub fn normalize_functions(functions: &mut Vec<Info>, options: &NormalizationOptions) {
+    if options.strip_virtual_thunks {
+        strip_virtual_thunks(functions);
+    }
+    canonicalize(functions);
+}
+
+/// A function entry with extended information that is not stored in the function CSV.
+#[derive(Clone, Debug)]
+pub struct InfoV2 {
+    pub info: Info,
+    /// Name of the contributor who decompiled the function.
+    pub author: Option<String>,
+    /// When the function was first marked as matching (see `make_status_transition`).
+    pub date_matched: Option<NaiveDate>,
+}
+
+impl InfoV2 {
+    pub fn set_author(&mut self, author: &str) {
+        self.author = Some(author.to_string());
+    }
+
+    pub fn get_author(&self) -> Option<&str> {
+        self.author.as_deref()
+    }
+
+    /// Changes the status of the function. If the function becomes matching for the first time,
+    /// today's date (in UTC) is recorded in `date_matched`.
+    pub fn make_status_transition(&mut self, status: Status) {
+        self.make_status_transition_on(status, Utc::now().date_naive());
+    }
+
+    /// Same as `make_status_transition`, but records `date` instead of today's date.
+    pub fn make_status_transition_on(&mut self, status: Status, date: NaiveDate) {
+        if status == Status::Matching && self.date_matched.is_none() {
+            self.date_matched = Some(date);
+        }
+        self.info.status = status;
+    }
+}
+
+impl From<Info> for InfoV2 {
+    fn from(info: Info) -> Self {
+        Self {
+            info,
+            author: None,
+            date_matched: None,
+        }
+    }
+}
+
+impl std::ops::Deref for InfoV2 {
+    type Target = Info;
+
+    fn deref(&self) -> &Info {
+        &self.info
+    }
+}
+
+impl std::ops::DerefMut for InfoV2 {
+    fn deref_mut(&mut self) -> &mut Info {
+        &mut self.info
+    }
+}
+
+/// Returns all functions that are attributed to the specified contributor.
+pub fn get_functions_by_author<'a>(functions: &'a [InfoV2], author: &str) -> Vec<&'a InfoV2> {
+    functions
+        .iter()
+        .filter(|function| function.get_author() == Some(author))
+        .collect()
+}
+
+/// Returns all functions that were first marked as matching on or after the specified date.
+pub fn get_functions_matched_after(functions: &[InfoV2], date: NaiveDate) -> Vec<&InfoV2> {
+    functions
+        .iter()
+        .filter(|function| matches!(function.date_matched, Some(matched) if matched >= date))
+        .collect()
+}
+
+/// Returns all functions that were first marked as matching between `start` and `end`
+/// (both inclusive).
+pub fn get_functions_matched_between(
+    functions: &[InfoV2],
+    start: NaiveDate,
+    end: NaiveDate,
+) -> Vec<&InfoV2> {
+    functions
+        .iter()
+        .filter(|function| {
+            matches!(function.date_matched, Some(matched) if start <= matched && matched <= end)
+        })
+        .collect()
+}
+
+/// Computes progress statistics for each contributor.
+/// Functions that are not attributed to anyone are ignored.
+pub fn get_author_stats(functions: &[InfoV2]) -> FxHashMap<String, Stats> {
+    let mut stats: FxHashMap<String, Stats> = FxHashMap::default();
+    for function in functions {
+        if let Some(author) = function.get_author() {
+            stats.entry(author.to_string()).or_default().add(function);
+        }
+    }
+    stats
 }
 
 pub const CSV_HEADER: &[&str] = &["Address", "Quality", "Size", "Name"];
@@ -56,7 +327,8 @@ lazy_static! {
     };
 }
 
-fn parse_base_16(value: &str) -> Result<u64> {
+/// Parses a hexadecimal number. The `0x` prefix is optional.
+pub fn parse_hex_u64(value: &str) -> Result<u64> {
     if let Some(stripped) = value.strip_prefix("0x") {
         Ok(u64::from_str_radix(stripped, 16)?)
     } else {
@@ -64,160 +336,2834 @@ fn parse_base_16(value: &str) -> Result<u64> {
     }
 }
 
+/// Parses an address from the function list. This is the inverse of `format_addr`.
+///
+/// Any address is accepted, including data addresses; use `parse_code_address`
+/// for addresses that must point to an instruction.
 pub fn parse_address(value: &str) -> Result<u64> {
-    Ok(parse_base_16(value)? - ADDRESS_BASE)
+    let addr = parse_hex_u64(value)?;
+    addr.checked_sub(ADDRESS_BASE).with_context(|| {
+        format!(
+            "{:#x} is below the base address ({:#x})",
+            addr, ADDRESS_BASE
+        )
+    })
+}
+
+/// Alignment of AArch64 instructions.
+pub const CODE_ALIGNMENT: u64 = 4;
+
+/// Error for code addresses that do not point to the start of an instruction
+/// (e.g. Thumb-style odd addresses or addresses in the middle of an instruction).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MisalignedAddressError {
+    /// *Note*: does not contain the IDA base (0x7100000000).
+    pub addr: u64,
+}
+
+impl MisalignedAddressError {
+    /// Returns the closest aligned address below `addr`.
+    pub fn previous_aligned(&self) -> u64 {
+        self.addr & !(CODE_ALIGNMENT - 1)
+    }
+
+    /// Returns the closest aligned address above `addr`.
+    pub fn next_aligned(&self) -> u64 {
+        self.previous_aligned() + CODE_ALIGNMENT
+    }
+}
+
+impl std::fmt::Display for MisalignedAddressError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} is not {}-byte aligned; the nearest aligned addresses are {} and {}",
+            format_addr(self.addr),
+            CODE_ALIGNMENT,
+            format_addr(self.previous_aligned()),
+            format_addr(self.next_aligned())
+        )
+    }
+}
+
+impl std::error::Error for MisalignedAddressError {}
+
+/// Checks that a code address is aligned like an instruction.
+pub fn check_code_alignment(addr: u64) -> std::result::Result<(), MisalignedAddressError> {
+    if addr & (CODE_ALIGNMENT - 1) != 0 {
+        return Err(MisalignedAddressError { addr });
+    }
+    Ok(())
+}
+
+/// Same as `parse_address`, but also checks that the address is aligned like an instruction.
+/// Misaligned addresses return a `MisalignedAddressError`.
+///
+/// See `AddressIndex::parse_code_address` for a version that mentions the nearest function.
+pub fn parse_code_address(value: &str) -> Result<u64> {
+    let addr = parse_address(value)?;
+    check_code_alignment(addr)?;
+    Ok(addr)
+}
+
+/// Formats an address (which does not include `ADDRESS_BASE`) the way it is written
+/// in the function list, e.g. `0x0000007100001234`. This is the inverse of `parse_address`.
+pub fn format_addr(addr: u64) -> String {
+    format!("0x{:016x}", addr | ADDRESS_BASE)
 }
 
-fn parse_function_csv_entry(record: &csv::StringRecord) -> Result<Info> {
-    ensure!(record.len() == 4, "invalid record");
+/// Same as `parse_address`, but also accepts addresses that do not include `ADDRESS_BASE`.
+pub fn parse_address_or_offset(value: &str) -> Result<u64> {
+    let addr = parse_hex_u64(value)?;
+    Ok(addr.checked_sub(ADDRESS_BASE).unwrap_or(addr))
+}
+
+/// Parses a line that contains an address followed by a name, e.g. `0x71001a1234 nn::os::Foo`.
+/// The address may or may not include `ADDRESS_BASE` (see `parse_address_or_offset`);
+/// the name is everything after the first run of whitespace.
+pub fn parse_address_with_name(line: &str) -> Result<(u64, String)> {
+    let line = line.trim();
+    let (addr, name) = line
+        .split_once(char::is_whitespace)
+        .with_context(|| format!("expected an address and a name: {:?}", line))?;
+    let addr =
+        parse_address_or_offset(addr).with_context(|| format!("invalid address: {:?}", addr))?;
+    Ok((addr, name.trim_start().to_string()))
+}
+
+/// Reads a file with one address and name per line (see `parse_address_with_name`),
+/// e.g. from a disassembler script. Empty lines are skipped.
+pub fn parse_address_name_file(path: &Path) -> Result<Vec<(u64, String)>> {
+    let contents =
+        std::fs::read_to_string(path).with_context(|| format!("failed to read {:?}", path))?;
+    contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            parse_address_with_name(line)
+                .with_context(|| format!("failed to parse line {} of {:?}", i + 1, path))
+        })
+        .collect()
+}
 
-    let addr = parse_address(&record[0])?;
-    let status_code = record[1].chars().next();
-    let size = record[2].parse::<u32>()?;
-    let decomp_name = record[3].to_string();
+/// Parses a row of the function list.
+///
+/// This runs for every row, so the only allocations are for the name and the extra values:
+/// fields are parsed in place and error messages are only formatted on failure.
+pub(crate) fn parse_function_csv_entry(
+    record: &csv::StringRecord,
+    base: u64,
+    schema: &CsvSchema,
+) -> Result<Info> {
+    ensure!(record.len() == schema.column_count(), "invalid record");
+
+    let addr_field = &record[0];
+    let addr = u64::from_str_radix(addr_field.strip_prefix("0x").unwrap_or(addr_field), 16)
+        .map_err(|err| anyhow!("invalid address {:?}: {}", addr_field, err))?;
+    let addr = match addr.checked_sub(base) {
+        Some(addr) => addr,
+        None => bail!(
+            "address {} is lower than the base address {:#x}",
+            addr_field,
+            base
+        ),
+    };
 
-    let status = match status_code {
-        Some('m') => Status::NonMatchingMinor,
-        Some('M') => Status::NonMatchingMajor,
-        Some('O') => Status::Matching,
-        Some('U') => Status::NotDecompiled,
-        Some('W') => Status::Wip,
-        Some('L') => Status::Library,
-        Some(code) => bail!("unexpected status code: {}", code),
+    let status = match record[1].chars().next() {
+        Some(code) => match Status::from_code(code) {
+            Some(status) => status,
+            None => bail!("unexpected status code: {}", code),
+        },
         None => bail!("missing status code"),
     };
 
+    let size_field = &record[2];
+    let size = size_field
+        .parse::<u32>()
+        .map_err(|err| anyhow!("invalid size {:?}: {}", size_field, err))?;
+
+    // Reserve once instead of growing; this does not allocate for lists without extra columns.
+    let mut extra = IndexMap::with_capacity(schema.extra_columns.len());
+    for (column, field) in schema.extra_columns.iter().zip(record.iter().skip(4)) {
+        if let Some(value) = column.parse(field)? {
+            extra.insert(column.name.clone(), value);
+        }
+    }
+
     Ok(Info {
         addr,
         size,
-        name: decomp_name,
+        name: record[3].to_string(),
         status,
+        extra,
     })
 }
 
-/// Returns a Vec of all functions that are listed in the specified CSV.
-pub fn get_functions_for_path(csv_path: &Path) -> Result<Vec<Info>> {
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CsvFormat {
+    /// Comma-separated values.
+    Csv,
+    /// Tab-separated values.
+    Tsv,
+}
+
+impl CsvFormat {
+    fn delimiter(self) -> u8 {
+        match self {
+            CsvFormat::Csv => b',',
+            CsvFormat::Tsv => b'\t',
+        }
+    }
+
+    pub(crate) fn make_reader_builder(self) -> csv::ReaderBuilder {
+        let mut builder = csv::ReaderBuilder::new();
+        builder
+            .has_headers(false)
+            .quoting(false)
+            .delimiter(self.delimiter());
+        builder
+    }
+}
+
+/// Detects whether a function list is comma-separated or tab-separated from its header line.
+pub fn detect_format(first_line: &str) -> CsvFormat {
+    if first_line.contains('\t') && !first_line.contains(',') {
+        CsvFormat::Tsv
+    } else {
+        CsvFormat::Csv
+    }
+}
+
+/// Version of the function list format.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CsvFormatVersion {
+    /// Legacy format from before commit 1d4c815fbae3: no header, or a header with other
+    /// column names or another column order (see `convert::from_legacy`).
+    V1,
+    /// Current format, with `CSV_HEADER`.
+    V2,
+    /// Empty files and files that are not function lists.
+    Unknown,
+}
+
+/// Detects the format of a function list from its first line.
+pub fn detect_csv_format_version(csv_path: &Path) -> Result<CsvFormatVersion> {
+    // Same settings as convert::from_legacy, so that anything classified as V1 can be converted.
     let mut reader = csv::ReaderBuilder::new()
         .has_headers(false)
-        .quoting(false)
-        .from_path(csv_path)?;
+        .flexible(true)
+        .comment(Some(b'#'))
+        .from_path(csv_path)
+        .with_context(|| format!("failed to open {:?}", csv_path))?;
 
-    // We build the result array manually without using csv iterators for performance reasons.
-    let mut result = Vec::with_capacity(110_000);
     let mut record = csv::StringRecord::new();
-    let mut line_number = 1;
-    let mut num_names = 0;
-    if reader.read_record(&mut record)? {
-        // Verify that the CSV has the correct format.
-        ensure!(record.len() == 4, "invalid record; expected 4 fields");
-        ensure!(record == *CSV_HEADER,
-            "wrong CSV format; this program only works with the new function list format (added in commit 1d4c815fbae3)"
-        );
-        line_number += 1;
+    if !reader.read_record(&mut record)? {
+        return Ok(CsvFormatVersion::Unknown);
     }
+    Ok(get_format_version_for_first_record(&record))
+}
 
-    while reader.read_record(&mut record)? {
-        let entry = parse_function_csv_entry(&record)
-            .with_context(|| format!("failed to parse CSV record at line {}", line_number))?;
+fn get_format_version_for_first_record(record: &csv::StringRecord) -> CsvFormatVersion {
+    if record.len() >= CSV_HEADER.len() && record.iter().take(4).eq(CSV_HEADER.iter().copied()) {
+        CsvFormatVersion::V2
+    } else if convert::is_legacy_header_or_entry(record) {
+        CsvFormatVersion::V1
+    } else {
+        CsvFormatVersion::Unknown
+    }
+}
+
+/// Number of bytes at the start of a function list that are used to estimate how many
+/// functions are in the list.
+const FUNCTION_COUNT_SAMPLE_LEN: u64 = 64 * 1024;
+
+/// Returns roughly how many functions are listed in a function list of `file_len` bytes
+/// that starts with `sample`, assuming that rows have the same average length as in the sample.
+/// The estimate is rounded up by 10%: rows vary in length across the list (e.g. between
+/// regions with mostly named and mostly unnamed functions) and reallocating is more expensive
+/// than allocating slightly too much.
+fn estimate_function_count(sample: &[u8], file_len: u64) -> usize {
+    let num_lines = sample.iter().filter(|&&c| c == b'\n').count();
+    if num_lines == 0 || sample.len() as u64 >= file_len {
+        return num_lines;
+    }
+    let estimate = file_len as f64 * num_lines as f64 / sample.len() as f64;
+    (estimate * 1.1) as usize
+}
+
+/// Returns the number of functions in the project (`expected_function_count` in the config),
+/// if it is set. This is only used as a size hint.
+fn get_expected_function_count() -> Option<usize> {
+    repo::CONFIG
+        .get("expected_function_count")
+        .and_then(toml::Value::as_integer)
+        .and_then(|count| usize::try_from(count).ok())
+}
 
-        if !entry.name.is_empty() {
-            num_names += 1;
+/// Opens a function list and returns a reader for it, together with the expected number
+/// of functions. If `expected_count` is None, the number of functions is estimated from the
+/// start and the size of the file (see `estimate_function_count`).
+fn open_functions_reader(
+    csv_path: &Path,
+    expected_count: Option<usize>,
+) -> Result<(csv::Reader<impl Read>, usize)> {
+    let mut file = File::open(csv_path)?;
+    let mut sample = Vec::new();
+    let capacity = match expected_count {
+        Some(count) => count,
+        None => {
+            (&mut file)
+                .take(FUNCTION_COUNT_SAMPLE_LEN)
+                .read_to_end(&mut sample)?;
+            estimate_function_count(&sample, file.metadata()?.len())
         }
+    };
+    let reader = CsvFormat::Csv
+        .make_reader_builder()
+        .from_reader(std::io::Cursor::new(sample).chain(file));
+    Ok((reader, capacity))
+}
 
-        result.push(entry);
-        line_number += 1;
+/// Reads a function list. See `open_functions_reader` for `expected_count`.
+fn read_functions_for_path(
+    csv_path: &Path,
+    base: u64,
+    expected_count: Option<usize>,
+) -> Result<Vec<Info>> {
+    let (reader, capacity) = open_functions_reader(csv_path, expected_count)?;
+    parse_functions(reader, base, capacity)
+}
+
+/// Returns a Vec of all functions that are listed in the specified CSV.
+pub fn get_functions_for_path(csv_path: &Path) -> Result<Vec<Info>> {
+    read_functions_for_path(csv_path, ADDRESS_BASE, None)
+}
+
+/// Same as `get_functions_for_path`, but waits for edits that are in progress to be written first
+/// (see `lock::lock_shared`).
+pub fn get_functions_for_path_shared(csv_path: &Path, options: &LockOptions) -> Result<Vec<Info>> {
+    let _lock = lock::lock_shared(csv_path, options)?;
+    get_functions_for_path(csv_path)
+}
+
+/// Same as `get_functions_for_path`, but for executables that are not loaded at `ADDRESS_BASE`.
+/// Addresses in the returned list are relative to `base`.
+pub fn get_functions_for_path_with_base(csv_path: &Path, base: u64) -> Result<Vec<Info>> {
+    read_functions_for_path(csv_path, base, None)
+}
+
+/// Same as `get_functions_for_path`, but the base address is determined from the NSO
+/// the function list is for. See `nso::detect_address_base_from_nso`.
+pub fn get_functions_for_path_autobase(csv_path: &Path, nso_path: &Path) -> Result<Vec<Info>> {
+    let nso_bytes = std::fs::read(nso_path)?;
+    let base = nso::detect_address_base_from_nso(&nso_bytes)
+        .with_context(|| format!("failed to detect base address from {:?}", nso_path))?;
+    get_functions_for_path_with_base(csv_path, base)
+}
+
+/// A function name that is listed in more than one of the function lists that were merged
+/// by `get_functions_for_multiple_paths`.
+#[derive(Clone, Debug)]
+pub struct CrossFileDuplicate {
+    pub name: String,
+    /// Address of each entry with this name and the path of the list it is in.
+    pub entries: Vec<(u64, PathBuf)>,
+}
+
+/// A function name that is listed several times in one of the function lists that were
+/// merged by `get_functions_for_multiple_paths`.
+#[derive(Clone, Debug)]
+pub struct InFileDuplicate {
+    pub name: String,
+    pub path: PathBuf,
+    /// Address of each entry with this name, in file order.
+    pub addrs: Vec<u64>,
+}
+
+/// Problems that were found while merging function lists with `get_functions_for_multiple_paths`.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct MergeWarnings {
+    /// Names that are listed in several files for the same address. Only the entry from
+    /// the first file is kept.
+    pub aliases: Vec<CrossFileDuplicate>,
+    /// Names that are listed in several files for different addresses. All entries are kept.
+    pub conflicts: Vec<CrossFileDuplicate>,
+    /// Addresses that are listed in several files under different names, with the paths
+    /// of the lists they are in.
+    pub shared_addresses: Vec<(u64, Vec<PathBuf>)>,
+    /// Names that are listed several times in the same file. Entries that have the same
+    /// address are only kept once.
+    pub in_file_duplicates: Vec<InFileDuplicate>,
+}
+
+impl MergeWarnings {
+    pub fn is_empty(&self) -> bool {
+        self.aliases.is_empty()
+            && self.conflicts.is_empty()
+            && self.shared_addresses.is_empty()
+            && self.in_file_duplicates.is_empty()
     }
+}
 
-    // Check for duplicate names in the CSV.
-    let mut known_names = HashSet::with_capacity(num_names);
-    let mut duplicates = Vec::new();
-    for entry in &result {
-        if entry.is_decompiled() && entry.name.is_empty() {
-            bail!(
-                "function at {:016x} is marked as O/M/m but has an empty name",
-                entry.addr | ADDRESS_BASE
-            );
+/// Reads several function lists (e.g. one per module) in parallel and merges them into
+/// a single list in canonical order (see `canonicalize`).
+///
+/// Entries that are listed in several files are reported in the returned `MergeWarnings`.
+pub fn get_functions_for_multiple_paths(csv_paths: &[&Path]) -> Result<(Vec<Info>, MergeWarnings)> {
+    // Duplicate names are reported in the warnings instead of being rejected.
+    let lists = csv_paths
+        .par_iter()
+        .map(|path| {
+            open_functions_reader(path, None)
+                .and_then(|(reader, capacity)| {
+                    parse_functions_allow_duplicates(reader, ADDRESS_BASE, capacity)
+                })
+                .with_context(|| format!("failed to read {:?}", path))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut warnings = MergeWarnings::default();
+    {
+        // Entries are in file order.
+        let mut entries_by_name: FxHashMap<&str, Vec<(u64, usize)>> = FxHashMap::default();
+        let mut names_by_addr: FxHashMap<u64, Vec<(usize, &str)>> = FxHashMap::default();
+        for (i, list) in lists.iter().enumerate() {
+            for info in list {
+                if !info.name.is_empty() {
+                    entries_by_name
+                        .entry(&info.name)
+                        .or_default()
+                        .push((info.addr, i));
+                }
+                names_by_addr
+                    .entry(info.addr)
+                    .or_default()
+                    .push((i, &info.name));
+            }
         }
 
-        if !entry.name.is_empty() && !known_names.insert(&entry.name) {
-            duplicates.push(&entry.name);
+        for (name, entries) in entries_by_name {
+            let mut addrs_by_file: Vec<(usize, Vec<u64>)> = Vec::new();
+            for &(addr, i) in &entries {
+                match addrs_by_file.last_mut() {
+                    Some((j, addrs)) if *j == i => addrs.push(addr),
+                    _ => addrs_by_file.push((i, vec![addr])),
+                }
+            }
+            for (i, addrs) in &addrs_by_file {
+                if addrs.len() > 1 {
+                    warnings.in_file_duplicates.push(InFileDuplicate {
+                        name: name.to_string(),
+                        path: csv_paths[*i].to_path_buf(),
+                        addrs: addrs.clone(),
+                    });
+                }
+            }
+            if addrs_by_file.len() < 2 {
+                continue;
+            }
+            let duplicate = CrossFileDuplicate {
+                name: name.to_string(),
+                entries: entries
+                    .iter()
+                    .map(|&(addr, i)| (addr, csv_paths[i].to_path_buf()))
+                    .collect(),
+            };
+            if entries.iter().all(|&(addr, _)| addr == entries[0].0) {
+                warnings.aliases.push(duplicate);
+            } else {
+                warnings.conflicts.push(duplicate);
+            }
         }
+        warnings.aliases.sort_by(|a, b| a.name.cmp(&b.name));
+        warnings.conflicts.sort_by(|a, b| a.name.cmp(&b.name));
+        warnings
+            .in_file_duplicates
+            .sort_by(|a, b| (&a.path, &a.name).cmp(&(&b.path, &b.name)));
+
+        for (addr, names) in names_by_addr {
+            // Entries are in file order.
+            let mut names_by_file: Vec<(usize, Vec<&str>)> = Vec::new();
+            for (i, name) in names {
+                match names_by_file.last_mut() {
+                    Some((j, names)) if *j == i => names.push(name),
+                    _ => names_by_file.push((i, vec![name])),
+                }
+            }
+            if names_by_file.len() < 2 {
+                continue;
+            }
+            for (_, names) in &mut names_by_file {
+                names.sort_unstable();
+            }
+            // Files that list the same names for an address only contain aliases,
+            // which are already reported.
+            if names_by_file
+                .iter()
+                .any(|(_, names)| *names != names_by_file[0].1)
+            {
+                warnings.shared_addresses.push((
+                    addr,
+                    names_by_file
+                        .iter()
+                        .map(|&(i, _)| csv_paths[i].to_path_buf())
+                        .collect(),
+                ));
+            }
+        }
+        warnings.shared_addresses.sort_by_key(|(addr, _)| *addr);
     }
-    if !duplicates.is_empty() {
-        bail!("found duplicates: {:#?}", duplicates);
+
+    let mut seen_names = HashSet::new();
+    let mut functions = Vec::with_capacity(lists.iter().map(Vec::len).sum());
+    for info in lists.into_iter().flatten() {
+        if info.name.is_empty() || seen_names.insert((info.addr, info.name.clone())) {
+            functions.push(info);
+        }
     }
+    canonicalize(&mut functions);
+    Ok((functions, warnings))
+}
 
-    Ok(result)
+/// An address that is listed in several of the lists that were passed to `check_disjoint_lists`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AddressConflict {
+    /// *Note*: does not contain the IDA base (0x7100000000).
+    pub addr: u64,
+    /// Indices of the lists that contain the address, in ascending order.
+    pub lists: Vec<usize>,
 }
 
-pub fn write_functions_to_path(csv_path: &Path, functions: &[Info]) -> Result<()> {
-    let mut writer = csv::Writer::from_path(csv_path)?;
-    writer.write_record(CSV_HEADER)?;
+/// Returns the addresses that are listed in more than one of `lists`, sorted by address.
+/// Aliases within a single list are not conflicts.
+pub fn check_disjoint_lists(lists: &[&[Info]]) -> Vec<AddressConflict> {
+    let mut lists_by_addr: FxHashMap<u64, Vec<usize>> = FxHashMap::default();
+    for (i, list) in lists.iter().enumerate() {
+        for info in list.iter() {
+            let entry = lists_by_addr.entry(info.addr).or_default();
+            if entry.last() != Some(&i) {
+                entry.push(i);
+            }
+        }
+    }
 
-    for function in functions {
-        let addr = format!("0x{:016x}", function.addr | ADDRESS_BASE);
-        let status = match function.status {
-            Status::Matching => "O",
-            Status::NonMatchingMinor => "m",
-            Status::NonMatchingMajor => "M",
-            Status::NotDecompiled => "U",
-            Status::Wip => "W",
-            Status::Library => "L",
-        }
-        .to_string();
-        let size = format!("{:06}", function.size);
-        let name = function.name.clone();
-        writer.write_record(&[addr, status, size, name])?;
+    let mut conflicts: Vec<AddressConflict> = lists_by_addr
+        .into_iter()
+        .filter(|(_, lists)| lists.len() > 1)
+        .map(|(addr, lists)| AddressConflict { addr, lists })
+        .collect();
+    conflicts.sort_unstable_by_key(|conflict| conflict.addr);
+    conflicts
+}
+
+/// Merges function lists that cover disjoint sets of addresses (e.g. sub-lists for game code
+/// and engine code that are maintained separately) into a single list in canonical order.
+///
+/// Unlike `get_functions_for_multiple_paths`, which reports overlapping entries and keeps them,
+/// this fails if any address is listed in more than one list, as that means a function
+/// was assigned to the wrong list. See `check_disjoint_lists`.
+pub fn interleave_function_lists(lists: &[&[Info]]) -> Result<Vec<Info>> {
+    let conflicts = check_disjoint_lists(lists);
+    if !conflicts.is_empty() {
+        let descriptions: Vec<String> = conflicts
+            .iter()
+            .map(|conflict| {
+                let lists: Vec<String> = conflict.lists.iter().map(usize::to_string).collect();
+                format!(
+                    "{} (lists {})",
+                    format_addr(conflict.addr),
+                    lists.join(", ")
+                )
+            })
+            .collect();
+        bail!(
+            "{} address(es) are listed in more than one function list:\n{}",
+            conflicts.len(),
+            descriptions.join("\n")
+        );
     }
 
-    Ok(())
+    let mut functions = Vec::with_capacity(lists.iter().map(|list| list.len()).sum());
+    for list in lists {
+        functions.extend_from_slice(list);
+    }
+    canonicalize(&mut functions);
+    Ok(functions)
 }
 
-/// Returns a Vec of all known functions in the executable.
-pub fn get_functions() -> Result<Vec<Info>> {
-    get_functions_for_path(FUNCTIONS_CSV_PATH.as_path())
+/// Shifts every address by `new_base - old_base`, e.g. for a binary that was dumped with
+/// different segment offsets than the one the function list was made for.
+///
+/// Fails if any address would fall outside of the address space.
+pub fn remap_function_addresses(
+    mut functions: Vec<Info>,
+    old_base: u64,
+    new_base: u64,
+) -> Result<Vec<Info>> {
+    let delta = new_base as i128 - old_base as i128;
+    for info in &mut functions {
+        let new_addr = info.addr as i128 + delta;
+        ensure!(
+            (0..=u64::MAX as i128).contains(&new_addr),
+            "cannot remap {} ({}) from base {:#x} to base {:#x}: the address would overflow",
+            format_addr(info.addr),
+            info.name,
+            old_base,
+            new_base
+        );
+        info.addr = new_addr as u64;
+    }
+    Ok(functions)
 }
 
-pub fn write_functions(functions: &[Info]) -> Result<()> {
-    write_functions_to_path(FUNCTIONS_CSV_PATH.as_path(), functions)
+/// Returns the content hash (see `Info::content_hash`) of every function, in list order.
+pub fn content_hash_all(functions: &[Info]) -> Vec<u64> {
+    functions.par_iter().map(Info::content_hash).collect()
 }
 
-pub fn make_known_function_map(functions: &[Info]) -> FxHashMap<u64, &Info> {
-    let mut known_functions =
-        FxHashMap::with_capacity_and_hasher(functions.len(), Default::default());
+/// Pairs up the entries of two versions of a function list by address and returns the pairs
+/// whose content hash differs, sorted by address. Added entries have no `before` entry and
+/// removed entries have no `after` entry.
+///
+/// Entries that share an address (aliases) are paired by content first, then by name,
+/// then in list order.
+pub fn find_changed_functions_by_hash<'a>(
+    before: &'a [Info],
+    after: &'a [Info],
+) -> Vec<(Option<&'a Info>, Option<&'a Info>)> {
+    type Entry<'a> = (&'a Info, u64);
+    type Entries<'a> = Vec<Option<Entry<'a>>>;
+    let mut by_addr: BTreeMap<u64, (Entries<'a>, Entries<'a>)> = BTreeMap::new();
+    for (info, hash) in before.iter().zip(content_hash_all(before)) {
+        by_addr
+            .entry(info.addr)
+            .or_default()
+            .0
+            .push(Some((info, hash)));
+    }
+    for (info, hash) in after.iter().zip(content_hash_all(after)) {
+        by_addr
+            .entry(info.addr)
+            .or_default()
+            .1
+            .push(Some((info, hash)));
+    }
 
-    for function in functions {
-        if function.name.is_empty() {
-            continue;
-        }
-        known_functions.insert(function.addr, function);
+    let mut changed = Vec::new();
+    for (_, (mut old_entries, mut new_entries)) in by_addr {
+        // Drops unchanged entries and pairs up entries that satisfy `is_pair`.
+        let mut pair_up = |is_pair: fn(&Entry, &Entry) -> bool, keep: bool| {
+            for old in old_entries.iter_mut() {
+                let old_entry = match old {
+                    Some(entry) => *entry,
+                    None => continue,
+                };
+                let position = new_entries.iter().position(|new| match new {
+                    Some(new_entry) => is_pair(&old_entry, new_entry),
+                    None => false,
+                });
+                if let Some(position) = position {
+                    let (new_info, _) = new_entries[position].take().unwrap();
+                    *old = None;
+                    if keep {
+                        changed.push((Some(old_entry.0), Some(new_info)));
+                    }
+                }
+            }
+        };
+        pair_up(|old, new| old.1 == new.1, false);
+        pair_up(|old, new| old.0.name == new.0.name, true);
+        pair_up(|_, _| true, true);
+
+        changed.extend(
+            old_entries
+                .into_iter()
+                .flatten()
+                .map(|(info, _)| (Some(info), None)),
+        );
+        changed.extend(
+            new_entries
+                .into_iter()
+                .flatten()
+                .map(|(info, _)| (None, Some(info))),
+        );
     }
+    changed
+}
 
-    known_functions
+/// Returns a Vec of all functions that are listed in the CSV read from `reader`.
+pub fn get_functions_for_reader(reader: &mut dyn Read) -> Result<Vec<Info>> {
+    parse_functions(
+        CsvFormat::Csv.make_reader_builder().from_reader(reader),
+        ADDRESS_BASE,
+        0,
+    )
 }
 
-/// Demangle a C++ symbol.
-pub fn demangle_str(name: &str) -> Result<String> {
-    if !name.starts_with("_Z") {
-        bail!("not an external mangled name");
+/// Same as `get_functions_for_reader`, but for tab-separated values.
+pub fn read_functions_tsv(reader: &mut dyn Read) -> Result<Vec<Info>> {
+    parse_functions(
+        CsvFormat::Tsv.make_reader_builder().from_reader(reader),
+        ADDRESS_BASE,
+        0,
+    )
+}
+
+/// Reads a function list and checks that names are unique.
+/// `capacity` is a hint for the number of functions in the list.
+pub(crate) fn parse_functions<R: Read>(
+    reader: csv::Reader<R>,
+    base: u64,
+    capacity: usize,
+) -> Result<Vec<Info>> {
+    let result = parse_functions_allow_duplicates(reader, base, capacity)?;
+
+    // Check for duplicate names in the CSV.
+    let mut known_names = HashSet::with_capacity(result.len());
+    let mut duplicates = Vec::new();
+    for entry in &result {
+        if !entry.name.is_empty() && !known_names.insert(&entry.name) {
+            duplicates.push(&entry.name);
+        }
+    }
+    if !duplicates.is_empty() {
+        bail!("found duplicates: {:#?}", duplicates);
     }
 
-    let symbol = cpp_demangle::Symbol::new(name)?;
-    let options = cpp_demangle::DemangleOptions::new();
-    Ok(symbol.demangle(&options)?)
+    Ok(result)
 }
 
-pub fn find_function_fuzzy<'a>(functions: &'a [Info], name: &str) -> Option<&'a Info> {
-    functions
-        .par_iter()
-        .find_first(|function| function.name == name)
-        .or_else(|| {
-            // Comparing the demangled names is more expensive than a simple string comparison,
-            // so only do this as a last resort.
-            functions.par_iter().find_first(|function| {
-                demangle_str(&function.name)
-                    .unwrap_or_else(|_| "".to_string())
-                    .contains(name)
-            })
+/// Same as `parse_functions`, but names can be listed several times. Errors are reported
+/// with the line number of the record, which accounts for lines that `reader` skips
+/// (e.g. comments).
+fn parse_functions_allow_duplicates<R: Read>(
+    mut reader: csv::Reader<R>,
+    base: u64,
+    capacity: usize,
+) -> Result<Vec<Info>> {
+    // We build the result array manually without using csv iterators for performance reasons.
+    let mut result = Vec::with_capacity(capacity);
+    let mut record = csv::StringRecord::new();
+    let mut schema = CsvSchema::default();
+    if reader.read_record(&mut record)? {
+        // Verify that the CSV has the correct format.
+        // Reuse the header to detect legacy lists instead of opening the file again.
+        match get_format_version_for_first_record(&record) {
+            CsvFormatVersion::V2 => {}
+            CsvFormatVersion::V1 => bail!(
+                "the function list is in the legacy format (from before commit 1d4c815fbae3); \
+                 convert it with convert::migrate_csv_v1_header"
+            ),
+            CsvFormatVersion::Unknown => bail!(
+                "wrong CSV format; this program only works with the new function list format (added in commit 1d4c815fbae3). Old lists can be converted with convert::migrate_csv_v1_header"
+            ),
+        }
+        schema = CsvSchema::for_header(&record)?;
+    }
+
+    while reader.read_record(&mut record)? {
+        // Only build the error context on the failure path: this loop runs for every row.
+        let entry = match parse_function_csv_entry(&record, base, &schema) {
+            Ok(entry) => entry,
+            Err(err) => {
+                let line = record.position().map_or(0, csv::Position::line);
+                return Err(err.context(format!("failed to parse CSV record at line {}", line)));
+            }
+        };
+
+        if entry.is_decompiled() && entry.name.is_empty() {
+            bail!(
+                "function at {} is marked as O/M/m but has an empty name",
+                format_addr(entry.addr)
+            );
+        }
+
+        result.push(entry);
+    }
+
+    Ok(result)
+}
+
+/// Writes a function list. Extra columns are only written if some functions have extra values;
+/// see `schema::CsvSchema::for_functions`.
+pub fn write_functions_to_writer(writer: &mut dyn Write, functions: &[Info]) -> Result<()> {
+    let schema = CsvSchema::for_functions(functions)?;
+    write_functions_ex(csv::Writer::from_writer(writer), functions, &schema)
+}
+
+/// Writes a line-by-line comparison of the function lists `current` and `proposed`, as they
+/// would be written by `write_functions_to_writer`. Removed lines are prefixed with `-`, added
+/// lines with `+` and changed lines with `~` (followed by the old and the new line).
+/// Every run of unchanged lines is replaced with `... N unchanged lines ...`.
+pub fn write_functions_preview(
+    current: &[Info],
+    proposed: &[Info],
+    writer: &mut dyn Write,
+) -> Result<()> {
+    let serialize = |functions: &[Info]| -> Result<String> {
+        let mut contents = Vec::new();
+        write_functions_to_writer(&mut contents, functions)?;
+        Ok(String::from_utf8(contents)?)
+    };
+    let current_contents = serialize(current)?;
+    let proposed_contents = serialize(proposed)?;
+    let current_lines: Vec<&str> = current_contents.lines().collect();
+    let proposed_lines: Vec<&str> = proposed_contents.lines().collect();
+
+    let ops =
+        similar::capture_diff_slices(similar::Algorithm::Myers, &current_lines, &proposed_lines);
+    for op in ops {
+        match op {
+            similar::DiffOp::Equal { len, .. } => {
+                let noun = if len == 1 { "line" } else { "lines" };
+                writeln!(writer, "... {} unchanged {} ...", len, noun)?;
+            }
+            similar::DiffOp::Delete {
+                old_index, old_len, ..
+            } => {
+                for line in &current_lines[old_index..old_index + old_len] {
+                    writeln!(writer, "- {}", line)?;
+                }
+            }
+            similar::DiffOp::Insert {
+                new_index, new_len, ..
+            } => {
+                for line in &proposed_lines[new_index..new_index + new_len] {
+                    writeln!(writer, "+ {}", line)?;
+                }
+            }
+            similar::DiffOp::Replace {
+                old_index,
+                old_len,
+                new_index,
+                new_len,
+            } => {
+                let old = &current_lines[old_index..old_index + old_len];
+                let new = &proposed_lines[new_index..new_index + new_len];
+                for i in 0..old_len.max(new_len) {
+                    match (old.get(i), new.get(i)) {
+                        (Some(old), Some(new)) => writeln!(writer, "~ {} -> {}", old, new)?,
+                        (Some(old), None) => writeln!(writer, "- {}", old)?,
+                        (None, Some(new)) => writeln!(writer, "+ {}", new)?,
+                        (None, None) => unreachable!(),
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Same as `write_functions_to_writer`, but writes tab-separated values.
+/// Names must not contain tabs.
+pub fn write_functions_tsv(writer: &mut dyn Write, functions: &[Info]) -> Result<()> {
+    if let Some(function) = functions.iter().find(|info| info.name.contains('\t')) {
+        bail!(
+            "name of function at {} contains a tab: {:?}",
+            format_addr(function.addr),
+            function.name
+        );
+    }
+
+    let writer = csv::WriterBuilder::new()
+        .delimiter(CsvFormat::Tsv.delimiter())
+        .quote_style(csv::QuoteStyle::Never)
+        .from_writer(writer);
+    let schema = CsvSchema::for_functions(functions)?;
+    write_functions_ex(writer, functions, &schema)
+}
+
+fn write_functions_ex<W: Write>(
+    mut writer: csv::Writer<W>,
+    functions: &[Info],
+    schema: &CsvSchema,
+) -> Result<()> {
+    writer.write_record(schema.header())?;
+    write_function_records(&mut writer, functions, schema)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Returns the lines (without line terminators) that `write_functions_to_writer` writes for
+/// `functions` with `schema`, without the header line unless `include_header` is set.
+pub(crate) fn serialize_function_lines(
+    functions: &[Info],
+    schema: &CsvSchema,
+    include_header: bool,
+) -> Result<Vec<String>> {
+    let mut contents = Vec::new();
+    {
+        let mut writer = csv::Writer::from_writer(&mut contents);
+        if include_header {
+            writer.write_record(schema.header())?;
+        }
+        write_function_records(&mut writer, functions, schema)?;
+        writer.flush()?;
+    }
+    Ok(String::from_utf8(contents)?
+        .lines()
+        .map(str::to_string)
+        .collect())
+}
+
+fn write_function_records<W: Write>(
+    writer: &mut csv::Writer<W>,
+    functions: &[Info],
+    schema: &CsvSchema,
+) -> Result<()> {
+    for function in functions {
+        let addr = format_addr(function.addr);
+        let status = function.status.code().to_string();
+        let size = format!("{:06}", function.size);
+        let name = function.name.clone();
+        if schema.extra_columns.is_empty() {
+            writer.write_record(&[addr, status, size, name])?;
+        } else {
+            let extra = schema.get_extra_fields(function).with_context(|| {
+                format!("invalid extra values for {}", format_addr(function.addr))
+            })?;
+            writer.write_record([addr, status, size, name].iter().chain(&extra))?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes a function list with the project's options (see `WriteOptions::from_config`).
+/// The columns of the existing file are kept, even if all of their fields are now empty.
+///
+/// *Note*: like the other writers, this does not lock the file. Code that reads a list,
+/// changes it and writes it back must hold `lock::lock_exclusive` for the whole cycle so that
+/// concurrent changes are not lost; `update_functions_at_path` does that.
+pub fn write_functions_to_path(csv_path: &Path, functions: &[Info]) -> Result<()> {
+    write_functions_to_path_ex(csv_path, functions, &WriteOptions::from_config())
+}
+
+/// Default for `WriteOptions::max_shrink_fraction`.
+pub const DEFAULT_MAX_SHRINK_FRACTION: f64 = 0.01;
+
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct WriteOptions {
+    /// Fail instead of writing a function list that is not in canonical order
+    /// (see `canonicalize`).
+    pub require_canonical_order: bool,
+    /// Fail instead of overwriting a function list with one that has fewer rows, if more than
+    /// this fraction of the existing rows would disappear (e.g. 0.01 for 1%).
+    pub max_shrink_fraction: f64,
+    /// Skip the `max_shrink_fraction` check and allow overwriting lists that cannot be read,
+    /// e.g. for intentional migrations.
+    pub allow_shrink: bool,
+    /// How many backups of the function list to keep in the repo's backup directory
+    /// (see `undo_last_write`). 0 disables backups.
+    pub max_backups: usize,
+}
+
+impl Default for WriteOptions {
+    fn default() -> Self {
+        Self {
+            require_canonical_order: false,
+            max_shrink_fraction: DEFAULT_MAX_SHRINK_FRACTION,
+            allow_shrink: false,
+            max_backups: 0,
+        }
+    }
+}
+
+impl WriteOptions {
+    /// Returns the options for the project's function list. Canonical order is required
+    /// if `require_canonical_order` is set in the config, the shrink threshold can be
+    /// changed with `max_shrink_fraction` and backups are enabled with `max_csv_backups`.
+    pub fn from_config() -> Self {
+        Self {
+            require_canonical_order: repo::CONFIG
+                .get("require_canonical_order")
+                .and_then(toml::Value::as_bool)
+                .unwrap_or(false),
+            max_shrink_fraction: repo::CONFIG
+                .get("max_shrink_fraction")
+                .and_then(toml::Value::as_float)
+                .unwrap_or(DEFAULT_MAX_SHRINK_FRACTION),
+            allow_shrink: false,
+            max_backups: backup::get_max_backups(),
+        }
+    }
+
+    fn check(&self, csv_path: &Path, functions: &[Info]) -> Result<()> {
+        if self.require_canonical_order {
+            if let Some(i) = find_non_canonical_entry(functions) {
+                bail!(
+                    "function list is not in canonical order: {} ({}) is out of place",
+                    format_addr(functions[i].addr),
+                    functions[i].name
+                );
+            }
+        }
+        self.check_size_change(csv_path, functions)
+    }
+
+    /// Compares the new function list against the one at `csv_path` (if any)
+    /// and prints the change in row count and total size.
+    ///
+    /// Existing files that cannot be read can only be overwritten with `allow_shrink`,
+    /// since there is no way to tell how many rows would be lost.
+    fn check_size_change(&self, csv_path: &Path, functions: &[Info]) -> Result<()> {
+        // There is nothing to compare against for new files.
+        if !csv_path.exists() {
+            return Ok(());
+        }
+        match get_functions_for_path(csv_path) {
+            Ok(old_functions) => self.check_shrink(csv_path, &old_functions, functions),
+            Err(_) if self.allow_shrink => Ok(()),
+            Err(err) => Err(err.context(format!(
+                "refusing to write {:?}: the existing list could not be read to check how many \
+                 rows would be removed (set allow_shrink to overwrite it anyway)",
+                csv_path
+            ))),
+        }
+    }
+
+    /// Same as the check that is done before writing `csv_path`, but against `old_functions`
+    /// instead of the file on disk, e.g. the version of the function list in a git commit.
+    /// Fails if too many rows were removed (see `max_shrink_fraction`), otherwise prints
+    /// the change in row count and total size.
+    pub fn check_shrink(
+        &self,
+        csv_path: &Path,
+        old_functions: &[Info],
+        functions: &[Info],
+    ) -> Result<()> {
+        if old_functions.is_empty() {
+            return Ok(());
+        }
+
+        let total_size =
+            |functions: &[Info]| -> u64 { functions.iter().map(|info| info.size as u64).sum() };
+        let (old_rows, new_rows) = (old_functions.len(), functions.len());
+        let (old_bytes, new_bytes) = (total_size(old_functions), total_size(functions));
+        if old_rows == new_rows && old_bytes == new_bytes {
+            return Ok(());
+        }
+
+        let delta = format!(
+            "{} -> {} rows ({:+}), {} -> {} bytes ({:+})",
+            old_rows,
+            new_rows,
+            new_rows as i64 - old_rows as i64,
+            old_bytes,
+            new_bytes,
+            new_bytes as i64 - old_bytes as i64
+        );
+
+        let removed_rows = old_rows.saturating_sub(new_rows);
+        if !self.allow_shrink && removed_rows as f64 > old_rows as f64 * self.max_shrink_fraction {
+            bail!(
+                "refusing to write {:?}: {}; more than {}% of the rows would be removed \
+                 (set allow_shrink or raise max_shrink_fraction if this is intended)",
+                csv_path,
+                delta,
+                self.max_shrink_fraction * 100.0
+            );
+        }
+
+        ui::print_note(&format!("{:?}: {}", csv_path, delta));
+        Ok(())
+    }
+}
+
+/// Returns the schema for replacing the function list at `csv_path` with `functions`: the one
+/// the existing file was written with, so that declared columns are kept even if all of their
+/// fields are now empty. New lists and lists with the standard columns only get
+/// `CsvSchema::for_functions`.
+pub(crate) fn get_schema_for_path(csv_path: &Path, functions: &[Info]) -> Result<CsvSchema> {
+    let file = match File::open(csv_path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            return CsvSchema::for_functions(functions)
+        }
+        Err(err) => return Err(err).with_context(|| format!("failed to open {:?}", csv_path)),
+    };
+    let mut header = String::new();
+    BufReader::new(file).read_line(&mut header)?;
+    let mut record = csv::StringRecord::new();
+    let has_header = detect_format(&header)
+        .make_reader_builder()
+        .from_reader(header.as_bytes())
+        .read_record(&mut record)?;
+    if has_header && get_format_version_for_first_record(&record) == CsvFormatVersion::V2 {
+        let schema = CsvSchema::for_header(&record)?;
+        if !schema.extra_columns.is_empty() {
+            return Ok(schema);
+        }
+    }
+    CsvSchema::for_functions(functions)
+}
+
+/// Serializes `functions` for replacing the function list at `csv_path`
+/// (see `get_schema_for_path`).
+fn serialize_functions_for_path(csv_path: &Path, functions: &[Info]) -> Result<Vec<u8>> {
+    serialize_functions(functions, &get_schema_for_path(csv_path, functions)?)
+}
+
+/// Returns what `write_functions_to_writer` would write for `functions` with `schema`.
+pub(crate) fn serialize_functions(functions: &[Info], schema: &CsvSchema) -> Result<Vec<u8>> {
+    let mut contents = Vec::new();
+    write_functions_ex(csv::Writer::from_writer(&mut contents), functions, schema)?;
+    Ok(contents)
+}
+
+pub fn write_functions_to_path_ex(
+    csv_path: &Path,
+    functions: &[Info],
+    options: &WriteOptions,
+) -> Result<()> {
+    options.check(csv_path, functions)?;
+    let contents = serialize_functions_for_path(csv_path, functions)?;
+    backup::backup_before_write(csv_path, &contents, options.max_backups)?;
+    std::fs::write(csv_path, contents)?;
+    Ok(())
+}
+
+/// Same as `write_functions_to_path`, but the file is replaced atomically:
+/// readers see either the old or the new function list, never a partially written file.
+pub fn write_functions_atomic(csv_path: &Path, functions: &[Info]) -> Result<()> {
+    write_functions_atomic_ex(csv_path, functions, &WriteOptions::from_config())
+}
+
+pub fn write_functions_atomic_ex(
+    csv_path: &Path,
+    functions: &[Info],
+    options: &WriteOptions,
+) -> Result<()> {
+    options.check(csv_path, functions)?;
+    let contents = serialize_functions_for_path(csv_path, functions)?;
+    backup::backup_before_write(csv_path, &contents, options.max_backups)?;
+    file_utils::write_atomic(csv_path, &contents)
+}
+
+/// Reads the function list at `csv_path`, lets `update` change it and writes it back with
+/// `write_functions_atomic`. The list is locked (see `lock::lock_exclusive`) from before
+/// the read until the write has finished, so that concurrent updates are not lost.
+/// Nothing is written if `update` fails.
+pub fn update_functions_at_path<T>(
+    csv_path: &Path,
+    update: impl FnOnce(&mut Vec<Info>) -> Result<T>,
+) -> Result<T> {
+    let _lock = lock::lock_exclusive(csv_path, &LockOptions::default())?;
+    let mut functions = get_functions_for_path(csv_path)?;
+    let result = update(&mut functions)?;
+    write_functions_atomic(csv_path, &functions)?;
+    Ok(result)
+}
+
+/// Same as `write_functions_atomic`, but the existing file is first copied to
+/// `<name>.csv.bak.N`. Only the newest `max_backups` backups are kept; see
+/// `backup::list_backups` and `backup::restore_backup`.
+///
+/// These backups replace the ones in the backup directory (`WriteOptions::max_backups`),
+/// so the write cannot be undone with `undo_last_write`.
+pub fn write_functions_with_backup(
+    csv_path: &Path,
+    functions: &[Info],
+    max_backups: usize,
+) -> Result<()> {
+    write_functions_with_backup_ex(
+        csv_path,
+        functions,
+        max_backups,
+        &WriteOptions::from_config(),
+    )
+}
+
+/// Same as `write_functions_with_backup`, but with custom options. `options.max_backups`
+/// is ignored.
+pub fn write_functions_with_backup_ex(
+    csv_path: &Path,
+    functions: &[Info],
+    max_backups: usize,
+    options: &WriteOptions,
+) -> Result<()> {
+    options.check(csv_path, functions)?;
+    let contents = serialize_functions_for_path(csv_path, functions)?;
+    backup::backup_to_numbered_file(csv_path, &contents, max_backups)?;
+    file_utils::write_atomic(csv_path, &contents)
+}
+
+/// Makes sure that every address is written in the canonical format (`format_addr`) the next
+/// time `functions` is written.
+///
+/// *Note*: addresses are stored as integers and the writers always use `format_addr`,
+/// so there is nothing to change: this only documents the guarantee. To rewrite the addresses
+/// of a CSV without touching anything else, use `normalize_csv_addresses`.
+pub fn normalize_address_format(_functions: &mut [Info]) {}
+
+/// Rewrites the address column of a function list in the canonical format (`format_addr`),
+/// e.g. `71001a1234` or `0x00000071001A1234` becomes `0x00000071001a1234`.
+///
+/// Unlike reading and writing the list, this leaves all other fields, comments and line endings
+/// as they are, so it can be used as a one-time migration. The file is replaced atomically
+/// and only if something changed.
+pub fn normalize_csv_addresses(csv_path: &Path) -> Result<()> {
+    let _lock = lock::lock_exclusive(csv_path, &LockOptions::default())?;
+    let contents = std::fs::read_to_string(csv_path)
+        .with_context(|| format!("failed to read {:?}", csv_path))?;
+
+    let mut lines = contents.split_inclusive('\n');
+    let header = lines.next().unwrap_or_default();
+    let delimiter = detect_format(header).delimiter() as char;
+    let mut result = String::with_capacity(contents.len());
+    result.push_str(header);
+
+    for (i, line) in lines.enumerate() {
+        let content = line.trim_end_matches(&['\r', '\n'][..]);
+        let (addr, rest) = match content.split_once(delimiter) {
+            Some(fields) if !content.starts_with('#') => fields,
+            _ => {
+                result.push_str(line);
+                continue;
+            }
+        };
+        let addr = parse_address_or_offset(&addr.trim().to_ascii_lowercase())
+            .with_context(|| format!("invalid address at line {}: {:?}", i + 2, addr))?;
+        result.push_str(&format_addr(addr));
+        result.push(delimiter);
+        result.push_str(rest);
+        result.push_str(&line[content.len()..]);
+    }
+
+    if result != contents {
+        file_utils::write_atomic(csv_path, result.as_bytes())?;
+    }
+    Ok(())
+}
+
+/// Returns the path to the function list of the executable, as specified in the config.
+pub fn get_functions_csv_path() -> &'static Path {
+    FUNCTIONS_CSV_PATH.as_path()
+}
+
+/// Returns a Vec of all known functions in the executable.
+///
+/// `expected_function_count` can be set in the config to avoid reallocations
+/// while reading the list.
+pub fn get_functions() -> Result<Vec<Info>> {
+    read_functions_for_path(
+        FUNCTIONS_CSV_PATH.as_path(),
+        ADDRESS_BASE,
+        get_expected_function_count(),
+    )
+}
+
+/// Writes the function list of the executable.
+///
+/// If backups are enabled in the config, the current function list is backed up first
+/// and can be restored with `undo_last_write`.
+///
+/// *Note*: this does not lock the list (see `write_functions_to_path`);
+/// `function_source::ProjectFunctions` does.
+pub fn write_functions(functions: &[Info]) -> Result<()> {
+    write_functions_with_options(functions, &WriteOptions::from_config())
+}
+
+/// Same as `write_functions`, but with custom options (e.g. to allow removing many rows).
+/// See `WriteOptions::from_config` for the default options.
+pub fn write_functions_with_options(functions: &[Info], options: &WriteOptions) -> Result<()> {
+    let csv_path = FUNCTIONS_CSV_PATH.as_path();
+    options.check(csv_path, functions)?;
+    // Always write the declared columns, even if all of their fields are empty.
+    let schema = CsvSchema::from_config()?;
+    let mut contents = Vec::new();
+    write_functions_ex(csv::Writer::from_writer(&mut contents), functions, &schema)?;
+    backup::backup_before_write(csv_path, &contents, options.max_backups)?;
+    file_utils::write_atomic(csv_path, &contents)
+}
+
+/// Restores the function list from the most recent backup and returns the path to the backup.
+pub fn undo_last_write() -> Result<PathBuf> {
+    backup::restore_latest_backup(FUNCTIONS_CSV_PATH.as_path())
+}
+
+pub fn make_known_function_map(functions: &[Info]) -> FxHashMap<u64, &Info> {
+    let num_names = functions
+        .iter()
+        .filter(|function| !function.name.is_empty())
+        .count();
+    let mut known_functions = FxHashMap::with_capacity_and_hasher(num_names, Default::default());
+
+    for function in functions {
+        if function.name.is_empty() {
+            continue;
+        }
+        known_functions.insert(function.addr, function);
+    }
+
+    known_functions
+}
+
+/// Returns a map from addresses to indices in `functions`, so that entries can be updated
+/// in place (`functions[index]`). Unlike `make_known_function_map`, unnamed functions are
+/// included. If several entries share an address, the first one is used.
+pub fn make_known_function_index(functions: &[Info]) -> FxHashMap<u64, usize> {
+    let mut index = FxHashMap::with_capacity_and_hasher(functions.len(), Default::default());
+    for (i, function) in functions.iter().enumerate() {
+        index.entry(function.addr).or_insert(i);
+    }
+    index
+}
+
+/// An entry of a function list, compared with the other version of the list
+/// (see `diff_by_address`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AddressDiff<'a> {
+    Added(&'a Info),
+    Removed(&'a Info),
+    /// The address is in both lists, but the entry might have changed.
+    Kept {
+        old: &'a Info,
+        new: &'a Info,
+    },
+}
+
+impl AddressDiff<'_> {
+    pub fn addr(&self) -> u64 {
+        match self {
+            AddressDiff::Added(info) | AddressDiff::Removed(info) => info.addr,
+            AddressDiff::Kept { new, .. } => new.addr,
+        }
+    }
+}
+
+/// Matches the entries of two versions of a function list by address, which is what reports,
+/// stats, edit plans and the status log compare. If several entries share an address,
+/// the last one is used. The result is sorted by address.
+pub fn diff_by_address<'a>(old: &'a [Info], new: &'a [Info]) -> Vec<AddressDiff<'a>> {
+    let old_by_addr: FxHashMap<u64, &Info> = old.iter().map(|info| (info.addr, info)).collect();
+    let new_by_addr: FxHashMap<u64, &Info> = new.iter().map(|info| (info.addr, info)).collect();
+
+    let mut diff: Vec<AddressDiff> = new_by_addr
+        .values()
+        .map(|&new| match old_by_addr.get(&new.addr) {
+            Some(&old) => AddressDiff::Kept { old, new },
+            None => AddressDiff::Added(new),
         })
+        .collect();
+    diff.extend(
+        old_by_addr
+            .values()
+            .filter(|info| !new_by_addr.contains_key(&info.addr))
+            .map(|&info| AddressDiff::Removed(info)),
+    );
+    diff.sort_unstable_by_key(AddressDiff::addr);
+    diff
+}
+
+/// Demangle a C++ symbol.
+pub fn demangle_str(name: &str) -> Result<String> {
+    if !name.starts_with("_Z") {
+        bail!("not an external mangled name");
+    }
+
+    let symbol = cpp_demangle::Symbol::new(name)?;
+    let options = cpp_demangle::DemangleOptions::new();
+    Ok(symbol.demangle(&options)?)
+}
+
+/// Language of a symbol, as identified from its name by `get_symbol_language`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SymbolLanguage {
+    Rust,
+    Cpp,
+    /// Not mangled: a plain identifier.
+    C,
+    Unknown,
+}
+
+/// Returns whether `name` uses the legacy Rust mangling scheme. These names are also valid
+/// Itanium C++ names, but always end with a hash component (`17h` and 16 hex digits).
+fn is_rust_legacy_mangled_name(name: &str) -> bool {
+    const HASH_LEN: usize = 16;
+    // Names can contain arbitrary characters, so only slice at character boundaries.
+    let rest = match name
+        .strip_prefix("_ZN")
+        .and_then(|rest| rest.strip_suffix('E'))
+    {
+        Some(rest) => rest,
+        None => return false,
+    };
+    let split = match rest.len().checked_sub(HASH_LEN) {
+        Some(split) if rest.is_char_boundary(split) => split,
+        _ => return false,
+    };
+    let (path, hash) = rest.split_at(split);
+    path.ends_with("17h") && hash.bytes().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Returns whether `name` uses the Rust v0 mangling scheme (`_R`, or `__R` on platforms
+/// that add an extra underscore to symbol names).
+fn is_rust_v0_mangled_name(name: &str) -> bool {
+    name.starts_with("_R") || name.starts_with("__R")
+}
+
+/// Identifies the language of a symbol from its name. This only looks at the mangling
+/// scheme, so the name is not guaranteed to demangle successfully.
+pub fn get_symbol_language(name: &str) -> SymbolLanguage {
+    if is_rust_v0_mangled_name(name) || is_rust_legacy_mangled_name(name) {
+        return SymbolLanguage::Rust;
+    }
+    if name.starts_with("_Z") {
+        return SymbolLanguage::Cpp;
+    }
+
+    let mut chars = name.chars();
+    let is_identifier = matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if is_identifier {
+        SymbolLanguage::C
+    } else {
+        SymbolLanguage::Unknown
+    }
+}
+
+/// Demangle a Rust symbol (legacy or v0 mangling). The hash suffix of legacy names is omitted.
+pub fn demangle_str_rust(name: &str) -> Result<String> {
+    if get_symbol_language(name) != SymbolLanguage::Rust {
+        bail!("not a Rust mangled name");
+    }
+
+    let symbol = rustc_demangle::try_demangle(name)
+        .map_err(|_| anyhow!("failed to demangle Rust symbol: {}", name))?;
+    Ok(format!("{:#}", symbol))
+}
+
+/// Demangle a C++ or Rust symbol and return its language. Names of C symbols are returned
+/// unchanged.
+pub fn demangle_str_auto(name: &str) -> Result<(String, SymbolLanguage)> {
+    let language = get_symbol_language(name);
+    let demangled = match language {
+        SymbolLanguage::Rust => demangle_str_rust(name)?,
+        SymbolLanguage::Cpp => demangle_str(name)?,
+        SymbolLanguage::C => name.to_string(),
+        SymbolLanguage::Unknown => bail!("unknown symbol language: {}", name),
+    };
+    Ok((demangled, language))
+}
+
+/// Returns the length of a name component that cannot be tokenised by simply looking for
+/// `::` separators and brackets: operator names, `(anonymous namespace)` and lambdas.
+fn get_special_component_len(rest: &[u8]) -> Option<usize> {
+    const ANONYMOUS_NAMESPACE: &[u8] = b"(anonymous namespace)";
+    if rest.starts_with(ANONYMOUS_NAMESPACE) {
+        return Some(ANONYMOUS_NAMESPACE.len());
+    }
+
+    if rest.starts_with(b"{") {
+        let mut depth = 0;
+        for (i, c) in rest.iter().enumerate() {
+            match c {
+                b'{' => depth += 1,
+                b'}' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Some(i + 1);
+                    }
+                }
+                _ => (),
+            }
+        }
+        return Some(rest.len());
+    }
+
+    let op = rest.strip_prefix(b"operator")?;
+    match op.first() {
+        // Function call operator.
+        Some(b'(') if op.starts_with(b"()") => Some(b"operator()".len()),
+        // new, delete and conversion operators: everything up to the parameter list.
+        Some(b' ') => {
+            let mut depth = 0;
+            let len = op
+                .iter()
+                .position(|c| match c {
+                    b'<' => {
+                        depth += 1;
+                        false
+                    }
+                    b'>' => {
+                        depth -= 1;
+                        false
+                    }
+                    b'(' => depth == 0,
+                    _ => false,
+                })
+                .unwrap_or(op.len());
+            Some(b"operator".len() + len)
+        }
+        Some(c) if b"+-*/%^&|~!=<>,[]".contains(c) => {
+            let len = op
+                .iter()
+                .take_while(|c| b"+-*/%^&|~!=<>,[]".contains(c))
+                .count();
+            Some(b"operator".len() + len)
+        }
+        // Not an operator -- just an identifier that happens to start with "operator".
+        _ => None,
+    }
+}
+
+/// Returns the position after the parenthesis that closes the one at the start of `rest`.
+fn get_matching_paren(rest: &[u8]) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in rest.iter().enumerate() {
+        match c {
+            b'(' => depth += 1,
+            b')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i + 1);
+                }
+            }
+            _ => (),
+        }
+    }
+    None
+}
+
+struct QualifiedName<'a> {
+    components: Vec<&'a str>,
+    /// Offset of the qualified name (i.e. after the return type, if any).
+    start: usize,
+    /// Offset of the last component.
+    last_component_start: usize,
+    /// Offset of the parameter list, or the length of the string if there is none.
+    params_start: usize,
+}
+
+fn scan_qualified_name(demangled: &str) -> QualifiedName<'_> {
+    let bytes = demangled.as_bytes();
+    let mut components = Vec::new();
+    let mut name_start = 0;
+    let mut depth = 0usize;
+    // Start of the current component.
+    let mut start = 0;
+    // End of the current component (excluding template arguments), if already known.
+    let mut end = None;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if depth == 0 && i == start {
+            if let Some(len) = get_special_component_len(&bytes[i..]) {
+                i += len;
+                end = Some(i);
+                continue;
+            }
+        }
+
+        match bytes[i] {
+            b'(' if depth == 0 => {
+                let close = get_matching_paren(&bytes[i..]).map(|len| i + len);
+                match close {
+                    // Local entity (e.g. a lambda): the parameter list is part of the scope.
+                    Some(close) if bytes[close..].starts_with(b"::") => {
+                        if end.is_none() {
+                            end = Some(i);
+                        }
+                        i = close;
+                        continue;
+                    }
+                    // Start of the parameter list.
+                    _ => break,
+                }
+            }
+            b'<' | b'(' | b'[' | b'{' => {
+                if depth == 0 && end.is_none() {
+                    end = Some(i);
+                }
+                depth += 1;
+            }
+            b'>' | b')' | b']' | b'}' => depth = depth.saturating_sub(1),
+            b':' if depth == 0 && bytes[i..].starts_with(b"::") => {
+                components.push(&demangled[start..end.unwrap_or(i)]);
+                i += 2;
+                start = i;
+                end = None;
+                continue;
+            }
+            // Anything before a top-level space is a return type or a prefix
+            // such as "non-virtual thunk to".
+            b' ' if depth == 0 => {
+                components.clear();
+                start = i + 1;
+                name_start = start;
+                end = None;
+            }
+            _ => (),
+        }
+        i += 1;
+    }
+
+    components.push(&demangled[start..end.unwrap_or(i)]);
+    QualifiedName {
+        components,
+        start: name_start,
+        last_component_start: start,
+        params_start: i,
+    }
+}
+
+/// The parts of a demangled function name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DemangledSignature<'a> {
+    /// The return type, if it is part of the mangled name (e.g. for function templates).
+    pub return_type: Option<&'a str>,
+    /// The scope of the function including template arguments (e.g. `sead::Foo<int>`), if any.
+    pub scope: Option<&'a str>,
+    /// Qualified name components, without template arguments.
+    pub components: Vec<&'a str>,
+    pub params: Vec<&'a str>,
+    /// Qualifiers that follow the parameter list (e.g. `const`).
+    pub qualifiers: &'a str,
+}
+
+/// Splits a demangled function name into its parts.
+///
+/// For example, `sead::Foo<int>::bar(sead::Heap*, int) const` has the scope `sead::Foo<int>`,
+/// the parameters `["sead::Heap*", "int"]` and the qualifiers `const`.
+/// Returns None if the name has no parameter list (e.g. for variables).
+pub fn parse_demangled_signature(demangled: &str) -> Option<DemangledSignature<'_>> {
+    let name = scan_qualified_name(demangled);
+    let params_len = get_matching_paren(&demangled.as_bytes()[name.params_start..])?;
+    let params_str = &demangled[name.params_start + 1..name.params_start + params_len - 1];
+
+    let return_type = demangled[..name.start].trim();
+    let scope = if name.last_component_start > name.start {
+        Some(&demangled[name.start..name.last_component_start - 2])
+    } else {
+        None
+    };
+    let params = if params_str.trim().is_empty() {
+        Vec::new()
+    } else {
+        split_top_level(params_str, b',')
+            .into_iter()
+            .map(str::trim)
+            .collect()
+    };
+
+    Some(DemangledSignature {
+        return_type: Some(return_type).filter(|return_type| !return_type.is_empty()),
+        scope,
+        components: name.components,
+        params,
+        qualifiers: demangled[name.params_start + params_len..].trim(),
+    })
+}
+
+/// Splits a demangled name into its qualified name components, ignoring template arguments,
+/// the parameter list and the return type.
+///
+/// For example, `sead::Foo<int>::bar(sead::Heap*) const` is split into `["sead", "Foo", "bar"]`.
+pub fn split_qualified_name(demangled: &str) -> Vec<&str> {
+    scan_qualified_name(demangled).components
+}
+
+/// Returns whether the qualified name components of `query` are exactly the trailing
+/// components of `name`. Template arguments and parameter lists are ignored.
+///
+/// `BaseProc::init` matches `ksys::act::BaseProc::init(sead::Heap*)`,
+/// but not `ksys::act::BaseProcInitializer::initImpl()`.
+pub fn is_anchored_name_match(name: &str, query: &str) -> bool {
+    let name_components = split_qualified_name(name);
+    let query_components = split_qualified_name(query);
+    query_components.len() <= name_components.len() && name_components.ends_with(&query_components)
+}
+
+/// Finds a function whose demangled name ends with the qualified name `query`.
+/// See [`is_anchored_name_match`].
+pub fn find_function_anchored<'a>(functions: &'a [Info], query: &str) -> Option<&'a Info> {
+    find_function_anchored_ex(functions, query, &MatchPreference::AnyStatus)
+}
+
+/// Same as `find_function_anchored`, but `preference` decides between the functions that match.
+pub fn find_function_anchored_ex<'a>(
+    functions: &'a [Info],
+    query: &str,
+    preference: &MatchPreference,
+) -> Option<&'a Info> {
+    find_best_match(functions, preference, &|i| {
+        is_anchored_match(&functions[i], query)
+    })
+}
+
+fn is_anchored_match(function: &Info, query: &str) -> bool {
+    demangle_str(&function.name)
+        .map(|demangled| is_anchored_name_match(&demangled, query))
+        .unwrap_or(false)
+}
+
+/// How to choose between functions that match a name query equally well
+/// (e.g. `init` often matches both decompiled and undecompiled functions).
+/// This is only used as a tie-breaker: better matches (see `find_function_fuzzy`)
+/// always win, whatever their status.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum MatchPreference {
+    /// The first function in list order.
+    #[default]
+    AnyStatus,
+    /// Decompiled functions (see `Info::is_decompiled`), e.g. for the check tool.
+    PreferDecompiled,
+    /// Functions that have not been decompiled yet, e.g. for tools that scaffold new work.
+    PreferNotDecompiled,
+    /// Only functions with one of these statuses can match.
+    RequireStatus(Vec<Status>),
+}
+
+impl MatchPreference {
+    /// Returns the rank of a candidate (lower is better), or None if it cannot match.
+    fn rank(&self, info: &Info) -> Option<u8> {
+        match self {
+            MatchPreference::AnyStatus => Some(0),
+            MatchPreference::PreferDecompiled => Some(!info.is_decompiled() as u8),
+            MatchPreference::PreferNotDecompiled => Some(info.is_decompiled() as u8),
+            MatchPreference::RequireStatus(statuses) => {
+                statuses.contains(&info.status).then_some(0)
+            }
+        }
+    }
+}
+
+/// Returns the functions for which `is_match(index)` returns true and that have the best rank
+/// for `preference`, in list order.
+fn find_best_candidates<'a>(
+    functions: &'a [Info],
+    preference: &MatchPreference,
+    is_match: &(dyn Fn(usize) -> bool + Sync),
+) -> Vec<&'a Info> {
+    let candidates: Vec<(u8, usize)> = (0..functions.len())
+        .into_par_iter()
+        .filter(|&i| is_match(i))
+        .filter_map(|i| Some((preference.rank(&functions[i])?, i)))
+        .collect();
+    let best_rank = candidates.iter().map(|&(rank, _)| rank).min();
+    candidates
+        .into_iter()
+        .filter(|&(rank, _)| Some(rank) == best_rank)
+        .map(|(_, i)| &functions[i])
+        .collect()
+}
+
+fn find_best_match<'a>(
+    functions: &'a [Info],
+    preference: &MatchPreference,
+    is_match: &(dyn Fn(usize) -> bool + Sync),
+) -> Option<&'a Info> {
+    if *preference == MatchPreference::AnyStatus {
+        return (0..functions.len())
+            .into_par_iter()
+            .find_first(|&i| is_match(i))
+            .map(|i| &functions[i]);
+    }
+    find_best_candidates(functions, preference, is_match)
+        .into_iter()
+        .next()
+}
+
+#[inline]
+fn is_identifier_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// Removes `__attribute__((...))` specifiers.
+fn strip_attributes(prototype: &str) -> String {
+    const ATTRIBUTE: &str = "__attribute__";
+    let mut result = String::with_capacity(prototype.len());
+    let mut rest = prototype;
+    while let Some(pos) = rest.find(ATTRIBUTE) {
+        result.push_str(&rest[..pos]);
+        rest = rest[pos + ATTRIBUTE.len()..].trim_start();
+        if rest.starts_with('(') {
+            rest = &rest[get_matching_paren(rest.as_bytes()).unwrap_or(rest.len())..];
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Removes whitespace, except for a single space between two identifiers (e.g. `unsigned int`)
+/// and between a pointer, reference or template argument list and an identifier
+/// (e.g. `sead::Heap* heap`).
+fn collapse_whitespace(prototype: &str) -> String {
+    let mut result = String::with_capacity(prototype.len());
+    let mut had_space = false;
+    for c in prototype.chars() {
+        if c.is_whitespace() {
+            had_space = true;
+            continue;
+        }
+
+        if is_identifier_char(c) {
+            let needs_space = match result.chars().last() {
+                Some('*') | Some('&') => true,
+                Some(prev) if is_identifier_char(prev) || prev == '>' => had_space,
+                _ => false,
+            };
+            if needs_space {
+                result.push(' ');
+            }
+        }
+
+        had_space = false;
+        result.push(c);
+    }
+    result
+}
+
+/// Splits `s` at every occurrence of `separator` that is not nested inside brackets.
+fn split_top_level(s: &str, separator: u8) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0i32;
+    let mut start = 0;
+    for (i, c) in s.bytes().enumerate() {
+        match c {
+            b'<' | b'(' | b'[' | b'{' => depth += 1,
+            b'>' | b')' | b']' | b'}' => depth -= 1,
+            c if c == separator && depth == 0 => {
+                parts.push(&s[start..i]);
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+    parts.push(&s[start..]);
+    parts
+}
+
+const TYPE_KEYWORDS: &[&str] = &[
+    "void", "bool", "char", "wchar_t", "char8_t", "char16_t", "char32_t", "short", "int", "long",
+    "float", "double", "signed", "unsigned", "auto", "const", "volatile",
+];
+
+const TYPE_PREFIXES: &[&str] = &[
+    "const", "volatile", "signed", "unsigned", "struct", "class", "enum", "union", "typename",
+];
+
+/// Removes the name of a function pointer, array pointer or reference parameter
+/// (e.g. `void(* callback)(int)` or `int(& array)[4]`).
+fn strip_declarator_name(param: &str) -> String {
+    if let Some(open) = param.find("(*").or_else(|| param.find("(&")) {
+        let inner = &param[open + 2..];
+        if let Some(close) = inner.find(')') {
+            let name = inner[..close].trim();
+            if !name.is_empty() && name.chars().all(is_identifier_char) {
+                return format!("{}{}", &param[..open + 2], &inner[close..]);
+            }
+        }
+    }
+    param.to_string()
+}
+
+/// Normalizes a single (whitespace-collapsed) parameter: strips the parameter name and
+/// the default argument, and moves a leading `const` after the type like the demangler does.
+fn normalize_parameter(param: &str) -> String {
+    let mut param = strip_declarator_name(split_top_level(param, b'=')[0].trim_end());
+
+    let name_start = param.trim_end_matches(is_identifier_char).len();
+    if name_start >= 2 && param.as_bytes()[name_start - 1] == b' ' {
+        let name = &param[name_start..];
+        let before = &param[..name_start - 1];
+        let is_name = !TYPE_KEYWORDS.contains(&name)
+            && match before.chars().last() {
+                Some('*') | Some('&') | Some('>') => true,
+                Some(_) => {
+                    let prev_token = before.rsplit(|c| !is_identifier_char(c)).next();
+                    !TYPE_PREFIXES.contains(&prev_token.unwrap_or(""))
+                }
+                None => false,
+            };
+        if is_name {
+            param.truncate(name_start - 1);
+        }
+    }
+
+    if let Some(rest) = param.strip_prefix("const ") {
+        let mut depth = 0;
+        let pos = rest
+            .bytes()
+            .position(|c| match c {
+                b'<' => {
+                    depth += 1;
+                    false
+                }
+                b'>' => {
+                    depth -= 1;
+                    false
+                }
+                b'*' | b'&' => depth == 0,
+                _ => false,
+            })
+            .unwrap_or(rest.len());
+        param = format!("{} const{}", &rest[..pos], &rest[pos..]);
+    }
+
+    if param == "void" {
+        param.clear();
+    }
+    param
+}
+
+/// Normalizes a function prototype (as pasted from a compiler error message or an IDE)
+/// so that it can be compared against demangled names: parameter names, default arguments,
+/// attributes, specifiers like `virtual`, the return type and unnecessary whitespace are removed,
+/// and trailing qualifiers other than `const` and `volatile` are dropped.
+///
+/// Demangled names should be passed through this function too before comparing.
+pub fn normalize_prototype(prototype: &str) -> String {
+    let mut prototype = collapse_whitespace(&strip_attributes(prototype));
+    for specifier in &["virtual ", "static ", "inline ", "explicit ", "constexpr "] {
+        if let Some(stripped) = prototype.strip_prefix(specifier) {
+            prototype = stripped.to_string();
+        }
+    }
+
+    let name = scan_qualified_name(&prototype);
+    let mut result = prototype[name.start..name.params_start].to_string();
+
+    let rest = &prototype[name.params_start..];
+    if rest.is_empty() {
+        return result;
+    }
+
+    let (params, suffix) = match get_matching_paren(rest.as_bytes()) {
+        Some(len) => (&rest[1..len - 1], &rest[len..]),
+        None => (&rest[1..], ""),
+    };
+
+    let params = split_top_level(params, b',')
+        .into_iter()
+        .map(normalize_parameter)
+        .collect::<Vec<_>>();
+    result.push('(');
+    if params.len() != 1 || !params[0].is_empty() {
+        result.push_str(&params.join(","));
+    }
+    result.push(')');
+
+    for qualifier in suffix.split(|c| !is_identifier_char(c)) {
+        if qualifier == "const" || qualifier == "volatile" {
+            result.push(' ');
+            result.push_str(qualifier);
+        }
+    }
+
+    result
+}
+
+/// Returns whether `demangled` contains `name`, either as is or after normalizing both names.
+/// `normalized_name` is only initialized (with `normalize_prototype(name)`) when the names
+/// need to be normalized.
+fn is_fuzzy_name_match(demangled: &str, name: &str, normalized_name: &Lazy<String>) -> bool {
+    demangled.contains(name)
+        || normalize_prototype(demangled).contains(
+            normalized_name
+                .get_or_create(|| normalize_prototype(name))
+                .as_str(),
+        )
+}
+
+pub fn find_function_fuzzy<'a>(functions: &'a [Info], name: &str) -> Option<&'a Info> {
+    find_function_fuzzy_ex(functions, name, &MatchPreference::AnyStatus)
+}
+
+/// Predicates for the ways in which `find_function_fuzzy` matches names, best match first.
+fn get_fuzzy_match_tiers<'a>(
+    functions: &'a [Info],
+    name: &'a str,
+) -> [Box<dyn Fn(usize) -> bool + Sync + 'a>; 3] {
+    // Most queries are found by one of the first tiers, so only normalize the name if needed.
+    let normalized_name = Lazy::new();
+    [
+        Box::new(move |i| functions[i].name == name),
+        Box::new(move |i| is_anchored_match(&functions[i], name)),
+        // Comparing the demangled names is more expensive than a simple string comparison,
+        // so only do this as a last resort.
+        Box::new(move |i| {
+            demangle_str(&functions[i].name)
+                .map(|demangled| is_fuzzy_name_match(&demangled, name, &normalized_name))
+                .unwrap_or(false)
+        }),
+    ]
+}
+
+/// Same as `find_function_fuzzy`, but `preference` decides between functions that match
+/// equally well.
+pub fn find_function_fuzzy_ex<'a>(
+    functions: &'a [Info],
+    name: &str,
+    preference: &MatchPreference,
+) -> Option<&'a Info> {
+    get_fuzzy_match_tiers(functions, name)
+        .iter()
+        .find_map(|is_match| find_best_match(functions, preference, is_match.as_ref()))
+}
+
+/// Same as `find_function_fuzzy_ex`, but fails if several functions match equally well.
+/// The error lists every candidate with its status and size so that the query can be refined.
+pub fn find_unique_function_fuzzy<'a>(
+    functions: &'a [Info],
+    name: &str,
+    preference: &MatchPreference,
+) -> Result<&'a Info> {
+    for is_match in get_fuzzy_match_tiers(functions, name).iter() {
+        let candidates = find_best_candidates(functions, preference, is_match.as_ref());
+        match candidates.as_slice() {
+            [] => continue,
+            [info] => return Ok(info),
+            _ => {
+                let descriptions: Vec<String> = candidates
+                    .iter()
+                    .map(|info| {
+                        format!(
+                            "{} {} ({}, {:#x} bytes)",
+                            format_addr(info.addr),
+                            demangle_str(&info.name).unwrap_or_else(|_| info.name.clone()),
+                            info.status.description(),
+                            info.size
+                        )
+                    })
+                    .collect();
+                bail!(
+                    "{} is ambiguous; {} functions match:\n{}",
+                    name,
+                    candidates.len(),
+                    descriptions.join("\n")
+                );
+            }
+        }
+    }
+    bail!("unknown function: {}", name)
+}
+
+/// Caches the demangled and normalized names of functions,
+/// for tools that need to perform many lookups.
+pub struct DemangledIndex<'a> {
+    functions: &'a [Info],
+    /// Demangled names (for mangled C++ and Rust names only) and languages.
+    demangled: Vec<(Option<String>, SymbolLanguage)>,
+    normalized: Vec<Option<String>>,
+}
+
+impl<'a> DemangledIndex<'a> {
+    pub fn build(functions: &'a [Info]) -> Self {
+        let demangled: Vec<(Option<String>, SymbolLanguage)> = functions
+            .par_iter()
+            .map(|function| match demangle_str_auto(&function.name) {
+                Ok((_, SymbolLanguage::C)) => (None, SymbolLanguage::C),
+                Ok((demangled, language)) => (Some(demangled), language),
+                Err(_) => (None, get_symbol_language(&function.name)),
+            })
+            .collect();
+        let normalized = demangled
+            .par_iter()
+            .map(|(demangled, _)| demangled.as_deref().map(normalize_prototype))
+            .collect();
+
+        Self {
+            functions,
+            demangled,
+            normalized,
+        }
+    }
+
+    /// Returns the demangled name of `functions[index]`.
+    pub fn get_demangled(&self, index: usize) -> Option<&str> {
+        self.demangled[index].0.as_deref()
+    }
+
+    /// Returns the language of `functions[index]` (see `get_symbol_language`).
+    pub fn get_language(&self, index: usize) -> SymbolLanguage {
+        self.demangled[index].1
+    }
+
+    /// Same as `find_function_fuzzy`, but without demangling any name.
+    pub fn find_function_fuzzy(&self, name: &str) -> Option<&'a Info> {
+        self.find_function_fuzzy_ex(name, &MatchPreference::AnyStatus)
+    }
+
+    /// Same as `find_function_fuzzy_ex`, but without demangling any name.
+    pub fn find_function_fuzzy_ex(
+        &self,
+        name: &str,
+        preference: &MatchPreference,
+    ) -> Option<&'a Info> {
+        let find = |predicate: &(dyn Fn(usize) -> bool + Sync)| {
+            find_best_match(self.functions, preference, predicate)
+        };
+
+        let normalized_name = Lazy::new();
+        find(&|i| self.functions[i].name == name)
+            .or_else(|| {
+                find(&|i| {
+                    self.get_demangled(i)
+                        .map(|demangled| is_anchored_name_match(demangled, name))
+                        .unwrap_or(false)
+                })
+            })
+            .or_else(|| {
+                find(&|i| match (&self.demangled[i].0, &self.normalized[i]) {
+                    (Some(demangled), Some(normalized)) => {
+                        demangled.contains(name)
+                            || normalized.contains(
+                                normalized_name
+                                    .get_or_create(|| normalize_prototype(name))
+                                    .as_str(),
+                            )
+                    }
+                    _ => false,
+                })
+            })
+    }
+}
+
+/// Functions sorted by address, for tools that need to map many addresses to functions.
+pub struct AddressIndex<'a> {
+    /// Sorted by address. Only the first entry is kept for duplicate addresses.
+    functions: Vec<&'a Info>,
+    text_range: Option<Range<u64>>,
+}
+
+impl<'a> AddressIndex<'a> {
+    pub fn build(functions: &'a [Info]) -> Self {
+        let mut sorted: Vec<&'a Info> = functions.iter().collect();
+        sorted.sort_by_key(|info| info.addr);
+        sorted.dedup_by_key(|info| info.addr);
+        Self {
+            functions: sorted,
+            text_range: None,
+        }
+    }
+
+    /// Sets the address range of the code (see `binary::BaseBinary::text_range`), which is
+    /// used by `check_code_address` instead of the end of the last function.
+    pub fn with_text_range(mut self, text_range: Range<u64>) -> Self {
+        self.text_range = Some(text_range);
+        self
+    }
+
+    /// Returns the function that contains `addr`. Empty functions contain their own address.
+    pub fn find_containing(&self, addr: u64) -> Option<&'a Info> {
+        let index = self.functions.partition_point(|info| info.addr <= addr);
+        let info = *self.functions.get(index.checked_sub(1)?)?;
+        if addr == info.addr || addr < info.addr + info.size as u64 {
+            Some(info)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the function whose start is closest to `addr`, whether or not it contains
+    /// `addr`. Ties go to the function before `addr`.
+    pub fn nearest_function(&self, addr: u64) -> Option<&'a Info> {
+        let index = self.functions.partition_point(|info| info.addr <= addr);
+        let previous = index
+            .checked_sub(1)
+            .and_then(|index| self.functions.get(index));
+        let next = self.functions.get(index);
+        match (previous, next) {
+            (Some(previous), Some(next)) if next.addr - addr < addr - previous.addr => Some(*next),
+            (Some(previous), _) => Some(*previous),
+            (None, next) => next.copied(),
+        }
+    }
+
+    /// Same as `parse_code_address`, but errors mention the nearest function
+    /// (see `check_code_address`).
+    pub fn parse_code_address(&self, value: &str) -> Result<u64> {
+        let addr = parse_address(value)?;
+        self.check_code_address(addr)?;
+        Ok(addr)
+    }
+
+    /// Checks that `addr` is aligned like an instruction (mentioning the nearest function,
+    /// e.g. "did you mean ... (0x0000007100001230)?", if it is not) and that it is inside
+    /// the text range (see `with_text_range`) or, if there is none, not past the end of
+    /// the last function.
+    ///
+    /// Misaligned addresses return a `MisalignedAddressError` (with the nearest function
+    /// as context), which can be retrieved with `downcast_ref`.
+    pub fn check_code_address(&self, addr: u64) -> Result<()> {
+        if let Err(err) = check_code_alignment(addr) {
+            let err = anyhow::Error::new(err);
+            return Err(match self.nearest_function(addr) {
+                Some(info) => err.context(format!(
+                    "{} is not {}-byte aligned; did you mean {} ({})?",
+                    format_addr(addr),
+                    CODE_ALIGNMENT,
+                    get_display_name(info),
+                    format_addr(info.addr)
+                )),
+                None => err,
+            });
+        }
+
+        if let Some(text_range) = &self.text_range {
+            ensure!(
+                text_range.contains(&addr),
+                "{} is outside of the code ({}..{})",
+                format_addr(addr),
+                format_addr(text_range.start),
+                format_addr(text_range.end)
+            );
+        } else if let Some(last) = self.functions.last() {
+            let end = last.addr + last.size as u64;
+            ensure!(
+                addr == last.addr || addr < end,
+                "{} is past the end of the last function ({} ends at {})",
+                format_addr(addr),
+                get_display_name(last),
+                format_addr(end)
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Returns the demangled name of a function for messages, or its name if it cannot
+/// be demangled.
+fn get_display_name(info: &Info) -> String {
+    demangle_str_auto(&info.name)
+        .map(|(demangled, _)| demangled)
+        .unwrap_or_else(|_| info.name.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{make_function, TempDir};
+
+    /// Virtual dispatch thunks for ksys::act::BaseProc (and a covariant one for ksys::Foo).
+    const THUNKS: &[&str] = &[
+        // non-virtual thunk to ksys::act::BaseProc::~BaseProc()
+        "_ZThn8_N4ksys3act8BaseProcD1Ev",
+        // non-virtual thunk to ksys::act::BaseProc::init()
+        "_ZThn16_N4ksys3act8BaseProc4initEv",
+        // virtual thunk to ksys::act::BaseProc::~BaseProc()
+        "_ZTv0_n24_N4ksys3act8BaseProcD0Ev",
+        // covariant return thunk to ksys::Foo::clone()
+        "_ZTch0_h8_N4ksys3Foo5cloneEv",
+    ];
+
+    /// Names that look similar to thunks but are not.
+    const NOT_THUNKS: &[&str] = &[
+        // ksys::act::BaseProc::~BaseProc()
+        "_ZN4ksys3act8BaseProcD1Ev",
+        // vtable for ksys::act::BaseProc
+        "_ZTVN4ksys3act8BaseProcE",
+        // typeinfo for ksys::act::BaseProc
+        "_ZTIN4ksys3act8BaseProcE",
+        // typeinfo name for ksys::act::BaseProc
+        "_ZTSN4ksys3act8BaseProcE",
+        "OUTLINED_FUNCTION_12",
+        "",
+    ];
+
+    #[test]
+    fn virtual_thunks_are_detected() {
+        for name in THUNKS {
+            assert!(is_virtual_thunk(name), "{}", name);
+            assert!(demangle_str(name).unwrap().contains("thunk"), "{}", name);
+        }
+        for name in NOT_THUNKS {
+            assert!(!is_virtual_thunk(name), "{}", name);
+        }
+    }
+
+    #[test]
+    fn virtual_thunks_are_stripped() {
+        let mut functions: Vec<Info> = THUNKS
+            .iter()
+            .chain(NOT_THUNKS)
+            .enumerate()
+            .map(|(i, name)| {
+                make_function(0x10 * (i as u64 + 1), 0x10, name, Status::NotDecompiled)
+            })
+            .collect();
+        assert_eq!(count_virtual_thunks(&functions), THUNKS.len());
+
+        let mut normalized = functions.clone();
+        normalize_functions(&mut normalized, &NormalizationOptions::default());
+        assert_eq!(normalized.len(), functions.len());
+
+        let options = NormalizationOptions {
+            strip_virtual_thunks: true,
+        };
+        normalize_functions(&mut normalized, &options);
+        strip_virtual_thunks(&mut functions);
+        assert_eq!(normalized, functions);
+        let names: Vec<&str> = functions.iter().map(|info| info.name.as_str()).collect();
+        assert_eq!(names, NOT_THUNKS);
+        assert_eq!(count_virtual_thunks(&functions), 0);
+    }
+
+    #[test]
+    fn qualified_names_with_templates() {
+        assert_eq!(
+            split_qualified_name("sead::Buffer<int>::size() const"),
+            ["sead", "Buffer", "size"]
+        );
+        assert_eq!(
+            split_qualified_name("sead::Foo<sead::Bar<int>::Baz, 3>::get<float>(float)"),
+            ["sead", "Foo", "get"]
+        );
+        // Function templates have a return type.
+        assert_eq!(
+            split_qualified_name("sead::Buffer<int> sead::make<int>(sead::Heap*)"),
+            ["sead", "make"]
+        );
+        assert_eq!(
+            split_qualified_name("(anonymous namespace)::Foo<int>::bar()"),
+            ["(anonymous namespace)", "Foo", "bar"]
+        );
+    }
+
+    #[test]
+    fn qualified_names_with_operators() {
+        assert_eq!(
+            split_qualified_name("sead::Vector2<float>::operator=(sead::Vector2<float> const&)"),
+            ["sead", "Vector2", "operator="]
+        );
+        assert_eq!(
+            split_qualified_name(
+                "sead::operator<(sead::SafeString const&, sead::SafeString const&)"
+            ),
+            ["sead", "operator<"]
+        );
+        assert_eq!(
+            split_qualified_name("sead::operator<<(sead::Stream&, int)"),
+            ["sead", "operator<<"]
+        );
+        assert_eq!(
+            split_qualified_name("ksys::Foo::operator()() const"),
+            ["ksys", "Foo", "operator()"]
+        );
+        assert_eq!(
+            split_qualified_name("ksys::Foo::operator->() const"),
+            ["ksys", "Foo", "operator->"]
+        );
+        assert_eq!(
+            split_qualified_name("ksys::Foo::operator bool() const"),
+            ["ksys", "Foo", "operator bool"]
+        );
+        assert_eq!(
+            split_qualified_name("ksys::Foo::operator new(unsigned long)"),
+            ["ksys", "Foo", "operator new"]
+        );
+    }
+
+    #[test]
+    fn qualified_names_with_destructors() {
+        assert_eq!(
+            split_qualified_name("ksys::act::BaseProc::~BaseProc()"),
+            ["ksys", "act", "BaseProc", "~BaseProc"]
+        );
+        assert_eq!(
+            split_qualified_name("sead::Buffer<int>::~Buffer()"),
+            ["sead", "Buffer", "~Buffer"]
+        );
+    }
+
+    #[test]
+    fn qualified_names_with_local_entities() {
+        assert_eq!(
+            split_qualified_name("ksys::Foo::f()::{lambda()#1}::operator()() const"),
+            ["ksys", "Foo", "f", "{lambda()#1}", "operator()"]
+        );
+        // Thunk prefixes are not part of the name.
+        assert_eq!(
+            split_qualified_name("non-virtual thunk to ksys::Foo::~Foo()"),
+            ["ksys", "Foo", "~Foo"]
+        );
+    }
+
+    #[test]
+    fn anchored_name_matches() {
+        let name = "ksys::act::BaseProc::init(sead::Heap*)";
+        assert!(is_anchored_name_match(name, "BaseProc::init"));
+        assert!(is_anchored_name_match(name, "act::BaseProc::init"));
+        assert!(is_anchored_name_match(
+            name,
+            "ksys::act::BaseProc::init(sead::Heap*)"
+        ));
+        assert!(!is_anchored_name_match(name, "BaseProc"));
+        assert!(!is_anchored_name_match(name, "Proc::init"));
+        assert!(!is_anchored_name_match(
+            "ksys::act::BaseProcInitializer::initImpl()",
+            "BaseProc::init"
+        ));
+
+        // Template arguments are ignored.
+        assert!(is_anchored_name_match(
+            "sead::Buffer<float>::size() const",
+            "Buffer<int>::size"
+        ));
+        assert!(is_anchored_name_match(
+            "sead::Buffer<int>::~Buffer()",
+            "Buffer::~Buffer"
+        ));
+        assert!(!is_anchored_name_match(
+            "sead::Buffer<int>::~Buffer()",
+            "Buffer::Buffer"
+        ));
+        assert!(is_anchored_name_match(
+            "sead::Vector2<float>::operator=(sead::Vector2<float> const&)",
+            "Vector2::operator="
+        ));
+        assert!(!is_anchored_name_match(
+            "sead::Vector2<float>::operator==(sead::Vector2<float> const&)",
+            "Vector2::operator="
+        ));
+    }
+
+    #[test]
+    fn fuzzy_search_prefers_anchored_matches() {
+        let functions = vec![
+            // ksys::act::BaseProcInitializer::initImpl()
+            make_function(
+                0x10,
+                0x10,
+                "_ZN4ksys3act19BaseProcInitializer8initImplEv",
+                Status::NotDecompiled,
+            ),
+            // ksys::act::BaseProc::init()
+            make_function(
+                0x20,
+                0x10,
+                "_ZN4ksys3act8BaseProc4initEv",
+                Status::NotDecompiled,
+            ),
+        ];
+        let found = find_function_fuzzy(&functions, "BaseProc::init").unwrap();
+        assert_eq!(found.addr, 0x20);
+        // Substring matches are still found if there is no anchored match.
+        let found = find_function_fuzzy(&functions, "ProcInitializer::init").unwrap();
+        assert_eq!(found.addr, 0x10);
+    }
+
+    #[test]
+    fn prototypes_are_normalized() {
+        assert_eq!(
+            normalize_prototype("void ksys::act::BaseProc::init ( sead::Heap * heap , bool sub )"),
+            "ksys::act::BaseProc::init(sead::Heap*,bool)"
+        );
+        assert_eq!(
+            normalize_prototype("ksys::act::BaseProc::init(sead::Heap*, bool)"),
+            "ksys::act::BaseProc::init(sead::Heap*,bool)"
+        );
+        assert_eq!(
+            normalize_prototype(
+                "virtual const sead::SafeString& ksys::Foo::getName(int idx = 0) const override"
+            ),
+            "ksys::Foo::getName(int) const"
+        );
+        assert_eq!(
+            normalize_prototype(
+                "__attribute__((noinline)) static void ksys::Foo::reset(  const sead::Vector3f&  v,\n\
+                 unsigned int   flags) noexcept"
+            ),
+            "ksys::Foo::reset(sead::Vector3f const&,unsigned int)"
+        );
+        assert_eq!(
+            normalize_prototype("int sead::Buffer<int>::get(int index) const volatile &"),
+            "sead::Buffer<int>::get(int) const volatile"
+        );
+        assert_eq!(
+            normalize_prototype("void ksys::Foo::bar(void (*callback)(int), int (&array)[4])"),
+            "ksys::Foo::bar(void(*)(int),int(&)[4])"
+        );
+        assert_eq!(
+            normalize_prototype("ksys::Foo::bar(void (*)(int), int (&) [4])"),
+            "ksys::Foo::bar(void(*)(int),int(&)[4])"
+        );
+        assert_eq!(normalize_prototype("ksys::Foo::bar()"), "ksys::Foo::bar()");
+        assert_eq!(normalize_prototype("ksys::Foo::bar"), "ksys::Foo::bar");
+    }
+
+    #[test]
+    fn fuzzy_search_normalizes_pasted_prototypes() {
+        let functions = vec![
+            // ksys::act::BaseProc::init(sead::Heap*, bool)
+            make_function(
+                0x10,
+                0x10,
+                "_ZN4ksys3act8BaseProc4initEPN4sead4HeapEb",
+                Status::NotDecompiled,
+            ),
+            // ksys::act::BaseProc::init()
+            make_function(
+                0x20,
+                0x10,
+                "_ZN4ksys3act8BaseProc4initEv",
+                Status::NotDecompiled,
+            ),
+        ];
+        let query = "void ksys::act::BaseProc::init ( sead::Heap * heap , bool sub )";
+        assert_eq!(find_function_fuzzy(&functions, query).unwrap().addr, 0x10);
+        let index = DemangledIndex::build(&functions);
+        assert_eq!(index.find_function_fuzzy(query).unwrap().addr, 0x10);
+    }
+
+    #[test]
+    fn code_addresses_must_be_aligned() {
+        assert_eq!(parse_code_address("0x7100001230").unwrap(), 0x1230);
+        let err = parse_code_address("0x7100001231").unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<MisalignedAddressError>(),
+            Some(&MisalignedAddressError { addr: 0x1231 })
+        );
+        assert_eq!(
+            err.to_string(),
+            "0x0000007100001231 is not 4-byte aligned; the nearest aligned addresses are \
+             0x0000007100001230 and 0x0000007100001234"
+        );
+        // Data addresses don't need to be aligned.
+        assert_eq!(parse_address("0x7100001231").unwrap(), 0x1231);
+
+        let functions = [
+            make_function(0x1200, 0x30, "_ZN4ksys4calcEv", Status::NotDecompiled),
+            make_function(0x1240, 0x10, "_ZN4ksys4nextEv", Status::NotDecompiled),
+        ];
+        let index = AddressIndex::build(&functions);
+        assert_eq!(index.nearest_function(0x1238).unwrap().addr, 0x1240);
+        assert_eq!(index.nearest_function(0x1100).unwrap().addr, 0x1200);
+        let err = index.parse_code_address("0x7100001206").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "0x0000007100001206 is not 4-byte aligned; did you mean ksys::calc() \
+             (0x0000007100001200)?"
+        );
+        // Callers can still get the aligned addresses.
+        let misaligned = err.downcast_ref::<MisalignedAddressError>().unwrap();
+        assert_eq!(
+            (misaligned.previous_aligned(), misaligned.next_aligned()),
+            (0x1204, 0x1208)
+        );
+        let err = AddressIndex::build(&[])
+            .check_code_address(0x1231)
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<MisalignedAddressError>(),
+            Some(&MisalignedAddressError { addr: 0x1231 })
+        );
+    }
+
+    #[test]
+    fn code_addresses_below_the_base() {
+        let err = parse_code_address("0x1230").unwrap_err();
+        assert!(
+            err.to_string().contains("below the base address"),
+            "{}",
+            err
+        );
+        let index = AddressIndex::build(&[]);
+        assert!(index.parse_code_address("0x7000001230").is_err());
+    }
+
+    #[test]
+    fn code_addresses_above_the_range() {
+        let functions = [make_function(
+            0x1200,
+            0x30,
+            "_ZN4ksys4calcEv",
+            Status::NotDecompiled,
+        )];
+        let index = AddressIndex::build(&functions);
+        assert!(index.check_code_address(0x122c).is_ok());
+        assert_eq!(
+            index.check_code_address(0x1230).unwrap_err().to_string(),
+            "0x0000007100001230 is past the end of the last function (ksys::calc() ends at \
+             0x0000007100001230)"
+        );
+
+        // With a text range, addresses after the last function are fine.
+        let index = AddressIndex::build(&functions).with_text_range(0x1000..0x2000);
+        assert!(index.check_code_address(0x1230).is_ok());
+        assert!(index.check_code_address(0x1000).is_ok());
+        assert_eq!(
+            index.check_code_address(0x2000).unwrap_err().to_string(),
+            "0x0000007100002000 is outside of the code (0x0000007100001000..0x0000007100002000)"
+        );
+        assert!(index.check_code_address(0xffc).is_err());
+    }
+
+    #[test]
+    fn merge_reports_duplicates_by_file() {
+        let dir = TempDir::new("functions_merge");
+        let a = dir.join("a.csv");
+        let b = dir.join("b.csv");
+        std::fs::write(
+            &a,
+            "Address,Quality,Size,Name\n\
+             0x0000007100000010,O,000016,_Z5aliasv\n\
+             0x0000007100000020,O,000016,_Z4dupev\n\
+             0x0000007100000030,O,000016,_Z4dupev\n\
+             0x0000007100000040,O,000016,_Z8conflictv\n",
+        )
+        .unwrap();
+        std::fs::write(
+            &b,
+            "Address,Quality,Size,Name\n\
+             0x0000007100000010,O,000016,_Z5aliasv\n\
+             0x0000007100000050,O,000016,_Z8conflictv\n\
+             0x0000007100000060,U,000016,_Z4samev\n\
+             0x0000007100000060,U,000016,_Z4samev\n",
+        )
+        .unwrap();
+
+        let (functions, warnings) = get_functions_for_multiple_paths(&[&a, &b]).unwrap();
+        let addrs: Vec<u64> = functions.iter().map(|info| info.addr).collect();
+        assert_eq!(addrs, [0x10, 0x20, 0x30, 0x40, 0x50, 0x60]);
+
+        let names = |duplicates: &[CrossFileDuplicate]| -> Vec<String> {
+            duplicates.iter().map(|dup| dup.name.clone()).collect()
+        };
+        assert_eq!(names(&warnings.aliases), ["_Z5aliasv"]);
+        assert_eq!(names(&warnings.conflicts), ["_Z8conflictv"]);
+        assert!(warnings.shared_addresses.is_empty());
+
+        let in_file: Vec<(&str, &Path, &[u64])> = warnings
+            .in_file_duplicates
+            .iter()
+            .map(|dup| (dup.name.as_str(), dup.path.as_path(), dup.addrs.as_slice()))
+            .collect();
+        assert_eq!(
+            in_file,
+            [
+                ("_Z4dupev", a.as_path(), &[0x20, 0x30][..]),
+                ("_Z4samev", b.as_path(), &[0x60, 0x60][..]),
+            ]
+        );
+    }
+
+    #[test]
+    fn hand_written_addresses_are_normalized() {
+        let dir = TempDir::new("functions_normalize_addresses");
+        let path = dir.join("functions.csv");
+        std::fs::write(
+            &path,
+            "Address,Quality,Size,Name\r\n\
+             71001a1234,O,000016,_ZN4ksys3act8BaseProc4initEv\r\n\
+             0x00000071001A1240,U,000032,\n\
+             0x0000007100000010,m,000008,memcpy\n",
+        )
+        .unwrap();
+
+        // Reading and writing the list normalizes the addresses too, along with the rest.
+        let mut functions = get_functions_for_path(&path).unwrap();
+        normalize_address_format(&mut functions);
+        let mut contents = Vec::new();
+        write_functions_to_writer(&mut contents, &functions).unwrap();
+        assert!(String::from_utf8(contents)
+            .unwrap()
+            .contains("\n0x00000071001a1234,O,000016,_ZN4ksys3act8BaseProc4initEv\n"));
+
+        normalize_csv_addresses(&path).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "Address,Quality,Size,Name\r\n\
+             0x00000071001a1234,O,000016,_ZN4ksys3act8BaseProc4initEv\r\n\
+             0x00000071001a1240,U,000032,\n\
+             0x0000007100000010,m,000008,memcpy\n"
+        );
+
+        std::fs::write(
+            &path,
+            "Address,Quality,Size,Name\nnot_an_address,U,000016,\n",
+        )
+        .unwrap();
+        let err = normalize_csv_addresses(&path).unwrap_err();
+        assert!(format!("{:#}", err).starts_with("invalid address at line 2"));
+    }
+
+    #[test]
+    fn csv_entries_are_parsed_in_place() {
+        let schema: toml::Value =
+            toml::from_str("columns = [{ name = \"Notes\", type = \"string\" }]").unwrap();
+        let schema = CsvSchema::parse(&schema).unwrap();
+        let parse = |fields: &[&str]| {
+            parse_function_csv_entry(&csv::StringRecord::from(fields), ADDRESS_BASE, &schema)
+        };
+
+        let info = parse(&["0x0000007100001234", "O", "000016", "_Z1fv", "vtable"]).unwrap();
+        assert_eq!(
+            (info.addr, info.size, info.name.as_str()),
+            (0x1234, 16, "_Z1fv")
+        );
+        assert_eq!(info.status, Status::Matching);
+        assert_eq!(
+            info.extra.get("Notes"),
+            Some(&schema::Value::String("vtable".to_string()))
+        );
+        assert!(parse(&["7100001234", "U", "16", "", ""])
+            .unwrap()
+            .extra
+            .is_empty());
+
+        let error = |fields: &[&str]| format!("{:#}", parse(fields).unwrap_err());
+        assert_eq!(
+            error(&["0x71000012zz", "O", "16", "_Z1fv", ""]),
+            "invalid address \"0x71000012zz\": invalid digit found in string"
+        );
+        assert_eq!(
+            error(&["0x1234", "O", "16", "_Z1fv", ""]),
+            "address 0x1234 is lower than the base address 0x7100000000"
+        );
+        assert_eq!(
+            error(&["0x7100001234", "X", "16", "_Z1fv", ""]),
+            "unexpected status code: X"
+        );
+        assert_eq!(
+            error(&["0x7100001234", "", "16", "_Z1fv", ""]),
+            "missing status code"
+        );
+        assert_eq!(
+            error(&["0x7100001234", "O", "-16", "_Z1fv", ""]),
+            "invalid size \"-16\": invalid digit found in string"
+        );
+        assert_eq!(
+            error(&["0x7100001234", "O", "16", "_Z1fv"]),
+            "invalid record"
+        );
+    }
+
+    #[test]
+    fn csv_errors_mention_the_line() {
+        let err = get_functions_for_reader(
+            &mut "Address,Quality,Size,Name\n\
+                  0x0000007100001234,O,000016,_Z1fv\n\
+                  0x0000007100001244,O,abc,_Z1gv\n"
+                .as_bytes(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            format!("{:#}", err),
+            "failed to parse CSV record at line 3: invalid size \"abc\": invalid digit found in string"
+        );
+    }
+
+    #[test]
+    fn non_ascii_names_are_not_rust_symbols() {
+        // The hash would start in the middle of 'é' if the name were sliced by bytes.
+        let names = [
+            "_ZN3foo17h\u{e9}0123456789abcdeE",
+            "_ZN\u{e9}0123456789abcdeE",
+            "_ZN\u{1f600}E",
+        ];
+        for name in names.iter() {
+            assert_ne!(get_symbol_language(name), SymbolLanguage::Rust, "{}", name);
+        }
+        assert_eq!(
+            get_symbol_language("_ZN3std2io5stdio6_print17h0123456789abcdefE"),
+            SymbolLanguage::Rust
+        );
+
+        let functions: Vec<Info> = names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| Info::new(i as u64 * 4, 4, name.to_string(), Status::NotDecompiled))
+            .collect();
+        let index = DemangledIndex::build(&functions);
+        for i in 0..names.len() {
+            assert_ne!(index.get_language(i), SymbolLanguage::Rust);
+        }
+    }
+
+    #[test]
+    fn entries_are_diffed_by_address() {
+        let f = |addr, name: &str| Info::new(addr, 0x10, name.to_string(), Status::Matching);
+        let old = vec![f(0x300, "c"), f(0x100, "a"), f(0x200, "b")];
+        let new = vec![f(0x200, "b2"), f(0x400, "d"), f(0x200, "b3"), f(0x100, "a")];
+        assert_eq!(
+            diff_by_address(&old, &new),
+            [
+                AddressDiff::Kept {
+                    old: &old[1],
+                    new: &new[3]
+                },
+                AddressDiff::Kept {
+                    old: &old[2],
+                    new: &new[2]
+                },
+                AddressDiff::Removed(&old[0]),
+                AddressDiff::Added(&new[1]),
+            ]
+        );
+    }
+
+    #[test]
+    fn unreadable_lists_are_only_overwritten_with_allow_shrink() {
+        crate::testing::use_test_config();
+        let dir = TempDir::new("functions_unreadable");
+        let path = dir.join("functions.csv");
+        let functions = vec![Info::new(
+            0x100,
+            0x10,
+            "_Z1fv".to_string(),
+            Status::Matching,
+        )];
+        std::fs::write(
+            &path,
+            "Address,Quality,Size,Name\nnot an address,O,16,_Z1fv\n",
+        )
+        .unwrap();
+
+        let err =
+            write_functions_atomic_ex(&path, &functions, &WriteOptions::default()).unwrap_err();
+        assert!(
+            err.to_string().starts_with("refusing to write"),
+            "{:#}",
+            err
+        );
+        assert!(std::fs::read_to_string(&path)
+            .unwrap()
+            .contains("not an address"));
+
+        let options = WriteOptions {
+            allow_shrink: true,
+            ..WriteOptions::default()
+        };
+        write_functions_atomic_ex(&path, &functions, &options).unwrap();
+        assert_eq!(get_functions_for_path(&path).unwrap(), functions);
+    }
+
+    #[test]
+    fn declared_columns_are_kept_when_all_values_are_empty() {
+        crate::testing::use_test_config();
+        let dir = TempDir::new("functions_schema_roundtrip");
+        let path = dir.join("functions.csv");
+        let header = |path: &Path| {
+            let contents = std::fs::read_to_string(path).unwrap();
+            contents.lines().next().unwrap().to_string()
+        };
+
+        let mut functions = vec![
+            Info::new(0x100, 0x10, "_Z1fv".to_string(), Status::Matching),
+            Info::new(0x110, 0x10, "_Z1gv".to_string(), Status::NotDecompiled),
+        ];
+        write_functions_atomic(&path, &functions).unwrap();
+        assert_eq!(header(&path), "Address,Quality,Size,Name");
+
+        functions[0].extra.insert(
+            "Notes".to_string(),
+            schema::Value::String("inlined".to_string()),
+        );
+        write_functions_atomic(&path, &functions).unwrap();
+        assert_eq!(header(&path), "Address,Quality,Size,Name,Notes");
+
+        let mut functions = get_functions_for_path(&path).unwrap();
+        functions[0].extra.clear();
+        write_functions_to_path(&path, &functions).unwrap();
+        assert_eq!(header(&path), "Address,Quality,Size,Name,Notes");
+        assert_eq!(get_functions_for_path(&path).unwrap(), functions);
+        assert!(crate::edit::plan_write(&path, &functions)
+            .unwrap()
+            .is_empty());
+    }
 }
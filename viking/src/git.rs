@@ -0,0 +1,252 @@
+use crate::functions::{self, Info};
+use anyhow::{bail, ensure, Context, Result};
+use chrono::{DateTime, Utc};
+use rustc_hash::{FxHashMap, FxHashSet};
+use std::ffi::{OsStr, OsString};
+use std::path::Path;
+use std::process::Command;
+use std::time::{Duration, UNIX_EPOCH};
+
+/// The commit that last changed a line of a function list.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GitBlameInfo {
+    pub commit: String,
+    pub author: String,
+    pub date: DateTime<Utc>,
+    /// First line of the commit message.
+    pub message: String,
+}
+
+/// Returns the line number (1-based) of every function in a function list, by address.
+fn get_line_numbers(csv_path: &Path) -> Result<FxHashMap<u64, usize>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(true)
+        .from_path(csv_path)
+        .with_context(|| format!("failed to open {:?}", csv_path))?;
+
+    let mut lines = FxHashMap::default();
+    for record in reader.records() {
+        let record = record?;
+        let line = match record.position() {
+            Some(position) => position.line() as usize,
+            None => continue,
+        };
+        if let Some(addr) = record
+            .get(0)
+            .and_then(|addr| functions::parse_address(addr).ok())
+        {
+            lines.entry(addr).or_insert(line);
+        }
+    }
+    Ok(lines)
+}
+
+/// Parses the output of `git blame --line-porcelain` and returns blame info by line number.
+fn parse_line_porcelain(output: &str) -> Result<FxHashMap<usize, GitBlameInfo>> {
+    let mut result = FxHashMap::default();
+    let mut lines = output.lines();
+
+    while let Some(header) = lines.next() {
+        // <commit> <original line> <final line> [<line count>]
+        let mut fields = header.split(' ');
+        let commit = fields.next().unwrap_or_default().to_string();
+        let final_line: usize = fields
+            .nth(1)
+            .with_context(|| format!("invalid blame header: {:?}", header))?
+            .parse()?;
+
+        let mut author = String::new();
+        let mut time = None;
+        let mut message = String::new();
+        for line in &mut lines {
+            // The line contents (prefixed with a tab) end the entry.
+            if line.starts_with('\t') {
+                break;
+            }
+            if let Some(value) = line.strip_prefix("author ") {
+                author = value.to_string();
+            } else if let Some(value) = line.strip_prefix("author-time ") {
+                time = Some(value.parse::<u64>()?);
+            } else if let Some(value) = line.strip_prefix("summary ") {
+                message = value.to_string();
+            }
+        }
+
+        let time = time.with_context(|| format!("missing author time for {}", commit))?;
+        result.insert(
+            final_line,
+            GitBlameInfo {
+                commit,
+                author,
+                date: DateTime::<Utc>::from(UNIX_EPOCH + Duration::from_secs(time)),
+                message,
+            },
+        );
+    }
+    Ok(result)
+}
+
+/// Splits a path into the directory to run git in and the file name.
+fn get_dir_and_file_name(path: &Path) -> Result<(&Path, &OsStr)> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let file_name = path
+        .file_name()
+        .with_context(|| format!("invalid path: {:?}", path))?;
+    Ok((dir, file_name))
+}
+
+/// Runs `git blame` on a file (or on a single line of it).
+fn blame(path: &Path, line: Option<usize>) -> Result<FxHashMap<usize, GitBlameInfo>> {
+    let (dir, file_name) = get_dir_and_file_name(path)?;
+
+    let mut command = Command::new("git");
+    command
+        .current_dir(dir)
+        .arg("blame")
+        .arg("--line-porcelain");
+    if let Some(line) = line {
+        command.arg("-L").arg(format!("{},{}", line, line));
+    }
+    let output = command
+        .arg("--")
+        .arg(file_name)
+        .output()
+        .context("failed to launch git")?;
+    if !output.status.success() {
+        bail!(
+            "git blame failed for {:?}: {}",
+            path,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    parse_line_porcelain(&String::from_utf8(output.stdout)?)
+}
+
+/// Returns the commit that last changed the entry for the function at `addr`.
+pub fn get_function_blame(csv_path: &Path, addr: u64) -> Result<GitBlameInfo> {
+    let line = get_line_numbers(csv_path)?
+        .get(&addr)
+        .copied()
+        .with_context(|| format!("unknown function at {}", functions::format_addr(addr)))?;
+    let mut blames = blame(csv_path, Some(line))?;
+    blames
+        .remove(&line)
+        .with_context(|| format!("git blame returned no data for line {}", line))
+}
+
+/// Returns the commit that last changed each line of a file, by line number (1-based).
+pub fn get_file_blame(path: &Path) -> Result<FxHashMap<usize, GitBlameInfo>> {
+    blame(path, None)
+}
+
+/// Same as `get_function_blame`, but for many functions at once (git is only run once).
+/// Functions that are not in the file at `csv_path` are left out.
+pub fn get_all_function_blames(
+    csv_path: &Path,
+    functions: &[Info],
+) -> Result<FxHashMap<u64, GitBlameInfo>> {
+    let line_numbers = get_line_numbers(csv_path)?;
+    let blames = blame(csv_path, None)?;
+    ensure!(
+        blames.len() >= line_numbers.len(),
+        "git blame returned fewer lines than expected for {:?}",
+        csv_path
+    );
+
+    Ok(functions
+        .iter()
+        .filter_map(|info| {
+            let line = line_numbers.get(&info.addr)?;
+            Some((info.addr, blames.get(line)?.clone()))
+        })
+        .collect())
+}
+
+/// Reads the version of the function list at `csv_path` that is stored in git at `revision`
+/// (a commit hash, branch or tag), or the version in the index (the staged version) if
+/// `revision` is empty. Returns None if the file does not exist at that revision.
+pub fn get_functions_at_revision(csv_path: &Path, revision: &str) -> Result<Option<Vec<Info>>> {
+    let (dir, file_name) = get_dir_and_file_name(csv_path)?;
+    let mut object = OsString::from(format!("{}:./", revision));
+    object.push(file_name);
+
+    let output = Command::new("git")
+        .current_dir(dir)
+        // The error message is checked below.
+        .env("LC_ALL", "C")
+        .arg("show")
+        .arg(&object)
+        .output()
+        .context("failed to launch git")?;
+
+    let describe_revision = || match revision {
+        "" => "the index".to_string(),
+        revision => revision.to_string(),
+    };
+    if output.status.success() {
+        let functions =
+            functions::get_functions_for_reader(&mut &output.stdout[..]).with_context(|| {
+                format!("failed to parse {:?} at {}", csv_path, describe_revision())
+            })?;
+        return Ok(Some(functions));
+    }
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    // The file may have been added after the commit (or not be staged yet).
+    let exists =
+        !stderr.contains("does not exist in") && !stderr.contains("exists on disk, but not in");
+    ensure!(
+        !exists,
+        "git show failed for {:?} at {}: {}",
+        csv_path,
+        describe_revision(),
+        stderr.trim()
+    );
+    Ok(None)
+}
+
+/// Returns the committer date of `commit_ref` (a commit hash, branch or tag) in the repository
+/// that contains `path`.
+pub fn get_commit_date(path: &Path, commit_ref: &str) -> Result<DateTime<Utc>> {
+    let (dir, _) = get_dir_and_file_name(path)?;
+    let output = Command::new("git")
+        .current_dir(dir)
+        .arg("show")
+        .arg("--no-patch")
+        .arg("--format=%ct")
+        .arg(commit_ref)
+        .arg("--")
+        .output()
+        .context("failed to launch git")?;
+    if !output.status.success() {
+        bail!(
+            "git show failed for {}: {}",
+            commit_ref,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    let time: u64 = String::from_utf8(output.stdout)?
+        .trim()
+        .parse()
+        .with_context(|| format!("git show returned an invalid date for {}", commit_ref))?;
+    Ok(DateTime::<Utc>::from(
+        UNIX_EPOCH + Duration::from_secs(time),
+    ))
+}
+
+/// Returns the functions that are in the function list at `csv_path` but were not in the
+/// version of that file at `commit_ref` (a commit hash, branch or tag). Functions are compared
+/// by address. If the file did not exist at `commit_ref`, all functions are returned.
+pub fn get_functions_added_since_commit(csv_path: &Path, commit_ref: &str) -> Result<Vec<Info>> {
+    let old_functions = get_functions_at_revision(csv_path, commit_ref)?.unwrap_or_default();
+    let old_addrs: FxHashSet<u64> = old_functions.iter().map(|info| info.addr).collect();
+    Ok(functions::get_functions_for_path(csv_path)?
+        .into_iter()
+        .filter(|info| !old_addrs.contains(&info.addr))
+        .collect())
+}
@@ -0,0 +1,393 @@
+use crate::functions::{self, AddressDiff, Info, Status};
+#[cfg(feature = "git")]
+use crate::git;
+use crate::lock::{self, LockOptions};
+use crate::repo;
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+const LOG_HEADER: &[&str] = &["Timestamp", "Address", "Name", "Old", "New", "Reason"];
+
+/// A status transition, from the status log or from a comparison of two versions
+/// of a function list (see `get_status_changes`).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StatusChange {
+    pub timestamp: DateTime<Utc>,
+    pub addr: u64,
+    pub name: String,
+    /// The size of the function, if known. Sizes are not recorded in the status log.
+    pub size: Option<u32>,
+    pub old_status: Status,
+    pub new_status: Status,
+    pub reason: Option<String>,
+}
+
+/// Orders statuses from least to most complete. Library functions are not decompiled
+/// and rank with `NotDecompiled`.
+fn get_status_rank(status: &Status) -> u8 {
+    match status {
+        Status::NotDecompiled | Status::Library => 0,
+        Status::Wip => 1,
+        Status::NonMatchingMajor => 2,
+        Status::NonMatchingMinor => 3,
+        Status::Matching => 4,
+    }
+}
+
+impl StatusChange {
+    /// Returns whether the function is closer to matching than before.
+    pub fn is_promotion(&self) -> bool {
+        get_status_rank(&self.new_status) > get_status_rank(&self.old_status)
+    }
+
+    /// Returns whether the function is further from matching than before.
+    pub fn is_regression(&self) -> bool {
+        get_status_rank(&self.new_status) < get_status_rank(&self.old_status)
+    }
+}
+
+/// Returns the path to the status log, if status changes should be logged.
+/// Logging is disabled unless `status_log_csv` (relative to the repo root) is set in the config.
+pub fn get_status_log_path() -> Result<Option<PathBuf>> {
+    match repo::CONFIG
+        .get("status_log_csv")
+        .and_then(toml::Value::as_str)
+    {
+        Some(path) => Ok(Some(repo::get_repo_root()?.join(path))),
+        None => Ok(None),
+    }
+}
+
+/// Returns the status transitions between two versions of a function list, sorted by address.
+/// Functions are matched by address (see `functions::diff_by_address`); added and removed
+/// functions are ignored.
+pub fn get_status_changes(old: &[Info], new: &[Info], reason: Option<&str>) -> Vec<StatusChange> {
+    let timestamp = Utc::now();
+
+    functions::diff_by_address(old, new)
+        .into_iter()
+        .filter_map(|diff| match diff {
+            AddressDiff::Kept { old, new } if old.status != new.status => Some(StatusChange {
+                timestamp,
+                addr: new.addr,
+                name: new.name.clone(),
+                size: Some(new.size),
+                old_status: old.status.clone(),
+                new_status: new.status.clone(),
+                reason: reason.map(str::to_string),
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Appends entries to the log at `log_path`, which is created if it doesn't exist.
+pub fn append_to_path(log_path: &Path, changes: &[StatusChange]) -> Result<()> {
+    if changes.is_empty() {
+        return Ok(());
+    }
+
+    // Hold the lock until the entries have been written, so that the header is only written by
+    // the writer that creates the log, before any entry.
+    let _lock = lock::lock_exclusive(log_path, &LockOptions::default())?;
+    let is_new = std::fs::metadata(log_path)
+        .map(|metadata| metadata.len() == 0)
+        .unwrap_or(true);
+
+    let mut contents = Vec::new();
+    {
+        let mut writer = csv::WriterBuilder::new()
+            .has_headers(false)
+            .from_writer(&mut contents);
+        if is_new {
+            writer.write_record(LOG_HEADER)?;
+        }
+        for change in changes {
+            writer.write_record(&[
+                change
+                    .timestamp
+                    .to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+                functions::format_addr(change.addr),
+                change.name.clone(),
+                change.old_status.code().to_string(),
+                change.new_status.code().to_string(),
+                change.reason.clone().unwrap_or_default(),
+            ])?;
+        }
+        writer.flush()?;
+    }
+
+    // Write all entries at once so that writers that don't take the lock (older versions)
+    // cannot interleave partial lines.
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_path)
+        .with_context(|| format!("failed to open {:?}", log_path))?;
+    file.write_all(&contents)?;
+    Ok(())
+}
+
+fn parse_status(value: &str) -> Result<Status> {
+    match value.chars().next().and_then(Status::from_code) {
+        Some(status) => Ok(status),
+        None => bail!("invalid status code: {}", value),
+    }
+}
+
+fn parse_log_entry(record: &csv::StringRecord) -> Result<StatusChange> {
+    if record.len() != LOG_HEADER.len() {
+        bail!("invalid record; expected {} fields", LOG_HEADER.len());
+    }
+
+    Ok(StatusChange {
+        timestamp: DateTime::parse_from_rfc3339(&record[0])?.with_timezone(&Utc),
+        addr: functions::parse_address(&record[1])?,
+        name: record[2].to_string(),
+        size: None,
+        old_status: parse_status(&record[3])?,
+        new_status: parse_status(&record[4])?,
+        reason: Some(record[5].to_string()).filter(|reason| !reason.is_empty()),
+    })
+}
+
+/// Reads all entries from the log at `log_path`. A missing log is treated as an empty log.
+pub fn read_from_path(log_path: &Path) -> Result<Vec<StatusChange>> {
+    if !log_path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(true)
+        .from_path(log_path)?;
+    let mut changes = Vec::new();
+    for (i, record) in reader.records().enumerate() {
+        let change = parse_log_entry(&record?)
+            .with_context(|| format!("failed to parse status log entry at line {}", i + 2))?;
+        changes.push(change);
+    }
+    Ok(changes)
+}
+
+/// Records status transitions between two versions of the executable's function list.
+/// Nothing is done if the status log is disabled.
+pub fn record_status_changes(old: &[Info], new: &[Info], reason: Option<&str>) -> Result<()> {
+    match get_status_log_path()? {
+        Some(log_path) => append_to_path(&log_path, &get_status_changes(old, new, reason)),
+        None => Ok(()),
+    }
+}
+
+/// Returns all recorded status changes for the function at `addr`, from oldest to newest.
+pub fn for_function(addr: u64) -> Result<Vec<StatusChange>> {
+    let log_path = match get_status_log_path()? {
+        Some(log_path) => log_path,
+        None => return Ok(Vec::new()),
+    };
+    Ok(read_from_path(&log_path)?
+        .into_iter()
+        .filter(|change| change.addr == addr)
+        .collect())
+}
+
+/// Where the entries of a `Changelog` come from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChangelogSource {
+    /// The status log, which has reasons and the time of each change.
+    StatusLog,
+    /// A comparison of two versions of the function list. All changes have the same timestamp
+    /// (the time of the comparison) and no reason.
+    FunctionListDiff,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Changelog {
+    pub source: ChangelogSource,
+    /// From oldest to newest.
+    pub changes: Vec<StatusChange>,
+}
+
+/// Returns the entries of the log at `log_path` that were recorded after `since`,
+/// or None if the log is missing or empty.
+pub fn read_changes_since(
+    log_path: &Path,
+    since: DateTime<Utc>,
+) -> Result<Option<Vec<StatusChange>>> {
+    let changes = read_from_path(log_path)?;
+    if changes.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(
+        changes
+            .into_iter()
+            .filter(|change| change.timestamp > since)
+            .collect(),
+    ))
+}
+
+/// Same as `get_changelog_since_commit_ex`, with the status log from the config.
+#[cfg(feature = "git")]
+pub fn get_changelog_since_commit(csv_path: &Path, commit_ref: &str) -> Result<Changelog> {
+    get_changelog_since_commit_ex(csv_path, commit_ref, get_status_log_path()?.as_deref())
+}
+
+/// Returns the status changes of the functions in the function list at `csv_path` since
+/// `commit_ref` (a commit hash, branch or tag).
+///
+/// The status log at `log_path` is preferred: if it exists and is not empty, the changes that
+/// were logged after the commit date of `commit_ref` are returned. This assumes that the log
+/// was enabled before that commit. Otherwise, the current function list is compared with its
+/// version at `commit_ref` (see `get_status_changes`).
+#[cfg(feature = "git")]
+pub fn get_changelog_since_commit_ex(
+    csv_path: &Path,
+    commit_ref: &str,
+    log_path: Option<&Path>,
+) -> Result<Changelog> {
+    if let Some(log_path) = log_path {
+        let since = git::get_commit_date(csv_path, commit_ref)?;
+        if let Some(changes) = read_changes_since(log_path, since)? {
+            return Ok(Changelog {
+                source: ChangelogSource::StatusLog,
+                changes,
+            });
+        }
+    }
+
+    let old = git::get_functions_at_revision(csv_path, commit_ref)?.unwrap_or_default();
+    let new = functions::get_functions_for_path(csv_path)?;
+    Ok(Changelog {
+        source: ChangelogSource::FunctionListDiff,
+        changes: get_status_changes(&old, &new, None),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use chrono::TimeZone;
+
+    fn change(time: i64, addr: u64, new_status: Status) -> StatusChange {
+        StatusChange {
+            timestamp: Utc.timestamp_opt(time, 0).unwrap(),
+            addr,
+            name: format!("func_{:x}", addr),
+            size: None,
+            old_status: Status::NotDecompiled,
+            new_status,
+            reason: Some("decompiled, with a comma".to_string()),
+        }
+    }
+
+    #[test]
+    fn concurrent_appends_write_one_header() {
+        let dir = TempDir::new("status_log_concurrent");
+        let log_path = dir.join("status_log.csv");
+        let threads: Vec<_> = (0..8u64)
+            .map(|i| {
+                let log_path = log_path.clone();
+                std::thread::spawn(move || {
+                    append_to_path(
+                        &log_path,
+                        &[change(1_700_000_000, i * 0x10, Status::Matching)],
+                    )
+                    .unwrap();
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let contents = std::fs::read_to_string(&log_path).unwrap();
+        assert!(contents.starts_with("Timestamp,Address,Name,Old,New,Reason\n"));
+        assert_eq!(contents.matches("Timestamp").count(), 1);
+        let mut addrs: Vec<u64> = read_from_path(&log_path)
+            .unwrap()
+            .iter()
+            .map(|change| change.addr)
+            .collect();
+        addrs.sort_unstable();
+        assert_eq!(addrs, (0..8).map(|i| i * 0x10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn changes_since_a_date_are_read_from_the_log() {
+        let dir = TempDir::new("status_log_since");
+        let log_path = dir.join("status_log.csv");
+        let since = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+        assert_eq!(read_changes_since(&log_path, since).unwrap(), None);
+
+        let old = change(1_699_999_999, 0x10, Status::Wip);
+        let new = change(1_700_000_001, 0x10, Status::Matching);
+        append_to_path(&log_path, &[old, new.clone()]).unwrap();
+        assert_eq!(
+            read_changes_since(&log_path, since).unwrap(),
+            Some(vec![new])
+        );
+    }
+
+    #[cfg(feature = "git")]
+    #[test]
+    fn changelogs_prefer_the_status_log() {
+        use std::process::Command;
+
+        let dir = TempDir::new("status_log_changelog");
+        let csv_path = dir.join("functions.csv");
+        let git = |args: &[&str]| {
+            let status = Command::new("git")
+                .current_dir(dir.path())
+                .args([
+                    "-c",
+                    "user.name=viking",
+                    "-c",
+                    "user.email=viking@example.com",
+                ])
+                .args(args)
+                .env("GIT_COMMITTER_DATE", "@1700000000 +0000")
+                .env("GIT_AUTHOR_DATE", "@1700000000 +0000")
+                .status()
+                .unwrap();
+            assert!(status.success());
+        };
+        std::fs::write(
+            &csv_path,
+            "Address,Quality,Size,Name\n0x0000007100000010,U,000016,func_10\n",
+        )
+        .unwrap();
+        git(&["init", "--quiet"]);
+        git(&["add", "functions.csv"]);
+        git(&["commit", "--quiet", "-m", "Add functions"]);
+        std::fs::write(
+            &csv_path,
+            "Address,Quality,Size,Name\n0x0000007100000010,O,000016,func_10\n",
+        )
+        .unwrap();
+
+        let log_path = dir.join("status_log.csv");
+        let changelog = get_changelog_since_commit_ex(&csv_path, "HEAD", Some(&log_path)).unwrap();
+        assert_eq!(changelog.source, ChangelogSource::FunctionListDiff);
+        assert_eq!(changelog.changes.len(), 1);
+        assert_eq!(changelog.changes[0].old_status, Status::NotDecompiled);
+        assert_eq!(changelog.changes[0].new_status, Status::Matching);
+        assert_eq!(changelog.changes[0].reason, None);
+
+        let logged = change(1_700_000_100, 0x10, Status::Matching);
+        append_to_path(
+            &log_path,
+            &[change(1_699_000_000, 0x10, Status::Wip), logged.clone()],
+        )
+        .unwrap();
+        let changelog = get_changelog_since_commit_ex(&csv_path, "HEAD", Some(&log_path)).unwrap();
+        assert_eq!(
+            changelog,
+            Changelog {
+                source: ChangelogSource::StatusLog,
+                changes: vec![logged],
+            }
+        );
+    }
+}
@@ -0,0 +1,397 @@
+use crate::functions::{self, Info, WriteOptions};
+use crate::git;
+use crate::lint::{Linter, Severity};
+use crate::ui;
+use anyhow::{bail, ensure, Context, Result};
+use rustc_hash::FxHashSet;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Hooks that are managed by `install` and `uninstall`.
+pub const HOOK_NAMES: &[&str] = &["pre-commit", "pre-push"];
+
+/// Lines that delimit the part of a hook script that is managed by viking.
+/// Anything outside of these markers is left untouched.
+const BEGIN_MARKER: &str = "# BEGIN viking hooks";
+const END_MARKER: &str = "# END viking hooks";
+
+const SHEBANG: &str = "#!/bin/sh";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HookState {
+    /// The hook script does not exist.
+    Missing,
+    /// The hook script exists but does not run viking.
+    Foreign,
+    /// The hook script runs viking with the current command.
+    Installed,
+    /// The hook script runs viking, but with a different command (e.g. from an older version).
+    Outdated,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HookStatus {
+    pub name: &'static str,
+    pub path: PathBuf,
+    pub state: HookState,
+}
+
+/// Returns the managed block for a hook. Hooks are run from the root of the working tree,
+/// so the check tool is referred to by its path relative to the repo root.
+fn make_block(hook_name: &str) -> String {
+    format!(
+        "{}\ntools/check --hook={} || exit 1\n{}\n",
+        BEGIN_MARKER, hook_name, END_MARKER
+    )
+}
+
+/// Returns the byte range of the managed block in a hook script (including the end marker
+/// and its line break), if there is one.
+fn find_block(script: &str) -> Result<Option<std::ops::Range<usize>>> {
+    let begin = match script.find(BEGIN_MARKER) {
+        Some(begin) => begin,
+        None => return Ok(None),
+    };
+    let end = script[begin..]
+        .find(END_MARKER)
+        .map(|end| begin + end + END_MARKER.len())
+        .context("hook script has a begin marker without an end marker")?;
+    let end = if script[end..].starts_with('\n') {
+        end + 1
+    } else {
+        end
+    };
+    Ok(Some(begin..end))
+}
+
+/// Returns the directory that contains the hooks of the git repo at `repo_root`.
+/// This respects `core.hooksPath` and works for worktrees.
+pub fn get_hooks_dir(repo_root: &Path) -> Result<PathBuf> {
+    let output = Command::new("git")
+        .current_dir(repo_root)
+        .arg("rev-parse")
+        .arg("--git-path")
+        .arg("hooks")
+        .output()
+        .context("failed to launch git")?;
+    if !output.status.success() {
+        return Ok(repo_root.join(".git").join("hooks"));
+    }
+    // The path is relative to repo_root unless it is absolute.
+    let path = String::from_utf8(output.stdout)?;
+    Ok(repo_root.join(path.trim()))
+}
+
+fn make_executable(path: &Path) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755))
+            .with_context(|| format!("failed to make {:?} executable", path))?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct InstallOptions {
+    /// Whether to append the viking block to existing hook scripts that do not have one.
+    /// If false, such scripts are left untouched and reported as `HookState::Foreign`.
+    pub append_to_foreign_hooks: bool,
+}
+
+/// Same as `install_ex`, with the default options.
+pub fn install(repo_root: &Path) -> Result<Vec<HookStatus>> {
+    install_ex(repo_root, &InstallOptions::default())
+}
+
+/// Installs (or updates) the hooks in `HOOK_NAMES` for the git repo at `repo_root`.
+///
+/// Existing hook scripts are never overwritten: if a script already has a viking block,
+/// only that block is replaced. Scripts without a block are only modified (by appending
+/// the block) if `options.append_to_foreign_hooks` is set.
+pub fn install_ex(repo_root: &Path, options: &InstallOptions) -> Result<Vec<HookStatus>> {
+    let hooks_dir = get_hooks_dir(repo_root)?;
+    std::fs::create_dir_all(&hooks_dir)
+        .with_context(|| format!("failed to create {:?}", hooks_dir))?;
+
+    for &name in HOOK_NAMES {
+        let path = hooks_dir.join(name);
+        let block = make_block(name);
+        let script = match std::fs::read_to_string(&path) {
+            Ok(script) => match find_block(&script)? {
+                Some(range) => {
+                    let mut script = script;
+                    script.replace_range(range, &block);
+                    script
+                }
+                None if !options.append_to_foreign_hooks => continue,
+                None => {
+                    let mut script = script;
+                    if !script.is_empty() && !script.ends_with('\n') {
+                        script.push('\n');
+                    }
+                    script.push_str(&block);
+                    script
+                }
+            },
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                format!("{}\n{}", SHEBANG, block)
+            }
+            Err(err) => return Err(err).with_context(|| format!("failed to read {:?}", path)),
+        };
+        std::fs::write(&path, script).with_context(|| format!("failed to write {:?}", path))?;
+        make_executable(&path)?;
+    }
+    status(repo_root)
+}
+
+/// Removes the viking block from the hooks in `HOOK_NAMES`. Hook scripts that are empty
+/// afterwards (apart from the shebang) are deleted; other scripts are left in place.
+pub fn uninstall(repo_root: &Path) -> Result<Vec<HookStatus>> {
+    let hooks_dir = get_hooks_dir(repo_root)?;
+    for &name in HOOK_NAMES {
+        let path = hooks_dir.join(name);
+        let mut script = match std::fs::read_to_string(&path) {
+            Ok(script) => script,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err).with_context(|| format!("failed to read {:?}", path)),
+        };
+        let range = match find_block(&script)? {
+            Some(range) => range,
+            None => continue,
+        };
+        script.replace_range(range, "");
+
+        if script.trim().is_empty() || script.trim() == SHEBANG {
+            std::fs::remove_file(&path).with_context(|| format!("failed to remove {:?}", path))?;
+        } else {
+            std::fs::write(&path, script).with_context(|| format!("failed to write {:?}", path))?;
+        }
+    }
+    status(repo_root)
+}
+
+/// Returns the state of every hook in `HOOK_NAMES`.
+pub fn status(repo_root: &Path) -> Result<Vec<HookStatus>> {
+    let hooks_dir = get_hooks_dir(repo_root)?;
+    HOOK_NAMES
+        .iter()
+        .map(|&name| {
+            let path = hooks_dir.join(name);
+            let state = match std::fs::read_to_string(&path) {
+                Ok(script) => match find_block(&script)? {
+                    Some(range)
+                        if script[range.clone()].trim_end() == make_block(name).trim_end() =>
+                    {
+                        HookState::Installed
+                    }
+                    Some(_) => HookState::Outdated,
+                    None => HookState::Foreign,
+                },
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => HookState::Missing,
+                Err(err) => return Err(err).with_context(|| format!("failed to read {:?}", path)),
+            };
+            Ok(HookStatus { name, path, state })
+        })
+        .collect()
+}
+
+/// Checks a new version of the function list against an older one: the function list must
+/// not shrink too much (see `WriteOptions::check_shrink`) and must not introduce lint errors.
+/// Errors that were already in the old version and warnings are only printed, so that
+/// the hooks can be enabled in projects that have not fixed every issue yet.
+fn check_revision(csv_path: &Path, old_functions: &[Info], functions: &[Info]) -> Result<()> {
+    WriteOptions::from_config().check_shrink(csv_path, old_functions, functions)?;
+
+    // Most names are in both versions, so they only need to be demangled once.
+    let linter = Linter::new();
+    let old_issues = if old_functions.is_empty() {
+        Vec::new()
+    } else {
+        linter.validate_project(old_functions)?
+    };
+    let old_errors: FxHashSet<(Option<u64>, &str)> = old_issues
+        .iter()
+        .filter(|issue| issue.severity == Severity::Error)
+        .map(|issue| (issue.addr, issue.message.as_str()))
+        .collect();
+
+    let mut num_new_errors = 0;
+    for issue in linter.validate_project(functions)? {
+        let is_new_error = issue.severity == Severity::Error
+            && !old_errors.contains(&(issue.addr, issue.message.as_str()));
+        if is_new_error {
+            ui::print_error(&issue.to_string());
+            num_new_errors += 1;
+        } else {
+            ui::print_warning(&issue.to_string());
+        }
+    }
+    ensure!(
+        num_new_errors == 0,
+        "{:?} has {} new lint error(s)",
+        csv_path,
+        num_new_errors
+    );
+    Ok(())
+}
+
+/// Entry point for the pre-commit hook: checks the staged version of the function list
+/// against the version in HEAD. The working tree is ignored, so unstaged changes
+/// cannot hide problems (or cause failures).
+pub fn run_pre_commit() -> Result<()> {
+    let csv_path = functions::get_functions_csv_path();
+    let functions = match git::get_functions_at_revision(csv_path, "")? {
+        Some(functions) => functions,
+        None => bail!("{:?} is not in the index", csv_path),
+    };
+    check_revision(csv_path, &get_baseline(csv_path, "HEAD")?, &functions)
+}
+
+/// Returns whether `revision` exists, e.g. to check whether the current branch has
+/// an upstream branch or whether there are any commits yet.
+fn has_revision(dir: &Path, revision: &str) -> Result<bool> {
+    let status = Command::new("git")
+        .current_dir(dir)
+        .arg("rev-parse")
+        .arg("--verify")
+        .arg("--quiet")
+        .arg(revision)
+        .output()
+        .context("failed to launch git")?
+        .status;
+    Ok(status.success())
+}
+
+/// Returns the version of the function list at `revision`, or an empty list if the revision
+/// or the file does not exist.
+fn get_baseline(csv_path: &Path, revision: &str) -> Result<Vec<Info>> {
+    let dir = match csv_path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    if !has_revision(dir, revision)? {
+        return Ok(Vec::new());
+    }
+    Ok(git::get_functions_at_revision(csv_path, revision)?.unwrap_or_default())
+}
+
+/// Entry point for the pre-push hook: checks the version of the function list in HEAD
+/// against the version in the upstream branch. Branches without an upstream are checked
+/// without a baseline, so every lint error fails.
+pub fn run_pre_push() -> Result<()> {
+    let csv_path = functions::get_functions_csv_path();
+    let functions = match git::get_functions_at_revision(csv_path, "HEAD")? {
+        Some(functions) => functions,
+        None => bail!("{:?} is not in HEAD", csv_path),
+    };
+    check_revision(
+        csv_path,
+        &get_baseline(csv_path, "@{upstream}")?,
+        &functions,
+    )
+}
+
+/// Runs the entry point for `hook_name` (one of `HOOK_NAMES`).
+pub fn run(hook_name: &str) -> Result<()> {
+    match hook_name {
+        "pre-commit" => run_pre_commit(),
+        "pre-push" => run_pre_push(),
+        _ => bail!("unknown hook: {}", hook_name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    fn make_repo(name: &str) -> TempDir {
+        let dir = TempDir::new(name);
+        // Without git, get_hooks_dir falls back to .git/hooks, which is also fine.
+        let _ = Command::new("git")
+            .current_dir(dir.path())
+            .args(["init", "-q"])
+            .status();
+        dir
+    }
+
+    fn states(statuses: &[HookStatus]) -> Vec<HookState> {
+        statuses.iter().map(|status| status.state).collect()
+    }
+
+    #[test]
+    fn install_and_uninstall() {
+        let repo = make_repo("hooks_install");
+        assert_eq!(
+            states(&status(repo.path()).unwrap()),
+            [HookState::Missing, HookState::Missing]
+        );
+
+        let statuses = install(repo.path()).unwrap();
+        assert_eq!(
+            states(&statuses),
+            [HookState::Installed, HookState::Installed]
+        );
+        let script = std::fs::read_to_string(&statuses[0].path).unwrap();
+        assert_eq!(script, format!("{}\n{}", SHEBANG, make_block("pre-commit")));
+
+        // Installing again replaces only the block.
+        let edited = format!(
+            "{}\necho before\n{}echo after\n",
+            SHEBANG,
+            make_block("old")
+        );
+        std::fs::write(&statuses[0].path, &edited).unwrap();
+        assert_eq!(
+            states(&status(repo.path()).unwrap())[0],
+            HookState::Outdated
+        );
+        install(repo.path()).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&statuses[0].path).unwrap(),
+            edited.replace(&make_block("old"), &make_block("pre-commit"))
+        );
+
+        assert_eq!(
+            states(&uninstall(repo.path()).unwrap()),
+            [HookState::Foreign, HookState::Missing]
+        );
+        assert_eq!(
+            std::fs::read_to_string(&statuses[0].path).unwrap(),
+            format!("{}\necho before\necho after\n", SHEBANG)
+        );
+    }
+
+    #[test]
+    fn foreign_hooks_are_left_alone() {
+        let repo = make_repo("hooks_foreign");
+        let hooks_dir = get_hooks_dir(repo.path()).unwrap();
+        std::fs::create_dir_all(&hooks_dir).unwrap();
+        let foreign = "#!/bin/sh\nexec lint-staged";
+        std::fs::write(hooks_dir.join("pre-commit"), foreign).unwrap();
+
+        assert_eq!(
+            states(&install(repo.path()).unwrap()),
+            [HookState::Foreign, HookState::Installed]
+        );
+        assert_eq!(
+            std::fs::read_to_string(hooks_dir.join("pre-commit")).unwrap(),
+            foreign
+        );
+
+        let options = InstallOptions {
+            append_to_foreign_hooks: true,
+        };
+        assert_eq!(
+            states(&install_ex(repo.path(), &options).unwrap()),
+            [HookState::Installed, HookState::Installed]
+        );
+        assert_eq!(
+            std::fs::read_to_string(hooks_dir.join("pre-commit")).unwrap(),
+            format!("{}\n{}", foreign, make_block("pre-commit"))
+        );
+    }
+}
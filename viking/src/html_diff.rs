@@ -0,0 +1,573 @@
+use crate::capstone_utils::translate_cs_error;
+use crate::checks::Mismatch;
+use crate::elf;
+use crate::functions::{self, Info};
+use anyhow::Result;
+use capstone as cs;
+use cs::arch::arm64::{Arm64Insn, Arm64Operand, Arm64OperandType};
+use similar::{Algorithm, DiffOp};
+use std::fmt::Write;
+
+/// Default for `HtmlDiffOptions::context`.
+pub const DEFAULT_HTML_DIFF_CONTEXT: usize = 4;
+
+/// Styles for `render_html_diff`. These are embedded in the page so that the file can be
+/// shared on its own (e.g. attached to an issue).
+const HTML_DIFF_CSS: &str = "\
+body { background: #1e1f22; color: #dcdcdc; font-family: sans-serif; margin: 1.5em; }
+h1 { font-size: 1.2em; font-family: monospace; word-break: break-all; }
+dl { display: grid; grid-template-columns: max-content auto; gap: 0.2em 1em; }
+dt { color: #9a9a9a; }
+dd { margin: 0; font-family: monospace; white-space: pre-wrap; word-break: break-all; }
+table { border-collapse: collapse; font-family: monospace; font-size: 0.9em; }
+th { text-align: left; padding: 0.3em 0.6em; border-bottom: 1px solid #555; }
+td { padding: 0 0.6em; white-space: pre; }
+td.offset { color: #7a7a7a; text-align: right; }
+td.insn { min-width: 28em; }
+tr.operands td.insn { background: #3a3520; }
+tr.replaced td.insn { background: #4a2a2a; }
+tr.deleted td.orig, tr.inserted td.decomp { background: #2a3f2a; }
+tr.collapsed td { color: #7a7a7a; font-style: italic; text-align: center; padding: 0.2em; }
+span.mnemonic { color: #6cb6ff; }
+span.diff { background: #8a3a3a; color: #fff; border-radius: 2px; }
+span[title] { text-decoration: underline dotted; cursor: help; }
+";
+
+/// Target of a branch or call instruction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BranchTarget {
+    pub addr: u64,
+    /// Name of the function at `addr`, if it is known.
+    pub name: Option<String>,
+}
+
+/// An instruction, as it is shown in a diff.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DiffInstruction {
+    /// Offset from the start of the function.
+    pub offset: u64,
+    pub mnemonic: String,
+    /// Operands, as printed by the disassembler. Branch targets inside the function are
+    /// replaced with offsets and calls to known functions with the function name,
+    /// so that the operands can be compared between the original and the decomp function.
+    pub operands: Vec<String>,
+    /// Only set for branches and calls.
+    pub branch_target: Option<BranchTarget>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DiffRowKind {
+    Same,
+    /// Same mnemonic, but some operands are different.
+    Operands,
+    /// Different instructions.
+    Replaced,
+    /// The instruction is only in the original function.
+    Deleted,
+    /// The instruction is only in the decomp function.
+    Inserted,
+}
+
+impl DiffRowKind {
+    fn css_class(&self) -> &'static str {
+        match self {
+            DiffRowKind::Same => "same",
+            DiffRowKind::Operands => "operands",
+            DiffRowKind::Replaced => "replaced",
+            DiffRowKind::Deleted => "deleted",
+            DiffRowKind::Inserted => "inserted",
+        }
+    }
+}
+
+/// A pair of aligned instructions.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DiffRow {
+    pub kind: DiffRowKind,
+    pub orig: Option<DiffInstruction>,
+    pub decomp: Option<DiffInstruction>,
+}
+
+impl DiffRow {
+    /// Returns whether operand `i` differs between both sides of the row.
+    pub fn is_operand_different(&self, i: usize) -> bool {
+        match (&self.orig, &self.decomp) {
+            (Some(orig), Some(decomp)) => orig.operands.get(i) != decomp.operands.get(i),
+            _ => false,
+        }
+    }
+}
+
+/// Instruction-level diff between an original function and its decompiled version.
+///
+/// `checks::FunctionChecker` stops at the first mismatch and only records its address and
+/// cause (`checks::Mismatch`), and full diffs are left to asm-differ (`diff.py`), which runs
+/// outside of viking. Neither has per-instruction data that could be rendered, so this
+/// structure is built separately; the `Mismatch` is still shown in the page header.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct InstructionDiff {
+    pub rows: Vec<DiffRow>,
+}
+
+impl InstructionDiff {
+    /// Aligns instructions by mnemonic, so that an inserted or missing instruction
+    /// does not make the rest of the function look different.
+    pub fn new(orig: &[DiffInstruction], decomp: &[DiffInstruction]) -> Self {
+        let orig_mnemonics: Vec<&str> = orig.iter().map(|insn| insn.mnemonic.as_str()).collect();
+        let decomp_mnemonics: Vec<&str> =
+            decomp.iter().map(|insn| insn.mnemonic.as_str()).collect();
+
+        let mut rows = Vec::with_capacity(orig.len().max(decomp.len()));
+        let mut push = |kind, orig: Option<&DiffInstruction>, decomp: Option<&DiffInstruction>| {
+            rows.push(DiffRow {
+                kind,
+                orig: orig.cloned(),
+                decomp: decomp.cloned(),
+            })
+        };
+
+        let ops =
+            similar::capture_diff_slices(Algorithm::Myers, &orig_mnemonics, &decomp_mnemonics);
+        for op in ops {
+            match op {
+                DiffOp::Equal {
+                    old_index,
+                    new_index,
+                    len,
+                } => {
+                    for (o, d) in orig[old_index..old_index + len]
+                        .iter()
+                        .zip(&decomp[new_index..new_index + len])
+                    {
+                        let kind = if o.operands == d.operands {
+                            DiffRowKind::Same
+                        } else {
+                            DiffRowKind::Operands
+                        };
+                        push(kind, Some(o), Some(d));
+                    }
+                }
+                DiffOp::Delete {
+                    old_index, old_len, ..
+                } => {
+                    for o in &orig[old_index..old_index + old_len] {
+                        push(DiffRowKind::Deleted, Some(o), None);
+                    }
+                }
+                DiffOp::Insert {
+                    new_index, new_len, ..
+                } => {
+                    for d in &decomp[new_index..new_index + new_len] {
+                        push(DiffRowKind::Inserted, None, Some(d));
+                    }
+                }
+                DiffOp::Replace {
+                    old_index,
+                    old_len,
+                    new_index,
+                    new_len,
+                } => {
+                    for i in 0..old_len.max(new_len) {
+                        let o = orig[old_index..old_index + old_len].get(i);
+                        let d = decomp[new_index..new_index + new_len].get(i);
+                        let kind = match (o, d) {
+                            (Some(_), Some(_)) => DiffRowKind::Replaced,
+                            (Some(_), None) => DiffRowKind::Deleted,
+                            _ => DiffRowKind::Inserted,
+                        };
+                        push(kind, o, d);
+                    }
+                }
+            }
+        }
+
+        Self { rows }
+    }
+
+    /// Disassembles both functions and diffs them. `resolve_orig_name` and
+    /// `resolve_decomp_name` return the name of the function at an address
+    /// in the original and in the decomp executable respectively.
+    pub fn from_functions(
+        cs: &cs::Capstone,
+        orig_fn: &elf::Function,
+        decomp_fn: &elf::Function,
+        resolve_orig_name: &dyn Fn(u64) -> Option<String>,
+        resolve_decomp_name: &dyn Fn(u64) -> Option<String>,
+    ) -> Result<Self> {
+        let orig = disassemble_for_diff(cs, orig_fn, resolve_orig_name)?;
+        let decomp = disassemble_for_diff(cs, decomp_fn, resolve_decomp_name)?;
+        Ok(Self::new(&orig, &decomp))
+    }
+
+    /// Returns the number of rows that are not identical.
+    pub fn num_differences(&self) -> usize {
+        self.rows
+            .iter()
+            .filter(|row| row.kind != DiffRowKind::Same)
+            .count()
+    }
+}
+
+/// Splits an operand string at top-level commas (not inside memory operands like `[x0, #8]`).
+fn split_operands(op_str: &str) -> Vec<String> {
+    let mut operands = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in op_str.char_indices() {
+        match c {
+            '[' | '{' => depth += 1,
+            ']' | '}' => depth -= 1,
+            ',' if depth == 0 => {
+                operands.push(op_str[start..i].trim().to_string());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    let last = op_str[start..].trim();
+    if !last.is_empty() {
+        operands.push(last.to_string());
+    }
+    operands
+}
+
+fn is_branch(id: cs::InsnId) -> bool {
+    matches!(
+        id.0.into(),
+        Arm64Insn::ARM64_INS_B
+            | Arm64Insn::ARM64_INS_BL
+            | Arm64Insn::ARM64_INS_CBZ
+            | Arm64Insn::ARM64_INS_CBNZ
+            | Arm64Insn::ARM64_INS_TBZ
+            | Arm64Insn::ARM64_INS_TBNZ
+    )
+}
+
+/// Disassembles a function for `InstructionDiff::new`.
+pub(crate) fn disassemble_for_diff(
+    cs: &cs::Capstone,
+    function: &elf::Function,
+    resolve_name: &dyn Fn(u64) -> Option<String>,
+) -> Result<Vec<DiffInstruction>> {
+    let range = function.get_addr_range();
+    let mut instructions = Vec::new();
+    for insn in cs
+        .disasm_iter(function.code, function.addr)
+        .or_else(translate_cs_error)?
+    {
+        let mut operands = split_operands(insn.op_str().unwrap_or_default());
+        let mut branch_target = None;
+
+        if is_branch(insn.id()) {
+            let detail = cs.insn_detail(&insn).or_else(translate_cs_error)?;
+            let arch_detail = detail.arch_detail();
+            // The target is always the last operand.
+            let target = arch_detail
+                .arm64()
+                .and_then(|arm64| arm64.operands_ref().last())
+                .and_then(|op| match Arm64Operand::from(op).op_type {
+                    Arm64OperandType::Imm(imm) => Some(imm as u64),
+                    _ => None,
+                });
+            if let (Some(addr), Some(operand)) = (target, operands.last_mut()) {
+                let name = resolve_name(addr);
+                if range.contains(&addr) {
+                    *operand = format!("+{:#x}", addr - function.addr);
+                } else if let Some(name) = &name {
+                    *operand = name.clone();
+                }
+                branch_target = Some(BranchTarget { addr, name });
+            }
+        }
+
+        instructions.push(DiffInstruction {
+            offset: insn.address() - function.addr,
+            mnemonic: insn.mnemonic().unwrap_or_default().to_string(),
+            operands,
+            branch_target,
+        });
+    }
+    Ok(instructions)
+}
+
+#[derive(Clone, Debug)]
+pub struct HtmlDiffOptions {
+    /// Number of identical rows that are shown before and after every difference.
+    /// Longer runs of identical rows are collapsed.
+    pub context: usize,
+}
+
+impl Default for HtmlDiffOptions {
+    fn default() -> Self {
+        Self {
+            context: DEFAULT_HTML_DIFF_CONTEXT,
+        }
+    }
+}
+
+fn escape_html(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Removes the terminal colour codes that `Mismatch` descriptions contain.
+fn strip_ansi_codes(s: &str) -> String {
+    let mut stripped = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // Skip until the end of the escape sequence (a letter).
+            for c in &mut chars {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            stripped.push(c);
+        }
+    }
+    stripped
+}
+
+fn write_instruction_cells(
+    out: &mut String,
+    row: &DiffRow,
+    insn: Option<&DiffInstruction>,
+    side: &str,
+) {
+    let insn = match insn {
+        Some(insn) => insn,
+        None => {
+            let _ = write!(
+                out,
+                "<td class=\"offset {side}\"></td><td class=\"insn {side}\"></td>",
+                side = side
+            );
+            return;
+        }
+    };
+
+    let _ = write!(
+        out,
+        "<td class=\"offset {side}\">+{offset:#x}</td><td class=\"insn {side}\">\
+         <span class=\"mnemonic\">{mnemonic}</span>",
+        side = side,
+        offset = insn.offset,
+        mnemonic = escape_html(&insn.mnemonic),
+    );
+    let last = insn.operands.len().saturating_sub(1);
+    for (i, operand) in insn.operands.iter().enumerate() {
+        out.push_str(if i == 0 { " " } else { ", " });
+        let class = if row.is_operand_different(i) {
+            "operand diff"
+        } else {
+            "operand"
+        };
+        match &insn.branch_target {
+            Some(target) if i == last => {
+                let mut title = functions::format_addr(target.addr);
+                if let Some(name) = &target.name {
+                    title.push(' ');
+                    title.push_str(&functions::demangle_str(name).unwrap_or_else(|_| name.clone()));
+                }
+                let _ = write!(
+                    out,
+                    "<span class=\"{}\" title=\"{}\">{}</span>",
+                    class,
+                    escape_html(&title),
+                    escape_html(operand)
+                );
+            }
+            _ => {
+                let _ = write!(
+                    out,
+                    "<span class=\"{}\">{}</span>",
+                    class,
+                    escape_html(operand)
+                );
+            }
+        }
+    }
+    out.push_str("</td>");
+}
+
+/// Returns which rows are shown: every row that differs and up to `context` identical rows
+/// around it.
+fn get_visible_rows(diff: &InstructionDiff, context: usize) -> Vec<bool> {
+    let mut visible = vec![false; diff.rows.len()];
+    for (i, row) in diff.rows.iter().enumerate() {
+        if row.kind != DiffRowKind::Same {
+            let start = i.saturating_sub(context);
+            let end = (i + context + 1).min(diff.rows.len());
+            for v in &mut visible[start..end] {
+                *v = true;
+            }
+        }
+    }
+    visible
+}
+
+/// Same as `render_html_diff`, but with custom options.
+pub fn render_html_diff_with_options(
+    info: &Info,
+    mismatch: Option<&Mismatch>,
+    diff: &InstructionDiff,
+    options: &HtmlDiffOptions,
+) -> String {
+    let mut out = String::new();
+    let name = escape_html(&info.name);
+    let title =
+        escape_html(&functions::demangle_str(&info.name).unwrap_or_else(|_| info.name.clone()));
+
+    let _ = write!(
+        out,
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>{title}</title>\n<style>\n{css}</style>\n</head>\n<body>\n<header>\n\
+         <h1>{title}</h1>\n<dl>\n\
+         <dt>Name</dt><dd>{name}</dd>\n\
+         <dt>Address</dt><dd>{addr}</dd>\n\
+         <dt>Size</dt><dd>{size:#x} ({size} bytes)</dd>\n\
+         <dt>Status</dt><dd>{status}</dd>\n",
+        title = title,
+        css = HTML_DIFF_CSS,
+        name = name,
+        addr = functions::format_addr(info.addr),
+        size = info.size,
+        status = escape_html(info.status.description()),
+    );
+    if let Some(mismatch) = mismatch {
+        let _ = writeln!(
+            out,
+            "<dt>Mismatch</dt><dd>{}</dd>",
+            escape_html(&strip_ansi_codes(&mismatch.to_string()))
+        );
+    }
+    let _ = writeln!(
+        out,
+        "<dt>Differences</dt><dd>{} of {} instructions</dd>\n</dl>\n</header>",
+        diff.num_differences(),
+        diff.rows.len()
+    );
+
+    out.push_str(
+        "<table>\n<thead><tr><th colspan=\"2\">Original</th>\
+         <th colspan=\"2\">Decomp</th></tr></thead>\n<tbody>\n",
+    );
+    let visible = get_visible_rows(diff, options.context);
+    let mut i = 0;
+    while i < diff.rows.len() {
+        if !visible[i] {
+            let hidden = visible[i..].iter().take_while(|v| !**v).count();
+            // Collapsing a single row would not make the page any shorter.
+            if hidden > 1 {
+                let _ = writeln!(
+                    out,
+                    "<tr class=\"collapsed\"><td colspan=\"4\">{} identical instructions</td></tr>",
+                    hidden
+                );
+                i += hidden;
+                continue;
+            }
+        }
+
+        let row = &diff.rows[i];
+        let _ = write!(out, "<tr class=\"{}\">", row.kind.css_class());
+        write_instruction_cells(&mut out, row, row.orig.as_ref(), "orig");
+        write_instruction_cells(&mut out, row, row.decomp.as_ref(), "decomp");
+        out.push_str("</tr>\n");
+        i += 1;
+    }
+    out.push_str("</tbody>\n</table>\n</body>\n</html>\n");
+    out
+}
+
+/// Renders a diff as a self-contained HTML page: a header with the function's metadata and
+/// two aligned columns (original and decomp) in which differing operands are highlighted.
+/// Long runs of identical instructions are collapsed (see `HtmlDiffOptions::context`).
+pub fn render_html_diff(
+    info: &Info,
+    mismatch: Option<&Mismatch>,
+    diff: &InstructionDiff,
+) -> String {
+    render_html_diff_with_options(info, mismatch, diff, &HtmlDiffOptions::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checks::MismatchCause;
+    use crate::functions::Status;
+    use crate::testing::make_function;
+
+    fn insn(offset: u64, mnemonic: &str, operands: &[&str]) -> DiffInstruction {
+        DiffInstruction {
+            offset,
+            mnemonic: mnemonic.to_string(),
+            operands: operands.iter().map(|op| op.to_string()).collect(),
+            branch_target: None,
+        }
+    }
+
+    fn call(offset: u64, name: &str) -> DiffInstruction {
+        DiffInstruction {
+            branch_target: Some(BranchTarget {
+                addr: 0x1000,
+                name: Some(name.to_string()),
+            }),
+            ..insn(offset, "bl", &[name])
+        }
+    }
+
+    #[test]
+    fn render_snapshot() {
+        let mut orig = vec![insn(0x0, "stp", &["x29", "x30", "[sp, #-0x10]!"])];
+        let mut decomp = orig.clone();
+        for i in 1..5 {
+            orig.push(insn(i * 4, "nop", &[]));
+            decomp.push(insn(i * 4, "nop", &[]));
+        }
+        orig.push(insn(0x14, "ldr", &["w8", "[x0, #0x18]"]));
+        decomp.push(insn(0x14, "ldr", &["w8", "[x0, #0x1c]"]));
+        orig.push(call(0x18, "_ZN3Foo3barEv"));
+        decomp.push(insn(0x18, "mov", &["w0", "w8"]));
+        decomp.push(call(0x1c, "_ZN3Foo3barEv"));
+        orig.push(insn(0x1c, "ret", &[]));
+        decomp.push(insn(0x20, "ret", &[]));
+
+        let diff = InstructionDiff::new(&orig, &decomp);
+        let kinds: Vec<DiffRowKind> = diff.rows.iter().map(|row| row.kind).collect();
+        assert_eq!(
+            kinds[5..],
+            [
+                DiffRowKind::Operands,
+                DiffRowKind::Inserted,
+                DiffRowKind::Same,
+                DiffRowKind::Same,
+            ]
+        );
+
+        let info = make_function(0x1234, 0x20, "_ZN3Foo4testEPv", Status::NonMatchingMinor);
+        let mismatch = Mismatch {
+            addr_orig: functions::ADDRESS_BASE + 0x1248,
+            addr_decomp: 0x48,
+            cause: MismatchCause::Immediate,
+        };
+        let html = render_html_diff_with_options(
+            &info,
+            Some(&mismatch),
+            &diff,
+            &HtmlDiffOptions { context: 1 },
+        );
+        assert_eq!(html, include_str!("../tests/snapshots/html_diff.html"));
+    }
+}
@@ -0,0 +1,186 @@
+use crate::functions::{self, Info};
+use crate::lint::Issue;
+use crate::repo;
+use anyhow::{Context, Result};
+use rustc_hash::FxHashMap;
+use std::ops::Range;
+use std::path::Path;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum IgnoreRule {
+    /// Mangled name.
+    Name(String),
+    /// *Note*: addresses do not contain the IDA base (0x7100000000).
+    AddressRange(Range<u64>),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IgnoreEntry {
+    pub rule: IgnoreRule,
+    /// Comment at the end of the line, if any.
+    pub reason: Option<String>,
+    /// Line number in the ignore file (1-based).
+    pub line: usize,
+}
+
+impl std::fmt::Display for IgnoreEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.rule {
+            IgnoreRule::Name(name) => write!(f, "{}", name)?,
+            IgnoreRule::AddressRange(range) => write!(
+                f,
+                "{}..{}",
+                functions::format_addr(range.start),
+                functions::format_addr(range.end)
+            )?,
+        }
+        if let Some(reason) = &self.reason {
+            write!(f, " ({})", reason)?;
+        }
+        Ok(())
+    }
+}
+
+/// Functions that are excluded from checks and (optionally) from stats,
+/// e.g. compiler-generated outlined functions or init arrays.
+///
+/// Ignore files contain one entry per line, in any order. An entry is either a mangled name,
+/// an address or an address range (`start..end`, end exclusive). Everything after a `#`
+/// is a comment; comments at the end of an entry are used as the reason for ignoring it.
+///
+/// ```text
+/// # Outlined functions
+/// 0x7100a00000..0x7100a01000  # OUTLINED_FUNCTION_*
+/// _ZN4ksys12_GLOBAL__N_14initEv
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct IgnoreSet {
+    entries: Vec<IgnoreEntry>,
+    /// Indices of name entries in `entries`.
+    names: FxHashMap<String, usize>,
+}
+
+fn parse_rule(value: &str) -> Result<IgnoreRule> {
+    if !value.starts_with("0x") {
+        return Ok(IgnoreRule::Name(value.to_string()));
+    }
+
+    match value.split_once("..") {
+        Some((start, end)) => {
+            let start = functions::parse_address_or_offset(start.trim())?;
+            let end = functions::parse_address_or_offset(end.trim())?;
+            Ok(IgnoreRule::AddressRange(start..end))
+        }
+        None => {
+            let addr = functions::parse_address_or_offset(value)?;
+            Ok(IgnoreRule::AddressRange(addr..addr + 1))
+        }
+    }
+}
+
+impl IgnoreSet {
+    pub fn parse(contents: &str) -> Result<Self> {
+        let mut set = Self::default();
+        for (i, line) in contents.lines().enumerate() {
+            let (value, comment) = match line.split_once('#') {
+                Some((value, comment)) => (value.trim(), Some(comment.trim())),
+                None => (line.trim(), None),
+            };
+            if value.is_empty() {
+                continue;
+            }
+
+            let rule =
+                parse_rule(value).with_context(|| format!("invalid entry at line {}", i + 1))?;
+            if let IgnoreRule::Name(name) = &rule {
+                set.names.entry(name.clone()).or_insert(set.entries.len());
+            }
+            set.entries.push(IgnoreEntry {
+                rule,
+                reason: comment
+                    .filter(|comment| !comment.is_empty())
+                    .map(str::to_string),
+                line: i + 1,
+            });
+        }
+        Ok(set)
+    }
+
+    pub fn load_from_path(path: &Path) -> Result<Self> {
+        let contents =
+            std::fs::read_to_string(path).with_context(|| format!("failed to read {:?}", path))?;
+        Self::parse(&contents).with_context(|| format!("failed to parse {:?}", path))
+    }
+
+    /// Loads the project's ignore set from the file at `ignore_functions` (relative to the repo
+    /// root) in the config. Nothing is ignored if that key is not set.
+    pub fn load() -> Result<Self> {
+        match repo::CONFIG
+            .get("ignore_functions")
+            .and_then(toml::Value::as_str)
+        {
+            Some(path) => Self::load_from_path(&repo::get_repo_root()?.join(path)),
+            None => Ok(Self::default()),
+        }
+    }
+
+    pub fn entries(&self) -> &[IgnoreEntry] {
+        &self.entries
+    }
+
+    /// Returns the entry that causes a function to be ignored, if any.
+    pub fn get_matching_entry(&self, info: &Info) -> Option<&IgnoreEntry> {
+        if let Some(&index) = self.names.get(&info.name) {
+            return Some(&self.entries[index]);
+        }
+        self.entries.iter().find(|entry| match &entry.rule {
+            IgnoreRule::AddressRange(range) => range.contains(&info.addr),
+            IgnoreRule::Name(_) => false,
+        })
+    }
+
+    pub fn is_ignored(&self, info: &Info) -> bool {
+        self.get_matching_entry(info).is_some()
+    }
+
+    /// Returns entries that do not match any function.
+    pub fn find_unused_entries(&self, functions: &[Info]) -> Vec<&IgnoreEntry> {
+        let mut used = vec![false; self.entries.len()];
+        for info in functions {
+            if let Some(&index) = self.names.get(&info.name) {
+                used[index] = true;
+            }
+            for (i, entry) in self.entries.iter().enumerate() {
+                if let IgnoreRule::AddressRange(range) = &entry.rule {
+                    if range.contains(&info.addr) {
+                        used[i] = true;
+                    }
+                }
+            }
+        }
+
+        self.entries
+            .iter()
+            .zip(used)
+            .filter(|(_, used)| !used)
+            .map(|(entry, _)| entry)
+            .collect()
+    }
+}
+
+/// Reports ignore entries that no longer match any function.
+pub fn check_ignore_set(functions: &[Info], ignore_set: &IgnoreSet) -> Vec<Issue> {
+    ignore_set
+        .find_unused_entries(functions)
+        .into_iter()
+        .map(|entry| {
+            Issue::warning(
+                None,
+                format!(
+                    "ignore entry at line {} does not match any function: {}",
+                    entry.line, entry
+                ),
+            )
+        })
+        .collect()
+}
@@ -0,0 +1,131 @@
+use crate::functions::{Info, Status};
+use crate::lint::Issue;
+use crate::repo;
+use anyhow::{Context, Result};
+use rustc_hash::FxHashSet;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// A function that cannot match with the current compiler, e.g. because of a codegen bug.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KnownIssue {
+    /// Mangled name.
+    pub name: String,
+    /// Link to the issue that tracks the problem.
+    pub url: String,
+    /// Short explanation, e.g. "bad register allocation in loop".
+    pub reason: String,
+}
+
+/// Functions that are decompiled but intentionally not matching. These are "blocked":
+/// reports count them separately from other non-matching functions, and they can be left out
+/// of lists of functions that need work.
+///
+/// Known issue files are TOML tables keyed by mangled name:
+///
+/// ```toml
+/// ["_ZN4ksys3act8BaseProc6updateEv"]
+/// url = "https://github.com/someone/repo/issues/12"
+/// reason = "bad register allocation in loop"
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct KnownIssues {
+    entries: BTreeMap<String, KnownIssue>,
+}
+
+impl KnownIssues {
+    pub fn parse(contents: &str) -> Result<Self> {
+        let value: toml::Value = toml::from_str(contents)?;
+        let table = value.as_table().context("known issues must be a table")?;
+
+        let mut issues = Self::default();
+        for (name, fields) in table {
+            let get_field = |key: &str| {
+                fields
+                    .get(key)
+                    .and_then(toml::Value::as_str)
+                    .map(str::to_string)
+                    .with_context(|| format!("known issue for {} must have a string {}", name, key))
+            };
+            let issue = KnownIssue {
+                name: name.clone(),
+                url: get_field("url")?,
+                reason: get_field("reason")?,
+            };
+            issues.entries.insert(name.clone(), issue);
+        }
+        Ok(issues)
+    }
+
+    pub fn load_from_path(path: &Path) -> Result<Self> {
+        let contents =
+            std::fs::read_to_string(path).with_context(|| format!("failed to read {:?}", path))?;
+        Self::parse(&contents).with_context(|| format!("failed to parse {:?}", path))
+    }
+
+    /// Loads the project's known issues from the file at `known_issues` (relative to the repo
+    /// root) in the config. There are no known issues if that key is not set.
+    pub fn load() -> Result<Self> {
+        match repo::CONFIG
+            .get("known_issues")
+            .and_then(toml::Value::as_str)
+        {
+            Some(path) => Self::load_from_path(&repo::get_repo_root()?.join(path)),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Returns the entries, sorted by name.
+    pub fn entries(&self) -> impl Iterator<Item = &KnownIssue> {
+        self.entries.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&KnownIssue> {
+        self.entries.get(name)
+    }
+
+    /// Returns whether a function has a known issue and is not matching yet.
+    pub fn is_blocked(&self, info: &Info) -> bool {
+        info.status != Status::Matching && self.entries.contains_key(&info.name)
+    }
+}
+
+/// Reports known issues for functions that are now matching (stale entries)
+/// or that are not in the function list.
+pub fn check_known_issues(functions: &[Info], known_issues: &KnownIssues) -> Vec<Issue> {
+    let mut issues = Vec::new();
+    let mut found = FxHashSet::default();
+    for info in functions {
+        if known_issues.get(&info.name).is_none() {
+            continue;
+        }
+        found.insert(info.name.as_str());
+        if info.status == Status::Matching {
+            issues.push(Issue::warning(
+                Some(info.addr),
+                format!(
+                    "{} has a known issue but is matching; remove the stale entry",
+                    info.name
+                ),
+            ));
+        }
+    }
+
+    for issue in known_issues.entries() {
+        if !found.contains(issue.name.as_str()) {
+            issues.push(Issue::warning(
+                None,
+                format!("known issue for {} does not match any function", issue.name),
+            ));
+        }
+    }
+    issues
+}
@@ -0,0 +1,371 @@
+use crate::functions::{self, Info};
+use crate::repo;
+use crate::sources::{self, SourceMapping};
+use anyhow::{Context, Result};
+use rayon::prelude::*;
+use rustc_hash::FxHashMap;
+use std::path::{Path, PathBuf};
+
+/// Default for `NamespaceDirectoryTable::namespace_depth`.
+pub const DEFAULT_NAMESPACE_DEPTH: usize = 2;
+
+/// Describes which directory the functions of each namespace should be implemented in.
+///
+/// Tables look like this:
+///
+/// ```toml
+/// # Optional. Directories with functions from more namespaces than this are reported.
+/// max_namespaces_per_directory = 3
+/// # Optional. Number of scope components that make up a namespace (default: 2).
+/// namespace_depth = 2
+///
+/// [directories]
+/// "ksys::act" = "src/KingSystem/ActorSystem"
+/// "ksys::act::*" = "src/KingSystem/ActorSystem"
+/// "ksys::act::ai::*" = "src/KingSystem/ActorSystem/AI"
+///
+/// [allow]
+/// # Known exceptions.
+/// files = ["src/KingSystem/Utils/Thread/*"]
+/// namespaces = ["ksys::act::debug*"]
+/// ```
+///
+/// Patterns are matched against the scope of a function, i.e. its demangled qualified name
+/// without the last component (e.g. `ksys::act::BaseProc` for `ksys::act::BaseProc::init()`).
+/// When several patterns match a scope, the longest pattern wins.
+#[derive(Clone, Debug)]
+pub struct NamespaceDirectoryTable {
+    /// Sorted by decreasing pattern length.
+    entries: Vec<(glob::Pattern, PathBuf)>,
+    allowed_files: Vec<glob::Pattern>,
+    allowed_namespaces: Vec<glob::Pattern>,
+    pub max_namespaces_per_directory: Option<usize>,
+    /// Namespaces are counted (for `max_namespaces_per_directory`) after truncating
+    /// scopes to this many components, so that classes in the same namespace are not
+    /// counted separately.
+    pub namespace_depth: usize,
+}
+
+impl Default for NamespaceDirectoryTable {
+    fn default() -> Self {
+        Self {
+            entries: Vec::new(),
+            allowed_files: Vec::new(),
+            allowed_namespaces: Vec::new(),
+            max_namespaces_per_directory: None,
+            namespace_depth: DEFAULT_NAMESPACE_DEPTH,
+        }
+    }
+}
+
+fn parse_patterns(value: Option<&toml::Value>, key: &str) -> Result<Vec<glob::Pattern>> {
+    let array = match value {
+        Some(value) => value
+            .as_array()
+            .with_context(|| format!("{} must be an array", key))?,
+        None => return Ok(Vec::new()),
+    };
+    array
+        .iter()
+        .map(|pattern| {
+            let pattern = pattern
+                .as_str()
+                .with_context(|| format!("{} must only contain strings", key))?;
+            glob::Pattern::new(pattern).with_context(|| format!("invalid pattern: {}", pattern))
+        })
+        .collect()
+}
+
+impl NamespaceDirectoryTable {
+    pub fn parse(contents: &str) -> Result<Self> {
+        let value: toml::Value = toml::from_str(contents)?;
+        let mut table = Self::default();
+
+        if let Some(directories) = value.get("directories") {
+            let directories = directories
+                .as_table()
+                .context("directories must be a table")?;
+            table.entries = directories
+                .iter()
+                .map(|(pattern, path)| {
+                    let path = path
+                        .as_str()
+                        .with_context(|| format!("directory for {} must be a string", pattern))?;
+                    let pattern = glob::Pattern::new(pattern)
+                        .with_context(|| format!("invalid pattern: {}", pattern))?;
+                    Ok((pattern, PathBuf::from(path)))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            // The sort is stable, so ties are broken by key order.
+            table
+                .entries
+                .sort_by_key(|(pattern, _)| std::cmp::Reverse(pattern.as_str().len()));
+        }
+
+        if let Some(allow) = value.get("allow") {
+            table.allowed_files = parse_patterns(allow.get("files"), "allow.files")?;
+            table.allowed_namespaces = parse_patterns(allow.get("namespaces"), "allow.namespaces")?;
+        }
+
+        if let Some(max) = value.get("max_namespaces_per_directory") {
+            let max = max
+                .as_integer()
+                .context("max_namespaces_per_directory must be an integer")?;
+            table.max_namespaces_per_directory = Some(max.max(0) as usize);
+        }
+        if let Some(depth) = value.get("namespace_depth") {
+            let depth = depth
+                .as_integer()
+                .context("namespace_depth must be an integer")?;
+            table.namespace_depth = depth.max(1) as usize;
+        }
+
+        Ok(table)
+    }
+
+    /// Returns the directory that functions in `scope` should be implemented in.
+    pub fn get_expected_directory(&self, scope: &str) -> Option<&Path> {
+        self.entries
+            .iter()
+            .find(|(pattern, _)| pattern.matches(scope))
+            .map(|(_, path)| path.as_path())
+    }
+
+    pub fn is_allowed_file(&self, file: &Path) -> bool {
+        self.allowed_files
+            .iter()
+            .any(|pattern| pattern.matches_path(file))
+    }
+
+    pub fn is_allowed_namespace(&self, scope: &str) -> bool {
+        self.allowed_namespaces
+            .iter()
+            .any(|pattern| pattern.matches(scope))
+    }
+}
+
+pub fn load_namespace_directory_table(path: &Path) -> Result<NamespaceDirectoryTable> {
+    let contents =
+        std::fs::read_to_string(path).with_context(|| format!("failed to read {:?}", path))?;
+    NamespaceDirectoryTable::parse(&contents).with_context(|| format!("failed to parse {:?}", path))
+}
+
+/// Returns the path to the project's namespace directory table (`namespace_directories_toml`
+/// in the config, relative to the repo root), if there is one.
+pub fn get_namespace_directory_table_path() -> Result<Option<PathBuf>> {
+    match repo::CONFIG
+        .get("namespace_directories_toml")
+        .and_then(toml::Value::as_str)
+    {
+        Some(path) => Ok(Some(repo::get_repo_root()?.join(path))),
+        None => Ok(None),
+    }
+}
+
+/// Returns the scope of a function (see `NamespaceDirectoryTable`), or None if the function
+/// is not in a namespace or class.
+fn get_scope(info: &Info) -> Option<String> {
+    let demangled = functions::demangle_str(&info.name).ok()?;
+    let components = functions::split_qualified_name(&demangled);
+    if components.len() < 2 {
+        return None;
+    }
+    Some(components[..components.len() - 1].join("::"))
+}
+
+fn truncate_scope(scope: &str, depth: usize) -> String {
+    scope.split("::").take(depth).collect::<Vec<_>>().join("::")
+}
+
+/// A source file that implements functions outside of the directory of their namespace.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MisplacedFile {
+    pub file: PathBuf,
+    /// Number of functions in the file that have an expected directory.
+    pub checked_functions: usize,
+    pub misplaced_functions: usize,
+    /// Expected directories of the misplaced functions with the number of functions
+    /// for each directory, most common first.
+    pub expected_directories: Vec<(PathBuf, usize)>,
+}
+
+/// A directory whose files implement functions from many namespaces.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MixedDirectory {
+    pub directory: PathBuf,
+    /// Namespaces (truncated to `NamespaceDirectoryTable::namespace_depth`) with the number
+    /// of functions for each namespace, most common first.
+    pub namespaces: Vec<(String, usize)>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LayoutAudit {
+    /// Sorted by decreasing number of misplaced functions.
+    pub misplaced_files: Vec<MisplacedFile>,
+    /// Sorted by decreasing number of namespaces.
+    pub mixed_directories: Vec<MixedDirectory>,
+}
+
+impl LayoutAudit {
+    pub fn is_empty(&self) -> bool {
+        self.misplaced_files.is_empty() && self.mixed_directories.is_empty()
+    }
+}
+
+impl std::fmt::Display for LayoutAudit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for file in &self.misplaced_files {
+            let expected: Vec<String> = file
+                .expected_directories
+                .iter()
+                .map(|(directory, count)| format!("{} ({})", directory.display(), count))
+                .collect();
+            writeln!(
+                f,
+                "{}: {}/{} functions belong in {}",
+                file.file.display(),
+                file.misplaced_functions,
+                file.checked_functions,
+                expected.join(", ")
+            )?;
+        }
+        for directory in &self.mixed_directories {
+            let namespaces: Vec<String> = directory
+                .namespaces
+                .iter()
+                .map(|(namespace, count)| format!("{} ({})", namespace, count))
+                .collect();
+            writeln!(
+                f,
+                "{}: functions from {} namespaces: {}",
+                directory.directory.display(),
+                directory.namespaces.len(),
+                namespaces.join(", ")
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Sorts counts by decreasing count, then by key.
+fn into_sorted_counts<K: Ord>(counts: FxHashMap<K, usize>) -> Vec<(K, usize)> {
+    let mut counts: Vec<(K, usize)> = counts.into_iter().collect();
+    counts.sort_by(|(a_key, a), (b_key, b)| b.cmp(a).then_with(|| a_key.cmp(b_key)));
+    counts
+}
+
+/// Checks that functions are implemented in the directory of their namespace.
+///
+/// Results are grouped by file so that a file that is entirely misplaced is reported once.
+/// Functions without a source file, without a scope or without an expected directory
+/// are ignored, and so are allowed files and namespaces.
+pub fn audit_namespace_directories(
+    functions_by_file: &FxHashMap<Option<PathBuf>, Vec<&Info>>,
+    table: &NamespaceDirectoryTable,
+) -> LayoutAudit {
+    let mut files: Vec<(&Path, &Vec<&Info>)> = functions_by_file
+        .iter()
+        .filter_map(|(file, functions)| Some((file.as_deref()?, functions)))
+        .filter(|(file, _)| !table.is_allowed_file(file))
+        .collect();
+    files.sort_by_key(|(file, _)| *file);
+
+    let scopes: Vec<Vec<Option<String>>> = files
+        .par_iter()
+        .map(|(_, functions)| functions.iter().map(|info| get_scope(info)).collect())
+        .collect();
+
+    let mut audit = LayoutAudit::default();
+    let mut namespaces_by_directory: FxHashMap<&Path, FxHashMap<String, usize>> =
+        FxHashMap::default();
+
+    for ((file, _), scopes) in files.iter().zip(&scopes) {
+        let mut checked_functions = 0;
+        let mut expected_directories: FxHashMap<PathBuf, usize> = FxHashMap::default();
+
+        for scope in scopes.iter().flatten() {
+            if table.is_allowed_namespace(scope) {
+                continue;
+            }
+            if let Some(directory) = file.parent() {
+                *namespaces_by_directory
+                    .entry(directory)
+                    .or_default()
+                    .entry(truncate_scope(scope, table.namespace_depth))
+                    .or_default() += 1;
+            }
+
+            let expected = match table.get_expected_directory(scope) {
+                Some(expected) => expected,
+                None => continue,
+            };
+            checked_functions += 1;
+            if !file.starts_with(expected) {
+                *expected_directories
+                    .entry(expected.to_path_buf())
+                    .or_default() += 1;
+            }
+        }
+
+        if !expected_directories.is_empty() {
+            audit.misplaced_files.push(MisplacedFile {
+                file: file.to_path_buf(),
+                checked_functions,
+                misplaced_functions: expected_directories.values().sum(),
+                expected_directories: into_sorted_counts(expected_directories),
+            });
+        }
+    }
+
+    if let Some(max) = table.max_namespaces_per_directory {
+        audit.mixed_directories = namespaces_by_directory
+            .into_iter()
+            .filter(|(_, namespaces)| namespaces.len() > max)
+            .map(|(directory, namespaces)| MixedDirectory {
+                directory: directory.to_path_buf(),
+                namespaces: into_sorted_counts(namespaces),
+            })
+            .collect();
+    }
+
+    // The sorts are stable and the inputs are sorted by path.
+    audit
+        .misplaced_files
+        .sort_by_key(|file| std::cmp::Reverse(file.misplaced_functions));
+    audit.mixed_directories.sort_by(|a, b| {
+        b.namespaces
+            .len()
+            .cmp(&a.namespaces.len())
+            .then_with(|| a.directory.cmp(&b.directory))
+    });
+    audit
+}
+
+/// Same as `audit_namespace_directories`, for functions that are assigned to files
+/// with a source mapping.
+pub fn audit_namespace_directories_for_mapping(
+    functions: &[Info],
+    mapping: &SourceMapping,
+    table: &NamespaceDirectoryTable,
+) -> LayoutAudit {
+    let functions_by_file = sources::get_functions_by_translation_unit(functions, mapping);
+    audit_namespace_directories(&functions_by_file, table)
+}
+
+/// Audits the project's function list with the source mapping and namespace directory table
+/// from the config. Returns None if either of them is not configured.
+pub fn audit_project_layout(functions: &[Info]) -> Result<Option<LayoutAudit>> {
+    let mapping_path = match sources::get_source_mapping_path()? {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+    let table_path = match get_namespace_directory_table_path()? {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+    let mapping = sources::load_source_mapping(&mapping_path)?;
+    let table = load_namespace_directory_table(&table_path)?;
+    Ok(Some(audit_namespace_directories_for_mapping(
+        functions, &mapping, &table,
+    )))
+}
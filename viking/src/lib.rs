@@ -1,6 +1,93 @@
+//! Tools for decompilation projects that target Nintendo Switch executables.
+//!
+//! Most tools only need the function list (`Info`, `Status` and the functions that load and
+//! write it), searching and stats. These are re-exported in [`prelude`]:
+//!
+//! ```no_run
+//! use viking::prelude::*;
+//!
+//! # fn main() -> anyhow::Result<()> {
+//! let functions = get_functions_for_path(std::path::Path::new("data/functions.csv"))?;
+//! let stats = compute_stats(&functions);
+//! println!("{:.3}% matching", stats.matching_byte_fraction() * 100.0);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! See `examples/` for complete programs.
+//!
+//! # Stability
+//!
+//! Items that are re-exported in [`prelude`] follow semantic versioning: they are only removed
+//! or changed in incompatible ways in a new major version. Everything else (including the paths
+//! of the modules that the prelude re-exports from) can change in any release.
+//! Tools that need more than the prelude should pin an exact version.
+//!
+//! So that fields can be added in minor versions, the structs in the prelude are
+//! `#[non_exhaustive]`: create them with their constructors (e.g. `Info::new`) or
+//! `Default::default()` and then assign the fields that should differ. `Status` and
+//! `Resolution` are exhaustive on purpose, so adding a variant is a breaking change.
+
+pub mod analysis;
+pub mod asm;
+pub mod asm_hashes;
+pub mod backup;
+pub mod binary;
 pub mod capstone_utils;
+pub mod check_results;
 pub mod checks;
+pub mod claims;
+pub mod classify;
+pub mod compare;
+pub mod convert;
+#[cfg(feature = "http")]
+pub mod decompme;
+pub mod doctor;
+#[cfg(feature = "dwarf")]
+pub mod dwarf;
+pub mod edit;
 pub mod elf;
+pub mod export;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub(crate) mod file_utils;
+pub mod fingerprint;
+pub mod function_source;
 pub mod functions;
+#[cfg(feature = "git")]
+pub mod git;
+pub mod history;
+#[cfg(feature = "git")]
+pub mod hooks;
+pub mod html_diff;
+pub mod ignore;
+pub mod known_issues;
+pub mod layout;
+pub mod lint;
+pub mod lock;
+pub mod merge;
+pub mod metadata;
+pub mod modules;
+pub mod nso;
+pub mod object;
+pub mod outlined;
+pub mod overrides;
+pub mod ownership;
+pub mod paginate;
+pub mod prelude;
+pub mod progress;
+pub mod recommend;
+#[cfg(feature = "http")]
+pub mod remote;
 pub mod repo;
+pub mod report;
+pub mod review;
+pub mod schema;
+pub mod search;
+pub mod sources;
+pub(crate) mod stable_hash;
+pub mod stats;
+#[cfg(any(test, feature = "test-util"))]
+pub mod testing;
+pub mod tombstones;
 pub mod ui;
@@ -0,0 +1,571 @@
+use crate::classify::{self, Rules};
+use crate::functions::{self, Info};
+use crate::ignore::{self, IgnoreSet};
+use crate::known_issues::{self, KnownIssues};
+use crate::modules::{self, DuplicatePolicy, ModuleFunctions};
+use crate::overrides::{self, CompileCommands, FlagOverrides};
+use crate::paginate::{Paginated, RenderBudget};
+use crate::repo;
+use crate::tombstones::{self, Tombstones};
+use anyhow::{ensure, Context, Result};
+use itertools::Itertools;
+use rayon::prelude::*;
+use rustc_hash::{FxHashMap, FxHashSet};
+use std::cmp::Reverse;
+use std::convert::TryFrom;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::RwLock;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+#[derive(Clone, Debug)]
+pub struct Issue {
+    pub severity: Severity,
+    /// Address of the function the issue is about, if any.
+    pub addr: Option<u64>,
+    pub message: String,
+}
+
+impl Issue {
+    pub fn error(addr: Option<u64>, message: String) -> Self {
+        Self {
+            severity: Severity::Error,
+            addr,
+            message,
+        }
+    }
+
+    pub fn warning(addr: Option<u64>, message: String) -> Self {
+        Self {
+            severity: Severity::Warning,
+            addr,
+            message,
+        }
+    }
+
+    /// Sort key for renderings (see `paginate::Paginated`): errors first, then by address.
+    pub fn priority(&self) -> (Reverse<Severity>, Option<u64>) {
+        (Reverse(self.severity), self.addr)
+    }
+}
+
+impl std::fmt::Display for Issue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        match self.addr {
+            Some(addr) => write!(
+                f,
+                "{}: {}: {}",
+                severity,
+                functions::format_addr(addr),
+                self.message
+            ),
+            None => write!(f, "{}: {}", severity, self.message),
+        }
+    }
+}
+
+/// Renders issues as text, one per line, within `budget`.
+pub fn render_issues(issues: &Paginated<Issue>, budget: &RenderBudget) -> String {
+    issues.render(budget, Issue::to_string)
+}
+
+/// Renders issues as a JSON array. Nothing is left out.
+pub fn render_issues_json(issues: &[Issue]) -> String {
+    let issues: Vec<serde_json::Value> = issues
+        .iter()
+        .map(|issue| {
+            serde_json::json!({
+                "severity": match issue.severity {
+                    Severity::Warning => "warning",
+                    Severity::Error => "error",
+                },
+                "address": issue.addr.map(functions::format_addr),
+                "message": issue.message,
+            })
+        })
+        .collect();
+    serde_json::Value::Array(issues).to_string()
+}
+
+/// Returns groups of functions that share the same address. Every group has at least 2 entries.
+pub fn find_duplicate_addresses(functions: &[Info]) -> Vec<Vec<&Info>> {
+    let mut sorted = functions.iter().collect_vec();
+    sorted.par_sort_by_key(|info| info.addr);
+
+    sorted
+        .into_iter()
+        .group_by(|info| info.addr)
+        .into_iter()
+        .map(|(_, group)| group.collect_vec())
+        .filter(|group| group.len() >= 2)
+        .collect()
+}
+
+/// Returns groups of decompiled functions that share the same name.
+/// Every group has at least 2 entries.
+pub fn find_duplicate_names(functions: &[Info]) -> Vec<Vec<&Info>> {
+    let mut sorted = functions
+        .par_iter()
+        .filter(|info| info.is_decompiled() && !info.name.is_empty())
+        .collect::<Vec<_>>();
+    sorted.par_sort_by(|a, b| a.name.cmp(&b.name));
+
+    sorted
+        .into_iter()
+        .group_by(|info| info.name.as_str())
+        .into_iter()
+        .map(|(_, group)| group.collect_vec())
+        .filter(|group| group.len() >= 2)
+        .collect()
+}
+
+/// A function name that doesn't look like a valid Itanium mangled name or C function name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ManglingError {
+    pub addr: u64,
+    pub name: String,
+    pub error: String,
+    /// Names that start with `_Z` but cannot be demangled are errors. Other names are
+    /// only warnings because the C identifier check is a heuristic.
+    pub severity: Severity,
+}
+
+fn is_c_identifier(name: &str) -> bool {
+    !name.starts_with(|c: char| c.is_ascii_digit())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Checks that every name is either a valid Itanium C++ mangled name or a C function name.
+/// Unnamed functions are ignored.
+pub fn validate_mangled_names_itanium(functions: &[Info]) -> Vec<ManglingError> {
+    Linter::new().validate_mangled_names_itanium(functions)
+}
+
+fn check_itanium_name(name: &str) -> Option<(String, Severity)> {
+    if name.starts_with("_Z") {
+        match cpp_demangle::Symbol::new(name) {
+            Ok(_) => None,
+            Err(err) => Some((format!("invalid mangled name: {}", err), Severity::Error)),
+        }
+    } else if is_c_identifier(name) {
+        None
+    } else {
+        Some((
+            "not a mangled name and not a valid C identifier".to_string(),
+            Severity::Warning,
+        ))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ManglingIssueKind {
+    /// The name starts with `_Z` but cannot be demangled.
+    DemangleFailed(String),
+    /// The name demangles to an empty string.
+    EmptyOutput,
+    /// The demangled name contains the mangled name, which happens with some corrupted names
+    /// (e.g. truncated substitutions).
+    ContainsMangledName,
+}
+
+impl std::fmt::Display for ManglingIssueKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ManglingIssueKind::DemangleFailed(err) => write!(f, "failed to demangle: {}", err),
+            ManglingIssueKind::EmptyOutput => write!(f, "demangles to an empty name"),
+            ManglingIssueKind::ContainsMangledName => {
+                write!(f, "demangled name contains the mangled name")
+            }
+        }
+    }
+}
+
+/// A mangled name that does not demangle properly. See `validate_mangling`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ManglingIssue {
+    pub addr: u64,
+    pub name: String,
+    pub kind: ManglingIssueKind,
+}
+
+fn check_demangling(name: &str) -> Option<ManglingIssueKind> {
+    match functions::demangle_str(name) {
+        Ok(demangled) if demangled.is_empty() => Some(ManglingIssueKind::EmptyOutput),
+        Ok(demangled) if demangled.contains(name) => Some(ManglingIssueKind::ContainsMangledName),
+        Ok(_) => None,
+        Err(err) => Some(ManglingIssueKind::DemangleFailed(err.to_string())),
+    }
+}
+
+/// Checks that every mangled name (i.e. every name that starts with `_Z`) round-trips through
+/// the demangler: demangling must succeed and produce a plausible result.
+/// Unmangled names (e.g. C functions) are ignored.
+pub fn validate_mangling(functions: &[Info]) -> Vec<ManglingIssue> {
+    Linter::new().validate_mangling(functions)
+}
+
+/// Results of the name checks for one name.
+#[derive(Clone, Debug)]
+struct NameCheck {
+    /// See `validate_mangled_names_itanium`.
+    itanium: Option<(String, Severity)>,
+    /// See `validate_mangling`. Only set for names that start with `_Z`.
+    round_trip: Option<ManglingIssueKind>,
+}
+
+impl NameCheck {
+    fn new(name: &str) -> Self {
+        Self {
+            itanium: check_itanium_name(name),
+            round_trip: if name.starts_with("_Z") {
+                check_demangling(name)
+            } else {
+                None
+            },
+        }
+    }
+}
+
+/// Runs the lints and remembers the result of demangling each name, so that linting a function
+/// list again (or another version of it, e.g. in the git hooks) only demangles new names.
+///
+/// The cache lives as long as the linter, so create one linter per batch of lints.
+#[derive(Debug, Default)]
+pub struct Linter {
+    /// Name checks by name.
+    names: RwLock<FxHashMap<String, NameCheck>>,
+    num_demangled: AtomicUsize,
+}
+
+impl Linter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns how many names have been demangled, i.e. were not in the cache.
+    pub fn num_demangled(&self) -> usize {
+        self.num_demangled.load(Ordering::Relaxed)
+    }
+
+    /// Checks the names that are not cached yet and calls `f` with the checks of all names.
+    fn with_name_checks<R>(
+        &self,
+        functions: &[Info],
+        f: impl FnOnce(&FxHashMap<String, NameCheck>) -> R,
+    ) -> R {
+        let uncached: FxHashSet<&str> = {
+            let names = self.names.read().unwrap();
+            functions
+                .iter()
+                .map(|info| info.name.as_str())
+                .filter(|name| !name.is_empty() && !names.contains_key(*name))
+                .collect()
+        };
+        if !uncached.is_empty() {
+            self.num_demangled
+                .fetch_add(uncached.len(), Ordering::Relaxed);
+            let checks: Vec<(String, NameCheck)> = uncached
+                .into_par_iter()
+                .map(|name| (name.to_string(), NameCheck::new(name)))
+                .collect();
+            self.names.write().unwrap().extend(checks);
+        }
+        f(&self.names.read().unwrap())
+    }
+
+    /// See the free function `validate_mangled_names_itanium`.
+    pub fn validate_mangled_names_itanium(&self, functions: &[Info]) -> Vec<ManglingError> {
+        self.with_name_checks(functions, |checks| {
+            functions
+                .par_iter()
+                .filter(|info| !info.name.is_empty())
+                .filter_map(|info| {
+                    let (error, severity) = checks[&info.name].itanium.clone()?;
+                    Some(ManglingError {
+                        addr: info.addr,
+                        name: info.name.clone(),
+                        error,
+                        severity,
+                    })
+                })
+                .collect()
+        })
+    }
+
+    /// See the free function `validate_mangling`.
+    pub fn validate_mangling(&self, functions: &[Info]) -> Vec<ManglingIssue> {
+        self.with_name_checks(functions, |checks| {
+            functions
+                .par_iter()
+                .filter(|info| info.name.starts_with("_Z"))
+                .filter_map(|info| {
+                    Some(ManglingIssue {
+                        addr: info.addr,
+                        name: info.name.clone(),
+                        kind: checks[&info.name].round_trip.clone()?,
+                    })
+                })
+                .collect()
+        })
+    }
+}
+
+/// Returns all functions whose address is not in `[section_start, section_end)`.
+/// Addresses may or may not include `ADDRESS_BASE`.
+pub fn verify_functions_in_section(
+    functions: &[Info],
+    section_start: u64,
+    section_end: u64,
+) -> Vec<&Info> {
+    let strip = |addr: u64| addr.checked_sub(functions::ADDRESS_BASE).unwrap_or(addr);
+    let range = strip(section_start)..strip(section_end);
+    functions
+        .par_iter()
+        .filter(|info| !range.contains(&info.addr))
+        .collect()
+}
+
+/// Returns the address range of the text section from the config, if it is set:
+///
+/// ```toml
+/// [sections.text]
+/// start = 0x7100000000
+/// end = 0x7101234000
+/// ```
+pub fn get_text_section_from_config() -> Result<Option<(u64, u64)>> {
+    let table = match repo::CONFIG
+        .get("sections")
+        .and_then(|sections| sections.get("text"))
+    {
+        Some(table) => table,
+        None => return Ok(None),
+    };
+    let get = |key: &str| -> Result<u64> {
+        let value = table
+            .get(key)
+            .and_then(toml::Value::as_integer)
+            .with_context(|| format!("sections.text.{} must be an integer", key))?;
+        u64::try_from(value).with_context(|| format!("sections.text.{} is negative", key))
+    };
+    let (start, end) = (get("start")?, get("end")?);
+    ensure!(
+        start < end,
+        "sections.text: start ({:#x}) must be lower than end ({:#x})",
+        start,
+        end
+    );
+    Ok(Some((start, end)))
+}
+
+/// Runs every validation step on the function list and returns all issues that were found.
+pub fn validate_all(functions: &[Info]) -> Vec<Issue> {
+    Linter::new().validate_all(functions)
+}
+
+/// Same as `validate_all`, but also runs checks that depend on the project config
+/// (see `Linter::validate_project`).
+pub fn validate_project(functions: &[Info]) -> Result<Vec<Issue>> {
+    Linter::new().validate_project(functions)
+}
+
+impl Linter {
+    /// See the free function `validate_all`.
+    pub fn validate_all(&self, functions: &[Info]) -> Vec<Issue> {
+        let mut issues = Vec::new();
+
+        for group in find_duplicate_addresses(functions) {
+            issues.push(Issue::error(
+                Some(group[0].addr),
+                format!(
+                    "found {} functions at the same address: {:?}",
+                    group.len(),
+                    group.iter().map(|info| info.name.as_str()).collect_vec()
+                ),
+            ));
+        }
+
+        for group in find_duplicate_names(functions) {
+            issues.push(Issue::error(
+                Some(group[0].addr),
+                format!(
+                    "found {} functions with the same name {}: {}",
+                    group.len(),
+                    group[0].name,
+                    group
+                        .iter()
+                        .map(|info| functions::format_addr(info.addr))
+                        .join(", ")
+                ),
+            ));
+        }
+
+        if let Some(i) = functions::find_non_canonical_entry(functions) {
+            issues.push(Issue::warning(
+                Some(functions[i].addr),
+                "function list is not in canonical order (see functions::canonicalize)".to_string(),
+            ));
+        }
+
+        let mangling_errors = self.validate_mangled_names_itanium(functions);
+        for error in &mangling_errors {
+            issues.push(Issue {
+                severity: error.severity,
+                addr: Some(error.addr),
+                message: format!("{}: {}", error.name, error.error),
+            });
+        }
+
+        // Names that cannot be parsed at all have already been reported.
+        let invalid_names: FxHashSet<&str> = mangling_errors
+            .iter()
+            .map(|error| error.name.as_str())
+            .collect();
+        for issue in self.validate_mangling(functions) {
+            if !invalid_names.contains(issue.name.as_str()) {
+                issues.push(Issue::warning(
+                    Some(issue.addr),
+                    format!("{}: {}", issue.name, issue.kind),
+                ));
+            }
+        }
+
+        issues
+    }
+
+    /// Same as `validate_all`, but also runs checks that depend on the project config
+    /// (see `classify::check_with_rules`, `ignore::check_ignore_set`,
+    /// `known_issues::check_known_issues`, `tombstones::check_tombstones`,
+    /// `modules::check_duplicate_names` and `verify_functions_in_section`).
+    pub fn validate_project(&self, functions: &[Info]) -> Result<Vec<Issue>> {
+        let mut issues = self.validate_all(functions);
+        issues.extend(classify::check_with_rules(
+            functions,
+            &Rules::load_library_rules()?,
+            &Rules::load_project_rules()?,
+        ));
+        issues.extend(ignore::check_ignore_set(functions, &IgnoreSet::load()?));
+        issues.extend(known_issues::check_known_issues(
+            functions,
+            &KnownIssues::load()?,
+        ));
+        issues.extend(tombstones::check_tombstones(
+            functions,
+            &Tombstones::load()?,
+        ));
+        let flag_overrides = FlagOverrides::load()?;
+        if !flag_overrides.is_empty() {
+            // Overrides can only be checked once the project has been built.
+            if let Some(commands) = CompileCommands::load()? {
+                issues.extend(overrides::check_flag_overrides(
+                    functions,
+                    &flag_overrides,
+                    &commands,
+                ));
+            }
+        }
+        if modules::get_modules()?.len() > 1 {
+            let modules = ModuleFunctions::load_from_config_with_main(functions.to_vec())?;
+            issues.extend(modules::check_duplicate_names(
+                &modules,
+                &DuplicatePolicy::from_config()?,
+            ));
+        }
+        if let Some((start, end)) = get_text_section_from_config()? {
+            for info in verify_functions_in_section(functions, start, end) {
+                issues.push(Issue::error(
+                    Some(info.addr),
+                    format!(
+                        "{} is outside of the text section ({:#x}..{:#x})",
+                        info.name, start, end
+                    ),
+                ));
+            }
+        }
+        Ok(issues)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::Status;
+    use crate::testing::make_function;
+
+    #[test]
+    fn mangled_names_must_demangle() {
+        let functions = vec![
+            make_function(
+                0x10,
+                0x10,
+                "_ZN4ksys3act8BaseProc4initEv",
+                Status::NotDecompiled,
+            ),
+            // Substitution that refers to nothing.
+            make_function(
+                0x20,
+                0x10,
+                "_ZN4ksys3act8BaseProc4initERKS9_",
+                Status::NotDecompiled,
+            ),
+            make_function(
+                0x30,
+                0x10,
+                "_ZN4ksys3act8BaseProc4in",
+                Status::NotDecompiled,
+            ),
+            // Plain identifiers are fine.
+            make_function(0x40, 0x10, "memcpy", Status::NotDecompiled),
+            make_function(0x50, 0x10, "", Status::NotDecompiled),
+        ];
+        let issues = validate_mangling(&functions);
+        let addrs: Vec<u64> = issues.iter().map(|issue| issue.addr).collect();
+        assert_eq!(addrs, [0x20, 0x30]);
+        assert!(issues
+            .iter()
+            .all(|issue| matches!(issue.kind, ManglingIssueKind::DemangleFailed(_))));
+    }
+
+    #[test]
+    fn linters_only_demangle_new_names() {
+        let mut functions = vec![
+            make_function(
+                0x10,
+                0x10,
+                "_ZN4ksys3act8BaseProc4initEv",
+                Status::NotDecompiled,
+            ),
+            make_function(
+                0x20,
+                0x10,
+                "_ZN4ksys3act8BaseProc4initERKS9_",
+                Status::NotDecompiled,
+            ),
+            make_function(0x30, 0x10, "memcpy", Status::NotDecompiled),
+        ];
+        let linter = Linter::new();
+        let issues = linter.validate_all(&functions);
+        assert_eq!(linter.num_demangled(), 3);
+
+        // Both name passes and a second lint use the cached results.
+        assert_eq!(linter.validate_all(&functions).len(), issues.len());
+        assert_eq!(linter.validate_mangling(&functions).len(), 1);
+        assert_eq!(linter.num_demangled(), 3);
+
+        functions.push(make_function(
+            0x40,
+            0x10,
+            "_ZN4ksys3act8BaseProc4calcEv",
+            Status::NotDecompiled,
+        ));
+        linter.validate_all(&functions);
+        assert_eq!(linter.num_demangled(), 4);
+    }
+}
@@ -0,0 +1,536 @@
+use anyhow::{Context, Result};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// How long to wait between attempts to acquire a lock.
+const RETRY_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Clone, Debug)]
+pub struct LockOptions {
+    /// How long to wait for a lock that is held by someone else before giving up
+    /// with a `LockBusy` error.
+    pub timeout: Duration,
+    /// Locks that are older than this are assumed to have been left behind by a process
+    /// that crashed, even if that cannot be verified from the PID.
+    pub stale_after: Duration,
+}
+
+impl Default for LockOptions {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(10),
+            stale_after: Duration::from_secs(10 * 60),
+        }
+    }
+}
+
+/// The process that holds a lock, as recorded in the lock file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LockHolder {
+    pub pid: u32,
+    /// When the lock was acquired. Stored with a precision of one second.
+    pub timestamp: SystemTime,
+    /// Name of the program that holds the lock.
+    pub program: String,
+    /// Host name and PID namespace of the holder (see `get_pid_namespace`), if known.
+    /// The PID can only be checked if this is the same as for the current process.
+    pub namespace: Option<String>,
+}
+
+impl std::fmt::Display for LockHolder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let age = SystemTime::now()
+            .duration_since(self.timestamp)
+            .unwrap_or_default();
+        write!(
+            f,
+            "{} (PID {}, acquired {}s ago)",
+            self.program,
+            self.pid,
+            age.as_secs()
+        )
+    }
+}
+
+impl LockHolder {
+    fn current() -> Self {
+        let program = std::env::args()
+            .next()
+            .and_then(|arg| {
+                Path::new(&arg)
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+            })
+            .unwrap_or_else(|| "unknown".to_string());
+        Self {
+            pid: std::process::id(),
+            timestamp: SystemTime::now(),
+            program,
+            namespace: get_pid_namespace(),
+        }
+    }
+
+    fn serialize(&self) -> String {
+        let timestamp = self
+            .timestamp
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let mut contents = format!(
+            "pid={}\ntimestamp={}\nprogram={}\n",
+            self.pid, timestamp, self.program
+        );
+        if let Some(namespace) = &self.namespace {
+            contents.push_str(&format!("namespace={}\n", namespace));
+        }
+        contents
+    }
+
+    fn parse(contents: &str) -> Option<Self> {
+        let mut pid = None;
+        let mut timestamp = None;
+        let mut program = None;
+        let mut namespace = None;
+        for line in contents.lines() {
+            match line.split_once('=')? {
+                ("pid", value) => pid = value.parse().ok(),
+                ("timestamp", value) => {
+                    timestamp = value
+                        .parse()
+                        .ok()
+                        .map(|secs| UNIX_EPOCH + Duration::from_secs(secs))
+                }
+                ("program", value) => program = Some(value.to_string()),
+                ("namespace", value) => namespace = Some(value.to_string()),
+                _ => (),
+            }
+        }
+        Some(Self {
+            pid: pid?,
+            timestamp: timestamp?,
+            program: program?,
+            namespace,
+        })
+    }
+
+    fn is_stale(&self, options: &LockOptions) -> bool {
+        let age = SystemTime::now()
+            .duration_since(self.timestamp)
+            .unwrap_or_default();
+        if age > options.stale_after {
+            return true;
+        }
+        // PIDs mean nothing on other machines (e.g. on network file systems) or in other
+        // containers, so a lock is only known to be stale if it was taken in the same PID
+        // namespace and procfs has no entry for its PID. Otherwise the lock is busy.
+        match (&self.namespace, get_pid_namespace()) {
+            (Some(namespace), Some(current)) if *namespace == current => {
+                !Path::new(&format!("/proc/{}", self.pid)).exists()
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Returns the host name and the PID namespace of the current process, if procfs is available.
+fn get_pid_namespace() -> Option<String> {
+    let host = std::fs::read_to_string("/proc/sys/kernel/hostname").ok()?;
+    let namespace = std::fs::read_link("/proc/self/ns/pid").ok()?;
+    Some(format!("{}/{}", host.trim(), namespace.to_string_lossy()))
+}
+
+/// Returned (wrapped in an `anyhow::Error`) when a lock is still held by someone else
+/// after `LockOptions::timeout`.
+#[derive(Debug)]
+pub struct LockBusy {
+    pub lock_path: PathBuf,
+    /// None if the lock file could not be parsed (e.g. because it is still being written).
+    pub holder: Option<LockHolder>,
+}
+
+impl std::fmt::Display for LockBusy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.holder {
+            Some(holder) => write!(f, "{:?} is locked by {}", self.lock_path, holder),
+            None => write!(f, "{:?} is locked by an unknown process", self.lock_path),
+        }
+    }
+}
+
+impl std::error::Error for LockBusy {}
+
+/// An exclusive lock on a file. The lock is released when this is dropped.
+#[derive(Debug)]
+pub struct FileLock {
+    lock_path: PathBuf,
+    /// What was written to the lock file. This is unique, so that a lock that was taken over
+    /// by someone else (e.g. because it was considered stale) can be told apart from ours.
+    contents: String,
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        // Removing the lock by path would delete the lock of a process that took it over.
+        let _ = remove_lock_if_unchanged(&self.lock_path, &self.contents);
+    }
+}
+
+/// A shared lock on a file. See `lock_shared`.
+#[derive(Debug)]
+pub struct SharedLock {
+    _private: (),
+}
+
+/// Returns the path to the lock file for `path` (e.g. `functions.csv.lock`).
+pub fn get_lock_path(path: &Path) -> PathBuf {
+    let mut lock_path = path.as_os_str().to_owned();
+    lock_path.push(".lock");
+    PathBuf::from(lock_path)
+}
+
+/// Removes the lock at `lock_path` if it still has the given `contents` (e.g. those of a stale
+/// lock, or of our own lock). Returns false if the lock was replaced in the meantime.
+///
+/// The lock is moved out of the way with an atomic rename rather than removed by path, so that
+/// a fresh lock that another process took in the meantime cannot be deleted: such a lock is
+/// detected by its contents and linked back in place.
+/// Linking fails (and an error is returned) if yet another process took the lock in between.
+fn remove_lock_if_unchanged(lock_path: &Path, contents: &str) -> Result<bool> {
+    static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+    let mut stale_path = lock_path.as_os_str().to_owned();
+    stale_path.push(format!(
+        ".stale.{}.{}",
+        std::process::id(),
+        NEXT_ID.fetch_add(1, Ordering::Relaxed)
+    ));
+    let stale_path = PathBuf::from(stale_path);
+
+    match std::fs::rename(lock_path, &stale_path) {
+        Ok(()) => (),
+        // Someone else removed it first.
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(err) => {
+            return Err(err).with_context(|| format!("failed to remove stale {:?}", lock_path))
+        }
+    }
+
+    let taken = std::fs::read_to_string(&stale_path)
+        .with_context(|| format!("failed to read {:?}", stale_path))?;
+    if taken != contents {
+        // Hard links are created atomically and fail if the lock was taken again.
+        let restored = std::fs::hard_link(&stale_path, lock_path);
+        let _ = std::fs::remove_file(&stale_path);
+        restored.with_context(|| format!("failed to restore {:?}", lock_path))?;
+        return Ok(false);
+    }
+    std::fs::remove_file(&stale_path)
+        .with_context(|| format!("failed to remove {:?}", stale_path))?;
+    Ok(true)
+}
+
+/// Returns the holder of the lock at `lock_path` if the lock is held and not stale.
+/// Stale locks are removed (see `remove_lock_if_unchanged`).
+fn check_existing_lock(lock_path: &Path, options: &LockOptions) -> Result<Option<LockBusy>> {
+    let contents = match std::fs::read_to_string(lock_path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err).with_context(|| format!("failed to read {:?}", lock_path)),
+    };
+
+    let holder = LockHolder::parse(&contents);
+    let is_stale = match &holder {
+        Some(holder) => holder.is_stale(options),
+        // The lock file may be in the middle of being written; only remove it if it is old.
+        None => std::fs::metadata(lock_path)
+            .and_then(|metadata| metadata.modified())
+            .map(|modified| modified.elapsed().unwrap_or_default() > options.stale_after)
+            .unwrap_or(false),
+    };
+
+    if is_stale {
+        // If the lock was replaced, the new holder is checked on the next attempt.
+        remove_lock_if_unchanged(lock_path, &contents)?;
+        return Ok(None);
+    }
+
+    Ok(Some(LockBusy {
+        lock_path: lock_path.to_path_buf(),
+        holder,
+    }))
+}
+
+/// Acquires an exclusive lock on `path`, for read-modify-write cycles.
+///
+/// This is an advisory lock: it is implemented with a lock file next to `path`
+/// and only protects against other users of this function.
+pub fn lock_exclusive(path: &Path, options: &LockOptions) -> Result<FileLock> {
+    static NEXT_TOKEN: AtomicUsize = AtomicUsize::new(0);
+    let lock_path = get_lock_path(path);
+    // The holder is not unique enough: the same process can take the same lock again
+    // within a second. Other readers ignore the extra line.
+    let contents = format!(
+        "{}token={}\n",
+        LockHolder::current().serialize(),
+        NEXT_TOKEN.fetch_add(1, Ordering::Relaxed)
+    );
+    let start = Instant::now();
+    loop {
+        match std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock_path)
+        {
+            Ok(mut file) => {
+                let lock = FileLock {
+                    lock_path,
+                    contents,
+                };
+                file.write_all(lock.contents.as_bytes())
+                    .with_context(|| format!("failed to write {:?}", lock.lock_path))?;
+                return Ok(lock);
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {
+                if let Some(busy) = check_existing_lock(&lock_path, options)? {
+                    if start.elapsed() >= options.timeout {
+                        return Err(busy.into());
+                    }
+                    std::thread::sleep(RETRY_INTERVAL);
+                }
+            }
+            Err(err) => {
+                return Err(err).with_context(|| format!("failed to create {:?}", lock_path))
+            }
+        }
+    }
+}
+
+/// Waits until nobody holds an exclusive lock on `path`.
+///
+/// *Note*: this does not prevent writers from acquiring the lock afterwards. Because function
+/// lists are replaced atomically, readers always see a complete file anyway; waiting only
+/// makes sure that a read-modify-write cycle that is in progress has finished.
+pub fn lock_shared(path: &Path, options: &LockOptions) -> Result<SharedLock> {
+    let lock_path = get_lock_path(path);
+    let start = Instant::now();
+    while let Some(busy) = check_existing_lock(&lock_path, options)? {
+        if start.elapsed() >= options.timeout {
+            return Err(busy.into());
+        }
+        std::thread::sleep(RETRY_INTERVAL);
+    }
+    Ok(SharedLock { _private: () })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::edit::{self, Edit};
+    use crate::functions::{self, Info, Status};
+    use crate::testing::{self, TempDir};
+
+    fn short_timeout() -> LockOptions {
+        LockOptions {
+            timeout: Duration::from_millis(100),
+            ..Default::default()
+        }
+    }
+
+    fn write_lock(path: &Path, holder: &LockHolder) {
+        std::fs::write(get_lock_path(path), holder.serialize()).unwrap();
+    }
+
+    fn dead_holder(namespace: Option<String>) -> LockHolder {
+        LockHolder {
+            // PIDs are much smaller than this on Linux.
+            pid: u32::MAX,
+            timestamp: SystemTime::now(),
+            program: "test".to_string(),
+            namespace,
+        }
+    }
+
+    #[test]
+    fn holder_roundtrip() {
+        let mut holder = LockHolder::current();
+        holder.timestamp = UNIX_EPOCH + Duration::from_secs(1234);
+        assert_eq!(LockHolder::parse(&holder.serialize()), Some(holder.clone()));
+        holder.namespace = None;
+        assert_eq!(LockHolder::parse(&holder.serialize()), Some(holder));
+    }
+
+    #[test]
+    fn busy_lock_names_the_holder() {
+        let dir = TempDir::new("lock_busy");
+        let path = dir.join("functions.csv");
+        let _lock = lock_exclusive(&path, &short_timeout()).unwrap();
+
+        let err = lock_exclusive(&path, &short_timeout()).unwrap_err();
+        let busy = err.downcast_ref::<LockBusy>().unwrap();
+        assert_eq!(busy.holder.as_ref().unwrap().pid, std::process::id());
+        assert!(lock_shared(&path, &short_timeout()).is_err());
+    }
+
+    #[test]
+    fn lock_is_released_on_drop() {
+        let dir = TempDir::new("lock_release");
+        let path = dir.join("functions.csv");
+        drop(lock_exclusive(&path, &short_timeout()).unwrap());
+        assert!(!get_lock_path(&path).exists());
+        lock_exclusive(&path, &short_timeout()).unwrap();
+    }
+
+    #[test]
+    fn dead_holder_in_same_namespace_is_stale() {
+        let namespace = match get_pid_namespace() {
+            Some(namespace) => namespace,
+            None => return,
+        };
+        let dir = TempDir::new("lock_dead");
+        let path = dir.join("functions.csv");
+        write_lock(&path, &dead_holder(Some(namespace)));
+
+        let _lock = lock_exclusive(&path, &short_timeout()).unwrap();
+        let holder = LockHolder::parse(&std::fs::read_to_string(get_lock_path(&path)).unwrap());
+        assert_eq!(holder.unwrap().pid, std::process::id());
+    }
+
+    #[test]
+    fn holder_in_other_namespace_is_busy() {
+        let dir = TempDir::new("lock_namespace");
+        let path = dir.join("functions.csv");
+        for namespace in [Some("otherhost/pid:[1]".to_string()), None] {
+            write_lock(&path, &dead_holder(namespace));
+            let err = lock_exclusive(&path, &short_timeout()).unwrap_err();
+            assert!(err.is::<LockBusy>());
+        }
+    }
+
+    #[test]
+    fn old_lock_is_stale() {
+        let dir = TempDir::new("lock_old");
+        let path = dir.join("functions.csv");
+        let mut holder = dead_holder(None);
+        holder.timestamp = SystemTime::now() - Duration::from_secs(3600);
+        write_lock(&path, &holder);
+        lock_exclusive(&path, &short_timeout()).unwrap();
+    }
+
+    #[test]
+    fn replaced_stale_lock_is_kept() {
+        let dir = TempDir::new("lock_replaced");
+        let path = dir.join("functions.csv");
+        let lock_path = get_lock_path(&path);
+        let fresh = LockHolder::current().serialize();
+        std::fs::write(&lock_path, &fresh).unwrap();
+
+        // The lock that was found to be stale has been replaced by a fresh one.
+        assert!(!remove_lock_if_unchanged(&lock_path, "pid=1\n").unwrap());
+        assert_eq!(std::fs::read_to_string(&lock_path).unwrap(), fresh);
+
+        assert!(remove_lock_if_unchanged(&lock_path, &fresh).unwrap());
+        assert!(!lock_path.exists());
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn concurrent_edits_are_not_lost() {
+        testing::use_test_config();
+        let dir = TempDir::new("lock_concurrent");
+        let path = dir.join("functions.csv");
+        let functions: Vec<Info> = (0..40)
+            .map(|i| Info::new(i * 0x10, 0x10, format!("_Z3f{}v", i), Status::NotDecompiled))
+            .collect();
+        functions::write_functions_to_path(&path, &functions).unwrap();
+
+        // Both threads edit different functions of the same file at the same time.
+        std::thread::scope(|scope| {
+            for thread in 0..2u64 {
+                let path = &path;
+                scope.spawn(move || {
+                    for i in 0..20 {
+                        let addr = (i * 2 + thread) * 0x10;
+                        let edit = match thread {
+                            0 => Edit::SetStatus {
+                                addr,
+                                status: Status::Matching,
+                            },
+                            _ => Edit::Rename {
+                                addr,
+                                name: format!("_Z3g{}v", addr),
+                            },
+                        };
+                        edit::apply_edits_to_path(path, &[edit], false).unwrap();
+                    }
+                });
+            }
+        });
+
+        let functions = functions::get_functions_for_path(&path).unwrap();
+        assert_eq!(functions.len(), 40);
+        for (i, info) in functions.iter().enumerate() {
+            if i % 2 == 0 {
+                assert_eq!(info.status, Status::Matching, "{:?}", info);
+            } else {
+                assert_eq!(info.name, format!("_Z3g{}v", info.addr), "{:?}", info);
+            }
+        }
+    }
+
+    #[test]
+    fn taken_over_lock_survives_the_old_holder() {
+        let dir = TempDir::new("lock_taken_over");
+        let path = dir.join("functions.csv");
+        let old_lock = lock_exclusive(&path, &short_timeout()).unwrap();
+
+        // Someone considered the lock stale, removed it and took it.
+        std::fs::remove_file(get_lock_path(&path)).unwrap();
+        let new_lock = lock_exclusive(&path, &short_timeout()).unwrap();
+        drop(old_lock);
+        assert!(get_lock_path(&path).exists());
+        assert!(lock_exclusive(&path, &short_timeout()).is_err());
+
+        drop(new_lock);
+        assert!(!get_lock_path(&path).exists());
+    }
+
+    #[test]
+    fn concurrent_updates_are_not_lost() {
+        use crate::function_source::{CsvFile, FunctionSource};
+
+        testing::use_test_config();
+        let dir = TempDir::new("lock_updates");
+        let path = dir.join("functions.csv");
+        functions::write_functions_to_path(&path, &[]).unwrap();
+
+        // One thread goes through update_functions_at_path, the other through a source.
+        std::thread::scope(|scope| {
+            for thread in 0..2u64 {
+                let path = &path;
+                scope.spawn(move || {
+                    for i in 0..20 {
+                        let info = Info::new(
+                            (i * 2 + thread) * 0x10,
+                            0x10,
+                            String::new(),
+                            Status::NotDecompiled,
+                        );
+                        let mut add = |functions: &mut Vec<Info>| {
+                            functions.push(info.clone());
+                            functions.sort_by_key(|info| info.addr);
+                            Ok(())
+                        };
+                        match thread {
+                            0 => functions::update_functions_at_path(path, add).unwrap(),
+                            _ => CsvFile::new(path).update(&mut add).unwrap(),
+                        }
+                    }
+                });
+            }
+        });
+
+        let functions = functions::get_functions_for_path(&path).unwrap();
+        assert_eq!(functions.len(), 40);
+    }
+}
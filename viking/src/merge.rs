@@ -0,0 +1,499 @@
+use crate::functions::{self, Info, WriteOptions};
+use anyhow::{bail, Context, Result};
+use rustc_hash::FxHashMap;
+use std::collections::BTreeMap;
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+/// Entries at an address that could not be merged automatically.
+/// An empty list means that there was no entry at the address (e.g. it was removed).
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct MergeConflict {
+    pub addr: u64,
+    pub base: Vec<Info>,
+    pub ours: Vec<Info>,
+    pub theirs: Vec<Info>,
+}
+
+/// Sorted by address.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MergeConflicts(pub Vec<MergeConflict>);
+
+impl MergeConflicts {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns the base, our and their entries of all conflicts as function lists.
+    /// Merging these lists with `merge_functions` produces the same conflicts again.
+    pub fn to_lists(&self) -> (Vec<Info>, Vec<Info>, Vec<Info>) {
+        let collect = |get: fn(&MergeConflict) -> &Vec<Info>| -> Vec<Info> {
+            self.0.iter().flat_map(|c| get(c).iter().cloned()).collect()
+        };
+        (
+            collect(|c| &c.base),
+            collect(|c| &c.ours),
+            collect(|c| &c.theirs),
+        )
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct MergeResult {
+    /// Entries that were merged automatically, in canonical order.
+    /// Addresses with conflicts are left out.
+    pub merged: Vec<Info>,
+    pub conflicts: MergeConflicts,
+}
+
+/// Returns the merged value if at most one side changed it (or both made the same change).
+fn merge_value<'a, T: PartialEq>(base: &'a T, ours: &'a T, theirs: &'a T) -> Option<&'a T> {
+    if ours == theirs || theirs == base {
+        Some(ours)
+    } else if ours == base {
+        Some(theirs)
+    } else {
+        None
+    }
+}
+
+/// Merges a single entry field by field, so that e.g. a rename on one side and a status change
+/// on the other side do not conflict.
+fn merge_entry(base: &Info, ours: &Info, theirs: &Info) -> Option<Info> {
+    Some(Info {
+        addr: base.addr,
+        size: *merge_value(&base.size, &ours.size, &theirs.size)?,
+        name: merge_value(&base.name, &ours.name, &theirs.name)?.clone(),
+        status: merge_value(&base.status, &ours.status, &theirs.status)?.clone(),
+        extra: merge_value(&base.extra, &ours.extra, &theirs.extra)?.clone(),
+    })
+}
+
+fn group_by_addr(functions: &[Info]) -> FxHashMap<u64, Vec<Info>> {
+    let mut groups: FxHashMap<u64, Vec<Info>> = FxHashMap::default();
+    for info in functions {
+        groups.entry(info.addr).or_default().push(info.clone());
+    }
+    groups
+}
+
+/// Merges two function lists that were both derived from `base`. Entries are matched
+/// by address; aliases (several entries at the same address) are merged as a group.
+pub fn merge_functions(base: &[Info], ours: &[Info], theirs: &[Info]) -> MergeResult {
+    let base = group_by_addr(base);
+    let ours = group_by_addr(ours);
+    let theirs = group_by_addr(theirs);
+
+    let mut addrs: Vec<u64> = base
+        .keys()
+        .chain(ours.keys())
+        .chain(theirs.keys())
+        .copied()
+        .collect();
+    addrs.sort_unstable();
+    addrs.dedup();
+
+    let empty = Vec::new();
+    let mut result = MergeResult::default();
+    for addr in addrs {
+        let b = base.get(&addr).unwrap_or(&empty);
+        let o = ours.get(&addr).unwrap_or(&empty);
+        let t = theirs.get(&addr).unwrap_or(&empty);
+
+        if let Some(merged) = merge_value(b, o, t) {
+            result.merged.extend(merged.iter().cloned());
+            continue;
+        }
+        if let ([b], [o], [t]) = (&b[..], &o[..], &t[..]) {
+            if let Some(merged) = merge_entry(b, o, t) {
+                result.merged.push(merged);
+                continue;
+            }
+        }
+        result.conflicts.0.push(MergeConflict {
+            addr,
+            base: b.clone(),
+            ours: o.clone(),
+            theirs: t.clone(),
+        });
+    }
+
+    functions::canonicalize(&mut result.merged);
+    result
+}
+
+/// How to resolve a conflict.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Resolution {
+    Ours,
+    Theirs,
+    Base,
+    /// Use these entries instead. They must all be at the address of the conflict.
+    Custom(Vec<Info>),
+}
+
+/// Entries next to a conflict, to help with resolving it.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ConflictContext {
+    /// Closest entries before the conflict, in address order.
+    pub before: Vec<Info>,
+    /// Closest entries after the conflict, in address order.
+    pub after: Vec<Info>,
+}
+
+/// Resolves the conflicts of a `MergeResult` one by one.
+///
+/// Nothing here reads from stdin; see `resolve_interactively` for a prompt loop.
+#[derive(Clone, Debug)]
+pub struct MergeResolver {
+    result: MergeResult,
+    resolutions: BTreeMap<u64, Vec<Info>>,
+}
+
+impl MergeResolver {
+    pub fn new(result: MergeResult) -> Self {
+        Self {
+            result,
+            resolutions: BTreeMap::new(),
+        }
+    }
+
+    pub fn conflicts(&self) -> &[MergeConflict] {
+        &self.result.conflicts.0
+    }
+
+    pub fn unresolved(&self) -> impl Iterator<Item = &MergeConflict> {
+        self.conflicts()
+            .iter()
+            .filter(move |conflict| !self.resolutions.contains_key(&conflict.addr))
+    }
+
+    pub fn is_resolved(&self) -> bool {
+        self.unresolved().next().is_none()
+    }
+
+    /// Returns up to `n` merged entries before and after the conflict at `addr`.
+    pub fn get_context(&self, addr: u64, n: usize) -> ConflictContext {
+        let merged = &self.result.merged;
+        let start = merged.partition_point(|info| info.addr < addr);
+        let end = start + merged[start..].partition_point(|info| info.addr <= addr);
+        ConflictContext {
+            before: merged[start.saturating_sub(n)..start].to_vec(),
+            after: merged[end..(end + n).min(merged.len())].to_vec(),
+        }
+    }
+
+    /// Resolves (or re-resolves) the conflict at `addr`.
+    pub fn resolve(&mut self, addr: u64, resolution: Resolution) -> Result<()> {
+        let conflict = match self.conflicts().iter().find(|c| c.addr == addr) {
+            Some(conflict) => conflict,
+            None => bail!("no conflict at {}", functions::format_addr(addr)),
+        };
+        let entries = match resolution {
+            Resolution::Ours => conflict.ours.clone(),
+            Resolution::Theirs => conflict.theirs.clone(),
+            Resolution::Base => conflict.base.clone(),
+            Resolution::Custom(entries) => {
+                if let Some(info) = entries.iter().find(|info| info.addr != addr) {
+                    bail!(
+                        "resolution for the conflict at {} contains an entry at {}",
+                        functions::format_addr(addr),
+                        functions::format_addr(info.addr)
+                    );
+                }
+                entries
+            }
+        };
+        self.resolutions.insert(addr, entries);
+        Ok(())
+    }
+
+    /// Resolves every unresolved conflict for which `choose` returns a resolution.
+    pub fn resolve_with(
+        &mut self,
+        mut choose: impl FnMut(&MergeConflict) -> Option<Resolution>,
+    ) -> Result<()> {
+        let choices: Vec<(u64, Resolution)> = self
+            .unresolved()
+            .filter_map(|conflict| Some((conflict.addr, choose(conflict)?)))
+            .collect();
+        for (addr, resolution) in choices {
+            self.resolve(addr, resolution)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the merged list with the resolved conflicts applied and the conflicts that
+    /// are still unresolved, e.g. to save them (see `save_conflicts`) and finish later.
+    pub fn into_partial(self) -> MergeResult {
+        let mut merged = self.result.merged;
+        merged.extend(self.resolutions.values().flatten().cloned());
+        functions::canonicalize(&mut merged);
+        let resolutions = self.resolutions;
+        let conflicts = self
+            .result
+            .conflicts
+            .0
+            .into_iter()
+            .filter(|conflict| !resolutions.contains_key(&conflict.addr))
+            .collect();
+        MergeResult {
+            merged,
+            conflicts: MergeConflicts(conflicts),
+        }
+    }
+
+    /// Returns the fully merged list, in canonical order. Fails if some conflicts are unresolved.
+    pub fn finish(self) -> Result<Vec<Info>> {
+        let unresolved = self.unresolved().count();
+        if unresolved != 0 {
+            bail!("{} conflicts are still unresolved", unresolved);
+        }
+        Ok(self.into_partial().merged)
+    }
+}
+
+fn write_entries(writer: &mut dyn Write, label: &str, entries: &[Info]) -> Result<()> {
+    if entries.is_empty() {
+        writeln!(writer, "  {:<8} (no entry)", label)?;
+    }
+    for info in entries {
+        writeln!(
+            writer,
+            "  {:<8} {} {} {:06} {}",
+            label,
+            functions::format_addr(info.addr),
+            info.status.code(),
+            info.size,
+            info.name
+        )?;
+    }
+    Ok(())
+}
+
+/// Writes a description of a conflict and its context.
+pub(crate) fn write_conflict(
+    writer: &mut dyn Write,
+    conflict: &MergeConflict,
+    context: &ConflictContext,
+) -> Result<()> {
+    writeln!(
+        writer,
+        "conflict at {}:",
+        functions::format_addr(conflict.addr)
+    )?;
+    for info in &context.before {
+        write_entries(writer, "", std::slice::from_ref(info))?;
+    }
+    write_entries(writer, "base:", &conflict.base)?;
+    write_entries(writer, "ours:", &conflict.ours)?;
+    write_entries(writer, "theirs:", &conflict.theirs)?;
+    for info in &context.after {
+        write_entries(writer, "", std::slice::from_ref(info))?;
+    }
+    Ok(())
+}
+
+/// Asks how to resolve each unresolved conflict. `n` context entries are shown
+/// on each side of a conflict.
+///
+/// Conflicts can be skipped, and the loop can be left early; use `MergeResolver::is_resolved`
+/// to check whether everything was resolved. The loop also stops at the end of the input.
+pub fn resolve_interactively(
+    resolver: &mut MergeResolver,
+    input: &mut dyn BufRead,
+    output: &mut dyn Write,
+    n: usize,
+) -> Result<()> {
+    let addrs: Vec<u64> = resolver
+        .unresolved()
+        .map(|conflict| conflict.addr)
+        .collect();
+    for (i, addr) in addrs.iter().enumerate() {
+        let conflict = resolver
+            .conflicts()
+            .iter()
+            .find(|conflict| conflict.addr == *addr)
+            .unwrap();
+        write!(output, "[{}/{}] ", i + 1, addrs.len())?;
+        write_conflict(output, conflict, &resolver.get_context(*addr, n))?;
+
+        loop {
+            write!(output, "[o]urs, [t]heirs, [b]ase, [s]kip, [q]uit? ")?;
+            output.flush()?;
+
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0 {
+                return Ok(());
+            }
+            let resolution = match line.trim() {
+                "o" => Resolution::Ours,
+                "t" => Resolution::Theirs,
+                "b" => Resolution::Base,
+                "s" => break,
+                "q" => return Ok(()),
+                _ => continue,
+            };
+            resolver.resolve(*addr, resolution)?;
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Same as `resolve_interactively`, but reads from stdin and writes to stderr.
+pub fn resolve_with_stdin(resolver: &mut MergeResolver, n: usize) -> Result<()> {
+    let stdin = std::io::stdin();
+    let stderr = std::io::stderr();
+    resolve_interactively(resolver, &mut stdin.lock(), &mut stderr.lock(), n)
+}
+
+/// Saves conflicts as three function lists (`base.csv`, `ours.csv` and `theirs.csv`)
+/// in `dir` so that they can be resolved later (see `load_conflicts`).
+pub fn save_conflicts(dir: &Path, conflicts: &MergeConflicts) -> Result<()> {
+    std::fs::create_dir_all(dir).with_context(|| format!("failed to create {:?}", dir))?;
+    let (base, ours, theirs) = conflicts.to_lists();
+    // Conflicts that were saved previously are replaced, even if there were more of them.
+    let options = WriteOptions {
+        allow_shrink: true,
+        ..Default::default()
+    };
+    functions::write_functions_atomic_ex(&dir.join("base.csv"), &base, &options)?;
+    functions::write_functions_atomic_ex(&dir.join("ours.csv"), &ours, &options)?;
+    functions::write_functions_atomic_ex(&dir.join("theirs.csv"), &theirs, &options)
+}
+
+/// Loads conflicts that were saved with `save_conflicts`.
+pub fn load_conflicts(dir: &Path) -> Result<MergeConflicts> {
+    let load = |name: &str| {
+        let path = dir.join(name);
+        functions::get_functions_for_path(&path)
+            .with_context(|| format!("failed to load {:?}", path))
+    };
+    let result = merge_functions(&load("base.csv")?, &load("ours.csv")?, &load("theirs.csv")?);
+    Ok(result.conflicts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::Status;
+    use crate::testing::{self, make_function, TempDir};
+
+    /// Returns a merge with conflicts at 0x200 (both renamed), 0x300 (removed by us,
+    /// changed by them) and 0x400 (both changed the status).
+    fn make_merge() -> MergeResult {
+        let f = |addr, name, status| make_function(addr, 0x10, name, status);
+        let base = vec![
+            f(0x100, "_Z1av", Status::NotDecompiled),
+            f(0x200, "_Z1bv", Status::NotDecompiled),
+            f(0x300, "_Z1cv", Status::NotDecompiled),
+            f(0x400, "_Z1dv", Status::NotDecompiled),
+        ];
+        let ours = vec![
+            f(0x100, "_Z1av", Status::Matching),
+            f(0x200, "_Z4oursv", Status::NotDecompiled),
+            f(0x400, "_Z1dv", Status::Wip),
+        ];
+        let theirs = vec![
+            f(0x100, "_Z1av", Status::NotDecompiled),
+            f(0x200, "_Z6theirsv", Status::NotDecompiled),
+            f(0x300, "_Z1cv", Status::Matching),
+            f(0x400, "_Z1dv", Status::NonMatchingMinor),
+        ];
+        let result = merge_functions(&base, &ours, &theirs);
+        let addrs: Vec<u64> = result.conflicts.0.iter().map(|c| c.addr).collect();
+        assert_eq!(addrs, [0x200, 0x300, 0x400]);
+        result
+    }
+
+    fn names(functions: &[Info]) -> Vec<&str> {
+        functions.iter().map(|info| info.name.as_str()).collect()
+    }
+
+    #[test]
+    fn conflicts_are_resolved_with_either_side() {
+        let mut resolver = MergeResolver::new(make_merge());
+        resolver.resolve(0x200, Resolution::Ours).unwrap();
+        resolver.resolve(0x300, Resolution::Ours).unwrap();
+        assert!(!resolver.is_resolved());
+        assert!(resolver.clone().finish().is_err());
+        resolver.resolve(0x400, Resolution::Theirs).unwrap();
+        assert!(resolver.resolve(0x500, Resolution::Ours).is_err());
+        assert!(resolver
+            .resolve(
+                0x400,
+                Resolution::Custom(vec![make_function(0x410, 0, "", Status::Wip)])
+            )
+            .is_err());
+
+        let merged = resolver.finish().unwrap();
+        assert_eq!(names(&merged), ["_Z1av", "_Z4oursv", "_Z1dv"]);
+        assert_eq!(merged[0].status, Status::Matching);
+        assert_eq!(merged[2].status, Status::NonMatchingMinor);
+
+        let mut resolver = MergeResolver::new(make_merge());
+        resolver.resolve_with(|_| Some(Resolution::Theirs)).unwrap();
+        let merged = resolver.finish().unwrap();
+        assert_eq!(names(&merged), ["_Z1av", "_Z6theirsv", "_Z1cv", "_Z1dv"]);
+    }
+
+    #[test]
+    fn conflicts_can_be_skipped_interactively() {
+        let mut resolver = MergeResolver::new(make_merge());
+        let mut output = Vec::new();
+        resolve_interactively(&mut resolver, &mut &b"x\nt\ns\no\n"[..], &mut output, 1).unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with("[1/3] conflict at 0x0000007100000200:\n"));
+        assert!(output.contains("[3/3] conflict at 0x0000007100000400:\n"));
+        let unresolved: Vec<u64> = resolver.unresolved().map(|c| c.addr).collect();
+        assert_eq!(unresolved, [0x300]);
+
+        let partial = resolver.into_partial();
+        assert_eq!(names(&partial.merged), ["_Z1av", "_Z6theirsv", "_Z1dv"]);
+        assert_eq!(partial.merged[2].status, Status::Wip);
+        assert_eq!(partial.conflicts.len(), 1);
+
+        // Input that ends early leaves the remaining conflicts unresolved.
+        let mut resolver = MergeResolver::new(make_merge());
+        resolve_interactively(&mut resolver, &mut &b"b\n"[..], &mut Vec::new(), 0).unwrap();
+        assert_eq!(resolver.unresolved().count(), 2);
+    }
+
+    #[test]
+    fn partially_resolved_conflicts_are_saved_and_loaded() {
+        testing::use_test_config();
+        let dir = TempDir::new("merge_conflicts");
+        let mut resolver = MergeResolver::new(make_merge());
+        resolver.resolve(0x200, Resolution::Theirs).unwrap();
+        let partial = resolver.into_partial();
+
+        save_conflicts(dir.path(), &partial.conflicts).unwrap();
+        let conflicts = load_conflicts(dir.path()).unwrap();
+        assert_eq!(conflicts, partial.conflicts);
+        // The removed entry is still a conflict after the round trip.
+        assert!(conflicts.0[0].ours.is_empty());
+
+        // The remaining conflicts can be resolved on top of the partial result.
+        let mut resolver = MergeResolver::new(MergeResult {
+            merged: partial.merged,
+            conflicts,
+        });
+        resolver.resolve(0x300, Resolution::Theirs).unwrap();
+        resolver.resolve(0x400, Resolution::Base).unwrap();
+        let merged = resolver.finish().unwrap();
+        assert_eq!(names(&merged), ["_Z1av", "_Z6theirsv", "_Z1cv", "_Z1dv"]);
+        assert_eq!(merged[3].status, Status::NotDecompiled);
+
+        // Saving fewer conflicts replaces the previous ones.
+        save_conflicts(dir.path(), &MergeConflicts::default()).unwrap();
+        assert!(load_conflicts(dir.path()).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,319 @@
+use crate::file_utils;
+use crate::functions::{Info, Status};
+use crate::lint::Issue;
+use crate::lock::{self, LockOptions};
+use crate::repo;
+use anyhow::{bail, Context, Result};
+use rustc_hash::FxHashSet;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+pub const OWNER: &str = "owner";
+pub const URL: &str = "url";
+pub const NOTE: &str = "note";
+pub const UPDATED_AT: &str = "updated_at";
+/// When the current owner claimed the function (see `claims`). Unlike `updated_at`,
+/// this is not bumped by other changes to the entry.
+pub const CLAIMED_AT: &str = "claimed_at";
+
+/// Formats a timestamp like the ones in `updated_at` and `claimed_at`.
+pub fn format_timestamp(date: chrono::DateTime<chrono::Utc>) -> String {
+    date.format("%Y-%m-%dT%H:%M:%SZ").to_string()
+}
+
+/// Free-form information about a function that doesn't belong in the function list,
+/// e.g. who is working on it and a link to their branch.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Entry {
+    pub fields: BTreeMap<String, String>,
+}
+
+impl Entry {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.fields.get(key).map(String::as_str)
+    }
+
+    /// Sets a field and bumps `updated_at`.
+    pub fn set(&mut self, key: &str, value: String) {
+        self.fields.insert(key.to_string(), value);
+        self.touch();
+    }
+
+    /// Removes a field and bumps `updated_at`.
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        let value = self.fields.remove(key);
+        self.touch();
+        value
+    }
+
+    pub fn touch(&mut self) {
+        self.fields
+            .insert(UPDATED_AT.to_string(), format_timestamp(chrono::Utc::now()));
+    }
+
+    pub fn owner(&self) -> Option<&str> {
+        self.get(OWNER)
+    }
+
+    pub fn url(&self) -> Option<&str> {
+        self.get(URL)
+    }
+
+    pub fn note(&self) -> Option<&str> {
+        self.get(NOTE)
+    }
+
+    pub fn updated_at(&self) -> Option<&str> {
+        self.get(UPDATED_AT)
+    }
+
+    pub fn claimed_at(&self) -> Option<&str> {
+        self.get(CLAIMED_AT)
+    }
+}
+
+/// Function metadata, keyed by mangled name.
+///
+/// On disk, this is a TOML file with one table per function:
+///
+/// ```toml
+/// [functions._ZN4ksys3act8BaseProc4initEv]
+/// owner = "someone"
+/// url = "https://github.com/someone/repo/tree/baseproc"
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Metadata {
+    pub entries: BTreeMap<String, Entry>,
+}
+
+impl Metadata {
+    pub fn get(&self, name: &str) -> Option<&Entry> {
+        self.entries.get(name)
+    }
+
+    /// Returns the entry for the specified function, creating it if needed.
+    pub fn entry(&mut self, name: &str) -> &mut Entry {
+        self.entries.entry(name.to_string()).or_default()
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Entry> {
+        self.entries.remove(name)
+    }
+
+    pub fn parse(contents: &str) -> Result<Self> {
+        let value: toml::Value = toml::from_str(contents)?;
+        let mut metadata = Metadata::default();
+
+        let functions = match value.get("functions") {
+            Some(functions) => functions,
+            None => return Ok(metadata),
+        };
+        let functions = functions.as_table().context("functions must be a table")?;
+
+        for (name, fields) in functions {
+            let fields = fields
+                .as_table()
+                .with_context(|| format!("metadata for {} must be a table", name))?;
+            let mut entry = Entry::default();
+            for (key, value) in fields {
+                let value = match value.as_str() {
+                    Some(value) => value.to_string(),
+                    None => bail!("metadata field {} for {} must be a string", key, name),
+                };
+                entry.fields.insert(key.clone(), value);
+            }
+            metadata.entries.insert(name.clone(), entry);
+        }
+
+        Ok(metadata)
+    }
+
+    pub fn to_toml_string(&self) -> Result<String> {
+        let mut functions = toml::value::Table::new();
+        for (name, entry) in &self.entries {
+            let fields = entry
+                .fields
+                .iter()
+                .map(|(key, value)| (key.clone(), toml::Value::String(value.clone())))
+                .collect();
+            functions.insert(name.clone(), toml::Value::Table(fields));
+        }
+
+        let mut root = toml::value::Table::new();
+        root.insert("functions".to_string(), toml::Value::Table(functions));
+        Ok(toml::to_string(&toml::Value::Table(root))?)
+    }
+
+    /// Reads metadata from `path`. A missing file is treated as empty metadata.
+    pub fn load_from_path(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Metadata::default());
+        }
+        let contents = std::fs::read_to_string(path)?;
+        Metadata::parse(&contents).with_context(|| format!("failed to parse {:?}", path))
+    }
+
+    /// Writes metadata to `path`. The file is replaced atomically.
+    pub fn save_to_path(&self, path: &Path) -> Result<()> {
+        file_utils::write_atomic(path, self.to_toml_string()?.as_bytes())
+    }
+}
+
+/// Returns the path to the metadata sidecar file.
+/// This can be changed with the `metadata_toml` config key (relative to the repo root).
+pub fn get_metadata_path() -> Result<PathBuf> {
+    let path = repo::CONFIG
+        .get("metadata_toml")
+        .and_then(toml::Value::as_str)
+        .unwrap_or("data/function_metadata.toml");
+    Ok(repo::get_repo_root()?.join(path))
+}
+
+/// Returns the metadata for the executable's functions.
+pub fn load() -> Result<Metadata> {
+    Metadata::load_from_path(&get_metadata_path()?)
+}
+
+pub fn save(metadata: &Metadata) -> Result<()> {
+    metadata.save_to_path(&get_metadata_path()?)
+}
+
+/// Loads the metadata, lets `f` modify it and writes it back. See `update_path`.
+pub fn update<F: FnOnce(&mut Metadata) -> Result<()>>(f: F) -> Result<()> {
+    update_path(&get_metadata_path()?, f)
+}
+
+/// Loads the metadata at `path`, lets `f` modify it and writes it back.
+///
+/// The file is locked (see `lock::lock_exclusive`) until the changes have been written,
+/// so that concurrent updates are not lost.
+pub fn update_path<F: FnOnce(&mut Metadata) -> Result<()>>(path: &Path, f: F) -> Result<()> {
+    let _lock = lock::lock_exclusive(path, &LockOptions::default())?;
+    let mut metadata = Metadata::load_from_path(path)?;
+    f(&mut metadata)?;
+    metadata.save_to_path(path)
+}
+
+/// A function together with its metadata, if there is any.
+#[derive(Clone, Copy, Debug)]
+pub struct AnnotatedInfo<'a> {
+    pub info: &'a Info,
+    pub metadata: Option<&'a Entry>,
+}
+
+impl<'a> AnnotatedInfo<'a> {
+    pub fn owner(&self) -> Option<&'a str> {
+        self.metadata.and_then(Entry::owner)
+    }
+}
+
+pub fn join<'a>(functions: &'a [Info], metadata: &'a Metadata) -> Vec<AnnotatedInfo<'a>> {
+    functions
+        .iter()
+        .map(|info| AnnotatedInfo {
+            info,
+            metadata: if info.name.is_empty() {
+                None
+            } else {
+                metadata.get(&info.name)
+            },
+        })
+        .collect()
+}
+
+/// Returns the names of metadata entries that do not correspond to any function in the list
+/// (e.g. because the function was renamed).
+pub fn find_stale_entries<'a>(functions: &[Info], metadata: &'a Metadata) -> Vec<&'a str> {
+    let known: FxHashSet<&str> = functions
+        .iter()
+        .filter(|info| !info.name.is_empty())
+        .map(|info| info.name.as_str())
+        .collect();
+
+    metadata
+        .entries
+        .keys()
+        .filter(|name| !known.contains(name.as_str()))
+        .map(String::as_str)
+        .collect()
+}
+
+/// Checks metadata for stale entries and for claims on functions that are already matching.
+pub fn validate(functions: &[Info], metadata: &Metadata) -> Vec<Issue> {
+    let mut issues: Vec<Issue> = find_stale_entries(functions, metadata)
+        .into_iter()
+        .map(|name| Issue::warning(None, format!("metadata for unknown function {}", name)))
+        .collect();
+
+    for annotated in join(functions, metadata) {
+        if annotated.info.status != Status::Matching {
+            continue;
+        }
+        if let Some(owner) = annotated.owner() {
+            issues.push(Issue::warning(
+                Some(annotated.info.addr),
+                format!(
+                    "{} is claimed by {} but is already matching",
+                    annotated.info.name, owner
+                ),
+            ));
+        }
+    }
+
+    issues
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn concurrent_updates_are_not_lost() {
+        let dir = TempDir::new("metadata_update");
+        let path = dir.join("function_metadata.toml");
+        let names: Vec<String> = (0..8).map(|i| format!("_Z1fILi{}EEvv", i)).collect();
+
+        let threads: Vec<_> = names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                let path = path.clone();
+                let name = name.clone();
+                std::thread::spawn(move || {
+                    update_path(&path, |metadata| {
+                        let entry = metadata.entry(&name);
+                        // Give the other threads a chance to read the file in the meantime.
+                        std::thread::sleep(std::time::Duration::from_millis(5));
+                        entry.set(OWNER, format!("user{}", i));
+                        Ok(())
+                    })
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap().unwrap();
+        }
+
+        let metadata = Metadata::load_from_path(&path).unwrap();
+        assert_eq!(metadata.entries.len(), names.len());
+        for (i, name) in names.iter().enumerate() {
+            let expected = format!("user{}", i);
+            assert_eq!(metadata.get(name).unwrap().owner(), Some(expected.as_str()));
+        }
+        assert!(!lock::get_lock_path(&path).exists());
+    }
+
+    #[test]
+    fn failed_updates_are_not_written() {
+        let dir = TempDir::new("metadata_update_error");
+        let path = dir.join("function_metadata.toml");
+        let result = update_path(&path, |metadata| {
+            metadata.entry("_Z1fv").set(OWNER, "someone".to_string());
+            bail!("something went wrong")
+        });
+        assert!(result.is_err());
+        assert!(!path.exists());
+        assert!(!lock::get_lock_path(&path).exists());
+    }
+}
@@ -0,0 +1,487 @@
+use crate::functions::{self, Info};
+use crate::lint::Issue;
+use crate::repo;
+use anyhow::{bail, ensure, Context, Result};
+use rayon::prelude::*;
+use rustc_hash::{FxHashMap, FxHashSet};
+use std::path::PathBuf;
+
+/// Name of the module whose function list is `functions_csv`.
+pub const MAIN_MODULE_NAME: &str = "main";
+
+/// An executable (e.g. the main NSO or a subsdk) that has its own function list.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Module {
+    pub name: String,
+    pub csv_path: PathBuf,
+}
+
+/// Returns the main module followed by the modules that are listed in the `modules` array
+/// of the config. Each entry has a `name` and a `functions_csv` path (relative to the repo root):
+///
+/// ```toml
+/// [[modules]]
+/// name = "subsdk0"
+/// functions_csv = "data/subsdk0_functions.csv"
+/// ```
+pub fn get_modules() -> Result<Vec<Module>> {
+    let root = repo::get_repo_root()?;
+    let mut modules = vec![Module {
+        name: MAIN_MODULE_NAME.to_string(),
+        csv_path: functions::get_functions_csv_path().to_path_buf(),
+    }];
+
+    let entries = match repo::CONFIG.get("modules") {
+        Some(value) => value
+            .as_array()
+            .context("modules must be an array")?
+            .as_slice(),
+        None => &[],
+    };
+    for entry in entries {
+        let name = entry
+            .get("name")
+            .and_then(toml::Value::as_str)
+            .context("every module must have a name")?;
+        let csv_path = entry
+            .get("functions_csv")
+            .and_then(toml::Value::as_str)
+            .with_context(|| format!("module {} has no functions_csv", name))?;
+        ensure!(
+            modules.iter().all(|module| module.name != name),
+            "module {} is declared more than once",
+            name
+        );
+        modules.push(Module {
+            name: name.to_string(),
+            csv_path: root.join(csv_path),
+        });
+    }
+
+    Ok(modules)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DuplicateKind {
+    /// The name is listed more than once in the same module.
+    IntraModule,
+    /// The name is listed once in each of several modules, e.g. for library functions that are
+    /// statically linked into both the main executable and a subsdk.
+    CrossModule,
+}
+
+/// A name that is used by several functions.
+#[derive(Clone, Debug)]
+pub struct DuplicateName {
+    pub name: String,
+    pub kind: DuplicateKind,
+    /// Indices of the modules (in `ModuleFunctions::modules`) and addresses of the functions.
+    pub entries: Vec<(usize, u64)>,
+}
+
+impl DuplicateName {
+    /// Returns a one-line description of the duplicate, e.g. `_Z4copyv (across modules):
+    /// main 0x0000007100000010, subsdk0 0x0000007100000010`.
+    pub fn description(&self, modules: &[Module]) -> String {
+        let entries: Vec<String> = self
+            .entries
+            .iter()
+            .map(|&(i, addr)| format!("{} {}", modules[i].name, functions::format_addr(addr)))
+            .collect();
+        let kind = match self.kind {
+            DuplicateKind::IntraModule => "within a module",
+            DuplicateKind::CrossModule => "across modules",
+        };
+        format!("{} ({}): {}", self.name, kind, entries.join(", "))
+    }
+}
+
+/// Which cross-module duplicates are allowed. Intra-module duplicates are always rejected.
+#[derive(Clone, Debug, Default)]
+pub struct DuplicatePolicy {
+    /// Allow any name to be listed in several modules.
+    pub allow_cross_module_duplicates: bool,
+    /// Names that may be listed in several modules even if `allow_cross_module_duplicates`
+    /// is false.
+    pub allowed_names: FxHashSet<String>,
+}
+
+impl DuplicatePolicy {
+    /// Returns the policy from the config (`allow_cross_module_duplicates`
+    /// and `cross_module_duplicate_allowlist`). By default, no duplicates are allowed.
+    pub fn from_config() -> Result<Self> {
+        let allowed_names = match repo::CONFIG.get("cross_module_duplicate_allowlist") {
+            Some(value) => value
+                .as_array()
+                .context("cross_module_duplicate_allowlist must be an array")?
+                .iter()
+                .map(|name| {
+                    name.as_str()
+                        .map(str::to_string)
+                        .context("cross_module_duplicate_allowlist must only contain strings")
+                })
+                .collect::<Result<_>>()?,
+            None => FxHashSet::default(),
+        };
+
+        Ok(Self {
+            allow_cross_module_duplicates: repo::CONFIG
+                .get("allow_cross_module_duplicates")
+                .and_then(toml::Value::as_bool)
+                .unwrap_or(false),
+            allowed_names,
+        })
+    }
+
+    pub fn allows(&self, duplicate: &DuplicateName) -> bool {
+        duplicate.kind == DuplicateKind::CrossModule
+            && (self.allow_cross_module_duplicates || self.allowed_names.contains(&duplicate.name))
+    }
+}
+
+/// A function and the module it is in.
+#[derive(Clone, Copy, Debug)]
+pub struct ModuleInfo<'a> {
+    pub module: &'a Module,
+    pub info: &'a Info,
+}
+
+/// The function lists of several modules.
+#[derive(Clone, Debug)]
+pub struct ModuleFunctions {
+    pub modules: Vec<Module>,
+    /// `functions[i]` is the function list of `modules[i]`.
+    pub functions: Vec<Vec<Info>>,
+}
+
+impl ModuleFunctions {
+    pub fn new(modules: Vec<Module>, functions: Vec<Vec<Info>>) -> Self {
+        assert_eq!(modules.len(), functions.len());
+        Self { modules, functions }
+    }
+
+    /// Reads the function list of every module in parallel.
+    pub fn load(modules: Vec<Module>) -> Result<Self> {
+        let functions = modules
+            .par_iter()
+            .map(|module| {
+                functions::get_functions_for_path(&module.csv_path)
+                    .with_context(|| format!("failed to read the function list of {}", module.name))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self::new(modules, functions))
+    }
+
+    /// Reads the function lists of all modules in the config (see `get_modules`).
+    pub fn load_from_config() -> Result<Self> {
+        Self::load(get_modules()?)
+    }
+
+    /// Same as `load_from_config`, but `main_functions` is used as the function list of the
+    /// main module instead of reading it again.
+    pub fn load_from_config_with_main(main_functions: Vec<Info>) -> Result<Self> {
+        let mut modules = get_modules()?;
+        let others = modules.split_off(1);
+        let mut loaded = Self::load(others)?;
+        loaded.modules.insert(0, modules.remove(0));
+        loaded.functions.insert(0, main_functions);
+        Ok(loaded)
+    }
+
+    fn iter(&self) -> impl Iterator<Item = ModuleInfo<'_>> {
+        self.modules
+            .iter()
+            .zip(&self.functions)
+            .flat_map(|(module, functions)| {
+                functions
+                    .iter()
+                    .map(move |info| ModuleInfo { module, info })
+            })
+    }
+
+    /// Returns every name that is used by several functions, sorted by name.
+    pub fn find_duplicate_names(&self) -> Vec<DuplicateName> {
+        let mut entries_by_name: FxHashMap<&str, Vec<(usize, u64)>> = FxHashMap::default();
+        for (i, functions) in self.functions.iter().enumerate() {
+            for info in functions {
+                if !info.name.is_empty() {
+                    entries_by_name
+                        .entry(&info.name)
+                        .or_default()
+                        .push((i, info.addr));
+                }
+            }
+        }
+
+        let mut duplicates: Vec<DuplicateName> = entries_by_name
+            .into_iter()
+            .filter(|(_, entries)| entries.len() > 1)
+            .map(|(name, entries)| {
+                // Entries are in module order, so duplicates within a module are adjacent.
+                let kind = if entries.windows(2).any(|pair| pair[0].0 == pair[1].0) {
+                    DuplicateKind::IntraModule
+                } else {
+                    DuplicateKind::CrossModule
+                };
+                DuplicateName {
+                    name: name.to_string(),
+                    kind,
+                    entries,
+                }
+            })
+            .collect();
+        duplicates.sort_by(|a, b| a.name.cmp(&b.name));
+        duplicates
+    }
+
+    /// Checks that no name is used by several functions, except for cross-module duplicates
+    /// that `policy` allows. Returns the allowed duplicates.
+    pub fn validate_duplicate_names(&self, policy: &DuplicatePolicy) -> Result<Vec<DuplicateName>> {
+        let (allowed, rejected): (Vec<_>, Vec<_>) = self
+            .find_duplicate_names()
+            .into_iter()
+            .partition(|duplicate| policy.allows(duplicate));
+
+        if !rejected.is_empty() {
+            let descriptions: Vec<String> = rejected
+                .iter()
+                .map(|duplicate| duplicate.description(&self.modules))
+                .collect();
+            bail!("found duplicates:\n{}", descriptions.join("\n"));
+        }
+
+        Ok(allowed)
+    }
+
+    /// Returns every function with the specified name, in module order.
+    pub fn find_all_by_name(&self, name: &str) -> Vec<ModuleInfo<'_>> {
+        self.iter()
+            .filter(|entry| entry.info.name == name)
+            .collect()
+    }
+
+    /// Returns a map from names to the functions with that name, in module order.
+    pub fn make_name_map(&self) -> FxHashMap<&str, Vec<ModuleInfo<'_>>> {
+        let mut map: FxHashMap<&str, Vec<ModuleInfo<'_>>> = FxHashMap::default();
+        for entry in self.iter() {
+            if !entry.info.name.is_empty() {
+                map.entry(entry.info.name.as_str()).or_default().push(entry);
+            }
+        }
+        map
+    }
+
+    /// Same as `functions::find_function_fuzzy`, but searches every module.
+    ///
+    /// Exact name matches take priority over fuzzy matches in any module. If several modules
+    /// have a match, the first module wins (i.e. the main module, then the modules in config
+    /// order); use `find_all_by_name` or `find_function_fuzzy_in` to get a specific copy.
+    pub fn find_function_fuzzy(&self, name: &str) -> Option<ModuleInfo<'_>> {
+        self.iter()
+            .find(|entry| entry.info.name == name)
+            .or_else(|| {
+                self.modules
+                    .iter()
+                    .zip(&self.functions)
+                    .find_map(|(module, functions)| {
+                        functions::find_function_fuzzy(functions, name)
+                            .map(|info| ModuleInfo { module, info })
+                    })
+            })
+    }
+
+    /// Same as `functions::find_function_fuzzy`, but only in the module called `module_name`.
+    pub fn find_function_fuzzy_in(&self, module_name: &str, name: &str) -> Option<ModuleInfo<'_>> {
+        let i = self
+            .modules
+            .iter()
+            .position(|module| module.name == module_name)?;
+        functions::find_function_fuzzy(&self.functions[i], name).map(|info| ModuleInfo {
+            module: &self.modules[i],
+            info,
+        })
+    }
+}
+
+/// Reports the duplicate names that `policy` does not allow (see
+/// `ModuleFunctions::validate_duplicate_names`), except for duplicates within the main module,
+/// which `lint::validate_all` already reports.
+pub fn check_duplicate_names(modules: &ModuleFunctions, policy: &DuplicatePolicy) -> Vec<Issue> {
+    modules
+        .find_duplicate_names()
+        .into_iter()
+        .filter(|duplicate| !policy.allows(duplicate))
+        .filter(|duplicate| {
+            duplicate.kind == DuplicateKind::CrossModule
+                || duplicate.entries.iter().any(|&(i, _)| i != 0)
+        })
+        .map(|duplicate| {
+            // Addresses of issues are addresses in the main module.
+            let addr = duplicate
+                .entries
+                .iter()
+                .find(|&&(i, _)| i == 0)
+                .map(|&(_, addr)| addr);
+            Issue::error(
+                addr,
+                format!("duplicate name {}", duplicate.description(&modules.modules)),
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::Status;
+    use crate::testing::make_function;
+
+    fn make_modules(functions: Vec<Vec<Info>>) -> ModuleFunctions {
+        let modules = (0..functions.len())
+            .map(|i| Module {
+                name: if i == 0 {
+                    MAIN_MODULE_NAME.to_string()
+                } else {
+                    format!("subsdk{}", i - 1)
+                },
+                csv_path: PathBuf::from(format!("{}.csv", i)),
+            })
+            .collect();
+        ModuleFunctions::new(modules, functions)
+    }
+
+    /// `_Z4copyv` is in main and subsdk0, `_Z4dupev` twice in subsdk0.
+    fn make_test_modules() -> ModuleFunctions {
+        make_modules(vec![
+            vec![
+                make_function(0x10, 0x10, "_Z4copyv", Status::Matching),
+                make_function(0x20, 0x10, "_Z4mainv", Status::Matching),
+            ],
+            vec![
+                make_function(0x10, 0x10, "_Z4copyv", Status::Matching),
+                make_function(0x30, 0x10, "_Z4dupev", Status::Matching),
+                make_function(0x40, 0x10, "_Z4dupev", Status::Matching),
+            ],
+        ])
+    }
+
+    #[test]
+    fn duplicates_are_classified() {
+        let duplicates = make_test_modules().find_duplicate_names();
+        assert_eq!(duplicates.len(), 2);
+        assert_eq!(duplicates[0].name, "_Z4copyv");
+        assert_eq!(duplicates[0].kind, DuplicateKind::CrossModule);
+        assert_eq!(duplicates[0].entries, [(0, 0x10), (1, 0x10)]);
+        assert_eq!(duplicates[1].name, "_Z4dupev");
+        assert_eq!(duplicates[1].kind, DuplicateKind::IntraModule);
+        assert_eq!(duplicates[1].entries, [(1, 0x30), (1, 0x40)]);
+    }
+
+    #[test]
+    fn duplicates_are_rejected_by_default() {
+        let modules = make_modules(vec![
+            vec![make_function(0x10, 0x10, "_Z4copyv", Status::Matching)],
+            vec![make_function(0x10, 0x10, "_Z4copyv", Status::Matching)],
+        ]);
+        let err = modules
+            .validate_duplicate_names(&DuplicatePolicy::default())
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "found duplicates:\n_Z4copyv (across modules): main 0x0000007100000010, subsdk0 0x0000007100000010"
+        );
+    }
+
+    #[test]
+    fn cross_module_duplicates_can_be_allowed() {
+        let modules = make_modules(vec![
+            vec![
+                make_function(0x10, 0x10, "_Z4copyv", Status::Matching),
+                make_function(0x20, 0x10, "_Z4movev", Status::Matching),
+            ],
+            vec![
+                make_function(0x10, 0x10, "_Z4copyv", Status::Matching),
+                make_function(0x20, 0x10, "_Z4movev", Status::Matching),
+            ],
+        ]);
+
+        let policy = DuplicatePolicy {
+            allow_cross_module_duplicates: true,
+            ..Default::default()
+        };
+        let allowed = modules.validate_duplicate_names(&policy).unwrap();
+        assert_eq!(allowed.len(), 2);
+
+        let mut policy = DuplicatePolicy::default();
+        policy.allowed_names.insert("_Z4copyv".to_string());
+        let err = modules.validate_duplicate_names(&policy).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "found duplicates:\n_Z4movev (across modules): main 0x0000007100000020, subsdk0 0x0000007100000020"
+        );
+
+        policy.allowed_names.insert("_Z4movev".to_string());
+        let allowed = modules.validate_duplicate_names(&policy).unwrap();
+        let names: Vec<&str> = allowed
+            .iter()
+            .map(|duplicate| duplicate.name.as_str())
+            .collect();
+        assert_eq!(names, ["_Z4copyv", "_Z4movev"]);
+    }
+
+    #[test]
+    fn intra_module_duplicates_are_always_rejected() {
+        let mut policy = DuplicatePolicy {
+            allow_cross_module_duplicates: true,
+            ..Default::default()
+        };
+        policy.allowed_names.insert("_Z4dupev".to_string());
+        let err = make_test_modules()
+            .validate_duplicate_names(&policy)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "found duplicates:\n_Z4dupev (within a module): subsdk0 0x0000007100000030, subsdk0 0x0000007100000040"
+        );
+    }
+
+    #[test]
+    fn duplicates_are_reported_as_issues() {
+        let modules = make_test_modules();
+
+        let issues = check_duplicate_names(&modules, &DuplicatePolicy::default());
+        let issues: Vec<(Option<u64>, &str)> = issues
+            .iter()
+            .map(|issue| (issue.addr, issue.message.as_str()))
+            .collect();
+        assert_eq!(
+            issues,
+            [
+                (
+                    Some(0x10),
+                    "duplicate name _Z4copyv (across modules): main 0x0000007100000010, subsdk0 0x0000007100000010"
+                ),
+                (
+                    None,
+                    "duplicate name _Z4dupev (within a module): subsdk0 0x0000007100000030, subsdk0 0x0000007100000040"
+                ),
+            ]
+        );
+
+        let policy = DuplicatePolicy {
+            allow_cross_module_duplicates: true,
+            ..Default::default()
+        };
+        assert_eq!(check_duplicate_names(&modules, &policy).len(), 1);
+
+        // Duplicates within the main module are reported by lint::validate_all.
+        let modules = make_modules(vec![
+            vec![
+                make_function(0x10, 0x10, "_Z4dupev", Status::Matching),
+                make_function(0x20, 0x10, "_Z4dupev", Status::Matching),
+            ],
+            vec![],
+        ]);
+        assert!(check_duplicate_names(&modules, &DuplicatePolicy::default()).is_empty());
+    }
+}
@@ -0,0 +1,190 @@
+use crate::functions::ADDRESS_BASE;
+use anyhow::{bail, ensure, Context, Result};
+
+const NSO_MAGIC: &[u8; 4] = b"NSO0";
+const NSO_HEADER_SIZE: usize = 0x100;
+
+pub const FLAG_TEXT_COMPRESSED: u32 = 1 << 0;
+pub const FLAG_RO_COMPRESSED: u32 = 1 << 1;
+pub const FLAG_DATA_COMPRESSED: u32 = 1 << 2;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SegmentHeader {
+    pub file_offset: u32,
+    /// Offset of the segment from the start of the module in memory.
+    pub memory_offset: u32,
+    /// Size of the segment in memory (after decompression).
+    pub size: u32,
+    /// Size of the segment in the file (before decompression).
+    pub file_size: u32,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NsoHeader {
+    pub version: u32,
+    pub flags: u32,
+    pub text: SegmentHeader,
+    pub ro: SegmentHeader,
+    pub data: SegmentHeader,
+    pub bss_size: u32,
+    pub module_id: [u8; 0x20],
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    let mut buf = [0u8; 4];
+    buf.copy_from_slice(&bytes[offset..offset + 4]);
+    u32::from_le_bytes(buf)
+}
+
+fn read_segment_header(bytes: &[u8], offset: usize, file_size_offset: usize) -> SegmentHeader {
+    SegmentHeader {
+        file_offset: read_u32(bytes, offset),
+        memory_offset: read_u32(bytes, offset + 4),
+        size: read_u32(bytes, offset + 8),
+        file_size: read_u32(bytes, file_size_offset),
+    }
+}
+
+pub fn parse_nso_header(nso_bytes: &[u8]) -> Result<NsoHeader> {
+    ensure!(nso_bytes.len() >= NSO_HEADER_SIZE, "file is too small");
+    ensure!(&nso_bytes[0..4] == NSO_MAGIC, "not an NSO (wrong magic)");
+
+    let mut module_id = [0u8; 0x20];
+    module_id.copy_from_slice(&nso_bytes[0x40..0x60]);
+
+    Ok(NsoHeader {
+        version: read_u32(nso_bytes, 0x4),
+        flags: read_u32(nso_bytes, 0xc),
+        text: read_segment_header(nso_bytes, 0x10, 0x60),
+        ro: read_segment_header(nso_bytes, 0x20, 0x64),
+        data: read_segment_header(nso_bytes, 0x30, 0x68),
+        bss_size: read_u32(nso_bytes, 0x3c),
+        module_id,
+    })
+}
+
+/// Returns the address at which the code of an NSO starts when the NSO is loaded on its own,
+/// which is how function lists are generated.
+///
+/// *Note*: NSOs do not contain absolute addresses: the loader maps modules one after the other
+/// starting from 0x7100000000, so only the segment layout can be taken from the header.
+pub fn detect_address_base_from_nso(nso_bytes: &[u8]) -> Result<u64> {
+    let header = parse_nso_header(nso_bytes)?;
+    ensure!(
+        header.text.memory_offset <= header.ro.memory_offset
+            && header.ro.memory_offset <= header.data.memory_offset,
+        "unexpected segment layout"
+    );
+    Ok(ADDRESS_BASE + header.text.memory_offset as u64)
+}
+
+/// Decompresses an LZ4 block (without frame header) into exactly `size` bytes.
+fn decompress_lz4_block(input: &[u8], size: usize) -> Result<Vec<u8>> {
+    let mut output = Vec::with_capacity(size);
+    let mut pos = 0;
+
+    let read_byte = |pos: &mut usize| -> Result<u8> {
+        let byte = *input
+            .get(*pos)
+            .context("unexpected end of compressed data")?;
+        *pos += 1;
+        Ok(byte)
+    };
+
+    loop {
+        let token = read_byte(&mut pos)?;
+
+        let mut literal_len = (token >> 4) as usize;
+        if literal_len == 0xf {
+            loop {
+                let byte = read_byte(&mut pos)?;
+                literal_len += byte as usize;
+                if byte != 0xff {
+                    break;
+                }
+            }
+        }
+        ensure!(
+            pos + literal_len <= input.len(),
+            "unexpected end of compressed data"
+        );
+        output.extend_from_slice(&input[pos..pos + literal_len]);
+        pos += literal_len;
+
+        // The last sequence only contains literals.
+        if pos == input.len() {
+            break;
+        }
+
+        let offset = read_byte(&mut pos)? as usize | (read_byte(&mut pos)? as usize) << 8;
+        ensure!(
+            offset != 0 && offset <= output.len(),
+            "invalid match offset {:#x} at {:#x}",
+            offset,
+            pos - 2
+        );
+
+        let mut match_len = (token & 0xf) as usize + 4;
+        if token & 0xf == 0xf {
+            loop {
+                let byte = read_byte(&mut pos)?;
+                match_len += byte as usize;
+                if byte != 0xff {
+                    break;
+                }
+            }
+        }
+        ensure!(
+            output.len() + match_len <= size,
+            "decompressed data is larger than expected"
+        );
+
+        // Matches may overlap with the bytes they produce, so copy byte by byte.
+        let start = output.len() - offset;
+        for i in 0..match_len {
+            let byte = output[start + i];
+            output.push(byte);
+        }
+    }
+
+    ensure!(
+        output.len() == size,
+        "decompressed data is smaller than expected ({:#x} bytes instead of {:#x})",
+        output.len(),
+        size
+    );
+    Ok(output)
+}
+
+/// Returns the contents of a segment, decompressing it if necessary.
+///
+/// `compressed_flag` is the flag that indicates whether the segment is compressed
+/// (e.g. `FLAG_TEXT_COMPRESSED`).
+pub fn get_segment_data(
+    nso_bytes: &[u8],
+    header: &NsoHeader,
+    segment: &SegmentHeader,
+    compressed_flag: u32,
+) -> Result<Vec<u8>> {
+    let start = segment.file_offset as usize;
+    let file_size = if header.flags & compressed_flag != 0 {
+        segment.file_size
+    } else {
+        segment.size
+    } as usize;
+    if start + file_size > nso_bytes.len() {
+        bail!(
+            "segment at file offset {:#x} (size {:#x}) is out of bounds",
+            start,
+            file_size
+        );
+    }
+
+    let bytes = &nso_bytes[start..start + file_size];
+    if header.flags & compressed_flag != 0 {
+        decompress_lz4_block(bytes, segment.size as usize)
+            .with_context(|| format!("failed to decompress segment at file offset {:#x}", start))
+    } else {
+        Ok(bytes.to_vec())
+    }
+}
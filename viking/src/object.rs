@@ -0,0 +1,126 @@
+use crate::elf::{self, OwnedElf};
+use anyhow::{anyhow, ensure, Context, Result};
+use goblin::elf::header::ET_REL;
+use goblin::elf::sym;
+use std::path::Path;
+
+/// A relocation that applies to a function in an object file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ObjectRelocation {
+    /// Offset from the start of the function.
+    pub offset: u64,
+    pub r_type: u32,
+    /// Name of the target symbol. Since the object file is not linked, this is only
+    /// a placeholder for an address.
+    pub symbol: String,
+    pub addend: i64,
+}
+
+/// A function in a relocatable object file.
+pub struct ObjectFunction<'a> {
+    pub name: &'a str,
+    /// Name of the section that contains the function (e.g. `.text._ZN4ksys3fooEv`
+    /// with -ffunction-sections).
+    pub section_name: &'a str,
+    /// Offset of the function in its section.
+    pub offset: u64,
+    /// The bytes that make up the code for this function, with relocations left unapplied.
+    pub code: &'a [u8],
+    /// Sorted by offset.
+    pub relocations: Vec<ObjectRelocation>,
+}
+
+pub fn load_object(path: &Path) -> Result<OwnedElf> {
+    let elf = elf::load_elf(path).with_context(|| format!("failed to load {:?}", path))?;
+    ensure!(
+        elf.header.e_type == ET_REL,
+        "{:?} is not a relocatable object file",
+        path
+    );
+    Ok(elf)
+}
+
+/// Finds a function in an object file, along with the relocations that apply to it.
+pub fn get_object_function<'a>(object: &'a OwnedElf, name: &str) -> Result<ObjectFunction<'a>> {
+    let names = elf::get_all_symbol_names(object)?;
+    let (symbol, name) = object
+        .syms
+        .iter()
+        .zip(names.iter())
+        .find(|(symbol, symbol_name)| {
+            symbol.st_type() == sym::STT_FUNC && symbol.st_shndx != 0 && **symbol_name == name
+        })
+        .map(|(symbol, name)| (symbol, *name))
+        .ok_or_else(|| anyhow!("unknown function: {}", name))?;
+
+    // With -ffunction-sections, every function is in a section of its own.
+    let section_index = symbol.st_shndx;
+    let section = object
+        .section_headers
+        .get(section_index)
+        .ok_or_else(|| anyhow!("invalid section index for {}", name))?;
+
+    let start = (section.sh_offset + symbol.st_value) as usize;
+    let end = start + symbol.st_size as usize;
+    let code = object.as_owner().1.get(start..end).ok_or_else(|| {
+        anyhow!(
+            "{} ({:#x} bytes at {:#x} in {}) is out of bounds",
+            name,
+            symbol.st_size,
+            symbol.st_value,
+            elf::get_section_name(object, section)
+        )
+    })?;
+
+    let function_range = symbol.st_value..symbol.st_value + symbol.st_size;
+    let mut relocations = Vec::new();
+    for reloc in elf::get_section_relocations(object, section_index)? {
+        if !function_range.contains(&reloc.r_offset) {
+            continue;
+        }
+
+        let target = object
+            .syms
+            .get(reloc.r_sym)
+            .ok_or_else(|| anyhow!("invalid symbol index in relocation"))?;
+        let mut addend = reloc.r_addend.unwrap_or(0);
+        let symbol_name = if target.st_type() == sym::STT_SECTION {
+            // Refer to functions by name rather than by section + offset where possible.
+            let function = object.syms.iter().zip(names.iter()).find(|(symbol, _)| {
+                symbol.st_type() == sym::STT_FUNC
+                    && symbol.st_shndx == target.st_shndx
+                    && symbol.st_value as i64 == addend
+            });
+            match function {
+                Some((_, function_name)) => {
+                    addend = 0;
+                    function_name.to_string()
+                }
+                None => object
+                    .section_headers
+                    .get(target.st_shndx)
+                    .map(|shdr| elf::get_section_name(object, shdr))
+                    .unwrap_or("")
+                    .to_string(),
+            }
+        } else {
+            names[reloc.r_sym].to_string()
+        };
+
+        relocations.push(ObjectRelocation {
+            offset: reloc.r_offset - symbol.st_value,
+            r_type: reloc.r_type,
+            symbol: symbol_name,
+            addend,
+        });
+    }
+    relocations.sort_by_key(|reloc| reloc.offset);
+
+    Ok(ObjectFunction {
+        name,
+        section_name: elf::get_section_name(object, section),
+        offset: symbol.st_value,
+        code,
+        relocations,
+    })
+}
@@ -0,0 +1,170 @@
+use crate::asm::{Normalizer, NormalizerOptions, Token};
+use crate::elf;
+use crate::repo;
+use anyhow::Result;
+use lazy_static::lazy_static;
+use regex::Regex;
+use rustc_hash::FxHashMap;
+
+const DEFAULT_OUTLINED_FUNCTION_REGEX: &str = r"^OUTLINED_FUNCTION_\d+$";
+
+lazy_static! {
+    static ref OUTLINED_FUNCTION_REGEX: Regex = Regex::new(
+        repo::CONFIG
+            .get("outlined_function_regex")
+            .and_then(toml::Value::as_str)
+            .unwrap_or(DEFAULT_OUTLINED_FUNCTION_REGEX)
+    )
+    .expect("Failed to parse \"outlined_function_regex\" from config TOML");
+
+    /// Outlined functions call and reference other code, so only their instructions
+    /// are compared (not their targets).
+    static ref NORMALIZER: Normalizer = Normalizer::new(NormalizerOptions {
+        mask_pc_relative: true,
+        mask_absolute_addresses: true,
+        ..Default::default()
+    });
+}
+
+/// Returns whether a function was generated by the machine outliner (`OUTLINED_FUNCTION_123`).
+/// The pattern can be changed with `outlined_function_regex` in the config.
+///
+/// Outlined functions are numbered in the order in which they are generated,
+/// so names change from build to build and must not be used to match functions.
+pub fn is_outlined_function(name: &str) -> bool {
+    OUTLINED_FUNCTION_REGEX.is_match(name)
+}
+
+/// Outlined functions of an executable, indexed by content.
+pub struct OutlinedFunctionIndex<'a> {
+    by_content: FxHashMap<Vec<Token>, (&'a str, elf::Function<'a>)>,
+}
+
+impl<'a> OutlinedFunctionIndex<'a> {
+    pub fn build(elf: &'a elf::OwnedElf, symbols: &elf::SymbolTableByName<'a>) -> Result<Self> {
+        let mut functions = Vec::new();
+        for (name, symbol) in symbols {
+            if symbol.st_size == 0 || !is_outlined_function(name) {
+                continue;
+            }
+            functions.push((
+                *name,
+                elf::get_function(elf, symbol.st_value, symbol.st_size)?,
+            ));
+        }
+        Ok(Self::from_functions(functions))
+    }
+
+    /// Same as `build`, with outlined functions that have already been read from an executable.
+    pub fn from_functions(
+        functions: impl IntoIterator<Item = (&'a str, elf::Function<'a>)>,
+    ) -> Self {
+        let mut by_content = FxHashMap::default();
+        for (name, function) in functions {
+            // Functions with identical contents are interchangeable, so keeping any one is fine.
+            by_content
+                .entry(NORMALIZER.normalize(function.code))
+                .or_insert((name, function));
+        }
+        Self { by_content }
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_content.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_content.is_empty()
+    }
+
+    /// Returns the name and code of an outlined function whose instructions are the same
+    /// as `code`, ignoring branch targets and referenced addresses.
+    pub fn find(&self, code: &[u8]) -> Option<(&'a str, &elf::Function<'a>)> {
+        self.by_content
+            .get(&NORMALIZER.normalize(code))
+            .map(|(name, function)| (*name, function))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    fn encode(insns: &[u32]) -> Vec<u8> {
+        insns.iter().flat_map(|insn| insn.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn outlined_functions_are_detected() {
+        testing::use_test_config();
+        assert!(is_outlined_function("OUTLINED_FUNCTION_0"));
+        assert!(is_outlined_function("OUTLINED_FUNCTION_1234"));
+        assert!(!is_outlined_function("OUTLINED_FUNCTION_"));
+        assert!(!is_outlined_function("_Z16OUTLINED_FUNCTION_1v"));
+        assert!(!is_outlined_function("OUTLINED_FUNCTION_12_copy"));
+    }
+
+    #[test]
+    fn renumbered_functions_are_found_by_content() {
+        // adrp x8, #0x1000; add x8, x8, #0x10; ldr x0, [x8, #0x18]; b #0x20
+        let orig_load = encode(&[0xb0000008, 0x91004108, 0xf9400d00, 0x14000008]);
+        // str w1, [sp, #8]; bl #-4
+        let orig_call = encode(&[0xb9000be1, 0x97ffffff]);
+        let orig = [
+            ("OUTLINED_FUNCTION_1", &orig_load),
+            ("OUTLINED_FUNCTION_2", &orig_call),
+        ];
+
+        // The same functions in another build: renumbered, at other addresses
+        // and with other branch targets.
+        let decomp_load = encode(&[0x90000008, 0x91004108, 0xf9400d00, 0x14000004]);
+        let decomp_call = encode(&[0xb9000be1, 0x94000010]);
+        // ldr x1, [x0, #8]
+        let decomp_other = encode(&[0xf9400401]);
+        let index = OutlinedFunctionIndex::from_functions(vec![
+            (
+                "OUTLINED_FUNCTION_5",
+                elf::Function {
+                    addr: 0x2000,
+                    code: &decomp_call,
+                },
+            ),
+            (
+                "OUTLINED_FUNCTION_6",
+                elf::Function {
+                    addr: 0x2008,
+                    code: &decomp_load,
+                },
+            ),
+            (
+                "OUTLINED_FUNCTION_7",
+                elf::Function {
+                    addr: 0x2018,
+                    code: &decomp_other,
+                },
+            ),
+        ]);
+        assert_eq!(index.len(), 3);
+
+        let found: Vec<(&str, &str, u64)> = orig
+            .iter()
+            .map(|(name, code)| {
+                let (decomp_name, function) = index.find(code).unwrap();
+                (*name, decomp_name, function.addr)
+            })
+            .collect();
+        assert_eq!(
+            found,
+            [
+                ("OUTLINED_FUNCTION_1", "OUTLINED_FUNCTION_6", 0x2008),
+                ("OUTLINED_FUNCTION_2", "OUTLINED_FUNCTION_5", 0x2000),
+            ]
+        );
+
+        // Other registers or offsets are different code.
+        // ldr x0, [x8, #0x8]
+        let load_other_offset = encode(&[0xb0000008, 0x91004108, 0xf9400500, 0x14000008]);
+        assert!(index.find(&load_other_offset).is_none());
+    }
+}
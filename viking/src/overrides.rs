@@ -0,0 +1,511 @@
+use crate::functions::Info;
+use crate::lint::Issue;
+use crate::repo;
+use crate::sources::{self, SourceMapping};
+use anyhow::{bail, Context, Result};
+use rustc_hash::{FxHashMap, FxHashSet};
+use std::collections::BTreeMap;
+use std::path::{Component, Path, PathBuf};
+
+/// Compiler flags that must be added to or removed from the default flags
+/// for a function to match.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FlagSet {
+    /// Flags that must be passed. A flag that consists of several arguments
+    /// (e.g. `-mllvm -inline-threshold=300`) is written as a single string.
+    pub add: Vec<String>,
+    /// Flags that must not be passed.
+    pub remove: Vec<String>,
+}
+
+impl FlagSet {
+    pub fn is_empty(&self) -> bool {
+        self.add.is_empty() && self.remove.is_empty()
+    }
+
+    fn extend(&mut self, other: &FlagSet) {
+        self.add.extend(other.add.iter().cloned());
+        self.remove.extend(other.remove.iter().cloned());
+    }
+
+    fn from_value(value: &toml::Value, key: &str) -> Result<Self> {
+        let get = |field: &str| -> Result<Vec<String>> {
+            match value.get(field) {
+                None => Ok(Vec::new()),
+                Some(flags) => flags
+                    .as_array()
+                    .with_context(|| format!("{}.{} must be an array", key, field))?
+                    .iter()
+                    .map(|flag| {
+                        flag.as_str()
+                            .map(str::to_string)
+                            .with_context(|| format!("{}.{} must only contain strings", key, field))
+                    })
+                    .collect(),
+            }
+        };
+        Ok(Self {
+            add: get("add")?,
+            remove: get("remove")?,
+        })
+    }
+
+    /// Returns the flags that are not applied in `args` (the arguments of a compile command).
+    pub fn get_unapplied(&self, args: &[String]) -> FlagSet {
+        FlagSet {
+            add: self
+                .add
+                .iter()
+                .filter(|flag| !contains_flag(args, flag))
+                .cloned()
+                .collect(),
+            remove: self
+                .remove
+                .iter()
+                .filter(|flag| contains_flag(args, flag))
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+impl std::fmt::Display for FlagSet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut parts = Vec::new();
+        if !self.add.is_empty() {
+            parts.push(format!("requires {}", self.add.join(", ")));
+        }
+        if !self.remove.is_empty() {
+            parts.push(format!("must not use {}", self.remove.join(", ")));
+        }
+        write!(f, "{}", parts.join("; "))
+    }
+}
+
+/// Returns whether the arguments of a compile command contain `flag`, which may consist
+/// of several arguments separated by spaces.
+fn contains_flag(args: &[String], flag: &str) -> bool {
+    let flag: Vec<&str> = flag.split_whitespace().collect();
+    !flag.is_empty()
+        && args
+            .windows(flag.len())
+            .any(|window| window.iter().zip(&flag).all(|(arg, part)| arg == part))
+}
+
+/// Per-file and per-function compiler flag overrides, for functions that only match with
+/// flags that differ from the project defaults.
+///
+/// Flag override files are TOML files with a table for source files (relative to the repo root)
+/// and a table for functions (by mangled name). The flags of a function are those of its source
+/// file (see `sources::SourceMapping`) plus its own:
+///
+/// ```toml
+/// [files."src/KingSystem/ActorSystem/actBaseProc.cpp"]
+/// add = ["-mllvm -inline-threshold=300"]
+///
+/// [functions."_ZN4ksys3act8BaseProc6updateEv"]
+/// remove = ["-fno-omit-frame-pointer"]
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct FlagOverrides {
+    files: BTreeMap<PathBuf, FlagSet>,
+    functions: BTreeMap<String, FlagSet>,
+    /// Used to find the source file of functions.
+    source_mapping: Option<SourceMapping>,
+}
+
+impl FlagOverrides {
+    pub fn parse(contents: &str) -> Result<Self> {
+        let value: toml::Value = toml::from_str(contents)?;
+        let get_table = |key: &str| match value.get(key) {
+            None => Ok(None),
+            Some(table) => table
+                .as_table()
+                .map(Some)
+                .with_context(|| format!("{} must be a table", key)),
+        };
+
+        let mut overrides = Self::default();
+        if let Some(files) = get_table("files")? {
+            for (path, flags) in files {
+                let flags = FlagSet::from_value(flags, &format!("files.{:?}", path))?;
+                overrides
+                    .files
+                    .insert(normalize_path(Path::new(path)), flags);
+            }
+        }
+        if let Some(functions) = get_table("functions")? {
+            for (name, flags) in functions {
+                let flags = FlagSet::from_value(flags, &format!("functions.{:?}", name))?;
+                overrides.functions.insert(name.clone(), flags);
+            }
+        }
+        Ok(overrides)
+    }
+
+    pub fn load_from_path(path: &Path) -> Result<Self> {
+        let contents =
+            std::fs::read_to_string(path).with_context(|| format!("failed to read {:?}", path))?;
+        Self::parse(&contents).with_context(|| format!("failed to parse {:?}", path))
+    }
+
+    /// Loads the project's overrides from the file at `flag_overrides` (relative to the repo
+    /// root) in the config, together with the source mapping (see
+    /// `sources::get_source_mapping_path`). There are no overrides if that key is not set.
+    pub fn load() -> Result<Self> {
+        let path = match repo::CONFIG
+            .get("flag_overrides")
+            .and_then(toml::Value::as_str)
+        {
+            Some(path) => repo::get_repo_root()?.join(path),
+            None => return Ok(Self::default()),
+        };
+        let mut overrides = Self::load_from_path(&path)?;
+        if let Some(mapping_path) = sources::get_source_mapping_path()? {
+            overrides.source_mapping = Some(sources::load_source_mapping(&mapping_path)?);
+        }
+        Ok(overrides)
+    }
+
+    pub fn with_source_mapping(mut self, mapping: SourceMapping) -> Self {
+        self.source_mapping = Some(mapping);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty() && self.functions.is_empty()
+    }
+
+    /// Returns the source file of a function (relative to the repo root), if it is known.
+    pub fn get_source_file(&self, info: &Info) -> Option<PathBuf> {
+        self.source_mapping
+            .as_ref()?
+            .get_source_file(&info.name)
+            .map(normalize_path)
+    }
+
+    /// Returns the flag overrides of a function: those of its source file and its own.
+    pub fn for_function(&self, info: &Info) -> Option<FlagSet> {
+        let mut flags = FlagSet::default();
+        if let Some(file_flags) = self
+            .get_source_file(info)
+            .and_then(|path| self.files.get(&path))
+        {
+            flags.extend(file_flags);
+        }
+        if let Some(function_flags) = self.functions.get(&info.name) {
+            flags.extend(function_flags);
+        }
+        Some(flags).filter(|flags| !flags.is_empty())
+    }
+
+    /// Returns the overrides of a function that are not applied by its compile command,
+    /// if there are any. Functions whose source file has no compile command are skipped.
+    pub fn get_unapplied(&self, info: &Info, commands: &CompileCommands) -> Option<FlagSet> {
+        let flags = self.for_function(info)?;
+        let args = commands.get(&self.get_source_file(info)?)?;
+        Some(flags.get_unapplied(args)).filter(|flags| !flags.is_empty())
+    }
+}
+
+/// Lexically normalizes a path relative to the repo root (`./src/../src/a.cpp` is `src/a.cpp`).
+fn normalize_path(path: &Path) -> PathBuf {
+    let mut result = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir => {
+                result.pop();
+            }
+            _ => result.push(component),
+        }
+    }
+    result
+}
+
+/// Arguments of the compile command of every source file, from a compilation database
+/// (`compile_commands.json`).
+#[derive(Clone, Debug, Default)]
+pub struct CompileCommands {
+    /// Keys are relative to the repo root if the file is in the repo.
+    args: FxHashMap<PathBuf, Vec<String>>,
+}
+
+/// Splits a command line like a POSIX shell would (quotes and backslashes only).
+/// Fails if a quote is not closed.
+fn split_command_line(command: &str) -> Result<Vec<String>> {
+    let mut args = Vec::new();
+    let mut arg = String::new();
+    let mut in_arg = false;
+    let mut quote = None;
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('"'), '\\') | (None, '\\') => {
+                if let Some(escaped) = chars.next() {
+                    arg.push(escaped);
+                }
+                in_arg = true;
+            }
+            (Some(_), c) => arg.push(c),
+            (None, '"') | (None, '\'') => {
+                quote = Some(c);
+                in_arg = true;
+            }
+            (None, c) if c.is_whitespace() => {
+                if in_arg {
+                    args.push(std::mem::take(&mut arg));
+                    in_arg = false;
+                }
+            }
+            (None, c) => {
+                arg.push(c);
+                in_arg = true;
+            }
+        }
+    }
+    if let Some(q) = quote {
+        bail!("unterminated {} quote in command: {}", q, command);
+    }
+    if in_arg {
+        args.push(arg);
+    }
+    Ok(args)
+}
+
+impl CompileCommands {
+    /// Parses a compilation database. Relative paths are resolved against the `directory` of
+    /// each entry, and paths inside `repo_root` are made relative to it.
+    pub fn parse(json: &str, repo_root: &Path) -> Result<Self> {
+        let value: serde_json::Value = serde_json::from_str(json)?;
+        let entries = value
+            .as_array()
+            .context("compilation database must be an array")?;
+
+        let mut commands = Self::default();
+        for (i, entry) in entries.iter().enumerate() {
+            let get_str = |key: &str| entry.get(key).and_then(serde_json::Value::as_str);
+            let file = get_str("file").with_context(|| format!("entry {} has no file", i))?;
+            let directory = get_str("directory").unwrap_or_default();
+
+            let args = match (entry.get("arguments"), get_str("command")) {
+                (Some(serde_json::Value::Array(args)), _) => args
+                    .iter()
+                    .map(|arg| {
+                        arg.as_str()
+                            .map(str::to_string)
+                            .with_context(|| format!("entry {} has invalid arguments", i))
+                    })
+                    .collect::<Result<Vec<_>>>()?,
+                (_, Some(command)) => split_command_line(command)
+                    .with_context(|| format!("entry {} has an invalid command", i))?,
+                _ => bail!("entry {} has neither arguments nor a command", i),
+            };
+
+            let path = normalize_path(&Path::new(directory).join(file));
+            let path = match path.strip_prefix(normalize_path(repo_root)) {
+                Ok(relative) => relative.to_path_buf(),
+                Err(_) => path,
+            };
+            commands.args.insert(path, args);
+        }
+        Ok(commands)
+    }
+
+    /// Loads the project's compilation database from `compile_commands` (relative to the repo
+    /// root) in the config, or from `build/compile_commands.json` by default.
+    /// Returns None if the file does not exist, e.g. because the project has not been built.
+    pub fn load() -> Result<Option<Self>> {
+        let root = repo::get_repo_root()?;
+        let path = root.join(
+            repo::CONFIG
+                .get("compile_commands")
+                .and_then(toml::Value::as_str)
+                .unwrap_or("build/compile_commands.json"),
+        );
+        let json = match std::fs::read_to_string(&path) {
+            Ok(json) => json,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err).with_context(|| format!("failed to read {:?}", path)),
+        };
+        Self::parse(&json, &root)
+            .map(Some)
+            .with_context(|| format!("failed to parse {:?}", path))
+    }
+
+    /// Returns the arguments of the compile command for `path` (relative to the repo root).
+    pub fn get(&self, path: &Path) -> Option<&[String]> {
+        self.args.get(&normalize_path(path)).map(Vec::as_slice)
+    }
+}
+
+/// Reports source files and functions whose flag overrides are not applied by the build,
+/// as well as overrides that cannot be checked. Unapplied file overrides are only reported
+/// for the file, not for each function with its own overrides in the file.
+pub fn check_flag_overrides(
+    functions: &[Info],
+    overrides: &FlagOverrides,
+    commands: &CompileCommands,
+) -> Vec<Issue> {
+    let mut issues = Vec::new();
+    for (path, flags) in &overrides.files {
+        match commands.get(path) {
+            None => issues.push(Issue::warning(
+                None,
+                format!(
+                    "{} has compiler flag overrides but no compile command",
+                    path.display()
+                ),
+            )),
+            Some(args) => {
+                let unapplied = flags.get_unapplied(args);
+                if !unapplied.is_empty() {
+                    issues.push(Issue::error(
+                        None,
+                        format!(
+                            "{} is not built with its flag overrides: {}",
+                            path.display(),
+                            unapplied
+                        ),
+                    ));
+                }
+            }
+        }
+    }
+
+    let mut found = FxHashSet::default();
+    for info in functions {
+        let function_flags = match overrides.functions.get(&info.name) {
+            Some(flags) => flags,
+            None => continue,
+        };
+        found.insert(info.name.as_str());
+        let path = match overrides.get_source_file(info) {
+            Some(path) => path,
+            None => {
+                issues.push(Issue::warning(
+                    Some(info.addr),
+                    format!(
+                        "{} has compiler flag overrides but no source file",
+                        info.name
+                    ),
+                ));
+                continue;
+            }
+        };
+        let args = match commands.get(&path) {
+            Some(args) => args,
+            None => {
+                issues.push(Issue::warning(
+                    Some(info.addr),
+                    format!(
+                        "{} has compiler flag overrides but {} has no compile command",
+                        info.name,
+                        path.display()
+                    ),
+                ));
+                continue;
+            }
+        };
+        // The flags of the source file have been checked above.
+        let unapplied = function_flags.get_unapplied(args);
+        if !unapplied.is_empty() {
+            issues.push(Issue::error(
+                Some(info.addr),
+                format!(
+                    "{} is not built with its flag overrides: {}",
+                    info.name, unapplied
+                ),
+            ));
+        }
+    }
+
+    for name in overrides.functions.keys() {
+        if !found.contains(name.as_str()) {
+            issues.push(Issue::warning(
+                None,
+                format!("flag overrides for {} do not match any function", name),
+            ));
+        }
+    }
+    issues
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::Status;
+    use crate::lint::Severity;
+    use crate::testing::make_function;
+
+    #[test]
+    fn command_lines_are_split_like_a_shell() {
+        let split = |command| split_command_line(command).unwrap();
+        assert_eq!(split("  clang++ -c  a.cpp "), ["clang++", "-c", "a.cpp"]);
+        assert_eq!(
+            split(r#"clang -DNAME="a b" '-DX=\y' "q\"uote""#),
+            ["clang", "-DNAME=a b", "-DX=\\y", "q\"uote"]
+        );
+        assert_eq!(split(r"a\ b c\\d \'e"), ["a b", "c\\d", "'e"]);
+        assert_eq!(split(r#"a "" '' b"#), ["a", "", "", "b"]);
+        assert!(split("").is_empty());
+
+        assert!(split_command_line("clang -DNAME=\"a b").is_err());
+        assert!(split_command_line("clang 'a").is_err());
+        let err = CompileCommands::parse(
+            r#"[{"directory": "/repo", "file": "a.cpp", "command": "clang \"a"}]"#,
+            Path::new("/repo"),
+        )
+        .unwrap_err();
+        assert_eq!(err.to_string(), "entry 0 has an invalid command");
+    }
+
+    #[test]
+    fn file_overrides_are_reported_once() {
+        let overrides = FlagOverrides::parse(
+            r#"
+            [files."src/a.cpp"]
+            add = ["-O3"]
+
+            [functions."_Z1fv"]
+            add = ["-fno-inline"]
+
+            [functions."_Z1gv"]
+            add = ["-O2"]
+            "#,
+        )
+        .unwrap()
+        .with_source_mapping(SourceMapping::parse("[sources]\n\"*\" = \"src/a.cpp\"").unwrap());
+        let commands = CompileCommands::parse(
+            r#"[{"directory": "/repo", "file": "src/a.cpp", "command": "clang -O2 -c a.cpp"}]"#,
+            Path::new("/repo"),
+        )
+        .unwrap();
+        let functions = [
+            make_function(0x100, 0x10, "_Z1fv", Status::Matching),
+            make_function(0x110, 0x10, "_Z1gv", Status::Matching),
+        ];
+
+        let issues: Vec<(Severity, Option<u64>, String)> =
+            check_flag_overrides(&functions, &overrides, &commands)
+                .into_iter()
+                .map(|issue| (issue.severity, issue.addr, issue.message))
+                .collect();
+        assert_eq!(
+            issues,
+            [
+                (
+                    Severity::Error,
+                    None,
+                    "src/a.cpp is not built with its flag overrides: requires -O3".to_string()
+                ),
+                (
+                    Severity::Error,
+                    Some(0x100),
+                    "_Z1fv is not built with its flag overrides: requires -fno-inline".to_string()
+                ),
+            ]
+        );
+    }
+}
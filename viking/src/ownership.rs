@@ -0,0 +1,277 @@
+use crate::functions::{self, Info};
+#[cfg(feature = "git")]
+use crate::git;
+use crate::metadata::{self, Metadata};
+use crate::report::FunctionsDiff;
+use crate::sources::{self, SourceMapping};
+use crate::ui;
+use anyhow::Result;
+use rayon::prelude::*;
+use rustc_hash::{FxHashMap, FxHashSet};
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+/// Author name that git blame uses for lines that have not been committed.
+#[cfg(feature = "git")]
+const UNCOMMITTED_AUTHOR: &str = "Not Committed Yet";
+
+/// How an owner was found, from least to most reliable.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Confidence {
+    /// Nobody could be found.
+    Unknown,
+    /// The author of most lines of the source file, because the definition could not be found.
+    Low,
+    /// The author of most lines of the definition of the function, according to git blame.
+    Medium,
+    /// The owner in the metadata sidecar.
+    High,
+}
+
+impl Confidence {
+    pub fn description(&self) -> &'static str {
+        match &self {
+            Confidence::Unknown => "unknown",
+            Confidence::Low => "low",
+            Confidence::Medium => "medium",
+            Confidence::High => "high",
+        }
+    }
+}
+
+/// The person who is most likely responsible for a function.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Owner {
+    /// None if the owner is unknown.
+    pub name: Option<String>,
+    pub confidence: Confidence,
+}
+
+impl Owner {
+    pub fn unknown() -> Self {
+        Self {
+            name: None,
+            confidence: Confidence::Unknown,
+        }
+    }
+
+    fn new(name: String, confidence: Confidence) -> Self {
+        Self {
+            name: Some(name),
+            confidence,
+        }
+    }
+
+    pub fn is_known(&self) -> bool {
+        self.name.is_some()
+    }
+}
+
+impl std::fmt::Display for Owner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "{} ({})", name, self.confidence.description()),
+            None => write!(f, "unknown"),
+        }
+    }
+}
+
+/// Same as `resolve_with`, with the project's metadata sidecar and source mapping.
+/// Files that cannot be loaded are reported as warnings and treated as empty.
+pub fn resolve(functions: &[&Info], repo_root: &Path) -> Vec<(Info, Owner)> {
+    let metadata = metadata::load().unwrap_or_else(|err| {
+        ui::print_warning(&format!("failed to load function metadata: {:#}", err));
+        Metadata::default()
+    });
+    let mapping = sources::get_source_mapping_path()
+        .and_then(|path| {
+            path.map(|path| sources::load_source_mapping(&path))
+                .transpose()
+        })
+        .unwrap_or_else(|err| {
+            ui::print_warning(&format!("failed to load the source mapping: {:#}", err));
+            None
+        });
+    resolve_with(functions, repo_root, &metadata, mapping.as_ref())
+}
+
+/// Returns a best-guess owner for each function (in the same order): the owner in the
+/// metadata sidecar, or else the main author of the function according to git blame of its
+/// source file (see `sources::SourceMapping`; paths are relative to `repo_root`).
+///
+/// git is run once per source file. Functions whose owner cannot be found (e.g. because
+/// git blame failed) have an unknown owner.
+pub fn resolve_with(
+    functions: &[&Info],
+    repo_root: &Path,
+    metadata: &Metadata,
+    mapping: Option<&SourceMapping>,
+) -> Vec<(Info, Owner)> {
+    let mut owners: Vec<Option<Owner>> = functions
+        .iter()
+        .map(|info| {
+            let owner = metadata.get(&info.name)?.owner()?;
+            Some(Owner::new(owner.to_string(), Confidence::High))
+        })
+        .collect();
+
+    let mut by_file: BTreeMap<PathBuf, Vec<usize>> = BTreeMap::new();
+    if let Some(mapping) = mapping {
+        for (i, info) in functions.iter().enumerate() {
+            if owners[i].is_some() {
+                continue;
+            }
+            if let Some(path) = mapping.get_source_file(&info.name) {
+                by_file.entry(path.to_path_buf()).or_default().push(i);
+            }
+        }
+    }
+
+    let blamed: Vec<(usize, Owner)> = by_file
+        .par_iter()
+        .flat_map_iter(|(path, indices)| {
+            let file = BlamedFile::load(&repo_root.join(path))
+                .map_err(|err| {
+                    ui::print_warning(&format!("failed to blame {:?}: {:#}", path, err));
+                })
+                .ok();
+            indices
+                .iter()
+                .map(|&i| {
+                    let owner = file.as_ref().map(|file| file.get_owner(functions[i]));
+                    (i, owner.unwrap_or_else(Owner::unknown))
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    for (i, owner) in blamed {
+        owners[i] = Some(owner);
+    }
+
+    functions
+        .iter()
+        .zip(owners)
+        .map(|(info, owner)| ((*info).clone(), owner.unwrap_or_else(Owner::unknown)))
+        .collect()
+}
+
+/// Resolves the owners of the functions that regressed in `diff` (see `resolve`), by address.
+/// `functions` is the new version of the function list.
+pub fn resolve_regressions(
+    diff: &FunctionsDiff,
+    functions: &[Info],
+    repo_root: &Path,
+) -> FxHashMap<u64, Owner> {
+    let regressed: FxHashSet<u64> = diff.regressions().map(|change| change.addr).collect();
+    let regressed_functions: Vec<&Info> = functions
+        .iter()
+        .filter(|info| regressed.contains(&info.addr))
+        .collect();
+    resolve(&regressed_functions, repo_root)
+        .into_iter()
+        .map(|(info, owner)| (info.addr, owner))
+        .collect()
+}
+
+/// A source file together with the author of each line.
+struct BlamedFile {
+    source: String,
+    /// Indexed by line number - 1. None for lines that have not been committed.
+    authors: Vec<Option<String>>,
+}
+
+impl BlamedFile {
+    fn load(path: &Path) -> Result<Self> {
+        Ok(Self {
+            source: std::fs::read_to_string(path)?,
+            authors: get_line_authors(path)?,
+        })
+    }
+
+    fn get_owner(&self, info: &Info) -> Owner {
+        let demangled = functions::demangle_str(&info.name).unwrap_or_else(|_| info.name.clone());
+        if let Some(range) = find_definition(&self.source, &demangled) {
+            let authors = self.authors.get(range).unwrap_or_default();
+            if let Some(author) = get_main_author(authors) {
+                return Owner::new(author, Confidence::Medium);
+            }
+        }
+        match get_main_author(&self.authors) {
+            Some(author) => Owner::new(author, Confidence::Low),
+            None => Owner::unknown(),
+        }
+    }
+}
+
+#[cfg(feature = "git")]
+fn get_line_authors(path: &Path) -> Result<Vec<Option<String>>> {
+    let blames = git::get_file_blame(path)?;
+    let mut authors = vec![None; blames.keys().max().copied().unwrap_or(0)];
+    for (line, blame) in blames {
+        if line != 0 && blame.author != UNCOMMITTED_AUTHOR {
+            authors[line - 1] = Some(blame.author);
+        }
+    }
+    Ok(authors)
+}
+
+/// Without git support, owners can only come from the metadata sidecar.
+#[cfg(not(feature = "git"))]
+fn get_line_authors(_path: &Path) -> Result<Vec<Option<String>>> {
+    Ok(Vec::new())
+}
+
+/// Returns the author of the most lines (ties are broken by name).
+fn get_main_author(authors: &[Option<String>]) -> Option<String> {
+    let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+    for author in authors.iter().flatten() {
+        *counts.entry(author).or_default() += 1;
+    }
+    counts
+        .into_iter()
+        .max_by(|(a, a_count), (b, b_count)| a_count.cmp(b_count).then(b.cmp(a)))
+        .map(|(author, _)| author.to_string())
+}
+
+/// Returns whether `line` contains `needle` at the start of an identifier.
+fn contains_name(line: &str, needle: &str) -> bool {
+    line.match_indices(needle).any(|(i, _)| {
+        !line[..i]
+            .chars()
+            .next_back()
+            .map(|c| c.is_alphanumeric() || c == '_')
+            .unwrap_or(false)
+    })
+}
+
+/// Returns the lines (0-based) of the definition of a function in a source file.
+///
+/// This is a heuristic that relies on the usual formatting of definitions: the definition
+/// starts at the first unindented line that names the function (as qualified as possible)
+/// followed by a parameter list and does not end with `;`, and ends at the next line that
+/// starts with `}`.
+fn find_definition(source: &str, demangled: &str) -> Option<Range<usize>> {
+    let components = functions::split_qualified_name(demangled);
+    let lines: Vec<&str> = source.lines().collect();
+    for num_components in (1..=components.len()).rev() {
+        let needle = format!(
+            "{}(",
+            components[components.len() - num_components..].join("::")
+        );
+        let start = lines.iter().position(|line| {
+            !line.starts_with(char::is_whitespace)
+                && !line.trim_end().ends_with(';')
+                && contains_name(line, &needle)
+        });
+        if let Some(start) = start {
+            let len = lines[start..]
+                .iter()
+                .position(|line| line.starts_with('}'))
+                .map(|i| i + 1)
+                .unwrap_or(1);
+            return Some(start..start + len);
+        }
+    }
+    None
+}
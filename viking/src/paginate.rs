@@ -0,0 +1,139 @@
+/// Limits for text renderings of large result sets. `None` means that there is no limit.
+///
+/// Budgets only apply to text: JSON renderings always contain every item.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RenderBudget {
+    pub max_rows: Option<usize>,
+    /// Maximum length of the rendered rows in bytes (including line breaks, but not
+    /// the line that says how many rows were left out).
+    pub max_bytes: Option<usize>,
+}
+
+impl RenderBudget {
+    pub fn unlimited() -> Self {
+        Self::default()
+    }
+
+    pub fn rows(max_rows: usize) -> Self {
+        Self {
+            max_rows: Some(max_rows),
+            max_bytes: None,
+        }
+    }
+
+    /// Returns the budget for a `--limit` argument, where 0 means that everything is shown.
+    pub fn from_limit(limit: usize) -> Self {
+        match limit {
+            0 => Self::unlimited(),
+            _ => Self::rows(limit),
+        }
+    }
+}
+
+/// Returns the line that ends a truncated rendering.
+pub(crate) fn format_omitted(count: usize) -> String {
+    format!("…and {} more (use --limit 0 to show all)", count)
+}
+
+/// Rows that were rendered within a budget.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RenderedPage {
+    pub rows: Vec<String>,
+    /// Number of items that were left out.
+    pub omitted: usize,
+}
+
+impl RenderedPage {
+    /// Returns the line to write after the rows (see `format_omitted`), if items were left out.
+    pub fn omitted_line(&self) -> Option<String> {
+        match self.omitted {
+            0 => None,
+            omitted => Some(format_omitted(omitted)),
+        }
+    }
+
+    /// Writes one line per row, followed by the omitted line.
+    pub fn write_to(&self, out: &mut String) {
+        for row in self.rows.iter().chain(self.omitted_line().as_ref()) {
+            out.push_str(row);
+            out.push('\n');
+        }
+    }
+}
+
+/// Items of a result set, highest priority first, so that truncated renderings show
+/// the most important items (e.g. errors before warnings).
+#[derive(Clone, Debug, Default)]
+pub struct Paginated<T> {
+    items: Vec<T>,
+}
+
+impl<T> Paginated<T> {
+    /// Keeps the order of `items`.
+    pub fn new(items: Vec<T>) -> Self {
+        Self { items }
+    }
+
+    /// Sorts `items` by `key`, smallest first. Items with the same key keep their order.
+    pub fn sorted_by_key<K: Ord>(mut items: Vec<T>, key: impl FnMut(&T) -> K) -> Self {
+        items.sort_by_key(key);
+        Self { items }
+    }
+
+    pub fn items(&self) -> &[T] {
+        &self.items
+    }
+
+    pub fn into_items(self) -> Vec<T> {
+        self.items
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Renders one row per item in order until the budget is exhausted.
+    /// A row that would go over `max_bytes` is left out with every item after it.
+    pub fn render_rows(
+        &self,
+        budget: &RenderBudget,
+        mut render_item: impl FnMut(&T) -> String,
+    ) -> RenderedPage {
+        let mut rows = Vec::new();
+        let mut num_bytes = 0;
+        for item in &self.items {
+            if matches!(budget.max_rows, Some(max_rows) if rows.len() >= max_rows) {
+                break;
+            }
+            let row = render_item(item);
+            if matches!(budget.max_bytes, Some(max_bytes) if num_bytes + row.len() + 1 > max_bytes)
+            {
+                break;
+            }
+            num_bytes += row.len() + 1;
+            rows.push(row);
+        }
+
+        RenderedPage {
+            omitted: self.items.len() - rows.len(),
+            rows,
+        }
+    }
+
+    /// Same as `render_rows`, but returns the rendered text (see `RenderedPage::write_to`).
+    pub fn render(&self, budget: &RenderBudget, render_item: impl FnMut(&T) -> String) -> String {
+        let mut out = String::new();
+        self.render_rows(budget, render_item).write_to(&mut out);
+        out
+    }
+}
+
+impl<T> From<Vec<T>> for Paginated<T> {
+    fn from(items: Vec<T>) -> Self {
+        Self::new(items)
+    }
+}
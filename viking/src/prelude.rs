@@ -0,0 +1,22 @@
+// Everything that is re-exported here is covered by the semver guarantees in the crate docs.
+// Think twice before removing anything or changing a signature.
+
+pub use crate::function_source::{CsvFile, FunctionSource, MemorySource, ProjectFunctions};
+pub use crate::functions::{
+    demangle_str, find_function_fuzzy, format_addr, get_functions,
+    get_functions_for_multiple_paths, get_functions_for_path, get_functions_for_reader,
+    make_known_function_map, parse_address, parse_address_or_offset, write_functions,
+    write_functions_atomic, write_functions_to_path, write_functions_to_writer, DemangledIndex,
+    Info, MergeWarnings, Status, WriteOptions, ADDRESS_BASE,
+};
+pub use crate::merge::{
+    merge_functions, MergeConflict, MergeConflicts, MergeResolver, MergeResult, Resolution,
+};
+pub use crate::report::FunctionsDiff;
+pub use crate::search::{
+    get_functions_with_pattern, get_functions_with_pattern_ex, PatternMatchOptions, SizeIndex,
+};
+pub use crate::stats::{
+    calculate_matching_rate_change, calculate_matching_rate_change_for_functions, compute_stats,
+    Counts, Stats, StatsDelta,
+};
@@ -0,0 +1,194 @@
+use crate::functions::{Info, Status};
+use crate::stats::{self, Counts, Stats};
+use anyhow::{bail, ensure, Context, Result};
+use chrono::{DateTime, Utc};
+use rustc_hash::FxHashSet;
+use std::path::Path;
+use std::process::Command;
+
+/// Version of the JSON format that is written by `Progress::to_json`.
+const PROGRESS_FORMAT_VERSION: u64 = 1;
+
+/// State of the decompilation at a point in time, e.g. for storing as a CI artifact
+/// and comparing with the artifact of a previous run.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Progress {
+    pub timestamp: DateTime<Utc>,
+    /// Commit hash of HEAD when the snapshot was taken, if git is available.
+    pub commit: Option<String>,
+    pub stats: Stats,
+    /// Addresses of the matching functions, sorted.
+    pub matching: Vec<u64>,
+}
+
+/// Change between two snapshots. See `Progress::compare_with`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ProgressComparison {
+    pub matched_bytes_delta: i64,
+    /// Number of functions that are no longer matching.
+    pub regressions: usize,
+    /// Number of functions that are now matching.
+    pub improvements: usize,
+}
+
+/// Returns the commit hash of HEAD in the current directory, if it is in a git repo.
+fn get_head_commit() -> Option<String> {
+    let output = Command::new("git")
+        .arg("rev-parse")
+        .arg("HEAD")
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8(output.stdout).ok()?.trim().to_string())
+}
+
+fn get_stats_categories(stats: &Stats) -> [(&'static str, &Counts); 8] {
+    [
+        ("total", &stats.total),
+        ("matching", &stats.matching),
+        ("non_matching_minor", &stats.non_matching_minor),
+        ("non_matching_major", &stats.non_matching_major),
+        ("wip", &stats.wip),
+        ("not_decompiled", &stats.not_decompiled),
+        ("library", &stats.library),
+        ("blocked", &stats.blocked),
+    ]
+}
+
+fn parse_counts(value: &serde_json::Value, name: &str) -> Result<Counts> {
+    let get = |key: &str| {
+        value
+            .get(key)
+            .and_then(serde_json::Value::as_u64)
+            .with_context(|| format!("stats.{}.{} must be an unsigned integer", name, key))
+    };
+    Ok(Counts {
+        functions: get("functions")? as usize,
+        bytes: get("bytes")?,
+    })
+}
+
+impl Progress {
+    /// Captures the stats of `functions`, the current time and the current commit.
+    pub fn snapshot(functions: &[Info]) -> Progress {
+        let mut matching: Vec<u64> = functions
+            .iter()
+            .filter(|info| info.status == Status::Matching)
+            .map(|info| info.addr)
+            .collect();
+        matching.sort_unstable();
+
+        Progress {
+            timestamp: Utc::now(),
+            commit: get_head_commit(),
+            stats: stats::compute_stats(functions),
+            matching,
+        }
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        let stats: serde_json::Map<String, serde_json::Value> = get_stats_categories(&self.stats)
+            .iter()
+            .map(|(name, counts)| {
+                (
+                    name.to_string(),
+                    serde_json::json!({
+                        "functions": counts.functions,
+                        "bytes": counts.bytes,
+                    }),
+                )
+            })
+            .collect();
+
+        Ok(serde_json::to_string(&serde_json::json!({
+            "version": PROGRESS_FORMAT_VERSION,
+            "timestamp": self.timestamp.to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+            "commit": self.commit,
+            "stats": stats,
+            "matching": self.matching,
+        }))?)
+    }
+
+    pub fn from_json(s: &str) -> Result<Progress> {
+        let value: serde_json::Value = serde_json::from_str(s)?;
+
+        let version = value.get("version").and_then(serde_json::Value::as_u64);
+        ensure!(
+            version == Some(PROGRESS_FORMAT_VERSION),
+            "unsupported progress format version: {:?}",
+            version
+        );
+
+        let timestamp = value
+            .get("timestamp")
+            .and_then(serde_json::Value::as_str)
+            .context("missing timestamp")?;
+        let timestamp = DateTime::parse_from_rfc3339(timestamp)
+            .with_context(|| format!("invalid timestamp: {}", timestamp))?
+            .with_timezone(&Utc);
+
+        let commit = match value.get("commit") {
+            None | Some(serde_json::Value::Null) => None,
+            Some(commit) => Some(
+                commit
+                    .as_str()
+                    .context("commit must be a string")?
+                    .to_string(),
+            ),
+        };
+
+        let stats_value = value.get("stats").context("missing stats")?;
+        let mut stats = Stats::default();
+        let get = |name: &str| match stats_value.get(name) {
+            Some(counts) => parse_counts(counts, name),
+            // Snapshots from before a category was added do not have it.
+            None => Ok(Counts::default()),
+        };
+        stats.total = get("total")?;
+        stats.matching = get("matching")?;
+        stats.non_matching_minor = get("non_matching_minor")?;
+        stats.non_matching_major = get("non_matching_major")?;
+        stats.wip = get("wip")?;
+        stats.not_decompiled = get("not_decompiled")?;
+        stats.library = get("library")?;
+        stats.blocked = get("blocked")?;
+
+        let matching = match value.get("matching") {
+            Some(serde_json::Value::Array(addrs)) => addrs
+                .iter()
+                .map(|addr| addr.as_u64().context("invalid matching function address"))
+                .collect::<Result<Vec<u64>>>()?,
+            _ => bail!("missing matching function addresses"),
+        };
+
+        Ok(Progress {
+            timestamp,
+            commit,
+            stats,
+            matching,
+        })
+    }
+
+    /// Returns the change from `other` (e.g. the snapshot of the previous CI run) to `self`.
+    ///
+    /// Snapshots only record which functions are matching, so a matching function that was
+    /// removed from the function list counts as a regression.
+    pub fn compare_with(&self, other: &Progress) -> ProgressComparison {
+        let current: FxHashSet<u64> = self.matching.iter().copied().collect();
+        let previous: FxHashSet<u64> = other.matching.iter().copied().collect();
+        ProgressComparison {
+            matched_bytes_delta: self.stats.matching.bytes as i64
+                - other.stats.matching.bytes as i64,
+            regressions: previous.difference(&current).count(),
+            improvements: current.difference(&previous).count(),
+        }
+    }
+}
+
+/// Writes a snapshot of `functions` (see `Progress::snapshot`) to `path` as JSON.
+pub fn write_progress_artifact(path: &Path, functions: &[Info]) -> Result<()> {
+    std::fs::write(path, Progress::snapshot(functions).to_json()?)
+        .with_context(|| format!("failed to write {:?}", path))
+}
@@ -0,0 +1,174 @@
+use crate::analysis::{self, CallGraph};
+use crate::claims::{self, ClaimFilter};
+use crate::functions::{Info, Status};
+use crate::ignore::IgnoreSet;
+use crate::metadata;
+use crate::repo;
+use anyhow::Result;
+use rayon::prelude::*;
+use rustc_hash::{FxHashMap, FxHashSet};
+
+/// Functions of this size get half of the maximum size score.
+const SIZE_SCORE_HALF_SIZE: f64 = 0x80 as f64;
+
+/// How much each criterion contributes to the score of a recommendation.
+/// Every criterion is scored between 0 and 1 before it is weighted.
+#[derive(Clone, Debug)]
+pub struct RecommendWeights {
+    /// Smaller functions score higher.
+    pub size: f64,
+    /// Functions that call fewer other functions score higher (leaf functions score 1).
+    /// Only used if a call graph is available.
+    pub callees: f64,
+    /// Functions in a class or namespace in which more functions are already matching
+    /// score higher.
+    pub neighbors: f64,
+}
+
+impl Default for RecommendWeights {
+    fn default() -> Self {
+        Self {
+            size: 1.0,
+            callees: 1.0,
+            neighbors: 1.0,
+        }
+    }
+}
+
+impl RecommendWeights {
+    /// Returns the weights from the `[recommend]` table of the config
+    /// (`size_weight`, `callee_weight` and `neighbor_weight`). Missing weights are 1.
+    pub fn from_config() -> Self {
+        let get = |key: &str| {
+            repo::CONFIG
+                .get("recommend")
+                .and_then(|table| table.get(key))
+                .and_then(toml::Value::as_float)
+                .unwrap_or(1.0)
+        };
+        Self {
+            size: get("size_weight"),
+            callees: get("callee_weight"),
+            neighbors: get("neighbor_weight"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Recommendation {
+    pub info: Info,
+    pub score: f64,
+    /// Why the function is recommended, e.g. "small (0x20 bytes), leaf function".
+    pub reason: String,
+}
+
+/// Matching and total function counts of a class or namespace.
+#[derive(Clone, Copy, Debug, Default)]
+struct ClassCounts {
+    matching: usize,
+    total: usize,
+}
+
+/// Same as `first_functions_ex`, with the weights from the config. Functions that are claimed
+/// (see `claims`) or ignored (see `ignore`) are left out.
+pub fn first_functions(
+    functions: &[Info],
+    call_graph: Option<&CallGraph>,
+    n: usize,
+) -> Result<Vec<Recommendation>> {
+    let metadata = metadata::load()?;
+    let claimed: FxHashSet<u64> = claims::list_in(functions, &metadata, &ClaimFilter::default())
+        .into_iter()
+        .map(|claim| claim.info.addr)
+        .collect();
+    let ignore_set = IgnoreSet::load()?;
+
+    Ok(first_functions_ex(
+        functions,
+        call_graph,
+        n,
+        &RecommendWeights::from_config(),
+        &|info| claimed.contains(&info.addr) || ignore_set.is_ignored(info),
+    ))
+}
+
+/// Recommends up to `n` functions that have not been decompiled yet for new contributors,
+/// best first: small functions that call few other functions, in classes or namespaces
+/// that already have many matching functions. Functions for which `is_excluded` returns true
+/// are not recommended.
+pub fn first_functions_ex(
+    functions: &[Info],
+    call_graph: Option<&CallGraph>,
+    n: usize,
+    weights: &RecommendWeights,
+    is_excluded: &(dyn Fn(&Info) -> bool + Sync),
+) -> Vec<Recommendation> {
+    let class_names: Vec<String> = functions.par_iter().map(analysis::get_class_name).collect();
+    let mut class_counts: FxHashMap<&str, ClassCounts> = FxHashMap::default();
+    for (info, class_name) in functions.iter().zip(&class_names) {
+        let counts = class_counts.entry(class_name).or_default();
+        counts.total += 1;
+        if info.status == Status::Matching {
+            counts.matching += 1;
+        }
+    }
+
+    let mut recommendations: Vec<Recommendation> = functions
+        .par_iter()
+        .zip(class_names.par_iter())
+        .filter(|(info, _)| {
+            info.status == Status::NotDecompiled
+                && info.size != 0
+                && !info.name.is_empty()
+                && !is_excluded(info)
+        })
+        .map(|(info, class_name)| {
+            let mut score = 0.0;
+            let mut reasons = Vec::new();
+
+            let size_score = SIZE_SCORE_HALF_SIZE / (SIZE_SCORE_HALF_SIZE + info.size as f64);
+            score += weights.size * size_score;
+            reasons.push(format!("{:#x} bytes", info.size));
+
+            if let Some(call_graph) = call_graph {
+                let callees = call_graph.callees(info.addr).len();
+                score += weights.callees / (1 + callees) as f64;
+                reasons.push(match callees {
+                    0 => "leaf function".to_string(),
+                    1 => "calls 1 function".to_string(),
+                    _ => format!("calls {} functions", callees),
+                });
+            }
+
+            // Grouping every function without a scope together says nothing about neighbors.
+            if class_name != analysis::GLOBAL_CLASS_NAME {
+                let counts = class_counts[class_name.as_str()];
+                // The function itself is not matching, so there is at least one other function
+                // in the class if anything is matching.
+                if counts.matching != 0 {
+                    score += weights.neighbors * counts.matching as f64 / counts.total as f64;
+                    reasons.push(format!(
+                        "{}/{} functions in {} are matching",
+                        counts.matching, counts.total, class_name
+                    ));
+                }
+            }
+
+            Recommendation {
+                info: info.clone(),
+                score,
+                reason: reasons.join(", "),
+            }
+        })
+        .collect();
+
+    // Ties are broken by address so that the recommendations are stable.
+    recommendations.sort_by(|a, b| {
+        b.score
+            .partial_cmp(&a.score)
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then_with(|| a.info.addr.cmp(&b.info.addr))
+    });
+    recommendations.truncate(n);
+    recommendations
+}
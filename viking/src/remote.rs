@@ -0,0 +1,393 @@
+use crate::file_utils;
+use crate::functions::{self, Info};
+use crate::repo;
+use crate::stable_hash;
+use anyhow::{Context, Result};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Returned (wrapped in an `anyhow::Error`) when a function list could not be downloaded,
+/// as opposed to being downloaded but failing to parse.
+#[derive(Debug)]
+pub enum RemoteError {
+    /// The server could not be reached (DNS, connection or TLS failure, timeout...).
+    Network { url: String, message: String },
+    /// The server returned an error status.
+    Http { url: String, status: u16 },
+    /// The function list is larger than `RemoteOptions::max_size`.
+    TooLarge { url: String, max_size: u64 },
+}
+
+impl std::fmt::Display for RemoteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RemoteError::Network { url, message } => {
+                write!(f, "failed to fetch {}: {}", url, message)
+            }
+            RemoteError::Http { url, status } => {
+                write!(f, "failed to fetch {}: HTTP status {}", url, status)
+            }
+            RemoteError::TooLarge { url, max_size } => {
+                write!(f, "{} is larger than {} bytes", url, max_size)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RemoteError {}
+
+#[derive(Clone, Debug)]
+pub struct RemoteOptions {
+    /// Where to keep the last downloaded list for each URL. If None, nothing is cached
+    /// and the list is downloaded every time. Git ignores the directory.
+    ///
+    /// Defaults to `.viking/remote-cache` in the repo, or None outside of a repo.
+    pub cache_dir: Option<PathBuf>,
+    /// Maximum size of the function list in bytes.
+    pub max_size: u64,
+    pub timeout: Duration,
+}
+
+impl Default for RemoteOptions {
+    fn default() -> Self {
+        Self {
+            cache_dir: repo::get_repo_root()
+                .ok()
+                .map(|root| file_utils::get_local_dir(&root).join("remote-cache")),
+            max_size: 64 * 1024 * 1024,
+            timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Validators for conditional requests, stored next to a cached list.
+#[derive(Debug, Default)]
+struct CacheMetadata {
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+impl CacheMetadata {
+    fn load(path: &Path) -> Self {
+        let mut metadata = Self::default();
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(_) => return metadata,
+        };
+        for line in contents.lines() {
+            if let Some(value) = line.strip_prefix("etag=") {
+                metadata.etag = Some(value.to_string());
+            } else if let Some(value) = line.strip_prefix("last_modified=") {
+                metadata.last_modified = Some(value.to_string());
+            }
+        }
+        metadata
+    }
+
+    fn serialize(&self) -> String {
+        let mut contents = String::new();
+        if let Some(etag) = &self.etag {
+            contents.push_str(&format!("etag={}\n", etag));
+        }
+        if let Some(last_modified) = &self.last_modified {
+            contents.push_str(&format!("last_modified={}\n", last_modified));
+        }
+        contents
+    }
+}
+
+/// Returns the paths of the cached list and its metadata for `url`.
+fn get_cache_paths(cache_dir: &Path, url: &str) -> (PathBuf, PathBuf) {
+    let hash = stable_hash::hash(url.as_bytes());
+    (
+        cache_dir.join(format!("{:016x}.csv", hash)),
+        cache_dir.join(format!("{:016x}.meta", hash)),
+    )
+}
+
+fn parse_functions(bytes: &[u8], url: &str) -> Result<Vec<Info>> {
+    functions::get_functions_for_reader(&mut &bytes[..])
+        .with_context(|| format!("failed to parse function list from {}", url))
+}
+
+/// Reader that keeps a copy of everything that is read from the response (so that it can be
+/// cached) and enforces the size limit.
+///
+/// Read errors are recorded so that they can be reported as `RemoteError`s rather than as
+/// parse errors.
+struct ResponseReader<'a, R> {
+    inner: R,
+    url: &'a str,
+    max_size: u64,
+    bytes: Vec<u8>,
+    error: Option<RemoteError>,
+}
+
+impl<R: Read> Read for ResponseReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = match self.inner.read(buf) {
+            Ok(n) => n,
+            Err(err) => {
+                self.error = Some(RemoteError::Network {
+                    url: self.url.to_string(),
+                    message: err.to_string(),
+                });
+                return Err(err);
+            }
+        };
+        self.bytes.extend_from_slice(&buf[..n]);
+        if self.bytes.len() as u64 > self.max_size {
+            self.error = Some(RemoteError::TooLarge {
+                url: self.url.to_string(),
+                max_size: self.max_size,
+            });
+            return Err(std::io::Error::other("response is too large"));
+        }
+        Ok(n)
+    }
+}
+
+/// Sends a GET request, conditional if `metadata` is set.
+fn send_request(
+    agent: &ureq::Agent,
+    url: &str,
+    metadata: Option<&CacheMetadata>,
+) -> Result<ureq::Response> {
+    let mut request = agent.get(url);
+    if let Some(metadata) = metadata {
+        if let Some(etag) = &metadata.etag {
+            request = request.set("If-None-Match", etag);
+        }
+        if let Some(last_modified) = &metadata.last_modified {
+            request = request.set("If-Modified-Since", last_modified);
+        }
+    }
+
+    match request.call() {
+        Ok(response) => Ok(response),
+        Err(ureq::Error::Status(status, _)) => Err(RemoteError::Http {
+            url: url.to_string(),
+            status,
+        }
+        .into()),
+        Err(ureq::Error::Transport(err)) => Err(RemoteError::Network {
+            url: url.to_string(),
+            message: err.to_string(),
+        }
+        .into()),
+    }
+}
+
+/// Same as `get_functions_from_url_ex`, with default options.
+pub fn get_functions_from_url(url: &str) -> Result<Vec<Info>> {
+    get_functions_from_url_ex(url, &RemoteOptions::default())
+}
+
+/// Downloads a function list (in the same format as `functions::get_functions_for_reader`)
+/// and parses it as it is received.
+///
+/// If a cache directory is set, the list is only downloaded again if the server reports that
+/// it has changed (using `ETag` and `Last-Modified`). Otherwise the cached copy is parsed.
+/// If the cached copy cannot be read or parsed, it is downloaded again unconditionally.
+///
+/// Download failures are reported as `RemoteError`s; parse errors are not.
+pub fn get_functions_from_url_ex(url: &str, options: &RemoteOptions) -> Result<Vec<Info>> {
+    let cache_paths = options
+        .cache_dir
+        .as_deref()
+        .map(|cache_dir| get_cache_paths(cache_dir, url));
+    let cached_metadata = match &cache_paths {
+        Some((data_path, meta_path)) if data_path.is_file() => Some(CacheMetadata::load(meta_path)),
+        _ => None,
+    };
+
+    let agent = ureq::AgentBuilder::new().timeout(options.timeout).build();
+    let mut response = send_request(&agent, url, cached_metadata.as_ref())?;
+
+    if response.status() == 304 {
+        let cached = cache_paths
+            .as_ref()
+            .and_then(|(data_path, _)| std::fs::read(data_path).ok())
+            .and_then(|bytes| parse_functions(&bytes, url).ok());
+        if let Some(functions) = cached {
+            return Ok(functions);
+        }
+        // The cached copy is gone or unusable, so "not modified" is no help.
+        response = send_request(&agent, url, None)?;
+        if response.status() == 304 {
+            return Err(RemoteError::Http {
+                url: url.to_string(),
+                status: 304,
+            }
+            .into());
+        }
+    }
+
+    let metadata = CacheMetadata {
+        etag: response.header("ETag").map(str::to_string),
+        last_modified: response.header("Last-Modified").map(str::to_string),
+    };
+
+    let mut reader = ResponseReader {
+        inner: response.into_reader(),
+        url,
+        max_size: options.max_size,
+        bytes: Vec::new(),
+        error: None,
+    };
+    let result = functions::get_functions_for_reader(&mut reader);
+    if let Some(err) = reader.error {
+        return Err(err.into());
+    }
+    let functions =
+        result.with_context(|| format!("failed to parse function list from {}", url))?;
+
+    // Only cache lists that could be parsed.
+    if let (Some((data_path, meta_path)), Some(cache_dir)) = (&cache_paths, &options.cache_dir) {
+        file_utils::create_untracked_dir(cache_dir)?;
+        file_utils::write_atomic(data_path, &reader.bytes)?;
+        file_utils::write_atomic(meta_path, metadata.serialize().as_bytes())?;
+    }
+
+    Ok(functions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    const FUNCTIONS: &str = "Address,Quality,Size,Name\n\
+        0x0000007100000010,O,000016,_ZN4ksys3act8BaseProc4initEv\n\
+        0x0000007100000020,U,000032,_ZN4ksys3act8BaseProc4calcEv\n";
+
+    /// Serves one canned response per connection, in order, and returns the URL together with
+    /// a handle that yields the headers of each request.
+    fn serve(responses: Vec<String>) -> (String, std::thread::JoinHandle<Vec<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/functions.csv", listener.local_addr().unwrap());
+        let handle = std::thread::spawn(move || {
+            let mut requests = Vec::new();
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut headers = Vec::new();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    let line = line.trim_end();
+                    if line.is_empty() {
+                        break;
+                    }
+                    headers.push(line.to_string());
+                }
+                requests.push(headers);
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+            requests
+        });
+        (url, handle)
+    }
+
+    fn ok(body: &str, etag: &str) -> String {
+        format!(
+            "HTTP/1.1 200 OK\r\nETag: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            etag,
+            body.len(),
+            body
+        )
+    }
+
+    fn status(status: &str) -> String {
+        format!(
+            "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            status
+        )
+    }
+
+    fn has_header(headers: &[String], name: &str) -> bool {
+        headers
+            .iter()
+            .any(|header| header.to_ascii_lowercase().starts_with(name))
+    }
+
+    fn options(dir: &TempDir) -> RemoteOptions {
+        RemoteOptions {
+            cache_dir: Some(dir.join("cache")),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn unchanged_lists_are_read_from_the_cache() {
+        let dir = TempDir::new("remote_cached");
+        let (url, server) = serve(vec![ok(FUNCTIONS, "\"v1\""), status("304 Not Modified")]);
+
+        let downloaded = get_functions_from_url_ex(&url, &options(&dir)).unwrap();
+        let cached = get_functions_from_url_ex(&url, &options(&dir)).unwrap();
+        assert_eq!(downloaded.len(), 2);
+        assert_eq!(cached, downloaded);
+        assert!(dir.join("cache").join(".gitignore").is_file());
+
+        let requests = server.join().unwrap();
+        assert!(!has_header(&requests[0], "if-none-match"));
+        assert!(requests[1].contains(&"If-None-Match: \"v1\"".to_string()));
+    }
+
+    #[test]
+    fn not_modified_without_a_usable_cached_copy_is_a_miss() {
+        let dir = TempDir::new("remote_refetch");
+        let (url, server) = serve(vec![
+            ok(FUNCTIONS, "\"v1\""),
+            status("304 Not Modified"),
+            ok(FUNCTIONS, "\"v1\""),
+        ]);
+
+        get_functions_from_url_ex(&url, &options(&dir)).unwrap();
+        let (data_path, _) = get_cache_paths(&dir.join("cache"), &url);
+        std::fs::write(&data_path, "not a function list").unwrap();
+
+        let functions = get_functions_from_url_ex(&url, &options(&dir)).unwrap();
+        assert_eq!(functions.len(), 2);
+        assert_eq!(std::fs::read_to_string(&data_path).unwrap(), FUNCTIONS);
+
+        let requests = server.join().unwrap();
+        assert!(has_header(&requests[1], "if-none-match"));
+        assert!(!has_header(&requests[2], "if-none-match"));
+    }
+
+    #[test]
+    fn download_errors_are_distinguishable_from_parse_errors() {
+        let dir = TempDir::new("remote_errors");
+        let (url, server) = serve(vec![
+            status("404 Not Found"),
+            ok(FUNCTIONS, "\"v1\""),
+            ok("0x0000007100000010,O,000016\n", "\"v2\""),
+        ]);
+
+        let err = get_functions_from_url_ex(&url, &options(&dir)).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RemoteError>(),
+            Some(RemoteError::Http { status: 404, .. })
+        ));
+
+        let small = RemoteOptions {
+            max_size: 16,
+            ..options(&dir)
+        };
+        let err = get_functions_from_url_ex(&url, &small).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RemoteError>(),
+            Some(RemoteError::TooLarge { max_size: 16, .. })
+        ));
+
+        let err = get_functions_from_url_ex(&url, &options(&dir)).unwrap_err();
+        assert!(err.downcast_ref::<RemoteError>().is_none());
+        assert!(!get_cache_paths(&dir.join("cache"), &url).0.exists());
+
+        server.join().unwrap();
+    }
+}
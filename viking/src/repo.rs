@@ -5,6 +5,10 @@ use std::path::PathBuf;
 
 lazy_static! {
     pub static ref CONFIG: toml::Value = {
+        #[cfg(any(test, feature = "test-util"))]
+        if crate::testing::is_using_test_config() {
+            return crate::testing::TEST_CONFIG.parse().expect("invalid test config");
+        }
         let toml_path = get_repo_root().expect("Failed to get repo root").join("tools/config.toml");
         let toml = std::fs::read_to_string(toml_path.as_path()).expect("Failed to read config file").parse::<toml::Value>().expect("Failed to read TOML from config file");
         toml
@@ -12,6 +16,11 @@ lazy_static! {
 }
 
 pub fn get_repo_root() -> Result<PathBuf> {
+    #[cfg(any(test, feature = "test-util"))]
+    if crate::testing::is_using_test_config() {
+        bail!("the test config does not belong to a repo -- pass paths explicitly");
+    }
+
     let current_dir = std::env::current_dir()?;
     let mut dir = current_dir.as_path();
 
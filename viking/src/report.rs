@@ -0,0 +1,418 @@
+use crate::functions::{self, AddressDiff, Info};
+use crate::history::{self, StatusChange};
+use crate::outlined;
+use crate::ownership::Owner;
+use crate::paginate::{Paginated, RenderBudget};
+use crate::stats::Stats;
+use rustc_hash::FxHashMap;
+use std::cmp::Reverse;
+use std::fmt::Write;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rename {
+    pub addr: u64,
+    pub old_name: String,
+    pub new_name: String,
+}
+
+/// Differences between two versions of a function list. Functions are matched by address
+/// (see `functions::diff_by_address`). All lists are sorted by address.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct FunctionsDiff {
+    /// See `history::get_status_changes`. These changes have sizes but no reason.
+    pub status_changes: Vec<StatusChange>,
+    pub renames: Vec<Rename>,
+    pub added: Vec<Info>,
+    pub removed: Vec<Info>,
+}
+
+impl FunctionsDiff {
+    pub fn compute(old: &[Info], new: &[Info]) -> Self {
+        let mut diff = FunctionsDiff {
+            status_changes: history::get_status_changes(old, new, None),
+            ..FunctionsDiff::default()
+        };
+        for entry in functions::diff_by_address(old, new) {
+            match entry {
+                AddressDiff::Added(info) => diff.added.push(info.clone()),
+                AddressDiff::Removed(info) => diff.removed.push(info.clone()),
+                AddressDiff::Kept { old, new } => {
+                    // Outlined functions are renumbered by every build, so these renames
+                    // are noise.
+                    let is_outlined_rename = outlined::is_outlined_function(&old.name)
+                        && outlined::is_outlined_function(&new.name);
+                    if old.name != new.name && !is_outlined_rename {
+                        diff.renames.push(Rename {
+                            addr: new.addr,
+                            old_name: old.name.clone(),
+                            new_name: new.name.clone(),
+                        });
+                    }
+                }
+            }
+        }
+        diff
+    }
+
+    pub fn promotions(&self) -> impl Iterator<Item = &StatusChange> {
+        self.status_changes
+            .iter()
+            .filter(|change| change.is_promotion())
+    }
+
+    pub fn regressions(&self) -> impl Iterator<Item = &StatusChange> {
+        self.status_changes
+            .iter()
+            .filter(|change| change.is_regression())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.status_changes.is_empty()
+            && self.renames.is_empty()
+            && self.added.is_empty()
+            && self.removed.is_empty()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PrCommentOptions {
+    /// Maximum number of rows (and bytes) per table. Further rows are summarised
+    /// as "…and N more". The most important rows (e.g. the largest functions) are kept.
+    pub budget: RenderBudget,
+    /// Tables with more rows than this are put in a collapsed `<details>` section.
+    pub collapse_threshold: usize,
+    /// Owners of regressed functions by address (see `ownership::resolve_regressions`).
+    /// If set, the table of regressed functions has an Owner column.
+    pub owners: Option<FxHashMap<u64, Owner>>,
+}
+
+impl Default for PrCommentOptions {
+    fn default() -> Self {
+        Self {
+            budget: RenderBudget::rows(50),
+            collapse_threshold: 10,
+            owners: None,
+        }
+    }
+}
+
+/// Returns a name for display in markdown: demangled if possible, with table delimiters escaped.
+fn format_name(name: &str) -> String {
+    let name = functions::demangle_str(name).unwrap_or_else(|_| name.to_string());
+    format!("`{}`", name.replace('|', "\\|"))
+}
+
+fn format_percentage_delta(before: &Stats, after: &Stats) -> String {
+    let before_pct = before.matching_byte_fraction() * 100.0;
+    let after_pct = after.matching_byte_fraction() * 100.0;
+    format!(
+        "{:+.3}% matching ({:.3}% → {:.3}%)",
+        after_pct - before_pct,
+        before_pct,
+        after_pct
+    )
+}
+
+fn format_count_delta(before: usize, after: usize) -> String {
+    format!("{:+}", after as i64 - before as i64)
+}
+
+/// Largest functions first, so that truncated tables show the most significant changes.
+fn sort_by_size(changes: Vec<&StatusChange>) -> Paginated<&StatusChange> {
+    Paginated::sorted_by_key(changes, |change| Reverse(change.size))
+}
+
+/// Writes a table, collapsed if it is large and truncated to `options.budget`.
+fn write_table<T>(
+    out: &mut String,
+    title: &str,
+    header: &str,
+    items: &Paginated<T>,
+    render_row: impl FnMut(&T) -> String,
+    options: &PrCommentOptions,
+) {
+    if items.is_empty() {
+        return;
+    }
+
+    let collapse = items.len() > options.collapse_threshold;
+    if collapse {
+        writeln!(out, "<details>\n<summary>{}</summary>\n", title).unwrap();
+    } else {
+        writeln!(out, "#### {}\n", title).unwrap();
+    }
+
+    writeln!(out, "{}", header).unwrap();
+    let page = items.render_rows(&options.budget, render_row);
+    for row in &page.rows {
+        writeln!(out, "{}", row).unwrap();
+    }
+    // The blank line ends the table.
+    if let Some(line) = page.omitted_line() {
+        writeln!(out, "\n{}", line).unwrap();
+    }
+
+    if collapse {
+        writeln!(out, "\n</details>").unwrap();
+    }
+    writeln!(out).unwrap();
+}
+
+/// Same as `pr_comment`, with default options.
+pub fn pr_comment(diff: &FunctionsDiff, stats_before: &Stats, stats_after: &Stats) -> String {
+    pr_comment_ex(
+        diff,
+        stats_before,
+        stats_after,
+        &PrCommentOptions::default(),
+    )
+}
+
+/// Renders a markdown comment that summarises changes to the function list, for pull requests.
+/// The output only depends on the arguments.
+pub fn pr_comment_ex(
+    diff: &FunctionsDiff,
+    stats_before: &Stats,
+    stats_after: &Stats,
+    options: &PrCommentOptions,
+) -> String {
+    let mut out = String::new();
+    writeln!(out, "### Function list changes\n").unwrap();
+
+    if diff.is_empty() {
+        writeln!(out, "No changes to the function list.").unwrap();
+        return out;
+    }
+
+    writeln!(
+        out,
+        "**{}**, {} matching functions ({:+} bytes)\n",
+        format_percentage_delta(stats_before, stats_after),
+        format_count_delta(
+            stats_before.matching.functions,
+            stats_after.matching.functions
+        ),
+        stats_after.matching.bytes as i64 - stats_before.matching.bytes as i64
+    )
+    .unwrap();
+
+    let status_row = |change: &StatusChange| {
+        format!(
+            "| {} | {:#x} | {} → {} |",
+            format_name(&change.name),
+            change.size.unwrap_or_default(),
+            change.old_status.description(),
+            change.new_status.description()
+        )
+    };
+    let status_header = "| Function | Size | Status |\n|---|---:|---|";
+
+    let promotions = sort_by_size(diff.promotions().collect());
+    write_table(
+        &mut out,
+        &format!("Promoted functions ({})", promotions.len()),
+        status_header,
+        &promotions,
+        |change| status_row(change),
+        options,
+    );
+
+    let regressions = sort_by_size(diff.regressions().collect());
+    match &options.owners {
+        Some(owners) => write_table(
+            &mut out,
+            &format!("Regressed functions ({})", regressions.len()),
+            "| Function | Size | Status | Owner |\n|---|---:|---|---|",
+            &regressions,
+            |change| {
+                let owner = owners
+                    .get(&change.addr)
+                    .map(Owner::to_string)
+                    .unwrap_or_else(|| Owner::unknown().to_string());
+                format!("{} {} |", status_row(change), owner.replace('|', "\\|"))
+            },
+            options,
+        ),
+        None => write_table(
+            &mut out,
+            &format!("Regressed functions ({})", regressions.len()),
+            status_header,
+            &regressions,
+            |change| status_row(change),
+            options,
+        ),
+    }
+
+    let renames = Paginated::new(diff.renames.iter().collect());
+    write_table(
+        &mut out,
+        &format!("Renamed functions ({})", renames.len()),
+        "| Address | Old name | New name |\n|---|---|---|",
+        &renames,
+        |rename| {
+            format!(
+                "| {} | {} | {} |",
+                functions::format_addr(rename.addr),
+                format_name(&rename.old_name),
+                format_name(&rename.new_name)
+            )
+        },
+        options,
+    );
+
+    if !diff.added.is_empty() || !diff.removed.is_empty() {
+        writeln!(
+            out,
+            "{} functions added, {} removed",
+            diff.added.len(),
+            diff.removed.len()
+        )
+        .unwrap();
+    }
+
+    out.truncate(out.trim_end().len());
+    out.push('\n');
+    out
+}
+
+/// Renders a one-line summary of the changes, e.g. for a commit status description.
+pub fn summary_line(diff: &FunctionsDiff, stats_before: &Stats, stats_after: &Stats) -> String {
+    if diff.is_empty() {
+        return "no function list changes".to_string();
+    }
+
+    let mut parts = vec![format!(
+        "{:+.3}% matching",
+        (stats_after.matching_byte_fraction() - stats_before.matching_byte_fraction()) * 100.0
+    )];
+    let promotions = diff.promotions().count();
+    if promotions != 0 {
+        parts.push(format!("{} promoted", promotions));
+    }
+    let regressions = diff.regressions().count();
+    if regressions != 0 {
+        parts.push(format!("{} regressed", regressions));
+    }
+    if !diff.renames.is_empty() {
+        parts.push(format!("{} renamed", diff.renames.len()));
+    }
+    if !diff.added.is_empty() {
+        parts.push(format!("{} added", diff.added.len()));
+    }
+    if !diff.removed.is_empty() {
+        parts.push(format!("{} removed", diff.removed.len()));
+    }
+    parts.join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::Status;
+    use crate::ownership::Confidence;
+    use crate::stats::compute_stats;
+    use crate::testing::{self, make_function};
+
+    /// Returns a function list before and after a change that promotes, regresses,
+    /// renames, adds and removes functions.
+    fn make_lists() -> (Vec<Info>, Vec<Info>) {
+        testing::use_test_config();
+        let old = vec![
+            make_function(0x100, 0x40, "_ZN3Foo1aEv", Status::NotDecompiled),
+            make_function(0x140, 0x80, "_ZN3Foo1bEv", Status::Wip),
+            make_function(0x1c0, 0x20, "_ZN3Foo1cEv", Status::NotDecompiled),
+            make_function(0x1e0, 0x10, "_ZN3Foo1dEv", Status::Matching),
+            make_function(0x1f0, 0x10, "FUN_71000001f0", Status::NotDecompiled),
+            make_function(0x200, 0x8, "_ZN3Foo1eEv", Status::NotDecompiled),
+        ];
+        let new = vec![
+            make_function(0x100, 0x40, "_ZN3Foo1aEv", Status::Matching),
+            make_function(0x140, 0x80, "_ZN3Foo1bEv", Status::NonMatchingMinor),
+            make_function(0x1c0, 0x20, "_ZN3Foo1cEv", Status::Matching),
+            make_function(0x1e0, 0x10, "_ZN3Foo1dEv", Status::NonMatchingMajor),
+            make_function(0x1f0, 0x10, "_ZN3Foo3barERi", Status::NotDecompiled),
+            make_function(0x300, 0x8, "_ZN3Foo1fEv", Status::NotDecompiled),
+        ];
+        (old, new)
+    }
+
+    #[test]
+    fn outlined_renames_are_ignored() {
+        testing::use_test_config();
+        let old = vec![
+            make_function(0x100, 0x10, "OUTLINED_FUNCTION_1", Status::NotDecompiled),
+            make_function(0x110, 0x10, "OUTLINED_FUNCTION_2", Status::NotDecompiled),
+            make_function(0x120, 0x10, "OUTLINED_FUNCTION_3", Status::NotDecompiled),
+        ];
+        let new = vec![
+            make_function(0x100, 0x10, "OUTLINED_FUNCTION_7", Status::NotDecompiled),
+            make_function(0x110, 0x10, "OUTLINED_FUNCTION_8", Status::Matching),
+            make_function(0x120, 0x10, "_ZN3Foo1gEv", Status::NotDecompiled),
+        ];
+        let diff = FunctionsDiff::compute(&old, &new);
+        assert_eq!(
+            diff.renames,
+            [Rename {
+                addr: 0x120,
+                old_name: "OUTLINED_FUNCTION_3".to_string(),
+                new_name: "_ZN3Foo1gEv".to_string(),
+            }]
+        );
+        assert_eq!(diff.status_changes.len(), 1);
+        assert_eq!(diff.status_changes[0].name, "OUTLINED_FUNCTION_8");
+    }
+
+    #[test]
+    fn pr_comment_snapshot() {
+        let (old, new) = make_lists();
+        let diff = FunctionsDiff::compute(&old, &new);
+        let comment = pr_comment(&diff, &compute_stats(&old), &compute_stats(&new));
+        assert_eq!(comment, include_str!("../tests/snapshots/pr_comment.md"));
+    }
+
+    #[test]
+    fn pr_comment_truncated_snapshot() {
+        let (old, new) = make_lists();
+        let diff = FunctionsDiff::compute(&old, &new);
+        let mut owners = FxHashMap::default();
+        owners.insert(
+            0x1e0,
+            Owner {
+                name: Some("someone".to_string()),
+                confidence: Confidence::High,
+            },
+        );
+        let options = PrCommentOptions {
+            budget: RenderBudget::rows(2),
+            collapse_threshold: 2,
+            owners: Some(owners),
+        };
+        let comment = pr_comment_ex(&diff, &compute_stats(&old), &compute_stats(&new), &options);
+        assert_eq!(
+            comment,
+            include_str!("../tests/snapshots/pr_comment_truncated.md")
+        );
+    }
+
+    #[test]
+    fn summary_lines() {
+        let (old, new) = make_lists();
+        let diff = FunctionsDiff::compute(&old, &new);
+        let (before, after) = (compute_stats(&old), compute_stats(&new));
+        assert_eq!(
+            summary_line(&diff, &before, &after),
+            "+30.303% matching, 3 promoted, 1 regressed, 1 renamed, 1 added, 1 removed"
+        );
+
+        let empty = FunctionsDiff::compute(&old, &old);
+        assert_eq!(
+            summary_line(&empty, &before, &before),
+            "no function list changes"
+        );
+        assert_eq!(
+            pr_comment(&empty, &before, &before),
+            "### Function list changes\n\nNo changes to the function list.\n"
+        );
+    }
+}
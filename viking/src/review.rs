@@ -0,0 +1,136 @@
+use crate::functions::{self, CsvFormat, Info, ADDRESS_BASE};
+use anyhow::{Context, Result};
+use rustc_hash::FxHashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+
+const BASELINE_PREFIX: &str = "# baseline:";
+
+/// Returns the commit a baseline CSV was generated from, if it starts with a
+/// `# baseline: <sha>` comment.
+pub fn get_baseline_commit(baseline_path: &Path) -> Result<Option<String>> {
+    let mut first_line = String::new();
+    BufReader::new(std::fs::File::open(baseline_path)?).read_line(&mut first_line)?;
+    Ok(first_line
+        .strip_prefix(BASELINE_PREFIX)
+        .map(|sha| sha.trim().to_string())
+        .filter(|sha| !sha.is_empty()))
+}
+
+/// Writes a baseline CSV. If `commit` is specified, it is recorded in a comment on the first line.
+pub fn write_baseline(
+    baseline_path: &Path,
+    functions: &[Info],
+    commit: Option<&str>,
+) -> Result<()> {
+    let mut contents = Vec::new();
+    if let Some(commit) = commit {
+        writeln!(&mut contents, "{} {}", BASELINE_PREFIX, commit)?;
+    }
+    functions::write_functions_to_writer(&mut contents, functions)?;
+    std::fs::write(baseline_path, contents)?;
+    Ok(())
+}
+
+/// Reads a baseline CSV. Unlike function lists, baselines can contain comments
+/// (see `write_baseline`).
+fn read_baseline(baseline_path: &Path) -> Result<Vec<Info>> {
+    let mut builder = CsvFormat::Csv.make_reader_builder();
+    builder.comment(Some(b'#'));
+    let reader = builder
+        .from_path(baseline_path)
+        .with_context(|| format!("failed to open {:?}", baseline_path))?;
+    functions::parse_functions(reader, ADDRESS_BASE, 0)
+        .with_context(|| format!("failed to read {:?}", baseline_path))
+}
+
+/// Returns all functions whose status changed since the baseline, as well as functions
+/// that do not exist in the baseline.
+pub fn get_functions_needing_review<'a>(
+    current: &'a [Info],
+    baseline_path: &Path,
+) -> Result<Vec<&'a Info>> {
+    let baseline = read_baseline(baseline_path)?;
+    let baseline_by_addr: FxHashMap<u64, &Info> =
+        baseline.iter().map(|info| (info.addr, info)).collect();
+
+    Ok(current
+        .iter()
+        .filter(|info| match baseline_by_addr.get(&info.addr) {
+            Some(old_info) => old_info.status != info.status,
+            None => true,
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::Status;
+    use crate::testing::{make_function, TempDir};
+
+    #[test]
+    fn baseline_with_commit() {
+        let dir = TempDir::new("review_baseline");
+        let path = dir.join("baseline.csv");
+        let baseline = vec![
+            make_function(0x10, 0x10, "_Z1av", Status::NotDecompiled),
+            make_function(0x20, 0x10, "_Z1bv", Status::Matching),
+            make_function(0x30, 0x10, "_Z1cv", Status::Wip),
+        ];
+        write_baseline(&path, &baseline, Some("0123abcd")).unwrap();
+        assert_eq!(
+            get_baseline_commit(&path).unwrap().as_deref(),
+            Some("0123abcd")
+        );
+
+        let mut current = baseline.clone();
+        current[0].status = Status::Matching;
+        current[1].status = Status::NonMatchingMinor;
+        current.push(make_function(0x40, 0x10, "_Z1dv", Status::NotDecompiled));
+        let names: Vec<&str> = get_functions_needing_review(&current, &path)
+            .unwrap()
+            .iter()
+            .map(|info| info.name.as_str())
+            .collect();
+        assert_eq!(names, ["_Z1av", "_Z1bv", "_Z1dv"]);
+    }
+
+    #[test]
+    fn baseline_without_commit() {
+        let dir = TempDir::new("review_no_commit");
+        let path = dir.join("baseline.csv");
+        let baseline = vec![make_function(0x10, 0x10, "_Z1av", Status::Matching)];
+        write_baseline(&path, &baseline, None).unwrap();
+        assert_eq!(get_baseline_commit(&path).unwrap(), None);
+        assert!(get_functions_needing_review(&baseline, &path)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn errors_count_the_comment_line() {
+        let dir = TempDir::new("review_lines");
+        let path = dir.join("baseline.csv");
+        std::fs::write(
+            &path,
+            "# baseline: 0123abcd\nAddress,Quality,Size,Name\n\
+             0x0000007100000010,O,000016,_Z1av\n0x0000007100000020,X,000016,_Z1bv\n",
+        )
+        .unwrap();
+        let err = get_functions_needing_review(&[], &path).unwrap_err();
+        assert!(format!("{:#}", err).contains("at line 4"), "{:#}", err);
+    }
+
+    #[test]
+    fn function_lists_do_not_skip_comments() {
+        let dir = TempDir::new("review_list_comments");
+        let path = dir.join("functions.csv");
+        std::fs::write(
+            &path,
+            "Address,Quality,Size,Name\n#commented out\n0x0000007100000010,O,000016,_Z1av\n",
+        )
+        .unwrap();
+        assert!(functions::get_functions_for_path(&path).is_err());
+    }
+}
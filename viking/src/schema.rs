@@ -0,0 +1,235 @@
+use crate::functions::{Info, CSV_HEADER};
+use crate::repo;
+use anyhow::{bail, ensure, Context, Result};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ColumnType {
+    String,
+    Int,
+    /// One of the listed values.
+    Enum(Vec<String>),
+}
+
+/// An extra column of the function list.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ColumnSpec {
+    pub name: String,
+    pub column_type: ColumnType,
+}
+
+/// Value of an extra column (see `Info::extra`).
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Value {
+    String(String),
+    Int(i64),
+    Enum(String),
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::String(value) | Value::Enum(value) => write!(f, "{}", value),
+            Value::Int(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+impl ColumnSpec {
+    /// Parses a field. Empty fields have no value.
+    pub fn parse(&self, field: &str) -> Result<Option<Value>> {
+        if field.is_empty() {
+            return Ok(None);
+        }
+        let value = match &self.column_type {
+            ColumnType::String => Value::String(field.to_string()),
+            ColumnType::Int => Value::Int(
+                field
+                    .parse()
+                    .with_context(|| format!("invalid integer for {}: {}", self.name, field))?,
+            ),
+            ColumnType::Enum(values) => {
+                ensure!(
+                    values.iter().any(|value| value == field),
+                    "invalid value for {}: {} (expected one of {:?})",
+                    self.name,
+                    field,
+                    values
+                );
+                Value::Enum(field.to_string())
+            }
+        };
+        Ok(Some(value))
+    }
+
+    /// Checks that a value has the type of this column and can be written to a function list.
+    pub fn validate(&self, value: &Value) -> Result<()> {
+        match (&self.column_type, value) {
+            (ColumnType::String, Value::String(value)) => {
+                // Function lists are read without support for quoting.
+                ensure!(
+                    !value.contains(&[',', '\t', '"', '\n', '\r'][..]),
+                    "value for {} contains a delimiter, quote or line break: {:?}",
+                    self.name,
+                    value
+                );
+            }
+            (ColumnType::Int, Value::Int(_)) => (),
+            (ColumnType::Enum(_), Value::Enum(value)) => {
+                self.parse(value)?;
+            }
+            _ => bail!(
+                "wrong type for {}: expected {:?}, got {:?}",
+                self.name,
+                self.column_type,
+                value
+            ),
+        }
+        Ok(())
+    }
+}
+
+/// Columns of a function list: the four standard columns (see `CSV_HEADER`),
+/// followed by the extra columns.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CsvSchema {
+    pub extra_columns: Vec<ColumnSpec>,
+}
+
+fn parse_column_spec(value: &toml::Value) -> Result<ColumnSpec> {
+    let name = value
+        .get("name")
+        .and_then(toml::Value::as_str)
+        .context("missing column name")?;
+    ensure!(
+        !CSV_HEADER.contains(&name),
+        "{} is a standard column and cannot be redeclared",
+        name
+    );
+
+    let column_type = match value.get("type").and_then(toml::Value::as_str) {
+        Some("string") => ColumnType::String,
+        Some("int") => ColumnType::Int,
+        Some("enum") => {
+            let values = value
+                .get("values")
+                .and_then(toml::Value::as_array)
+                .with_context(|| format!("enum column {} has no values", name))?
+                .iter()
+                .map(|value| {
+                    value
+                        .as_str()
+                        .map(str::to_string)
+                        .with_context(|| format!("values of {} must be strings", name))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            ColumnType::Enum(values)
+        }
+        Some(other) => bail!("unknown type for column {}: {}", name, other),
+        None => bail!("missing type for column {}", name),
+    };
+
+    Ok(ColumnSpec {
+        name: name.to_string(),
+        column_type,
+    })
+}
+
+impl CsvSchema {
+    /// Parses a `[csv_schema]` table.
+    ///
+    /// ```toml
+    /// [csv_schema]
+    /// columns = [
+    ///     { name = "Version", type = "enum", values = ["1.0", "1.5"] },
+    ///     { name = "Notes", type = "string" },
+    /// ]
+    /// ```
+    pub fn parse(table: &toml::Value) -> Result<Self> {
+        let columns = match table.get("columns") {
+            Some(columns) => columns.as_array().context("columns must be an array")?,
+            None => return Ok(Self::default()),
+        };
+
+        let mut extra_columns: Vec<ColumnSpec> = Vec::with_capacity(columns.len());
+        for (i, column) in columns.iter().enumerate() {
+            let column =
+                parse_column_spec(column).with_context(|| format!("invalid column #{}", i))?;
+            ensure!(
+                extra_columns.iter().all(|other| other.name != column.name),
+                "column {} is declared twice",
+                column.name
+            );
+            extra_columns.push(column);
+        }
+        Ok(Self { extra_columns })
+    }
+
+    /// Returns the project's schema (`csv_schema` in the config). Without that section,
+    /// function lists have the standard columns only.
+    pub fn from_config() -> Result<Self> {
+        match repo::CONFIG.get("csv_schema") {
+            Some(table) => Self::parse(table).context("failed to parse csv_schema from config"),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Returns the schema to use for writing `functions`: the standard columns if no function
+    /// has extra values, and the project's schema otherwise.
+    pub fn for_functions(functions: &[Info]) -> Result<Self> {
+        if functions.iter().all(|info| info.extra.is_empty()) {
+            Ok(Self::default())
+        } else {
+            Self::from_config()
+        }
+    }
+
+    /// Returns the schema that a header row corresponds to. The standard columns
+    /// are not checked.
+    pub fn for_header(header: &csv::StringRecord) -> Result<Self> {
+        if header.len() == CSV_HEADER.len() {
+            return Ok(Self::default());
+        }
+        let schema = Self::from_config()?;
+        ensure!(
+            header.iter().eq(schema.header()),
+            "columns do not match csv_schema in the config: expected {:?}, got {:?}",
+            schema.header().collect::<Vec<_>>(),
+            header
+        );
+        Ok(schema)
+    }
+
+    /// Names of all columns, in order.
+    pub fn header(&self) -> impl Iterator<Item = &str> {
+        CSV_HEADER
+            .iter()
+            .copied()
+            .chain(self.extra_columns.iter().map(|column| column.name.as_str()))
+    }
+
+    pub fn column_count(&self) -> usize {
+        CSV_HEADER.len() + self.extra_columns.len()
+    }
+
+    /// Returns the extra fields of a function, in declaration order.
+    pub fn get_extra_fields(&self, info: &Info) -> Result<Vec<String>> {
+        if let Some(name) = info.extra.keys().find(|name| {
+            self.extra_columns
+                .iter()
+                .all(|column| column.name != **name)
+        }) {
+            bail!("{} is not a declared column", name);
+        }
+
+        self.extra_columns
+            .iter()
+            .map(|column| match info.extra.get(&column.name) {
+                Some(value) => {
+                    column.validate(value)?;
+                    Ok(value.to_string())
+                }
+                None => Ok(String::new()),
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,517 @@
+use crate::function_source::FunctionSource;
+use crate::functions::{self, demangle_str, Info, Status};
+use crate::ignore::IgnoreSet;
+use crate::metadata::{Entry, Metadata};
+use crate::paginate::{Paginated, RenderBudget};
+use crate::repo;
+use anyhow::{Context, Result};
+use rayon::prelude::*;
+use std::collections::BTreeMap;
+
+#[non_exhaustive]
+pub struct PatternMatchOptions {
+    pub case_sensitive: bool,
+    /// Also try to match the raw mangled name.
+    pub match_mangled: bool,
+}
+
+impl Default for PatternMatchOptions {
+    fn default() -> Self {
+        Self {
+            case_sensitive: true,
+            match_mangled: false,
+        }
+    }
+}
+
+/// Returns all functions whose demangled name matches a glob-style pattern (e.g. `nn::os::*`).
+/// Names that cannot be demangled (e.g. C functions) are matched as is.
+pub fn get_functions_with_pattern<'a>(
+    functions: &'a [Info],
+    pattern: &str,
+) -> Result<Vec<&'a Info>> {
+    get_functions_with_pattern_ex(functions, pattern, &PatternMatchOptions::default())
+}
+
+/// Same as `get_functions_with_pattern`, for functions that are loaded from `source`.
+pub fn get_functions_with_pattern_in_source(
+    source: &dyn FunctionSource,
+    pattern: &str,
+) -> Result<Vec<Info>> {
+    let functions = source.load()?;
+    Ok(get_functions_with_pattern(&functions, pattern)?
+        .into_iter()
+        .cloned()
+        .collect())
+}
+
+pub fn get_functions_with_pattern_ex<'a>(
+    functions: &'a [Info],
+    pattern: &str,
+    options: &PatternMatchOptions,
+) -> Result<Vec<&'a Info>> {
+    let pattern =
+        glob::Pattern::new(pattern).with_context(|| format!("invalid pattern: {}", pattern))?;
+    let match_options = glob::MatchOptions {
+        case_sensitive: options.case_sensitive,
+        require_literal_separator: false,
+        require_literal_leading_dot: false,
+    };
+
+    Ok(functions
+        .par_iter()
+        .filter(|function| {
+            if function.name.is_empty() {
+                return false;
+            }
+
+            if options.match_mangled && pattern.matches_with(&function.name, match_options) {
+                return true;
+            }
+
+            match demangle_str(&function.name) {
+                Ok(demangled) => pattern.matches_with(&demangled, match_options),
+                Err(_) => pattern.matches_with(&function.name, match_options),
+            }
+        })
+        .collect())
+}
+
+/// Compares names case-insensitively first so that e.g. `ksys::act` and `ksys::Act` sort
+/// next to each other, then case-sensitively to get a total order.
+fn compare_names(a: &str, b: &str) -> std::cmp::Ordering {
+    let a_lower = a.chars().flat_map(char::to_lowercase);
+    let b_lower = b.chars().flat_map(char::to_lowercase);
+    a_lower.cmp(b_lower).then_with(|| a.cmp(b))
+}
+
+fn starts_with_ignore_case(name: &str, prefix: &str) -> bool {
+    let mut name_lower = name.chars().flat_map(char::to_lowercase);
+    prefix
+        .chars()
+        .flat_map(char::to_lowercase)
+        .all(|c| name_lower.next() == Some(c))
+}
+
+/// Returns all named functions paired with their demangled name (or their mangled name
+/// if demangling fails), sorted by that name.
+pub fn build_demangled_sorted_index(functions: &[Info]) -> Vec<(String, &Info)> {
+    let mut index: Vec<(String, &Info)> = functions
+        .par_iter()
+        .filter(|function| !function.name.is_empty())
+        .map(|function| {
+            let name = demangle_str(&function.name).unwrap_or_else(|_| function.name.clone());
+            (name, function)
+        })
+        .collect();
+    index.par_sort_by(|(a, _), (b, _)| compare_names(a, b));
+    index
+}
+
+/// Returns the entries of an index built by `build_demangled_sorted_index` whose name starts
+/// with `prefix`. The comparison is case-insensitive.
+pub fn demangled_sorted_index_lookup<'a, 'b>(
+    index: &'a [(String, &'b Info)],
+    prefix: &str,
+) -> &'a [(String, &'b Info)] {
+    let prefix_lower = || prefix.chars().flat_map(char::to_lowercase);
+    let start = index
+        .partition_point(|(name, _)| name.chars().flat_map(char::to_lowercase).lt(prefix_lower()));
+    let len = index[start..].partition_point(|(name, _)| starts_with_ignore_case(name, prefix));
+    &index[start..start + len]
+}
+
+/// Returns all functions whose mangled name ends with `suffix`, in list order,
+/// e.g. for naming conventions like `_BROKEN` or `__stub`.
+pub fn get_functions_with_name_suffix<'a>(functions: &'a [Info], suffix: &str) -> Vec<&'a Info> {
+    functions
+        .par_iter()
+        .filter(|function| function.name.ends_with(suffix))
+        .collect()
+}
+
+/// Returns all functions whose mangled name contains `substring`, in list order.
+pub fn get_functions_with_name_containing<'a>(
+    functions: &'a [Info],
+    substring: &str,
+) -> Vec<&'a Info> {
+    functions
+        .par_iter()
+        .filter(|function| function.name.contains(substring))
+        .collect()
+}
+
+pub fn get_functions_by_exact_size(functions: &[Info], size: u32) -> Vec<&Info> {
+    functions
+        .par_iter()
+        .filter(|function| function.size == size)
+        .collect()
+}
+
+/// Returns all functions whose size is in `[min, max]`, in list order.
+pub fn get_functions_in_size_range(functions: &[Info], min: u32, max: u32) -> Vec<&Info> {
+    functions
+        .par_iter()
+        .filter(|function| (min..=max).contains(&function.size))
+        .collect()
+}
+
+/// Returns the `inline_threshold` from the config: functions that are at most this many bytes
+/// large are assumed to be inlined into their callers (see `get_functions_with_inline_hints`).
+pub fn get_inline_threshold() -> Option<u32> {
+    repo::CONFIG
+        .get("inline_threshold")
+        .and_then(toml::Value::as_integer)
+        .map(|threshold| threshold.clamp(0, u32::MAX as i64) as u32)
+}
+
+/// Returns all functions that are at most `max_size` bytes large, in list order.
+///
+/// Functions that small (e.g. 8 bytes, or two instructions on AArch64) are almost always inlined
+/// into their callers in the original executable, so the standalone copies are usually not worth
+/// tracking as decompilation targets.
+pub fn get_functions_with_inline_hints(functions: &[Info], max_size: u32) -> Vec<&Info> {
+    functions
+        .par_iter()
+        .filter(|function| function.size <= max_size)
+        .collect()
+}
+
+/// Functions indexed by size, for repeated size queries on the same list.
+pub struct SizeIndex<'a> {
+    functions: &'a [Info],
+    /// Indices into `functions`, by size.
+    by_size: BTreeMap<u32, Vec<usize>>,
+}
+
+impl<'a> SizeIndex<'a> {
+    pub fn build(functions: &'a [Info]) -> Self {
+        let mut by_size: BTreeMap<u32, Vec<usize>> = BTreeMap::new();
+        for (i, function) in functions.iter().enumerate() {
+            by_size.entry(function.size).or_default().push(i);
+        }
+        Self { functions, by_size }
+    }
+
+    pub fn query_exact(&self, size: u32) -> Vec<&'a Info> {
+        self.query_range(size, size)
+    }
+
+    /// Returns all functions whose size is in `[min, max]`, sorted by size
+    /// (functions of the same size are in list order).
+    pub fn query_range(&self, min: u32, max: u32) -> Vec<&'a Info> {
+        if min > max {
+            return Vec::new();
+        }
+        self.by_size
+            .range(min..=max)
+            .flat_map(|(_, indices)| indices.iter().map(|&i| &self.functions[i]))
+            .collect()
+    }
+}
+
+/// Returns all functions whose size is within `size_tolerance` bytes of the size of `target`,
+/// sorted by size. Functions at the address of `target` (i.e. the target and its aliases)
+/// are left out.
+///
+/// Functions with similar sizes are candidates for duplicates, e.g. template instantiations.
+pub fn get_similar_functions<'a>(
+    functions: &'a [Info],
+    target: &Info,
+    size_tolerance: u32,
+) -> Vec<&'a Info> {
+    let index = SizeIndex::build(functions);
+    let min = target.size.saturating_sub(size_tolerance);
+    let max = target.size.saturating_add(size_tolerance);
+    index
+        .query_range(min, max)
+        .into_iter()
+        .filter(|function| function.addr != target.addr)
+        .collect()
+}
+
+/// Groups functions by size and returns every group with more than one function,
+/// sorted by size. Functions in a group are in list order.
+pub fn get_all_size_duplicates(functions: &[Info]) -> Vec<Vec<&Info>> {
+    SizeIndex::build(functions)
+        .by_size
+        .into_values()
+        .filter(|indices| indices.len() > 1)
+        .map(|indices| indices.into_iter().map(|i| &functions[i]).collect())
+        .collect()
+}
+
+/// Returns the position of a status in the `get_functions_by_status_priority` order,
+/// or None if functions with this status need no work.
+fn get_status_priority(status: &Status) -> Option<u8> {
+    match status {
+        Status::NonMatchingMinor => Some(0),
+        Status::Wip => Some(1),
+        Status::NonMatchingMajor => Some(2),
+        Status::NotDecompiled => Some(3),
+        Status::Matching | Status::Library => None,
+    }
+}
+
+/// Returns the functions that still need work, most actionable first:
+///
+/// 1. non-matching (minor) functions, largest first, since they only need a small fix;
+/// 2. WIP functions;
+/// 3. non-matching (major) functions;
+/// 4. functions that have not been decompiled yet, largest first.
+///
+/// WIP and non-matching (major) functions are in list order.
+pub fn get_functions_by_status_priority(functions: &[Info]) -> Vec<&Info> {
+    let mut result: Vec<(u8, &Info)> = functions
+        .iter()
+        .filter_map(|function| Some((get_status_priority(&function.status)?, function)))
+        .collect();
+    result.sort_by_key(|(priority, function)| {
+        let size_key = match function.status {
+            Status::NonMatchingMinor | Status::NotDecompiled => std::cmp::Reverse(function.size),
+            _ => std::cmp::Reverse(0),
+        };
+        (*priority, size_key)
+    });
+    result.into_iter().map(|(_, function)| function).collect()
+}
+
+/// Returns the first `n` functions of `get_functions_by_status_priority` that are not in
+/// `ignore_set`.
+pub fn get_top_n_actionable<'a>(
+    functions: &'a [Info],
+    n: usize,
+    ignore_set: &IgnoreSet,
+) -> Vec<&'a Info> {
+    get_top_n_actionable_ex(functions, n, ignore_set, &|_| false)
+}
+
+/// Same as `get_top_n_actionable`, but functions for which `is_excluded` returns true are
+/// also left out, e.g. functions that are blocked by a known issue
+/// (see `KnownIssues::is_blocked`).
+pub fn get_top_n_actionable_ex<'a>(
+    functions: &'a [Info],
+    n: usize,
+    ignore_set: &IgnoreSet,
+    is_excluded: &dyn Fn(&Info) -> bool,
+) -> Vec<&'a Info> {
+    get_functions_by_status_priority(functions)
+        .into_iter()
+        .filter(|function| !ignore_set.is_ignored(function) && !is_excluded(function))
+        .take(n)
+        .collect()
+}
+
+/// Same as `get_top_n_actionable`, for functions that are loaded from `source`,
+/// with the project's ignore set (see `IgnoreSet::load`).
+pub fn get_top_n_actionable_in_source(source: &dyn FunctionSource, n: usize) -> Result<Vec<Info>> {
+    let functions = source.load()?;
+    Ok(get_top_n_actionable(&functions, n, &IgnoreSet::load()?)
+        .into_iter()
+        .cloned()
+        .collect())
+}
+
+/// Renders search results as text, one function per line (address, size, status and demangled
+/// name), within `budget`.
+///
+/// The caller decides which results are shown first, e.g. largest functions first:
+/// `Paginated::sorted_by_key(results, |info| std::cmp::Reverse(info.size))`.
+pub fn render_results(results: &Paginated<&Info>, budget: &RenderBudget) -> String {
+    results.render(budget, |info| {
+        format!(
+            "{} {:#08x} {} {}",
+            functions::format_addr(info.addr),
+            info.size,
+            info.status.code(),
+            demangle_str(&info.name).unwrap_or_else(|_| info.name.clone())
+        )
+    })
+}
+
+/// Returns the owner of a function according to the metadata sidecar (see `metadata::Entry`).
+fn get_owner<'a>(info: &Info, metadata: &'a Metadata) -> Option<&'a str> {
+    if info.name.is_empty() {
+        return None;
+    }
+    metadata.get(&info.name).and_then(Entry::owner)
+}
+
+/// Same as `render_results`, with an owner column (`-` for functions that nobody has claimed)
+/// before the name.
+pub fn render_results_with_owners(
+    results: &Paginated<&Info>,
+    budget: &RenderBudget,
+    metadata: &Metadata,
+) -> String {
+    let width = metadata
+        .entries
+        .values()
+        .filter_map(Entry::owner)
+        .map(str::len)
+        .max()
+        .unwrap_or(1);
+    results.render(budget, |info| {
+        format!(
+            "{} {:#08x} {} {:<width$} {}",
+            functions::format_addr(info.addr),
+            info.size,
+            info.status.code(),
+            get_owner(info, metadata).unwrap_or("-"),
+            demangle_str(&info.name).unwrap_or_else(|_| info.name.clone()),
+            width = width
+        )
+    })
+}
+
+fn result_to_json(info: &Info) -> serde_json::Value {
+    serde_json::json!({
+        "address": functions::format_addr(info.addr),
+        "size": info.size,
+        "status": info.status.code().to_string(),
+        "name": info.name,
+    })
+}
+
+/// Renders search results as a JSON array. Nothing is left out.
+pub fn render_results_json(results: &[&Info]) -> String {
+    let results: Vec<serde_json::Value> = results.iter().map(|info| result_to_json(info)).collect();
+    serde_json::Value::Array(results).to_string()
+}
+
+/// Same as `render_results_json`, with the owner of each function (null if there is none).
+pub fn render_results_json_with_owners(results: &[&Info], metadata: &Metadata) -> String {
+    let results: Vec<serde_json::Value> = results
+        .iter()
+        .map(|info| {
+            let mut result = result_to_json(info);
+            result["owner"] = get_owner(info, metadata).into();
+            result
+        })
+        .collect();
+    serde_json::Value::Array(results).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::OWNER;
+    use crate::testing::make_function;
+
+    fn make_metadata() -> Metadata {
+        let mut metadata = Metadata::default();
+        metadata
+            .entry("_ZN4ksys3act8BaseProc4initEv")
+            .set(OWNER, "alice".to_string());
+        metadata
+            .entry("_ZN4ksys3act8BaseProc4calcEv")
+            .set(OWNER, "somebody".to_string());
+        metadata
+    }
+
+    #[test]
+    fn top_n_results_show_owners() {
+        let functions = vec![
+            make_function(
+                0x10,
+                0x40,
+                "_ZN4ksys3act8BaseProc4initEv",
+                Status::NotDecompiled,
+            ),
+            make_function(
+                0x50,
+                0x20,
+                "_ZN4ksys3act8BaseProc4stopEv",
+                Status::NonMatchingMinor,
+            ),
+            make_function(0x70, 0x80, "", Status::NotDecompiled),
+            make_function(0xf0, 0x10, "_ZN4ksys3act8BaseProc4calcEv", Status::Matching),
+        ];
+        let top = get_top_n_actionable(&functions, 2, &IgnoreSet::default());
+        let rendered = render_results_with_owners(
+            &Paginated::new(top.clone()),
+            &RenderBudget::unlimited(),
+            &make_metadata(),
+        );
+        assert_eq!(
+            rendered,
+            "0x0000007100000050 0x000020 m -        ksys::act::BaseProc::stop()\n\
+             0x0000007100000070 0x000080 U -        \n"
+        );
+
+        let top: Vec<&Info> = functions.iter().take(1).collect();
+        assert_eq!(
+            render_results_json_with_owners(&top, &make_metadata()),
+            r#"[{"address":"0x0000007100000010","name":"_ZN4ksys3act8BaseProc4initEv","owner":"alice","size":64,"status":"U"}]"#
+        );
+        assert_eq!(
+            render_results_json_with_owners(&top, &Metadata::default()),
+            r#"[{"address":"0x0000007100000010","name":"_ZN4ksys3act8BaseProc4initEv","owner":null,"size":64,"status":"U"}]"#
+        );
+    }
+
+    #[test]
+    fn results_show_owners() {
+        let functions = [
+            make_function(
+                0x10,
+                0x40,
+                "_ZN4ksys3act8BaseProc4initEv",
+                Status::NotDecompiled,
+            ),
+            make_function(0x50, 0x20, "_ZN4ksys3act8BaseProc4stopEv", Status::Wip),
+        ];
+        let results: Vec<&Info> = functions.iter().collect();
+        assert_eq!(
+            render_results_with_owners(
+                &Paginated::new(results),
+                &RenderBudget::unlimited(),
+                &make_metadata(),
+            ),
+            "0x0000007100000010 0x000040 U alice    ksys::act::BaseProc::init()\n\
+             0x0000007100000050 0x000020 W -        ksys::act::BaseProc::stop()\n"
+        );
+    }
+
+    #[test]
+    fn ignored_functions_are_not_actionable() {
+        let functions = [
+            make_function(0x10, 0x100, "OUTLINED_FUNCTION_1", Status::NotDecompiled),
+            make_function(
+                0x110,
+                0x80,
+                "_ZN4ksys3act8BaseProc4initEv",
+                Status::NotDecompiled,
+            ),
+            make_function(0x190, 0x40, "_ZN4ksys3act8BaseProc4calcEv", Status::Wip),
+            make_function(
+                0x1d0,
+                0x20,
+                "_ZN4ksys3act8BaseProc4stopEv",
+                Status::NotDecompiled,
+            ),
+        ];
+        let ignore_set =
+            IgnoreSet::parse("0x10..0x110  # outlined functions\n_ZN4ksys3act8BaseProc4calcEv\n")
+                .unwrap();
+        let addrs = |top: Vec<&Info>| top.iter().map(|info| info.addr).collect::<Vec<_>>();
+
+        assert_eq!(
+            addrs(get_top_n_actionable(&functions, 3, &IgnoreSet::default())),
+            [0x190, 0x10, 0x110]
+        );
+        assert_eq!(
+            addrs(get_top_n_actionable(&functions, 3, &ignore_set)),
+            [0x110, 0x1d0]
+        );
+        assert_eq!(
+            addrs(get_top_n_actionable_ex(
+                &functions,
+                3,
+                &ignore_set,
+                &|info| { info.addr == 0x110 }
+            )),
+            [0x1d0]
+        );
+    }
+}
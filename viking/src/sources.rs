@@ -0,0 +1,101 @@
+use crate::functions::{demangle_str, Info};
+use crate::repo;
+use anyhow::{Context, Result};
+use rayon::prelude::*;
+use rustc_hash::FxHashMap;
+use std::path::{Path, PathBuf};
+
+/// Maps glob patterns of demangled names (e.g. `nn::os::*`) to source files.
+///
+/// Source mapping files look like this:
+///
+/// ```toml
+/// [sources]
+/// "nn::os::*" = "src/nn/os/os_mutex.cpp"
+/// "nn::os::detail::*" = "src/nn/os/os_detail.cpp"
+/// ```
+///
+/// When several patterns match a name, the longest pattern wins.
+#[derive(Clone, Debug, Default)]
+pub struct SourceMapping {
+    /// Sorted by decreasing pattern length.
+    entries: Vec<(glob::Pattern, PathBuf)>,
+}
+
+impl SourceMapping {
+    pub fn parse(contents: &str) -> Result<Self> {
+        let value: toml::Value = toml::from_str(contents)?;
+        let sources = match value.get("sources") {
+            Some(sources) => sources.as_table().context("sources must be a table")?,
+            None => return Ok(Self::default()),
+        };
+
+        let mut entries = sources
+            .iter()
+            .map(|(pattern, path)| {
+                let path = path
+                    .as_str()
+                    .with_context(|| format!("source path for {} must be a string", pattern))?;
+                let pattern = glob::Pattern::new(pattern)
+                    .with_context(|| format!("invalid pattern: {}", pattern))?;
+                Ok((pattern, PathBuf::from(path)))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        // The sort is stable, so ties are broken by key order.
+        entries.sort_by_key(|(pattern, _)| std::cmp::Reverse(pattern.as_str().len()));
+        Ok(Self { entries })
+    }
+
+    /// Returns the source file for a function name (mangled or not).
+    pub fn get_source_file(&self, name: &str) -> Option<&Path> {
+        if name.is_empty() {
+            return None;
+        }
+
+        let demangled = demangle_str(name);
+        let name = demangled.as_deref().unwrap_or(name);
+        self.entries
+            .iter()
+            .find(|(pattern, _)| pattern.matches(name))
+            .map(|(_, path)| path.as_path())
+    }
+}
+
+pub fn load_source_mapping(path: &Path) -> Result<SourceMapping> {
+    let contents =
+        std::fs::read_to_string(path).with_context(|| format!("failed to read {:?}", path))?;
+    SourceMapping::parse(&contents).with_context(|| format!("failed to parse {:?}", path))
+}
+
+/// Returns the path to the project's source mapping file (`source_mapping_toml` in the config,
+/// relative to the repo root), if there is one.
+pub fn get_source_mapping_path() -> Result<Option<PathBuf>> {
+    match repo::CONFIG
+        .get("source_mapping_toml")
+        .and_then(toml::Value::as_str)
+    {
+        Some(path) => Ok(Some(repo::get_repo_root()?.join(path))),
+        None => Ok(None),
+    }
+}
+
+/// Groups functions by source file. Functions that match no pattern are under `None`.
+pub fn get_functions_by_translation_unit<'a>(
+    functions: &'a [Info],
+    mapping: &SourceMapping,
+) -> FxHashMap<Option<PathBuf>, Vec<&'a Info>> {
+    let sources: Vec<Option<&Path>> = functions
+        .par_iter()
+        .map(|info| mapping.get_source_file(&info.name))
+        .collect();
+
+    let mut result: FxHashMap<Option<PathBuf>, Vec<&Info>> = FxHashMap::default();
+    for (info, source) in functions.iter().zip(sources) {
+        result
+            .entry(source.map(Path::to_path_buf))
+            .or_default()
+            .push(info);
+    }
+    result
+}
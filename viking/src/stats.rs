@@ -0,0 +1,505 @@
+use crate::function_source::FunctionSource;
+use crate::functions::{self, AddressDiff, Info, Status};
+use crate::ignore::IgnoreSet;
+use crate::known_issues::KnownIssues;
+use crate::repo;
+use crate::search;
+use anyhow::{bail, ensure, Context, Result};
+use std::convert::TryFrom;
+use std::io::Write;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Counts {
+    pub functions: usize,
+    pub bytes: u64,
+}
+
+impl Counts {
+    #[inline]
+    fn add(&mut self, info: &Info) {
+        self.functions += 1;
+        self.bytes += info.size as u64;
+    }
+}
+
+/// Progress statistics for a list of functions.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Stats {
+    pub total: Counts,
+    pub matching: Counts,
+    pub non_matching_minor: Counts,
+    pub non_matching_major: Counts,
+    pub wip: Counts,
+    pub not_decompiled: Counts,
+    pub library: Counts,
+    /// Functions that cannot match because of a known issue (see `known_issues`).
+    /// They are counted here instead of under their status.
+    pub blocked: Counts,
+}
+
+impl Stats {
+    pub fn add(&mut self, info: &Info) {
+        self.total.add(info);
+        self.get_mut(&info.status).add(info);
+    }
+
+    /// Same as `add`, but counts the function as blocked instead of under its status.
+    pub fn add_blocked(&mut self, info: &Info) {
+        self.total.add(info);
+        self.blocked.add(info);
+    }
+
+    pub fn get(&self, status: &Status) -> &Counts {
+        match status {
+            Status::Matching => &self.matching,
+            Status::NonMatchingMinor => &self.non_matching_minor,
+            Status::NonMatchingMajor => &self.non_matching_major,
+            Status::Wip => &self.wip,
+            Status::NotDecompiled => &self.not_decompiled,
+            Status::Library => &self.library,
+        }
+    }
+
+    fn get_mut(&mut self, status: &Status) -> &mut Counts {
+        match status {
+            Status::Matching => &mut self.matching,
+            Status::NonMatchingMinor => &mut self.non_matching_minor,
+            Status::NonMatchingMajor => &mut self.non_matching_major,
+            Status::Wip => &mut self.wip,
+            Status::NotDecompiled => &mut self.not_decompiled,
+            Status::Library => &mut self.library,
+        }
+    }
+
+    /// Functions that have been decompiled (matching or not). See `Info::is_decompiled`.
+    pub fn decompiled(&self) -> Counts {
+        Counts {
+            functions: self.total.functions
+                - self.not_decompiled.functions
+                - self.library.functions,
+            bytes: self.total.bytes - self.not_decompiled.bytes - self.library.bytes,
+        }
+    }
+
+    /// Fraction of bytes that are matching, from 0 to 1. Library functions are not counted.
+    pub fn matching_byte_fraction(&self) -> f64 {
+        let total = self.total.bytes - self.library.bytes;
+        if total == 0 {
+            0.0
+        } else {
+            self.matching.bytes as f64 / total as f64
+        }
+    }
+}
+
+impl<'a> std::iter::FromIterator<&'a Info> for Stats {
+    fn from_iter<I: IntoIterator<Item = &'a Info>>(iter: I) -> Self {
+        let mut stats = Stats::default();
+        for info in iter {
+            stats.add(info);
+        }
+        stats
+    }
+}
+
+pub fn compute_stats(functions: &[Info]) -> Stats {
+    functions.iter().collect()
+}
+
+/// Same as `compute_stats`, for functions that are loaded from `source`.
+pub fn compute_stats_for_source(source: &dyn FunctionSource) -> Result<Stats> {
+    Ok(compute_stats(&source.load()?))
+}
+
+/// Same as `compute_stats`, but ignored functions are left out of every count
+/// (including the totals).
+pub fn compute_stats_excluding(functions: &[Info], ignore_set: &IgnoreSet) -> Stats {
+    functions
+        .iter()
+        .filter(|info| !ignore_set.is_ignored(info))
+        .collect()
+}
+
+/// Same as `compute_stats`, but functions that are blocked by a known issue are counted
+/// in `Stats::blocked` (see `KnownIssues::is_blocked`).
+pub fn compute_stats_with_known_issues(functions: &[Info], known_issues: &KnownIssues) -> Stats {
+    let mut stats = Stats::default();
+    for info in functions {
+        if known_issues.is_blocked(info) {
+            stats.add_blocked(info);
+        } else {
+            stats.add(info);
+        }
+    }
+    stats
+}
+
+/// Same as `compute_stats`, but functions that are at most `inline_threshold` bytes large
+/// are left out of every count (see `search::get_functions_with_inline_hints`).
+pub fn compute_stats_excluding_inline(functions: &[Info], inline_threshold: u32) -> Stats {
+    functions
+        .iter()
+        .filter(|info| info.size > inline_threshold)
+        .collect()
+}
+
+/// Same as `compute_stats`, but leaves out likely inlined functions if `inline_threshold`
+/// is set in the config (see `search::get_inline_threshold`).
+pub fn compute_stats_with_config(functions: &[Info]) -> Stats {
+    match search::get_inline_threshold() {
+        Some(inline_threshold) => compute_stats_excluding_inline(functions, inline_threshold),
+        None => compute_stats(functions),
+    }
+}
+
+/// Change in progress between two snapshots. See `calculate_matching_rate_change`.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[non_exhaustive]
+pub struct StatsDelta {
+    pub matched_bytes_delta: i64,
+    pub matched_functions_delta: i32,
+    /// Change of the matching byte percentage (see `Stats::matching_byte_fraction`),
+    /// in percentage points.
+    pub byte_percentage_delta: f64,
+    /// Number of functions (and bytes) that are no longer listed. For aggregated stats, this is
+    /// the decrease of the totals. Matching functions that were removed are included in
+    /// the matched deltas but are not regressions.
+    pub removed_functions: usize,
+    pub removed_bytes: u64,
+    /// Addresses of functions that are now matching, sorted.
+    /// Only available from `calculate_matching_rate_change_for_functions`.
+    pub newly_matching: Vec<u64>,
+    /// Addresses of functions that are no longer matching, sorted.
+    /// Only available from `calculate_matching_rate_change_for_functions`.
+    pub regressions: Vec<u64>,
+}
+
+/// Computes the change in progress between two snapshots of aggregated stats
+/// (e.g. from the previous and the current CI run).
+///
+/// Percentages are relative to the total of each snapshot, so removing functions from the list
+/// only changes `byte_percentage_delta` by what the removed functions contributed.
+pub fn calculate_matching_rate_change(before: &Stats, after: &Stats) -> StatsDelta {
+    StatsDelta {
+        matched_bytes_delta: after.matching.bytes as i64 - before.matching.bytes as i64,
+        matched_functions_delta: after.matching.functions as i32 - before.matching.functions as i32,
+        byte_percentage_delta: (after.matching_byte_fraction() - before.matching_byte_fraction())
+            * 100.0,
+        removed_functions: before.total.functions.saturating_sub(after.total.functions),
+        removed_bytes: before.total.bytes.saturating_sub(after.total.bytes),
+        newly_matching: Vec::new(),
+        regressions: Vec::new(),
+    }
+}
+
+/// Same as `calculate_matching_rate_change`, but computes the stats from two versions of
+/// a function list and also fills in `newly_matching` and `regressions`. Functions are matched
+/// by address (see `functions::diff_by_address`); `removed_functions` counts the functions
+/// that are only in `before`.
+pub fn calculate_matching_rate_change_for_functions(before: &[Info], after: &[Info]) -> StatsDelta {
+    let mut delta = calculate_matching_rate_change(&compute_stats(before), &compute_stats(after));
+    delta.removed_functions = 0;
+    delta.removed_bytes = 0;
+
+    for entry in functions::diff_by_address(before, after) {
+        let (was_matching, is_matching) = match entry {
+            AddressDiff::Added(info) => (false, info.status == Status::Matching),
+            AddressDiff::Removed(info) => {
+                delta.removed_functions += 1;
+                delta.removed_bytes += info.size as u64;
+                continue;
+            }
+            AddressDiff::Kept { old, new } => (
+                old.status == Status::Matching,
+                new.status == Status::Matching,
+            ),
+        };
+        if is_matching && !was_matching {
+            delta.newly_matching.push(entry.addr());
+        } else if was_matching && !is_matching {
+            delta.regressions.push(entry.addr());
+        }
+    }
+    delta
+}
+
+/// Default bucket edges for `size_histogram`.
+pub const DEFAULT_SIZE_BUCKET_EDGES: &[u32] = &[0x10, 0x40, 0x100, 0x400, 0x800, 0x2000];
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HistogramBucket {
+    /// Smallest size in the bucket.
+    pub min_size: u32,
+    /// Exclusive upper bound, or None for the last bucket.
+    pub max_size: Option<u32>,
+    pub counts: Counts,
+}
+
+/// Function counts and sizes per size bucket. See `size_histogram`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Histogram {
+    pub buckets: Vec<HistogramBucket>,
+}
+
+impl Histogram {
+    /// Renders one line per bucket, with bars that are proportional to the number of functions.
+    pub fn render(&self, bar_width: usize) -> String {
+        let max_count = self
+            .buckets
+            .iter()
+            .map(|bucket| bucket.counts.functions)
+            .max()
+            .unwrap_or(0);
+
+        let mut result = String::new();
+        for bucket in &self.buckets {
+            let range = match bucket.max_size {
+                Some(max_size) => format!("{:#x}..{:#x}", bucket.min_size, max_size),
+                None => format!("{:#x}..", bucket.min_size),
+            };
+            // Non-empty buckets always get a bar so that they stand out from empty ones.
+            let bar_len = match bucket.counts.functions {
+                0 => 0,
+                count => (count * bar_width / max_count).max(1),
+            };
+            let line = format!(
+                "{:<16} {:>8} functions {:>12} bytes {}",
+                range,
+                bucket.counts.functions,
+                bucket.counts.bytes,
+                "#".repeat(bar_len)
+            );
+            result.push_str(line.trim_end());
+            result.push('\n');
+        }
+        result
+    }
+}
+
+impl std::fmt::Display for Histogram {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.render(40))
+    }
+}
+
+/// Counts functions by size. `bucket_edges` must be strictly increasing (otherwise an error
+/// is returned): bucket i contains
+/// functions whose size is in `[bucket_edges[i - 1], bucket_edges[i])`, with an implicit
+/// first edge of 0 and an unbounded last bucket. Empty buckets are included.
+///
+/// Only functions whose status is in `status_filter` are counted (all functions if None).
+/// `functions` can also be the result of a pattern search, e.g.
+/// `search::get_functions_with_pattern(&functions, "ksys::phys::*")?`.
+pub fn size_histogram<'a>(
+    functions: impl IntoIterator<Item = &'a Info>,
+    status_filter: Option<&[Status]>,
+    bucket_edges: &[u32],
+) -> Result<Histogram> {
+    if let Some(pair) = bucket_edges.windows(2).find(|pair| pair[0] >= pair[1]) {
+        bail!(
+            "bucket edges must be strictly increasing ({:#x} is followed by {:#x})",
+            pair[0],
+            pair[1]
+        );
+    }
+
+    let mut buckets: Vec<HistogramBucket> = std::iter::once(0)
+        .chain(bucket_edges.iter().copied().filter(|&edge| edge != 0))
+        .map(|min_size| HistogramBucket {
+            min_size,
+            ..Default::default()
+        })
+        .collect();
+    for i in 0..buckets.len() - 1 {
+        buckets[i].max_size = Some(buckets[i + 1].min_size);
+    }
+
+    for info in functions {
+        if let Some(statuses) = status_filter {
+            if !statuses.contains(&info.status) {
+                continue;
+            }
+        }
+        let index = buckets.partition_point(|bucket| bucket.min_size <= info.size) - 1;
+        buckets[index].counts.add(info);
+    }
+
+    Ok(Histogram { buckets })
+}
+
+/// An address range of the executable, e.g. the code of one game system.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Region {
+    pub name: String,
+    /// *Note*: does not contain the IDA base (0x7100000000).
+    pub start: u64,
+    /// End of the region (exclusive).
+    pub end: u64,
+}
+
+impl Region {
+    pub fn contains(&self, addr: u64) -> bool {
+        (self.start..self.end).contains(&addr)
+    }
+}
+
+/// Stats for the functions of one region. See `get_progress_by_region`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RegionStats {
+    pub region: Region,
+    pub stats: Stats,
+}
+
+/// Stats for every region, in the order the regions were given.
+pub type ProgressByRegion = Vec<RegionStats>;
+
+/// Computes stats for each region. Functions are counted in the first region that contains
+/// their start address, even if they extend past the end of it, so that no function is counted
+/// twice. Functions that are not in any region are not counted.
+pub fn get_progress_by_region(functions: &[Info], regions: &[Region]) -> ProgressByRegion {
+    let mut result: ProgressByRegion = regions
+        .iter()
+        .map(|region| RegionStats {
+            region: region.clone(),
+            stats: Stats::default(),
+        })
+        .collect();
+
+    for info in functions {
+        if let Some(entry) = result
+            .iter_mut()
+            .find(|entry| entry.region.contains(info.addr))
+        {
+            entry.stats.add(info);
+        }
+    }
+    result
+}
+
+/// Reads the regions from the `regions` array of the config. Addresses are offsets
+/// (without `functions::ADDRESS_BASE`), and `end` is exclusive:
+///
+/// ```toml
+/// [[regions]]
+/// name = "ksys"
+/// start = 0x1000
+/// end = 0x200000
+/// ```
+///
+/// Returns an empty list if there is no `regions` array.
+pub fn load_regions_from_config() -> Result<Vec<Region>> {
+    let entries = match repo::CONFIG.get("regions") {
+        Some(value) => value
+            .as_array()
+            .context("regions must be an array")?
+            .as_slice(),
+        None => &[],
+    };
+
+    let mut regions = Vec::with_capacity(entries.len());
+    for entry in entries {
+        let name = entry
+            .get("name")
+            .and_then(toml::Value::as_str)
+            .context("every region must have a name")?;
+        let get = |key: &str| -> Result<u64> {
+            let value = entry
+                .get(key)
+                .and_then(toml::Value::as_integer)
+                .with_context(|| format!("region {}: {} must be an integer", name, key))?;
+            u64::try_from(value).with_context(|| format!("region {}: {} is negative", name, key))
+        };
+        let (start, end) = (get("start")?, get("end")?);
+        ensure!(
+            start < end,
+            "region {}: start ({:#x}) must be lower than end ({:#x})",
+            name,
+            start,
+            end
+        );
+        regions.push(Region {
+            name: name.to_string(),
+            start,
+            end,
+        });
+    }
+    Ok(regions)
+}
+
+/// Writes one row per region with the function count and size for every status.
+pub fn export_region_stats_csv(stats: &[RegionStats], writer: &mut dyn Write) -> Result<()> {
+    const CATEGORIES: &[&str] = &[
+        "Total",
+        "Matching",
+        "Non-matching (minor)",
+        "Non-matching (major)",
+        "WIP",
+        "Not decompiled",
+        "Library",
+        "Blocked",
+    ];
+
+    let mut writer = csv::Writer::from_writer(writer);
+    let mut header = vec!["Region".to_string(), "Start".to_string(), "End".to_string()];
+    for category in CATEGORIES {
+        header.push(format!("{} functions", category));
+        header.push(format!("{} bytes", category));
+    }
+    writer.write_record(&header)?;
+
+    for entry in stats {
+        let s = &entry.stats;
+        let counts = [
+            &s.total,
+            &s.matching,
+            &s.non_matching_minor,
+            &s.non_matching_major,
+            &s.wip,
+            &s.not_decompiled,
+            &s.library,
+            &s.blocked,
+        ];
+        let mut record = vec![
+            entry.region.name.clone(),
+            functions::format_addr(entry.region.start),
+            functions::format_addr(entry.region.end),
+        ];
+        for counts in &counts {
+            record.push(counts.functions.to_string());
+            record.push(counts.bytes.to_string());
+        }
+        writer.write_record(&record)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::make_function;
+
+    #[test]
+    fn histogram_edges_must_be_increasing() {
+        let functions = [
+            make_function(0x100, 0x8, "_Z1av", Status::Matching),
+            make_function(0x110, 0x40, "_Z1bv", Status::NotDecompiled),
+        ];
+        let histogram = size_histogram(&functions, None, &[0x10, 0x40]).unwrap();
+        let counts: Vec<usize> = histogram
+            .buckets
+            .iter()
+            .map(|bucket| bucket.counts.functions)
+            .collect();
+        assert_eq!(counts, [1, 0, 1]);
+
+        let err = size_histogram(&functions, None, &[0x10, 0x40, 0x40]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "bucket edges must be strictly increasing (0x40 is followed by 0x40)"
+        );
+        assert!(size_histogram(&functions, None, &[0x40, 0x10]).is_err());
+    }
+}
@@ -0,0 +1,354 @@
+use crate::functions::{self, CsvFormat, Info, Status, ADDRESS_BASE};
+use crate::schema::{ColumnType, CsvSchema, Value};
+use anyhow::{ensure, Context, Result};
+use indexmap::IndexMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// Every status, in declaration order.
+pub const ALL_STATUSES: [Status; 6] = [
+    Status::Matching,
+    Status::NonMatchingMinor,
+    Status::NonMatchingMajor,
+    Status::NotDecompiled,
+    Status::Wip,
+    Status::Library,
+];
+
+/// Sizes that have broken parsing or writing in the past, or that are likely to:
+/// u8, u16, i32 and u32 boundaries, and the boundary of the zero-padded size column.
+pub const BOUNDARY_SIZES: &[u32] = &[
+    0,
+    1,
+    0xff,
+    0x100,
+    0xffff,
+    0x1_0000,
+    999_999,
+    1_000_000,
+    i32::MAX as u32,
+    i32::MAX as u32 + 1,
+    u32::MAX - 1,
+    u32::MAX,
+];
+
+/// Characters that may appear in names, grouped by class: mangled names use the first four
+/// classes, and demangled or hand-written names can also contain the others.
+///
+/// Function lists are read without support for quoting, so names must not contain commas,
+/// tabs, quotes or line breaks (see `is_writable_name`).
+pub const NAME_CHARACTER_CLASSES: &[&str] = &[
+    "abcdefghijklmnopqrstuvwxyz",
+    "ABCDEFGHIJKLMNOPQRSTUVWXYZ",
+    "0123456789",
+    "_$.",
+    ":<>()[]{}*&~!%^|?+-=/\\;@#'` ",
+    "éßλ漢字\u{1F600}",
+];
+
+/// Returns whether a name can be written to a function list and read back unchanged.
+pub fn is_writable_name(name: &str) -> bool {
+    !name.contains(&[',', '\t', '"', '\n', '\r'][..])
+}
+
+/// Deterministic pseudo-random number generator (SplitMix64), so that failures can be
+/// reproduced from the seed.
+#[derive(Clone, Debug)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a number in `0..n`. `n` must not be 0.
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    pub fn choose<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len())]
+    }
+}
+
+/// Returns a random string of 1 to 32 characters from `NAME_CHARACTER_CLASSES`.
+fn arbitrary_name(rng: &mut Rng) -> String {
+    let len = 1 + rng.below(32);
+    (0..len)
+        .map(|_| {
+            let class: Vec<char> = rng.choose(NAME_CHARACTER_CLASSES).chars().collect();
+            *rng.choose(&class)
+        })
+        .collect()
+}
+
+fn arbitrary_size(rng: &mut Rng) -> u32 {
+    match rng.below(4) {
+        0 => *rng.choose(BOUNDARY_SIZES),
+        1 => rng.next_u64() as u32,
+        _ => (rng.below(0x400) * 4) as u32,
+    }
+}
+
+/// Returns a function for test fixtures. Shorthand for `Info::new`.
+pub fn make_function(addr: u64, size: u32, name: &str, status: Status) -> Info {
+    Info::new(addr, size, name.to_string(), status)
+}
+
+/// Returns a random function. Addresses are below 4 GiB so that they do not overlap
+/// with `ADDRESS_BASE`. Functions that are not decompiled can have an empty name.
+pub fn arbitrary_info(rng: &mut Rng) -> Info {
+    let status = rng.choose(&ALL_STATUSES).clone();
+    let mut info = Info::new(
+        rng.next_u64() & 0xffff_fffc,
+        arbitrary_size(rng),
+        arbitrary_name(rng),
+        status,
+    );
+    if !info.is_decompiled() && rng.below(8) == 0 {
+        info.name.clear();
+    }
+    info
+}
+
+/// Same as `arbitrary_info`, but also generates values for the extra columns of `schema`.
+/// Columns are left empty at random.
+pub fn arbitrary_info_with_schema(rng: &mut Rng, schema: &CsvSchema) -> Info {
+    let mut info = arbitrary_info(rng);
+    let mut extra = IndexMap::new();
+    for column in &schema.extra_columns {
+        if rng.below(4) == 0 {
+            continue;
+        }
+        let value = match &column.column_type {
+            ColumnType::String => Value::String(arbitrary_name(rng)),
+            ColumnType::Int => Value::Int(match rng.below(3) {
+                0 => *rng.choose(&[i64::MIN, -1, 0, 1, i64::MAX]),
+                _ => rng.next_u64() as i64,
+            }),
+            ColumnType::Enum(values) => {
+                let values: Vec<&String> = values.iter().filter(|v| !v.is_empty()).collect();
+                if values.is_empty() {
+                    continue;
+                }
+                Value::Enum(rng.choose(&values).to_string())
+            }
+        };
+        extra.insert(column.name.clone(), value);
+    }
+    info.extra = extra;
+    info
+}
+
+/// Returns `n` random functions that can be written as one function list: names are unique
+/// (a suffix is appended to every non-empty name).
+pub fn arbitrary_functions(rng: &mut Rng, n: usize) -> Vec<Info> {
+    (0..n)
+        .map(|i| {
+            let mut info = arbitrary_info(rng);
+            if !info.name.is_empty() {
+                info.name.push_str(&format!("_{}", i));
+            }
+            info
+        })
+        .collect()
+}
+
+/// Returns functions that cover every status, every size in `BOUNDARY_SIZES`, every character
+/// of `NAME_CHARACTER_CLASSES` and the lowest and highest addresses.
+pub fn boundary_functions() -> Vec<Info> {
+    let mut names: Vec<String> = NAME_CHARACTER_CLASSES
+        .iter()
+        .map(|class| class.to_string())
+        .collect();
+    names.push(NAME_CHARACTER_CLASSES.concat());
+    names.push(String::new());
+
+    let mut functions = Vec::new();
+    for (i, &size) in BOUNDARY_SIZES.iter().enumerate() {
+        for (j, status) in ALL_STATUSES.iter().enumerate() {
+            let index = i * ALL_STATUSES.len() + j;
+            let mut name = names[index % names.len()].clone();
+            if name.is_empty() && !matches!(status, Status::NotDecompiled | Status::Library) {
+                name = NAME_CHARACTER_CLASSES[0].to_string();
+            }
+            if !name.is_empty() {
+                name.push_str(&format!("_{}", index));
+            }
+            let addr = match index {
+                0 => 0,
+                1 => 0xffff_ffff,
+                _ => index as u64 * 4,
+            };
+            functions.push(Info::new(addr, size, name, status.clone()));
+        }
+    }
+    functions
+}
+
+/// Writes `functions` with `functions::write_functions_to_writer`, reads them back
+/// with `functions::get_functions_for_reader` and checks that nothing changed.
+///
+/// Functions with extra values are written with the schema from the config, so forks that
+/// extend the schema can use this with `arbitrary_info_with_schema`.
+pub fn roundtrip(functions: &[Info]) -> Result<()> {
+    let mut csv = Vec::new();
+    functions::write_functions_to_writer(&mut csv, functions)
+        .context("failed to write the function list")?;
+    let parsed = functions::get_functions_for_reader(&mut csv.as_slice()).with_context(|| {
+        format!(
+            "failed to read the function list back:\n{}",
+            String::from_utf8_lossy(&csv)
+        )
+    })?;
+
+    ensure!(
+        parsed.len() == functions.len(),
+        "wrote {} functions but read {}",
+        functions.len(),
+        parsed.len()
+    );
+    for (i, (written, read)) in functions.iter().zip(&parsed).enumerate() {
+        ensure!(
+            written == read,
+            "function #{} changed after a roundtrip: wrote {:?}, read {:?}",
+            i,
+            written,
+            read
+        );
+    }
+    Ok(())
+}
+
+fn check_roundtrip_if_writable(functions: &[Info]) {
+    if functions.iter().all(|info| is_writable_name(&info.name)) {
+        if let Err(err) = roundtrip(functions) {
+            panic!("{:#}", err);
+        }
+    }
+}
+
+/// Fuzz target for the entry parser: parses one line of a function list with the standard
+/// columns. Entries that are parsed successfully must survive a roundtrip.
+pub fn fuzz_function_csv_entry(data: &[u8]) {
+    let line = match std::str::from_utf8(data) {
+        Ok(line) if !line.contains(&['\n', '\r'][..]) => line,
+        _ => return,
+    };
+    // This matches how the reader splits lines (without support for quoting).
+    let record = csv::StringRecord::from(line.split(',').collect::<Vec<_>>());
+    if let Ok(info) =
+        functions::parse_function_csv_entry(&record, ADDRESS_BASE, &CsvSchema::default())
+    {
+        check_roundtrip_if_writable(&[info]);
+    }
+}
+
+/// Fuzz target for the full reader (`functions::get_functions_for_reader`). Function lists
+/// that are read successfully must survive a roundtrip.
+///
+/// Inputs with extra columns are skipped because their schema comes from the config.
+pub fn fuzz_reader(data: &[u8]) {
+    let mut reader = CsvFormat::Csv.make_reader_builder().from_reader(data);
+    let mut header = csv::StringRecord::new();
+    match reader.read_record(&mut header) {
+        Ok(true) if header.len() <= functions::CSV_HEADER.len() => (),
+        _ => return,
+    }
+
+    if let Ok(functions) = functions::get_functions_for_reader(&mut &data[..]) {
+        check_roundtrip_if_writable(&functions);
+    }
+}
+
+/// A directory in the system temporary directory that is removed when this is dropped.
+#[derive(Debug)]
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    /// Creates an empty directory. `name` only makes the path easier to recognise;
+    /// the directory is unique even if several tests use the same name.
+    pub fn new(name: &str) -> Self {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "viking_{}_{}_{}",
+            name,
+            std::process::id(),
+            NEXT_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).expect("failed to create a temporary directory");
+        Self { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn join(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.path.join(path)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+/// The config that is used by `use_test_config`. It declares one extra column (see
+/// `schema::CsvSchema`), which lists only have if they are written with extra values.
+pub const TEST_CONFIG: &str = r#"
+functions_csv = "data/functions.csv"
+
+[csv_schema]
+columns = [{ name = "Notes", type = "string" }]
+"#;
+
+static USE_TEST_CONFIG: AtomicBool = AtomicBool::new(false);
+
+/// Makes `repo::CONFIG` use `TEST_CONFIG` instead of reading the config of the project in the
+/// current directory, so that code which reads the config can be tested. This must be called
+/// before anything reads the config.
+///
+/// Nothing is written to disk and the current directory is left alone. There is no repo root
+/// (`repo::get_repo_root` fails), so tests must pass paths explicitly, e.g. into a `TempDir`.
+pub fn use_test_config() {
+    USE_TEST_CONFIG.store(true, Ordering::Relaxed);
+}
+
+pub(crate) fn is_using_test_config() -> bool {
+    USE_TEST_CONFIG.load(Ordering::Relaxed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo;
+
+    #[test]
+    fn the_test_config_does_not_touch_the_file_system() {
+        let current_dir = std::env::current_dir().unwrap();
+        use_test_config();
+        assert_eq!(
+            repo::CONFIG
+                .get("functions_csv")
+                .and_then(toml::Value::as_str),
+            Some("data/functions.csv")
+        );
+        assert!(repo::get_repo_root().is_err());
+        assert_eq!(std::env::current_dir().unwrap(), current_dir);
+    }
+}
@@ -0,0 +1,216 @@
+use crate::file_utils;
+use crate::functions::{self, Info};
+use crate::lint::Issue;
+use crate::repo;
+use anyhow::{ensure, Context, Result};
+use rustc_hash::FxHashSet;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+const TOMBSTONE_CSV_HEADER: &[&str] = &["Address", "Reason"];
+
+/// A function that was deliberately removed from the function list.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Tombstone {
+    /// *Note*: does not contain the IDA base (0x7100000000).
+    pub addr: u64,
+    /// Why the function was removed, e.g. "duplicate of 0x7100123450".
+    pub reason: String,
+}
+
+/// Addresses of functions that were deliberately removed, so that entries which come back
+/// (e.g. after a rebase) can be reported. See `check_tombstones`.
+///
+/// Tombstone files are CSV files with an Address and a Reason column:
+///
+/// ```text
+/// Address,Reason
+/// 0x0000007100123450,duplicate of 0x0000007100123440
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Tombstones {
+    entries: BTreeMap<u64, Tombstone>,
+}
+
+impl Tombstones {
+    pub fn parse(contents: &str) -> Result<Self> {
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader(contents.as_bytes());
+
+        let mut tombstones = Self::default();
+        for (i, record) in reader.records().enumerate() {
+            let record = record?;
+            if i == 0 {
+                ensure!(
+                    record.iter().eq(TOMBSTONE_CSV_HEADER.iter().copied()),
+                    "wrong header; expected {}",
+                    TOMBSTONE_CSV_HEADER.join(",")
+                );
+                continue;
+            }
+
+            let addr = functions::parse_address_or_offset(record.get(0).unwrap_or_default())
+                .with_context(|| format!("invalid address at line {}", i + 1))?;
+            tombstones.insert(Tombstone {
+                addr,
+                reason: record.get(1).unwrap_or_default().to_string(),
+            });
+        }
+        Ok(tombstones)
+    }
+
+    /// Loads tombstones from `path`. A file that does not exist has no tombstones.
+    pub fn load_from_path(path: &Path) -> Result<Self> {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(err).with_context(|| format!("failed to read {:?}", path)),
+        };
+        Self::parse(&contents).with_context(|| format!("failed to parse {:?}", path))
+    }
+
+    /// Loads the project's tombstones from the file at `removed_functions` (relative to the
+    /// repo root) in the config. There are no tombstones if that key is not set.
+    pub fn load() -> Result<Self> {
+        match get_tombstone_path()? {
+            Some(path) => Self::load_from_path(&path),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Returns the entries, sorted by address.
+    pub fn entries(&self) -> impl Iterator<Item = &Tombstone> {
+        self.entries.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, addr: u64) -> Option<&Tombstone> {
+        self.entries.get(&addr)
+    }
+
+    /// Adds a tombstone, replacing any previous tombstone for the same address.
+    pub fn insert(&mut self, tombstone: Tombstone) {
+        self.entries.insert(tombstone.addr, tombstone);
+    }
+
+    /// Removes the tombstone for `addr`, e.g. to re-add a function on purpose.
+    pub fn remove(&mut self, addr: u64) -> Option<Tombstone> {
+        self.entries.remove(&addr)
+    }
+
+    pub fn to_csv(&self) -> Result<String> {
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        writer.write_record(TOMBSTONE_CSV_HEADER)?;
+        for tombstone in self.entries() {
+            writer.write_record(&[
+                functions::format_addr(tombstone.addr),
+                tombstone.reason.clone(),
+            ])?;
+        }
+        Ok(String::from_utf8(writer.into_inner()?)?)
+    }
+
+    /// Writes the tombstones to `path` atomically (see `file_utils::write_atomic`).
+    pub fn write_to_path(&self, path: &Path) -> Result<()> {
+        file_utils::write_atomic(path, self.to_csv()?.as_bytes())
+    }
+}
+
+/// Returns the path of the project's tombstone file (`removed_functions` in the config),
+/// if one is configured.
+pub fn get_tombstone_path() -> Result<Option<PathBuf>> {
+    match repo::CONFIG
+        .get("removed_functions")
+        .and_then(toml::Value::as_str)
+    {
+        Some(path) => Ok(Some(repo::get_repo_root()?.join(path))),
+        None => Ok(None),
+    }
+}
+
+/// Reports functions whose address has a tombstone, i.e. entries that were removed
+/// deliberately and have come back.
+pub fn check_tombstones(functions: &[Info], tombstones: &Tombstones) -> Vec<Issue> {
+    if tombstones.is_empty() {
+        return Vec::new();
+    }
+    functions
+        .iter()
+        .filter_map(|info| {
+            let tombstone = tombstones.get(info.addr)?;
+            Some(Issue::error(
+                Some(info.addr),
+                format!(
+                    "{} was removed deliberately ({}); remove its tombstone \
+                     if it was re-added on purpose",
+                    info.name, tombstone.reason
+                ),
+            ))
+        })
+        .collect()
+}
+
+/// Runs `write` (which writes `functions` over `old_functions`) and records a tombstone with
+/// `reason` for every address that was removed. If `write` fails, the tombstone file is
+/// restored, so that both files stay consistent.
+///
+/// Only `write` is run if no tombstone file is configured.
+pub(crate) fn write_with_tombstones(
+    old_functions: &[Info],
+    functions: &[Info],
+    reason: &str,
+    write: impl FnOnce() -> Result<()>,
+) -> Result<()> {
+    let path = match get_tombstone_path()? {
+        Some(path) => path,
+        None => return write(),
+    };
+
+    let remaining: FxHashSet<u64> = functions.iter().map(|info| info.addr).collect();
+    let removed: Vec<u64> = old_functions
+        .iter()
+        .map(|info| info.addr)
+        .filter(|addr| !remaining.contains(addr))
+        .collect();
+    if removed.is_empty() {
+        return write();
+    }
+
+    let old_contents = match std::fs::read(&path) {
+        Ok(contents) => Some(contents),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+        Err(err) => return Err(err).with_context(|| format!("failed to read {:?}", path)),
+    };
+    let mut tombstones = Tombstones::load_from_path(&path)?;
+    for addr in removed {
+        tombstones.insert(Tombstone {
+            addr,
+            reason: reason.to_string(),
+        });
+    }
+    tombstones.write_to_path(&path)?;
+
+    if let Err(err) = write() {
+        let restored = match &old_contents {
+            Some(contents) => file_utils::write_atomic(&path, contents),
+            None => {
+                std::fs::remove_file(&path).with_context(|| format!("failed to remove {:?}", path))
+            }
+        };
+        return Err(match restored {
+            Ok(()) => err,
+            Err(restore_err) => {
+                err.context(format!("failed to restore {:?}: {:#}", path, restore_err))
+            }
+        });
+    }
+    Ok(())
+}
@@ -5,14 +5,23 @@ use anyhow::Result;
 use capstone as cs;
 use capstone::arch::BuildsCapstone;
 use colored::*;
-use itertools::Itertools;
 use rayon::prelude::*;
 use std::cell::RefCell;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::AtomicBool;
-use viking::checks::FunctionChecker;
+use viking::check_results::{self, CheckOutcome, CheckResultBatch};
+use viking::checks::{self, FunctionChecker};
+use viking::edit::{self, Edit};
 use viking::elf;
+use viking::function_source::{FunctionSource, ProjectFunctions};
 use viking::functions;
 use viking::functions::Status;
+use viking::html_diff::{self, InstructionDiff};
+use viking::ignore::IgnoreSet;
+use viking::known_issues::KnownIssues;
+use viking::object;
+use viking::outlined::{self, OutlinedFunctionIndex};
+use viking::overrides::{CompileCommands, FlagOverrides};
 use viking::repo;
 use viking::ui;
 
@@ -21,21 +30,68 @@ use mimalloc::MiMalloc;
 #[global_allocator]
 static GLOBAL: MiMalloc = MiMalloc;
 
-/// Returns false if the program should exit with a failure code at the end.
+/// Returns the decomp function that corresponds to `function`.
+///
+/// Outlined functions are found by content because their names are not stable across builds.
+fn get_decomp_function<'a>(
+    orig_elf: &elf::OwnedElf,
+    decomp_elf: &'a elf::OwnedElf,
+    decomp_symtab: &elf::SymbolTableByName,
+    outlined_index: &OutlinedFunctionIndex<'a>,
+    function: &functions::Info,
+) -> Result<elf::Function<'a>> {
+    if !outlined::is_outlined_function(&function.name) {
+        return elf::get_function_by_name(decomp_elf, decomp_symtab, &function.name);
+    }
+
+    let orig_fn = elf::get_function(orig_elf, function.addr, function.size as u64)?;
+    match outlined_index.find(orig_fn.code) {
+        Some((_, decomp_fn)) => Ok(elf::Function {
+            addr: decomp_fn.addr,
+            code: decomp_fn.code,
+        }),
+        None => bail!("no outlined function in the decomp executable has the same instructions"),
+    }
+}
+
+/// The result of checking a single function.
+struct FunctionCheckResult {
+    /// False if the program should exit with a failure code at the end.
+    ok: bool,
+    /// What to record in the check result store, or None if the function was skipped.
+    outcome: Option<(CheckOutcome, Option<String>)>,
+}
+
+impl FunctionCheckResult {
+    fn skipped() -> Self {
+        Self {
+            ok: true,
+            outcome: None,
+        }
+    }
+}
+
 fn check_function(
     checker: &FunctionChecker,
     mut cs: &mut capstone::Capstone,
     orig_elf: &elf::OwnedElf,
     decomp_elf: &elf::OwnedElf,
     decomp_symtab: &elf::SymbolTableByName,
+    outlined_index: &OutlinedFunctionIndex,
     function: &functions::Info,
-) -> Result<bool> {
+) -> Result<FunctionCheckResult> {
     let name = function.name.as_str();
-    let decomp_fn = elf::get_function_by_name(&decomp_elf, &decomp_symtab, &name);
+    let decomp_fn = get_decomp_function(
+        orig_elf,
+        decomp_elf,
+        decomp_symtab,
+        outlined_index,
+        function,
+    );
 
     match function.status {
-        Status::NotDecompiled if decomp_fn.is_err() => return Ok(true),
-        Status::Library => return Ok(true),
+        Status::NotDecompiled if decomp_fn.is_err() => return Ok(FunctionCheckResult::skipped()),
+        Status::Library => return Ok(FunctionCheckResult::skipped()),
         _ => (),
     }
 
@@ -46,7 +102,10 @@ fn check_function(
             ui::format_symbol_name(name),
             error.to_string().dimmed(),
         ));
-        return Ok(true);
+        return Ok(FunctionCheckResult {
+            ok: true,
+            outcome: Some((CheckOutcome::Error, Some(error.to_string()))),
+        });
     }
 
     let decomp_fn = decomp_fn.unwrap();
@@ -61,7 +120,7 @@ fn check_function(
         })
     };
 
-    match function.status {
+    let result = match function.status {
         Status::Matching => {
             let orig_fn = get_orig_fn()?;
 
@@ -69,7 +128,7 @@ fn check_function(
                 .check(&mut cs, &orig_fn, &decomp_fn)
                 .with_context(|| format!("checking {}", name))?;
 
-            if let Some(mismatch) = result {
+            if let Some(mismatch) = &result {
                 let stderr = std::io::stderr();
                 let mut lock = stderr.lock();
                 ui::print_error_ex(
@@ -80,8 +139,8 @@ fn check_function(
                     ),
                 );
                 ui::print_detail_ex(&mut lock, &format!("{}", mismatch));
-                return Ok(false);
             }
+            result
         }
 
         Status::NotDecompiled
@@ -101,12 +160,30 @@ fn check_function(
                     function.status.description(),
                 ));
             }
+            result
         }
 
         Status::Library => unreachable!(),
     };
 
-    Ok(true)
+    Ok(match result {
+        None => FunctionCheckResult {
+            ok: true,
+            outcome: Some((CheckOutcome::Match, None)),
+        },
+        Some(mismatch) => FunctionCheckResult {
+            ok: function.status != Status::Matching,
+            outcome: Some((CheckOutcome::Mismatch, Some(mismatch.to_string()))),
+        },
+    })
+}
+
+/// Returns a batch for collecting check results, if check results should be kept
+/// (see `check_results::get_check_results_path`).
+fn make_result_batch(decomp_elf: &elf::OwnedElf) -> Result<Option<CheckResultBatch>> {
+    // Hashing the executable takes a while, so only do it if results are kept.
+    Ok(check_results::get_check_results_path()?
+        .map(|_| CheckResultBatch::new(check_results::hash_binary(&decomp_elf.as_owner().1))))
 }
 
 #[cold]
@@ -130,28 +207,66 @@ fn check_all(
     orig_elf: &elf::OwnedElf,
     decomp_elf: &elf::OwnedElf,
     decomp_symtab: &elf::SymbolTableByName,
+    outlined_index: &OutlinedFunctionIndex,
 ) -> Result<()> {
     let failed = AtomicBool::new(false);
+    let ignore_set = IgnoreSet::load()?;
+    let known_issues = KnownIssues::load()?;
+    let flag_overrides = load_flag_overrides()?;
+    let results = make_result_batch(decomp_elf)?;
 
     functions.par_iter().try_for_each(|function| {
+        if ignore_set.is_ignored(function) {
+            return Ok(());
+        }
+
         CAPSTONE.with(|cs| -> Result<()> {
             let mut cs = cs.borrow_mut();
-            let ok = check_function(
+            let result = check_function(
                 &checker,
                 &mut cs,
                 &orig_elf,
                 &decomp_elf,
                 &decomp_symtab,
+                outlined_index,
                 function,
             )?;
-            if !ok {
+            if known_issues.is_blocked(function)
+                && matches!(result.outcome, Some((CheckOutcome::Match, _)))
+            {
+                ui::print_note(&format!(
+                    "function {} matches; its known issue can be removed",
+                    ui::format_symbol_name(&function.name),
+                ));
+            }
+            if !result.ok {
                 failed.store(true, std::sync::atomic::Ordering::Relaxed);
+                print_unapplied_flag_overrides(&flag_overrides, function);
+            }
+            if let (Some(results), Some((outcome, diff))) = (&results, &result.outcome) {
+                results.add(&function.name, *outcome, diff.as_deref());
             }
 
             Ok(())
         })
     })?;
 
+    if let Some(results) = results {
+        check_results::record_check_results(results.into_records())
+            .context("failed to save check results")?;
+    }
+
+    let num_blocked = functions
+        .iter()
+        .filter(|function| known_issues.is_blocked(function) && !ignore_set.is_ignored(function))
+        .count();
+    if num_blocked != 0 {
+        ui::print_note(&format!(
+            "{} non-matching functions are blocked by known issues",
+            num_blocked
+        ));
+    }
+
     if failed.load(std::sync::atomic::Ordering::Relaxed) {
         bail!("found at least one error");
     } else {
@@ -159,6 +274,33 @@ fn check_all(
     }
 }
 
+/// Loads the compiler flag overrides and the compile commands they are checked against,
+/// if the project has any overrides and has been built.
+fn load_flag_overrides() -> Result<Option<(FlagOverrides, CompileCommands)>> {
+    let overrides = FlagOverrides::load()?;
+    if overrides.is_empty() {
+        return Ok(None);
+    }
+    Ok(CompileCommands::load()?.map(|commands| (overrides, commands)))
+}
+
+/// Explains a mismatch that may be caused by compiler flag overrides that the build
+/// does not apply (e.g. after a clean rebuild with a build system that lost them).
+fn print_unapplied_flag_overrides(
+    flag_overrides: &Option<(FlagOverrides, CompileCommands)>,
+    function: &functions::Info,
+) {
+    if let Some((overrides, commands)) = flag_overrides {
+        if let Some(unapplied) = overrides.get_unapplied(function, commands) {
+            ui::print_note(&format!(
+                "{} has compiler flag overrides that are not applied by the build: {}",
+                ui::format_symbol_name(&function.name),
+                unapplied
+            ));
+        }
+    }
+}
+
 fn get_function_to_check_from_args(args: &[String]) -> Result<String> {
     let mut maybe_fn_to_check: Vec<String> = args
         .iter()
@@ -180,6 +322,7 @@ fn check_single(
     orig_elf: &elf::OwnedElf,
     decomp_elf: &elf::OwnedElf,
     decomp_symtab: &elf::SymbolTableByName,
+    outlined_index: &OutlinedFunctionIndex,
     args: &Vec<String>,
 ) -> Result<()> {
     let fn_to_check = get_function_to_check_from_args(&args)?;
@@ -193,13 +336,19 @@ fn check_single(
         bail!("L functions should not be decompiled");
     }
 
-    let decomp_fn =
-        elf::get_function_by_name(&decomp_elf, &decomp_symtab, &name).with_context(|| {
-            format!(
-                "failed to get decomp function: {}",
-                ui::format_symbol_name(name)
-            )
-        })?;
+    let decomp_fn = get_decomp_function(
+        orig_elf,
+        decomp_elf,
+        decomp_symtab,
+        outlined_index,
+        function,
+    )
+    .with_context(|| {
+        format!(
+            "failed to get decomp function: {}",
+            ui::format_symbol_name(name)
+        )
+    })?;
 
     let orig_fn = elf::get_function(&orig_elf, function.addr, function.size as u64)?;
 
@@ -212,17 +361,49 @@ fn check_single(
         .find(|s| s.as_str() == "--always-diff")
         .is_some();
 
+    let dry_run = args.iter().any(|s| s.as_str() == "--dry-run");
+
     if let Some(mismatch) = &maybe_mismatch {
         eprintln!("{}\n{}", "mismatch".red().bold(), &mismatch);
+        if let Some(issue) = KnownIssues::load()?.get(name) {
+            ui::print_note(&format!("known issue: {} ({})", issue.reason, issue.url));
+        }
+        print_unapplied_flag_overrides(&load_flag_overrides()?, function);
         should_show_diff = true;
     } else {
         eprintln!("{}", "OK".green().bold());
     }
 
+    if let Some(html_path) = get_html_path_from_args(args) {
+        write_html_diff(
+            functions,
+            function,
+            maybe_mismatch.as_ref(),
+            &orig_fn,
+            &decomp_fn,
+            decomp_elf,
+            &html_path,
+        )?;
+    }
+
+    if let Some(results) = make_result_batch(decomp_elf)? {
+        match &maybe_mismatch {
+            Some(mismatch) => {
+                results.add(name, CheckOutcome::Mismatch, Some(&mismatch.to_string()))
+            }
+            None => results.add(name, CheckOutcome::Match, None),
+        }
+        check_results::record_check_results(results.into_records())
+            .context("failed to save check results")?;
+    }
+
     if should_show_diff {
-        let diff_args = args
-            .iter()
-            .filter(|s| s.as_str() != &fn_to_check && s.as_str() != "--always-diff");
+        let diff_args = args.iter().filter(|s| {
+            s.as_str() != &fn_to_check
+                && s.as_str() != "--always-diff"
+                && s.as_str() != "--dry-run"
+                && !s.starts_with("--html=")
+        });
 
         let differ_path = repo::get_tools_path()?.join("asm-differ").join("diff.py");
 
@@ -250,13 +431,124 @@ fn check_single(
             function.status, new_status
         ));
 
-        let mut new_functions = functions.iter().cloned().collect_vec();
-        new_functions
-            .iter_mut()
-            .find(|info| info.addr == function.addr)
-            .unwrap()
-            .status = new_status;
-        functions::write_functions(&new_functions)?;
+        // The function list is read again (under the lock) in case it was changed
+        // while the function was being checked.
+        let plan = edit::update_functions_ex(
+            &[Edit::SetStatus {
+                addr: function.addr,
+                status: new_status,
+            }],
+            dry_run,
+            Some("check"),
+        )?;
+
+        if dry_run {
+            eprintln!("{}", plan);
+        }
+    }
+
+    Ok(())
+}
+
+fn get_object_path_from_args(args: &[String]) -> Option<PathBuf> {
+    args.iter()
+        .find_map(|s| s.strip_prefix("--object="))
+        .map(PathBuf::from)
+}
+
+fn get_html_path_from_args(args: &[String]) -> Option<PathBuf> {
+    args.iter()
+        .find_map(|s| s.strip_prefix("--html="))
+        .map(PathBuf::from)
+}
+
+/// Writes an HTML diff of a function (see viking::html_diff), e.g. for sharing a mismatch.
+fn write_html_diff(
+    functions: &[functions::Info],
+    function: &functions::Info,
+    mismatch: Option<&checks::Mismatch>,
+    orig_fn: &elf::Function,
+    decomp_fn: &elf::Function,
+    decomp_elf: &elf::OwnedElf,
+    path: &Path,
+) -> Result<()> {
+    let known_functions = functions::make_known_function_map(functions);
+    let decomp_names = elf::make_addr_to_name_map(decomp_elf)?;
+    let diff = InstructionDiff::from_functions(
+        &make_cs()?,
+        orig_fn,
+        decomp_fn,
+        &|addr| known_functions.get(&addr).map(|info| info.name.clone()),
+        &|addr| decomp_names.get(&addr).map(|name| name.to_string()),
+    )?;
+    std::fs::write(path, html_diff::render_html_diff(function, mismatch, &diff))
+        .with_context(|| format!("failed to write {:?}", path))?;
+    ui::print_note(&format!("wrote HTML diff to {:?}", path));
+    Ok(())
+}
+
+fn get_hook_name_from_args(args: &[String]) -> Option<&str> {
+    args.iter().find_map(|s| s.strip_prefix("--hook="))
+}
+
+#[cfg(feature = "git")]
+fn run_hook(hook_name: &str) -> Result<()> {
+    viking::hooks::run(hook_name)
+}
+
+#[cfg(not(feature = "git"))]
+fn run_hook(hook_name: &str) -> Result<()> {
+    bail!(
+        "cannot run the {} hook: viking was built without the git feature",
+        hook_name
+    )
+}
+
+/// Checks a function against a relocatable object file instead of the decomp executable.
+/// The function status is left unchanged because the final link can still change the code.
+fn check_single_object(
+    functions: &[functions::Info],
+    orig_elf: &elf::OwnedElf,
+    object_path: &Path,
+    args: &[String],
+) -> Result<()> {
+    let fn_to_check = get_function_to_check_from_args(args)?;
+    let function = functions::find_function_fuzzy(functions, &fn_to_check)
+        .with_context(|| format!("unknown function: {}", ui::format_symbol_name(&fn_to_check)))?;
+    let name = function.name.as_str();
+
+    eprintln!("{}", ui::format_symbol_name(name).bold());
+
+    if matches!(function.status, Status::Library) {
+        bail!("L functions should not be decompiled");
+    }
+
+    let object = object::load_object(object_path)?;
+    let object_fn = object::get_object_function(&object, name).with_context(|| {
+        format!(
+            "failed to get function {} from {:?}",
+            ui::format_symbol_name(name),
+            object_path
+        )
+    })?;
+
+    let orig_fn = elf::get_function(orig_elf, function.addr, function.size as u64)?;
+    let known_functions = functions::make_known_function_map(functions);
+
+    let maybe_mismatch = checks::check_object_function(&orig_fn, &object_fn, &known_functions)
+        .with_context(|| format!("checking {}", name))?;
+
+    ui::print_note(&format!(
+        "object-level check against {} ({}): data references are not checked \
+         and section-anchored literals may differ after linking",
+        object_path.display(),
+        object_fn.section_name,
+    ));
+
+    if let Some(mismatch) = &maybe_mismatch {
+        eprintln!("{}\n{}", "mismatch".red().bold(), &mismatch);
+    } else {
+        eprintln!("{}", "OK".green().bold());
     }
 
     Ok(())
@@ -265,7 +557,20 @@ fn check_single(
 fn main() -> Result<()> {
     let args: Vec<String> = std::env::args().skip(1).collect();
 
+    // Hook mode: only the function list is checked (see viking::hooks).
+    if let Some(hook_name) = get_hook_name_from_args(&args) {
+        return run_hook(hook_name);
+    }
+
     let orig_elf = elf::load_orig_elf().context("failed to load original ELF")?;
+
+    // Object mode: the decomp executable is not needed (and might not be up to date).
+    if let Some(object_path) = get_object_path_from_args(&args) {
+        let functions = ProjectFunctions
+            .load()
+            .context("failed to load function CSV")?;
+        return check_single_object(&functions, &orig_elf, &object_path, &args);
+    }
     let decomp_elf = elf::load_decomp_elf().context("failed to load decomp ELF")?;
 
     // Load these in parallel.
@@ -276,7 +581,7 @@ fn main() -> Result<()> {
     rayon::scope(|s| {
         s.spawn(|_| decomp_symtab = Some(elf::make_symbol_map_by_name(&decomp_elf)));
         s.spawn(|_| decomp_glob_data_table = Some(elf::build_glob_data_table(&decomp_elf)));
-        s.spawn(|_| functions = Some(functions::get_functions()));
+        s.spawn(|_| functions = Some(ProjectFunctions.load()));
     });
 
     let decomp_symtab = decomp_symtab
@@ -289,6 +594,9 @@ fn main() -> Result<()> {
 
     let functions = functions.unwrap().context("failed to load function CSV")?;
 
+    let outlined_index = OutlinedFunctionIndex::build(&decomp_elf, &decomp_symtab)
+        .context("failed to index outlined functions")?;
+
     let checker = FunctionChecker::new(
         &orig_elf,
         &decomp_elf,
@@ -306,11 +614,19 @@ fn main() -> Result<()> {
             &orig_elf,
             &decomp_elf,
             &decomp_symtab,
+            &outlined_index,
             &args,
         )?;
     } else {
         // Normal check mode.
-        check_all(&functions, &checker, &orig_elf, &decomp_elf, &decomp_symtab)?;
+        check_all(
+            &functions,
+            &checker,
+            &orig_elf,
+            &decomp_elf,
+            &decomp_symtab,
+            &outlined_index,
+        )?;
     }
 
     Ok(())
@@ -0,0 +1,96 @@
+/*
+ * Test program for the C interface (include/viking.h).
+ *
+ * This is run by `cargo test --features ffi` (see tests/ffi.rs and build.rs). It can also be
+ * built against the shared library:
+ *
+ *   cargo rustc --release --lib --features ffi --crate-type cdylib
+ *   cc -Iinclude tests/ffi.c -Ltarget/release -lviking -o target/ffi_test
+ *   LD_LIBRARY_PATH=target/release target/ffi_test
+ *
+ * Exits with a non-zero status if a check fails.
+ */
+
+#include <stdio.h>
+#include <string.h>
+
+#include "viking.h"
+
+static int failures = 0;
+
+#define CHECK(cond)                                                   \
+  do {                                                                \
+    if (!(cond)) {                                                    \
+      fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__, __LINE__, #cond); \
+      failures++;                                                     \
+    }                                                                 \
+  } while (0)
+
+static const char* CSV =
+    "Address,Quality,Size,Name\n"
+    "0x0000007100000010,O,000016,_ZN4sead4Heap5allocEv\n"
+    "0x0000007100000020,U,000032,\n"
+    "0x0000007100000040,m,000008,nnMain\n";
+
+int viking_ffi_test(void) {
+  const char* path = "ffi_test_functions.csv";
+  FILE* file = fopen(path, "w");
+  if (!file) {
+    perror("fopen");
+    return 1;
+  }
+  fputs(CSV, file);
+  fclose(file);
+
+  CHECK(viking_load("does/not/exist.csv") == NULL);
+
+  ViKingHandle* handle = viking_load(path);
+  CHECK(handle != NULL);
+  if (!handle)
+    return 1;
+
+  ViKingInfo info;
+  CHECK(viking_lookup_addr(handle, 0x7100000010, &info) == 1);
+  CHECK(info.address == 0x7100000010 && info.size == 16 && info.status == 'O');
+  CHECK(strcmp(info.name, "_ZN4sead4Heap5allocEv") == 0);
+
+  /* Addresses inside a function. */
+  CHECK(viking_lookup_addr(handle, 0x710000003f, &info) == 1);
+  CHECK(info.address == 0x7100000020 && info.status == 'U' && info.name[0] == '\0');
+  CHECK(viking_lookup_addr(handle, 0x7100000048, &info) == 0);
+  CHECK(viking_lookup_addr(handle, 0x10, &info) == 0);
+  CHECK(viking_lookup_addr(handle, 0x7100000010, NULL) == -1);
+
+  CHECK(viking_lookup_name(handle, "nnMain", &info) == 1);
+  CHECK(info.address == 0x7100000040 && info.status == 'm');
+  CHECK(viking_lookup_name(handle, "unknown", &info) == 0);
+  CHECK(viking_lookup_name(NULL, "nnMain", &info) == -1);
+
+  char buf[64];
+  const char* expected = "sead::Heap::alloc()";
+  CHECK(viking_demangle("_ZN4sead4Heap5allocEv", buf, sizeof(buf)) == strlen(expected));
+  CHECK(strcmp(buf, expected) == 0);
+  /* Truncation works like snprintf. */
+  CHECK(viking_demangle("_ZN4sead4Heap5allocEv", buf, 5) == strlen(expected));
+  CHECK(strcmp(buf, "sead") == 0);
+  CHECK(viking_demangle("_ZN4sead4Heap5allocEv", NULL, 0) == strlen(expected));
+  CHECK(viking_demangle("nnMain", buf, sizeof(buf)) == 0);
+
+  viking_free(handle);
+  viking_free(NULL);
+  remove(path);
+
+  if (failures != 0) {
+    fprintf(stderr, "%d checks failed\n", failures);
+    return 1;
+  }
+  printf("all checks passed\n");
+  return 0;
+}
+
+/* The test harness calls viking_ffi_test directly. */
+#ifndef VIKING_FFI_TEST_HARNESS
+int main(void) {
+  return viking_ffi_test();
+}
+#endif
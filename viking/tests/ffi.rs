@@ -0,0 +1,24 @@
+//! Runs the C test program for the C interface (tests/ffi.c), which build.rs compiles
+//! when the `ffi` feature is enabled.
+#![cfg(feature = "ffi")]
+
+// The C test program calls the functions that are exported by the library.
+extern crate viking;
+
+use std::os::raw::c_int;
+
+#[link(name = "viking_ffi_test", kind = "static")]
+extern "C" {
+    fn viking_ffi_test() -> c_int;
+}
+
+#[test]
+fn c_interface() {
+    // The test program writes its function list to the working directory.
+    let dir = std::env::temp_dir().join(format!("viking-ffi-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::env::set_current_dir(&dir).unwrap();
+    let status = unsafe { viking_ffi_test() };
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(status, 0);
+}